@@ -22,8 +22,8 @@ impl<S: shell::Shell, A: args::Args> command::Command<A> for Boot<S> {
     }
     fn run(&self, args: &A) -> Result<(), Box<dyn Error>> {
         log::debug!("boot command invoked");
-        self.config.prepare_workflow()?;
         let workflow = config::runtime::Workflow::new(args, &self.config, false)?;
+        self.config.prepare_workflow(&workflow)?;
         let config = self.config.borrow();
         config.jobs.boot(&config, &workflow, &self.shell)?;
         return Ok(())
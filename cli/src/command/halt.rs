@@ -5,6 +5,7 @@ use log;
 use core::args;
 use core::config;
 use core::shell;
+use core::vcs;
 
 use crate::command;
 
@@ -24,6 +25,11 @@ impl<S: shell::Shell, A: args::Args> command::Command<A> for Halt<S> {
     fn run(&self, args: &A) -> Result<(), Box<dyn Error>> {
         log::debug!("halt command invoked");
         let workflow = config::runtime::Workflow::new(args, &self.config, false)?;
+        let diff_base = self.config.borrow().workflows.get(&workflow.name).and_then(|w| w.diff_base());
+        if diff_base == Some(vcs::DiffBase::LastDeploy) {
+            // changed files are needed to know which jobs should have deployed
+            self.config.prepare_workflow(&workflow)?;
+        }
         let config = self.config.borrow();
         config.jobs.halt(&config, &workflow)?;
         return Ok(())
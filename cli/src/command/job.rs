@@ -29,6 +29,14 @@ impl<S: shell::Shell> Job<S> {
         config.jobs.set_user_output(&config, key, value)?;
         Ok(())
     }
+    fn run_job<A: args::Args>(&self, args: &A) -> Result<(), Box<dyn Error>> {
+        let config = self.config.borrow();
+        let parent_workflow = args.value_or_die("parent_workflow");
+        let job = args.value_or_die("job");
+        let workflow = config::runtime::Workflow::with_payload(parent_workflow)?;
+        config.jobs.run_job(&config, &workflow, job, &self.shell)?;
+        Ok(())
+    }
     fn steps<A: args::Args>(&self, args: &A) -> Result<(), Box<dyn Error>> {
         let config = self.config.borrow();
         let parent_workflow = args.value_or_die("parent_workflow");
@@ -51,6 +59,7 @@ impl<S: shell::Shell, A: args::Args> command::Command<A> for Job<S> {
         match args.subcommand() {
             Some(("output", subargs)) => return self.output(&subargs),
             Some(("set-output", subargs)) => return self.set_output(&subargs),
+            Some(("run", subargs)) => return self.run_job(&subargs),
             Some(("run-steps", subargs)) => return self.steps(&subargs),
             Some((name, _)) => return escalate!(args.error(
                 &format!("no such subcommand: [{}]", name) 
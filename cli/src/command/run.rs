@@ -22,8 +22,8 @@ impl<S: shell::Shell, A: args::Args> command::Command<A> for Run<S> {
     }
     fn run(&self, args: &A) -> Result<(), Box<dyn Error>> {
         log::debug!("run command invoked");
-        self.config.prepare_workflow()?;
         let workflow = config::runtime::Workflow::new(args, &self.config, true)?;
+        self.config.prepare_workflow(&workflow)?;
         let config = self.config.borrow();
         config.jobs.run(&config, &workflow, &self.shell)?;
         return Ok(());
@@ -3,7 +3,8 @@ use std::error::Error;
 
 use core::config;
 use core::shell;
-use core::util::json_to_strmap;
+use core::vcs::{self, changelog, version};
+use core::util::{json_to_strmap, parse_duration};
 
 use crate::args;
 use crate::command;
@@ -18,15 +19,73 @@ impl<S: shell::Shell> VCS<S> {
     fn release<A: args::Args>(&self, args: &A) -> Result<(), Box<dyn Error>> {
         let config = self.config.borrow();
         let vcs = config.modules.vcs();
-        vcs.release(
-            (args.value_or_die("tag_name"), false),
-            &args.json_value_of("option")?
+        let tag_name = args.value_or_die("tag_name");
+        let mut options = args.json_value_of("option")?;
+        let body_key = changelog::release_body_key(&config);
+        if config.changelog.is_some() && options.get(body_key).is_none() {
+            match changelog::previous_tag(&config, tag_name)? {
+                Some(from) => {
+                    // tag may not be created yet. then changelog is made up to current HEAD
+                    let to = match vcs.commit_hash(Some(tag_name)) {
+                        Ok(_) => tag_name,
+                        Err(_) => "HEAD"
+                    };
+                    let body = changelog::generate(&config, &from, to)?;
+                    options.as_object_mut().unwrap().insert(body_key.to_string(), serde_json::json!(body));
+                },
+                None => log::info!("no previous release of {}, skip generating changelog", tag_name)
+            }
+        }
+        vcs.release((tag_name, false), &options)?;
+        Ok(())
+    }
+    fn bump<A: args::Args>(&self, args: &A) -> Result<(), Box<dyn Error>> {
+        let config = self.config.borrow();
+        let plan = version::plan(
+            &config, args.value_or_die("level").parse()?, args.value_of("release_target")
         )?;
+        if args.get_flag("dry_run") {
+            println!("{}", plan);
+            return Ok(());
+        }
+        config.modules.vcs().push_tag(&plan.next, &plan.commit)?;
+        log::info!("bump version: {}", plan);
+        println!("{}", plan.next);
+        Ok(())
+    }
+    fn gc<A: args::Args>(&self, args: &A) -> Result<(), Box<dyn Error>> {
+        let config = self.config.borrow();
+        let dry_run = args.get_flag("dry_run");
+        let stale = vcs::gc::run(&config, parse_duration(args.value_or_die("older_than"))?, dry_run)?;
+        for b in &stale {
+            println!("{}{}\t{}", if dry_run { "(dry run) " } else { "" }, b.name, b.last_commit_at);
+        }
+        log::info!("{} stale branches {}", stale.len(), if dry_run { "found" } else { "deleted" });
+        Ok(())
+    }
+    fn changelog<A: args::Args>(&self, args: &A) -> Result<(), Box<dyn Error>> {
+        let config = self.config.borrow();
+        println!("{}", changelog::generate(&config, args.value_or_die("from"), args.value_or_die("to"))?);
         Ok(())
     }
     fn release_assets<A: args::Args>(&self, args: &A) -> Result<(), Box<dyn Error>> {
         let config = self.config.borrow();
         let vcs = config.modules.vcs();
+        match args.subcommand() {
+            Some(("list", subargs)) => {
+                let assets = vcs.list_release_assets((subargs.value_or_die("tag_name"), false))?;
+                println!("{}", serde_json::to_string(&assets)?);
+                return Ok(());
+            },
+            Some(("download", subargs)) => {
+                let name = subargs.value_or_die("name");
+                return vcs::download_verified_release_asset(
+                    vcs.as_ref(), subargs.value_or_die("tag_name"), name,
+                    subargs.value_of("output").unwrap_or(name)
+                );
+            },
+            _ => {}
+        }
         let mut options = args.json_value_of("option")?;
         if args.get_flag("replace") {
             options.as_object_mut().unwrap().insert("replace".to_string(), serde_json::json!(true));
@@ -112,6 +171,9 @@ impl<S: shell::Shell, A: args::Args> command::Command<A> for VCS<S> {
         match args.subcommand() {
             Some(("release", subargs)) => return self.release(&subargs),
             Some(("release-assets", subargs)) => return self.release_assets(&subargs),
+            Some(("changelog", subargs)) => return self.changelog(&subargs),
+            Some(("bump", subargs)) => return self.bump(&subargs),
+            Some(("gc", subargs)) => return self.gc(&subargs),
             Some(("pr", subargs)) => return self.control_pr(&subargs),
             Some(("label", subargs)) => return self.control_label(&subargs),
             Some((name, _)) => return escalate!(args.error(
@@ -103,6 +103,21 @@ fn workflow_command_options(
         .long("silent")
         .action(clap::ArgAction::SetTrue)
         .required(false))
+    .arg(Arg::new("jobs")
+        .help(r#"max number of jobs that run concurrently on local machine. default is 1.
+jobs that does not depend on each other run in separated deplo processes,
+and local ci account is required to pass job outputs between them"#)
+        .long("jobs")
+        .required(false))
+    .arg(Arg::new("keep_going")
+        .help("if set, jobs that do not depend on failed job continue to run. by default, all running jobs are stopped at first failure")
+        .long("keep-going")
+        .action(clap::ArgAction::SetTrue)
+        .required(false))
+    .arg(Arg::new("log_dir")
+        .help("if set, output of concurrently running jobs is written to $log_dir/$job_name.log, instead of prefixed with job name")
+        .long("log-dir")
+        .required(false))
     .arg(Arg::new("timeout")
         .help("wait timeout for remote job. if --async is set, this option is ignored")
         .long("timeout")
@@ -309,6 +324,18 @@ lazy_static! {
                     .help("key to get value")
                     .index(2)
                     .required(true)))
+            .subcommand(
+                Command::new("run")
+                .about("run single job without its dependencies. designed to be used by deplo itself for running jobs concurrently")
+                .arg(Arg::new("job")
+                    .help("job name to run")
+                    .index(1)
+                    .required(true))
+                .arg(Arg::new("parent_workflow")
+                    .help("runtime workflow config of parent deplo process")
+                    .short('p')
+                    .long("parent_workflow")
+                    .required(true)))
             .subcommand(
                 Command::new("run-steps")
                 .about("run all steps of the job. designed to be used by deplo itself, you seldom can utilize this command")
@@ -401,13 +428,81 @@ lazy_static! {
                         .help("option for release creation.\n\
                                 -o $key=$value\n\
                                 for github, body options of https://docs.github.com/en/rest/reference/releases#create-a-release can be specified.\n\
-                                TODO: for gitlab")
+                                for gitlab, body options of https://docs.gitlab.com/ee/api/releases/#create-a-release can be specified.\n\
+                                if [changelog] is configured and body (description for gitlab) is omitted, generated changelog is used.")
                         .short('o')
                         .action(clap::ArgAction::Append))
                 )
+                .subcommand(
+                    Command::new("changelog")
+                    .about("print changelog between two refs")
+                    .arg(Arg::new("from")
+                        .help("ref of previous release")
+                        .index(1)
+                        .required(true))
+                    .arg(Arg::new("to")
+                        .help("ref of new release")
+                        .index(2)
+                        .required(true))
+                )
+                .subcommand(
+                    Command::new("gc")
+                    .about("delete deplo-auto-commits-* branches that are left by failed runs")
+                    .arg(Arg::new("older_than")
+                        .help("only delete branches whose last commit is older than this. eg. 12h, 7d")
+                        .long("older-than")
+                        .default_value("7d"))
+                    .arg(Arg::new("dry_run")
+                        .help("only list branches to delete")
+                        .long("dry-run")
+                        .action(clap::ArgAction::SetTrue))
+                )
+                .subcommand(
+                    Command::new("bump")
+                    .about("create and push next semantic version tag for current HEAD")
+                    .arg(Arg::new("level")
+                        .help("version part to increment. auto infers it from conventional commits since last version tag")
+                        .index(1)
+                        .value_parser(["major", "minor", "patch", "auto"])
+                        .default_value("auto"))
+                    .arg(Arg::new("release_target")
+                        .help("only consider tags that match with patterns of the release target")
+                        .short('r')
+                        .long("release-target"))
+                    .arg(Arg::new("dry_run")
+                        .help("only print next version tag")
+                        .long("dry-run")
+                        .action(clap::ArgAction::SetTrue))
+                )
                 .subcommand(
                     Command::new("release-assets")
                     .about("upload release assets")
+                    .args_conflicts_with_subcommands(true)
+                    .subcommand_negates_reqs(true)
+                    .subcommand(
+                        Command::new("list")
+                        .about("list assets of the release as json")
+                        .arg(Arg::new("tag_name")
+                            .help("tag name of the release")
+                            .index(1)
+                            .required(true))
+                    )
+                    .subcommand(
+                        Command::new("download")
+                        .about("download asset of the release. if $name.sha256 asset exists, checksum is verified")
+                        .arg(Arg::new("tag_name")
+                            .help("tag name of the release")
+                            .index(1)
+                            .required(true))
+                        .arg(Arg::new("name")
+                            .help("asset name to download")
+                            .index(2)
+                            .required(true))
+                        .arg(Arg::new("output")
+                            .help("file path to save the asset. default is the asset name in current directory")
+                            .short('o')
+                            .long("out"))
+                    )
                     .arg(Arg::new("tag_name")
                         .help("tag name to use for release")
                         .index(1)
@@ -424,6 +519,7 @@ lazy_static! {
                         .help("option for release creation.\n\
                                 -o name=$release_asset_name\n\
                                 -o content-type=$content_type_of_asset\n\
+                                -o package=$generic_package_name (gitlab only. default is repository name)\n\
                                 TODO: implement more options")
                         .short('o')
                         .action(clap::ArgAction::Append))
@@ -441,7 +537,7 @@ lazy_static! {
                                     for github, body options of https://docs.github.com/en/rest/pulls/pulls?apiVersion=2022-11-28#create-a-pull-request can be specified.\n\
                                     plus, -o labels=$json_array to attach labels.\n\
                                           -o assignees=$json_array to assign accounts.\n\
-                                    TODO: for gitlab")
+                                    for gitlab, body options of https://docs.gitlab.com/ee/api/merge_requests.html#create-mr can be specified.")
                             .short('o')
                             .action(clap::ArgAction::Append))
                     )
@@ -451,7 +547,8 @@ lazy_static! {
                         .arg(Arg::new("filter")
                             .help("filter for pull request search.\n\
                                     -f $key=$value\n\
-                                    for github, query parameters of https://docs.github.com/en/rest/issues/issues?apiVersion=2022-11-28#list-repository-issues can be specified.")
+                                    for github, query parameters of https://docs.github.com/en/rest/issues/issues?apiVersion=2022-11-28#list-repository-issues can be specified.\n\
+                                    for gitlab, query parameters of https://docs.gitlab.com/ee/api/merge_requests.html#list-project-merge-requests can be specified.")
                             .short('f')
                             .action(clap::ArgAction::Append))
                     )
@@ -469,7 +566,7 @@ lazy_static! {
                                     plus, -o auto_merge=true to enable auto merge.\n\
                                           -o message=$text to post comment to pull request.\n\
                                           -o approve=true to approve the pull request.\n\
-                                    TODO: for gitlab")
+                                    for gitlab, merge_method=squash, commit_title, commit_message and sha are supported.")
                             .short('o')
                             .action(clap::ArgAction::Append))
                     )
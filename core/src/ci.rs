@@ -99,6 +99,9 @@ impl Error for CIError {
 
 pub mod ghaction;
 pub mod circleci;
+pub mod gitlab;
+pub mod local;
+mod runner;
 
 // factorys
 fn factory_by<'a, T: CI + 'a>(
@@ -120,6 +123,48 @@ pub fn factory<'a>(
         config::ci::Account::CircleCI {..} => {
             return factory_by::<circleci::CircleCI>(config, account_name);
         },
-        _ => panic!("unsupported ci account type {}", account_name)
+        config::ci::Account::Gitlab {..} => {
+            return factory_by::<gitlab::GitlabCI>(config, account_name);
+        },
+        config::ci::Account::Local {..} => {
+            return factory_by::<local::Local>(config, account_name);
+        },
+        config::ci::Account::Module {..} => {
+            return factory_by::<runner::ModuleRunner>(config, account_name);
+        }
     };
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::Path;
+
+    use crate::config;
+    use crate::shell::mock;
+    use crate::vcs::{self, git, github};
+
+    // loads github vcs that treats root as repository root, to generate ci config files under root
+    pub fn load_vcs_at(config: &config::Container, root: &Path) {
+        let root = root.to_string_lossy().to_string();
+        let git_shell = mock::Mock::with(config, move |args, _| match args {
+            [git, rev_parse, toplevel] if git == "git" && rev_parse == "rev-parse" && toplevel == "--show-toplevel" => {
+                Ok(root.clone())
+            },
+            [git, config, _, key] if git == "git" && config == "config" && key == "remote.origin.url" => {
+                Ok("https://github.com/o/r.git".to_string())
+            },
+            _ => Ok("".to_string())
+        });
+        let value = config::Value::new;
+        let vcs = github::Github {
+            config: config.clone(),
+            git: <git::ShellGit<mock::Mock> as git::GitFeatures<mock::Mock>>::from_pat(
+                &value("o"), &value("mail@address.com"), &value("token"), &None, git_shell
+            ).unwrap(),
+            shell: mock::Mock::with(config, |args, _| panic!("unexpected api call {:?}", args)),
+            app_token_generator: None,
+            diff: vcs::ChangeSet::default()
+        };
+        config.borrow_mut().modules.vcs = Some(Box::new(vcs));
+    }
+}
@@ -1,14 +1,96 @@
 use std::fs;
+use std::io::Write;
 use std::error::Error;
 use std::result::Result;
 use std::collections::{HashMap};
 
 use maplit::hashmap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
 
 use crate::config;
 use crate::ci::{self, CheckoutOption};
 use crate::shell;
-use crate::util::{escalate,MultilineFormatString,rm};
+use crate::util::{escalate,MultilineFormatString,rm,sorted_key_iter,randombytes_as_string};
+
+// workspace root that is shared between jobs with attach_workspace/persist_to_workspace.
+// each job persists /tmp/deplo/jobs/$job_name, which contains outputs and cleanup marker.
+const DEPLO_CIRCLECI_WORKSPACE: &str = "/tmp/deplo";
+// workflow status that indicates workflow is still running
+const RUNNING_WORKFLOW_STATUSES: [&str; 3] = ["running", "failing", "on_hold"];
+
+fn common_envs(account_name: &str) -> Vec<String> {
+    vec![
+        format!("DEPLO_CI_ACCOUNT_NAME: \"{}\"", account_name),
+        "DEPLO_CIRCLECI_TRIGGER_SOURCE: << pipeline.trigger_source >>".to_string(),
+        "DEPLO_CIRCLECI_SCHEDULE_NAME: << pipeline.schedule.name >>".to_string(),
+        "DEPLO_CIRCLECI_SCHEDULE: << pipeline.parameters.deplo_schedule >>".to_string(),
+        "DEPLO_CIRCLECI_SYSTEM_DISPATCH: << pipeline.parameters.deplo_system_dispatch >>".to_string(),
+        "DEPLO_CIRCLECI_DISPATCH: << pipeline.parameters.deplo_dispatch >>".to_string(),
+        "DEPLO_CIRCLECI_DISPATCH_INPUTS: << pipeline.parameters.deplo_dispatch_inputs >>".to_string(),
+    ]
+}
+
+// event payload of circleci. generated from pipeline values and parameters,
+// which are passed to the jobs via common_envs.
+#[derive(Serialize, Deserialize)]
+struct PipelineEvent {
+    // pipeline.trigger_source (webhook, api, scheduled_pipeline)
+    pub trigger_source: String,
+    // CIRCLE_PULL_REQUEST
+    pub pull_request: Option<String>,
+    // pipeline.schedule.name
+    pub schedule_name: Option<String>,
+    // pipeline.parameters.deplo_schedule
+    pub schedule: Option<String>,
+    // pipeline.parameters.deplo_system_dispatch
+    pub system_dispatch: Option<String>,
+    // pipeline.parameters.deplo_dispatch
+    pub dispatch: Option<String>,
+    // pipeline.parameters.deplo_dispatch_inputs
+    pub inputs: Option<String>,
+}
+impl PipelineEvent {
+    fn from_env() -> Self {
+        Self::from_vars(|k| std::env::var(k).ok())
+    }
+    // env is given as function, to make it possible to test without process env
+    fn from_vars<F: Fn(&str) -> Option<String>>(env: F) -> Self {
+        let var = |k: &str| env(k).filter(|v| !v.is_empty());
+        Self {
+            trigger_source: var("DEPLO_CIRCLECI_TRIGGER_SOURCE").unwrap_or_else(|| "webhook".to_string()),
+            pull_request: var("CIRCLE_PULL_REQUEST"),
+            schedule_name: var("DEPLO_CIRCLECI_SCHEDULE_NAME"),
+            schedule: var("DEPLO_CIRCLECI_SCHEDULE"),
+            system_dispatch: var("DEPLO_CIRCLECI_SYSTEM_DISPATCH"),
+            dispatch: var("DEPLO_CIRCLECI_DISPATCH"),
+            inputs: var("DEPLO_CIRCLECI_DISPATCH_INPUTS"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PartialPipeline {
+    pub id: String,
+    pub number: u64,
+}
+#[derive(Deserialize)]
+struct PartialWorkflow {
+    pub status: String,
+}
+#[derive(Deserialize)]
+struct PartialWorkflows {
+    pub items: Vec<PartialWorkflow>,
+}
+#[derive(Deserialize)]
+struct PartialEnvVar {
+    pub name: String,
+}
+#[derive(Deserialize)]
+struct PartialEnvVars {
+    pub items: Vec<PartialEnvVar>,
+    pub next_page_token: Option<String>,
+}
 
 pub struct CircleCI<S: shell::Shell = shell::Default> {
     pub config: config::Container,
@@ -17,9 +99,43 @@ pub struct CircleCI<S: shell::Shell = shell::Default> {
 }
 
 impl<S: shell::Shell> CircleCI<S> {
-    fn generate_entrypoint<'a>(&self, _: &'a config::Config) -> Vec<String> {
-        include_str!("../../res/ci/circleci/entrypoint.yml.tmpl")
-            .to_string().split("\n").map(|s| s.to_string()).collect()
+    fn generate_entrypoint(&self, jobs: &HashMap<&String, &config::job::Job>) -> Vec<String> {
+        let mut workflow_jobs = vec![];
+        let mut halt_requires = vec!["- deplo-main: [success, failed]".to_string()];
+        for (name, job) in sorted_key_iter(jobs) {
+            let mut requires = vec!["deplo-main".to_string()];
+            if let Some(ref depends) = job.depends {
+                requires.extend(depends.iter().map(config::Value::resolve_to_string));
+            }
+            workflow_jobs.extend(format!(
+                include_str!("../../res/ci/circleci/workflow_job.yml.tmpl"),
+                name = name, requires = requires.join(", ")
+            ).split("\n").map(|s| s.to_string()));
+            // halt should run regardless of the result of each job
+            halt_requires.push(format!("- {}: [success, failed]", name));
+        }
+        format!(
+            include_str!("../../res/ci/circleci/entrypoint.yml.tmpl"),
+            jobs = MultilineFormatString{ strings: &workflow_jobs, postfix: None },
+            halt_requires = MultilineFormatString{ strings: &halt_requires, postfix: None }
+        ).split("\n").map(|s| s.to_string()).collect()
+    }
+    fn generate_fetchcli_steps(&self, runner: &config::job::Runner) -> Vec<String> {
+        let (path, uname, ext) = match runner {
+            config::job::Runner::Machine{ref os, ..} => match os {
+                config::job::RunnerOS::Windows => ("/usr/bin/deplo", "Windows", ".exe"),
+                config::job::RunnerOS::Linux => ("/usr/local/bin/deplo", "Linux-$(uname -m)", ""),
+                v => ("/usr/local/bin/deplo", v.uname(), "")
+            },
+            config::job::Runner::Container{..} => ("/usr/local/bin/deplo", "Linux-$(uname -m)", "")
+        };
+        format!(include_str!("../../res/ci/circleci/fetchcli.yml.tmpl"),
+            deplo_cli_path = path,
+            download_url = format!(
+                "{}/{}/deplo-{}{}",
+                config::DEPLO_RELEASE_URL_BASE, config::DEPLO_VERSION, uname, ext
+            )
+        ).split("\n").map(|s| s.to_string()).collect::<Vec<String>>()
     }
     fn generate_executor_setting<'a>(&self, runner: &'a config::job::Runner) -> String {
         return match runner {
@@ -38,23 +154,68 @@ impl<S: shell::Shell> CircleCI<S> {
                     None => "".to_string(),
                 }
             ),
-            config::job::Runner::Container{ image, .. } => format!("image: {}", image),
+            config::job::Runner::Container{ image, .. } => format!("docker:\n  - image: {}", image),
         }
     }
     fn generate_workdir_setting<'a>(&self, job: &'a config::job::Job) -> String {
-        return job.workdir.as_ref().map_or_else(|| "".to_string(), |wd| format!("workdir: {}", wd));
+        return job.workdir.as_ref().map_or_else(|| "".to_string(), |wd| format!("working_directory: {}", wd));
     }
     fn generate_checkout_steps(&self, _: &str, account: &config::ci::Account, options: &Option<config::job::CheckoutOption>) -> String {
-        let mut checkout_opts = options.as_ref().map_or_else(|| Vec::new(), |v| v.opt_str(account));
-        checkout_opts.push(format!("opts_hash: {}", options.as_ref().map_or_else(|| "".to_string(), |v| v.hash(account))));
         format!(
             include_str!("../../res/ci/circleci/checkout.yml.tmpl"), 
-            checkout_opts = MultilineFormatString{
-                strings: &checkout_opts,
-                postfix: None
-            }
+            lfs = options.as_ref().map_or_else(|| "", |v| if v.lfs.unwrap_or(false) { "true" } else { "" }),
+            opts_hash = options.as_ref().map_or_else(|| "".to_string(), |v| v.hash(account))
         )
     }
+    fn get_token(&self) -> Result<config::Value, Box<dyn Error>> {
+        let config = self.config.borrow();
+        match &config.ci.get(&self.account_name).expect(&format!("no ci config for {}", self.account_name)) {
+            config::ci::Account::CircleCI { key, .. } => Ok(key.clone()),
+            _ => escalate!(Box::new(ci::CIError {
+                cause: "should have circleci CI config but other config provided".to_string()
+            }))
+        }
+    }
+    fn project_api_url(&self, path: &str) -> Result<String, Box<dyn Error>> {
+        let config = self.config.borrow();
+        // circleci project slug is prefixed by vcs type (gh: github, bb: bitbucket)
+        let vcs_slug = match config.vcs {
+            config::vcs::Account::Github{..} | config::vcs::Account::GithubApp{..} => "gh",
+            _ => return escalate!(Box::new(ci::CIError {
+                cause: format!("circleci does not support {} as vcs", config.vcs)
+            }))
+        };
+        let user_and_repo = config.modules.vcs().user_and_repo()?;
+        Ok(format!(
+            "https://circleci.com/api/v2/project/{}/{}/{}{}",
+            vcs_slug, user_and_repo.0, user_and_repo.1, path
+        ))
+    }
+    // export user outputs of dependent jobs as the same form as other CI (base64 encoded json)
+    fn generate_job_envs(&self, job: &config::job::Job) -> Vec<String> {
+        let mut envs = match job.depends {
+            Some(ref depends) => depends.iter().map(|d| format!(
+                "export {}=\"$(cat {}/user.json 2>/dev/null | base64 | tr -d '\\n')\"",
+                ci::OutputKind::User.env_name_for_job(&d.resolve()), Self::job_dir(&d.resolve())
+            )).collect(),
+            None => vec![]
+        };
+        // values of matrix that the job is expanded from
+        for (k, v) in sorted_key_iter(&job.matrix_envs()) {
+            envs.push(format!("export {}='{}'", k, v.resolve().replace('\'', "'\\''")));
+        }
+        envs
+    }
+    // circleci only supports timeout for no output, so upper bound of whole job is used for it
+    fn generate_no_output_timeout(&self, job: &config::job::Job) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(match job.max_duration()? {
+            Some(d) => vec![format!("no_output_timeout: {}s", d.as_secs())],
+            None => vec![]
+        })
+    }
+    fn job_dir(job_name: &str) -> String {
+        format!("{}/jobs/{}", DEPLO_CIRCLECI_WORKSPACE, job_name)
+    }
 }
 
 impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
@@ -81,7 +242,9 @@ impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
         let config = self.config.borrow();
         let account = config.ci.get(&self.account_name).expect(&format!("no ci config for {}", self.account_name));
         let repository_root = config.modules.vcs().repository_root()?;
-        let jobs = config.jobs.as_map();
+        let jobs = config.jobs.as_map().iter().filter(
+            |(_,v)| v.is_enabled_for_account(&self.account_name)
+        ).collect::<HashMap<_,_>>();
         let create_main = config.ci.is_main("CircleCI");
         // TODO_PATH: use Path to generate path of /.circleci/...
         let circle_yml_path = format!("{}/.circleci/config.yml", repository_root);
@@ -89,13 +252,38 @@ impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
         let previously_no_file = !rm(&circle_yml_path);
         // generate job entries
         let mut job_descs = Vec::new();
-        for (name, job) in jobs {
+        for (name, job) in sorted_key_iter(&jobs) {
             let lines = format!(
                 include_str!("../../res/ci/circleci/job.yml.tmpl"),
                 name = name,
-                machine_or_container = self.generate_executor_setting(&job.runner),
-                workdir = self.generate_workdir_setting(job),
-                checkout = self.generate_checkout_steps(&name, account, &job.checkout),
+                machine_or_container = MultilineFormatString{
+                    strings: &self.generate_executor_setting(&job.runner).split("\n").collect::<Vec<_>>(),
+                    postfix: None
+                },
+                workdir = MultilineFormatString{
+                    strings: &vec![self.generate_workdir_setting(job)],
+                    postfix: None
+                },
+                common_envs = MultilineFormatString{
+                    strings: &common_envs(&self.account_name),
+                    postfix: None
+                },
+                checkout = MultilineFormatString{
+                    strings: &self.generate_checkout_steps(&name, account, &job.checkout).split("\n").collect::<Vec<_>>(),
+                    postfix: None
+                },
+                fetchcli = MultilineFormatString{
+                    strings: &self.generate_fetchcli_steps(&job.runner),
+                    postfix: None
+                },
+                job_envs = MultilineFormatString{
+                    strings: &self.generate_job_envs(job),
+                    postfix: None
+                },
+                no_output_timeout = MultilineFormatString{
+                    strings: &self.generate_no_output_timeout(job)?,
+                    postfix: None
+                }
             ).split("\n").map(|s| s.to_string()).collect::<Vec<String>>();
             job_descs = job_descs.into_iter().chain(lines.into_iter()).collect();
         }
@@ -113,8 +301,18 @@ impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
         }
         fs::write(&circle_yml_path, format!(
             include_str!("../../res/ci/circleci/main.yml.tmpl"),
+            common_envs = MultilineFormatString{
+                strings: &common_envs(&self.account_name),
+                postfix: None
+            },
             entrypoint = MultilineFormatString{ 
-                strings: &(if create_main { self.generate_entrypoint(&config) } else { vec![] }),
+                strings: &(if create_main { self.generate_entrypoint(&jobs) } else { vec![] }),
+                postfix: None
+            },
+            fetchcli = MultilineFormatString{
+                strings: &self.generate_fetchcli_steps(&config::job::Runner::Machine{
+                    os: config::job::RunnerOS::Linux, image: None, class: None, local_fallback: None, no_fallback: None
+                }),
                 postfix: None
             },
             jobs = MultilineFormatString{ 
@@ -143,37 +341,220 @@ impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
     }
     fn mark_need_cleanup(&self, job_name: &str) -> Result<(), Box<dyn Error>> {
         if config::Config::is_running_on_ci() {
-            fs::create_dir_all("/tmp/deplo/need_cleanup_jobs")?;
-            fs::write(format!("/tmp/deplo/need_cleanup_jobs/{}", job_name), "")?;
+            fs::create_dir_all(Self::job_dir(job_name))?;
+            fs::write(format!("{}/need_cleanup", Self::job_dir(job_name)), "")?;
         } else {
             log::debug!("mark_need_cleanup: {}", job_name);
         }
         Ok(())
     }
     fn filter_workflows(
-        &self, _trigger: Option<ci::WorkflowTrigger>
+        &self, trigger: Option<ci::WorkflowTrigger>
     ) -> Result<Vec<config::runtime::Workflow>, Box<dyn Error>> {
-        log::warn!("TODO: implement filter_workflows for circleci");
-        Ok(vec![])
+        let resolved_trigger = match trigger {
+            Some(t) => t,
+            // on circleci, event payload is generated from pipeline values passed by common_envs
+            None => ci::WorkflowTrigger::EventPayload(serde_json::to_string(&PipelineEvent::from_env())?)
+        };
+        let payload = match &resolved_trigger {
+            ci::WorkflowTrigger::EventPayload(payload) => payload
+        };
+        let event = serde_json::from_str::<PipelineEvent>(payload)?;
+        let config = self.config.borrow();
+        let mut matches = vec![];
+        // pipelines triggered by api with deplo parameters are dispatched to specific workflow.
+        // others (webhook, or api without parameters) are treated as push or pull request.
+        let dispatched = event.system_dispatch.is_some() || event.dispatch.is_some();
+        for (name, v) in sorted_key_iter(config.workflows.as_map()) {
+            match v {
+                config::workflow::Workflow::Deploy(..) => if !dispatched && event.trigger_source != "scheduled_pipeline" && event.pull_request.is_none() {
+                    matches.push(config::runtime::Workflow::with_context(name.to_string(), hashmap!{}));
+                },
+                config::workflow::Workflow::Integrate(..) => if !dispatched && event.trigger_source != "scheduled_pipeline" && event.pull_request.is_some() {
+                    matches.push(config::runtime::Workflow::with_context(name.to_string(), hashmap!{}));
+                },
+                config::workflow::Workflow::Cron{schedules, ..} => if event.trigger_source == "scheduled_pipeline" {
+                    // schedule is matched with its name or cron expression passed with deplo_schedule parameter
+                    match schedules.iter().find_map(|(k, v)| {
+                        let matched = [&event.schedule_name, &event.schedule].iter().any(|s| match s {
+                            Some(s) => *s == *k || *s == format!("{}.{}", name, k) || *s == v.resolve(),
+                            None => false
+                        });
+                        if matched { Some(k) } else { None }
+                    }) {
+                        Some(schedule_name) => matches.push(config::runtime::Workflow::with_context(
+                            name.to_string(), hashmap!{ "schedule".to_string() => config::AnyValue::new(schedule_name) }
+                        )),
+                        None => log::debug!("scheduled pipeline does not match with workflow {}", name)
+                    }
+                },
+                // circleci does not have repository events
+                config::workflow::Workflow::Repository{..} => {},
+                config::workflow::Workflow::Dispatch{inputs, ..} => {
+                    if name == config::DEPLO_SYSTEM_WORKFLOW_NAME {
+                        if let Some(ref system_dispatch) = event.system_dispatch {
+                            matches.push(config::runtime::Workflow::with_system_dispatch(
+                                // input format collectness is checked by this deserialize
+                                &serde_json::from_str(system_dispatch)?
+                            ));
+                        }
+                    } else if let Some(ref dispatch) = event.dispatch {
+                        if *dispatch == *name || *dispatch == name.replace("_", "-") {
+                            let client_payload = match event.inputs {
+                                Some(ref v) => serde_json::from_str::<JsonValue>(v)?,
+                                None => json!({})
+                            };
+                            inputs.verify(&client_payload); // panic!s when schema does not matched
+                            matches.push(config::runtime::Workflow::with_context(
+                                name.to_string(), serde_json::from_value(client_payload)?
+                            ));
+                        } else {
+                            log::debug!("dispatch name does not match {} != {}", dispatch, name)
+                        }
+                    }
+                },
+                config::workflow::Workflow::Module(c) => {
+                    if let Some(event_payload) = c.value(|v| {
+                        log::debug!("check module workflow [{}] matches with setting {:?}",
+                            v.uses.to_string(), v.with);
+                        config.modules.workflow(&v.uses).filter_event(payload, &v.with)
+                    })? {
+                        matches.push(config::runtime::Workflow::with_context(
+                            name.to_string(), serde_json::from_str(&event_payload)?
+                        ));
+                    } else {
+                        log::debug!("module workflow '{}' does not match with event payload", name);
+                    }
+                }
+            }
+        }
+        Ok(matches)
     }
-    fn run_job(&self, _job_config: &config::runtime::Workflow) -> Result<String, Box<dyn Error>> {
-        log::warn!("TODO: implement run_job for circleci");
-        Ok("".to_string())
+    fn run_job(&self, job_config: &config::runtime::Workflow) -> Result<String, Box<dyn Error>> {
+        let config = self.config.borrow();
+        let token = self.get_token()?;
+        let mut inputs = hashmap!{
+            "id" => randombytes_as_string!(16),
+            "workflow" => job_config.name.clone(),
+            "context" => serde_json::to_string(&job_config.context)?,
+            "exec" => serde_json::to_string(&job_config.exec)?,
+            "job" => match job_config.job {
+                Some(ref j) => j.name.clone(),
+                None => return escalate!(Box::new(ci::CIError {
+                    cause: format!("workflow {} has no job to run remotely", job_config.name)
+                }))
+            },
+        };
+        match job_config.job {
+            Some(ref j) => match j.command {
+                Some(ref c) => match c.args {
+                    Some(ref a) => { inputs.insert("command", a.join(" ")); },
+                    None => {}
+                },
+                None => {}
+            },
+            None => {}
+        }
+        let commit = match &job_config.exec.revision {
+            Some(v) => v.clone(),
+            None => config.modules.vcs().commit_hash(None)?
+        };
+        let remote_ref = if commit.starts_with("refs") {
+            commit
+        } else {
+            match config.modules.vcs().search_remote_ref(&commit)? {
+                Some(v) => v,
+                None => return escalate!(Box::new(ci::CIError {
+                    cause: format!("remote ref for {} not found", commit),
+                }))
+            }
+        };
+        let mut body = json!({
+            "parameters": { "deplo_system_dispatch": serde_json::to_string(&inputs)? }
+        });
+        match remote_ref.strip_prefix("refs/tags/") {
+            Some(tag) => body["tag"] = json!(tag),
+            None => body["branch"] = json!(remote_ref.trim_start_matches("refs/heads/"))
+        }
+        let response = self.shell.exec(shell::args![
+            "curl", "-sS", "-f", "-X", "POST", "-u", shell::fmtargs!("{}:", &token),
+            self.project_api_url("/pipeline")?,
+            "-H", "Content-Type: application/json",
+            "-H", "Accept: application/json",
+            "-d", serde_json::to_string(&body)?
+        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        log::trace!("response: [{}]", response);
+        let pipeline = serde_json::from_str::<PartialPipeline>(&response)?;
+        log::info!("remote job started as pipeline #{}", pipeline.number);
+        Ok(pipeline.id)
     }
-    fn check_job_finished(&self, _: &str) -> Result<Option<String>, Box<dyn Error>> {
-        log::warn!("TODO: implement check_job_finished for circleci");
-        Ok(None)
+    fn check_job_finished(&self, job_id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let token = self.get_token()?;
+        let response = self.shell.exec(shell::args![
+            "curl", "-sS", "-f", "-u", shell::fmtargs!("{}:", &token),
+            format!("https://circleci.com/api/v2/pipeline/{}/workflow", job_id),
+            "-H", "Accept: application/json"
+        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        let parsed = serde_json::from_str::<PartialWorkflows>(&response)?;
+        // workflows are not created yet
+        if parsed.items.len() == 0 {
+            return Ok(Some("created".to_string()));
+        }
+        match parsed.items.iter().find(|w| RUNNING_WORKFLOW_STATUSES.contains(&w.status.as_str())) {
+            Some(w) => Ok(Some(w.status.clone())),
+            None => Ok(None)
+        }
     }
-    fn job_output(&self, _: &str, _: ci::OutputKind, _: &str) -> Result<Option<String>, Box<dyn Error>> {
-        log::warn!("TODO: implement job_output for circleci");
-        Ok(None)
+    fn job_output(&self, job_name: &str, kind: ci::OutputKind, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let text = if config::Config::is_running_on_ci() {
+            // outputs of the other jobs are available via attached workspace
+            match fs::read_to_string(format!("{}/{}.json", Self::job_dir(job_name), kind.to_str())) {
+                Ok(v) => v,
+                Err(e) => {
+                    log::debug!("job_output: fail to read output of {} {:?}", job_name, e);
+                    return Ok(None);
+                }
+            }
+        } else {
+            match std::env::var(&kind.env_name_for_job(job_name)) {
+                Ok(v) if !v.is_empty() => match base64::decode(&v) {
+                    Ok(decoded) => String::from_utf8(decoded)?,
+                    Err(e) => return escalate!(Box::new(ci::CIError {
+                        cause: format!("output value[{}] is not valid base64 string: {:?}", v, e),
+                    }))
+                },
+                _ => return Ok(None)
+            }
+        };
+        match serde_json::from_str::<HashMap<String, String>>(&text)?.get(key) {
+            Some(v) => Ok(Some(v.to_string())),
+            None => Ok(None),
+        }
     }
-    fn set_job_output(&self, _: &str, _: ci::OutputKind, _: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
-        log::warn!("TODO: implement set_job_output for circleci");
+    fn set_job_output(&self, job_name: &str, kind: ci::OutputKind, outputs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+        let text = serde_json::to_string(&outputs)?;
+        if config::Config::is_running_on_ci() {
+            // persisted to workspace at the end of the job
+            fs::create_dir_all(Self::job_dir(job_name))?;
+            fs::write(format!("{}/{}.json", Self::job_dir(job_name), kind.to_str()), &text)?;
+        } else {
+            std::env::set_var(&kind.env_name_for_job(job_name), base64::encode(text.as_bytes()));
+        }
         Ok(())
     }
-    fn set_job_env(&self, _envs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
-        log::warn!("TODO: implement set_job_env for circleci");
+    fn set_job_env(&self, envs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+        match std::env::var("BASH_ENV") {
+            // BASH_ENV is evaluated at the start of each step
+            Ok(path) if config::Config::is_running_on_ci() => {
+                let mut f = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+                for (k, v) in envs {
+                    writeln!(f, "export {}='{}'", k, v.replace("'", "'\\''"))?;
+                }
+            },
+            _ => for (k, v) in envs {
+                std::env::set_var(k, v);
+            }
+        }
         Ok(())
     }
     fn process_env(&self) -> Result<HashMap<&str, String>, Box<dyn Error>> {
@@ -188,6 +569,8 @@ impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
             "DEPLO_CI_ID" => "CIRCLE_WORKFLOW_ID",
             "DEPLO_CI_PULL_REQUEST_URL" => "CIRCLE_PULL_REQUEST",
             "DEPLO_CI_CURRENT_COMMIT_ID" => "CIRCLE_SHA1",
+            "DEPLO_CI_BRANCH_NAME" => "CIRCLE_BRANCH",
+            "DEPLO_CI_TAG_NAME" => "CIRCLE_TAG",
         } {
             match std::env::var(src) {
                 Ok(v) => {
@@ -207,8 +590,25 @@ impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
         hashmap!{}
     }
     fn list_secret_name(&self) -> Result<Vec<String>, Box<dyn Error>> {
-        log::warn!("TODO: implement list_secret_name for circleci");
-        Ok(vec![])   
+        let token = self.get_token()?;
+        let mut names = vec![];
+        let mut page_token: Option<String> = None;
+        loop {
+            let response = serde_json::from_str::<PartialEnvVars>(&self.shell.exec(shell::args![
+                "curl", "-sS", "-f", "-u", shell::fmtargs!("{}:", &token),
+                match page_token {
+                    Some(ref t) => self.project_api_url(&format!("/envvar?page-token={}", t))?,
+                    None => self.project_api_url("/envvar")?
+                },
+                "-H", "Accept: application/json"
+            ], shell::no_env(), shell::no_cwd(), &shell::capture())?)?;
+            names.extend(response.items.into_iter().map(|v| v.name));
+            match response.next_page_token {
+                Some(t) if !t.is_empty() => page_token = Some(t),
+                _ => break
+            }
+        }
+        Ok(names)
     }
     fn set_var(&self, key: &str, value: &str, _targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
         self.set_secret(key, value, &None)
@@ -261,3 +661,142 @@ impl<'a, S: shell::Shell> ci::CI for CircleCI<S> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::CI;
+
+    fn circleci(jobs: &str) -> CircleCI {
+        let container = config::Config::with(Some(&format!(r#"
+version = 1
+project_name = "test"
+[release_targets]
+nightly = {{ patterns = ["main"] }}
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "circleci"
+key = "key"
+[workflows]
+cron = {{ schedules = {{ nightly = "0 3 * * *", hourly = "30 * * * *" }} }}
+release = {{ manual = true, inputs = {{}} }}
+[jobs]
+{}
+"#, jobs))).unwrap();
+        {
+            let mut config = container.borrow_mut();
+            config.workflows.setup().unwrap();
+            config.jobs.setup().unwrap();
+        }
+        <CircleCI as ci::CI>::new(&container, "default").unwrap()
+    }
+    fn workflows(ci: &CircleCI, event: JsonValue) -> Vec<(String, JsonValue)> {
+        ci.filter_workflows(Some(ci::WorkflowTrigger::EventPayload(event.to_string()))).unwrap().into_iter().map(
+            |w| (w.name, serde_json::to_value(&w.context).unwrap())
+        ).collect()
+    }
+
+    #[test]
+    fn filter_workflows_test() {
+        let ci = circleci("");
+        let wf = |name: &str, context: JsonValue| (name.to_string(), context);
+        // webhook or api without deplo parameters are treated as push or pull request
+        assert_eq!(workflows(&ci, json!({"trigger_source": "webhook"})), vec![wf("deploy", json!({}))]);
+        assert_eq!(
+            workflows(&ci, json!({"trigger_source": "api", "pull_request": "https://github.com/o/r/pull/1"})),
+            vec![wf("integrate", json!({}))]
+        );
+        // scheduled pipeline matches with schedule name, qualified name or cron expression
+        for schedule in [json!({"schedule_name": "hourly"}), json!({"schedule": "cron.hourly"}), json!({"schedule": "30 * * * *"})] {
+            let mut event = json!({"trigger_source": "scheduled_pipeline"});
+            event.as_object_mut().unwrap().extend(schedule.as_object().unwrap().clone());
+            assert_eq!(workflows(&ci, event), vec![wf("cron", json!({"schedule": "hourly"}))]);
+        }
+        assert!(workflows(&ci, json!({"trigger_source": "scheduled_pipeline", "schedule_name": "weekly"})).is_empty());
+        // dispatched pipelines only match with the dispatched workflow
+        assert_eq!(
+            workflows(&ci, json!({"trigger_source": "api", "dispatch": "release", "inputs": r#"{"target":"prod"}"#})),
+            vec![wf("release", json!({"target": "prod"}))]
+        );
+        assert!(workflows(&ci, json!({"trigger_source": "api", "dispatch": "unknown"})).is_empty());
+        let system_dispatch = json!({
+            "id": "1", "workflow": "deploy", "context": r#"{"foo":"bar"}"#,
+            "exec": serde_json::to_string(&config::runtime::ExecOptions::default()).unwrap(),
+            "job": "build", "command": "echo ok"
+        });
+        let matched = ci.filter_workflows(Some(ci::WorkflowTrigger::EventPayload(
+            json!({"trigger_source": "api", "system_dispatch": system_dispatch.to_string()}).to_string()
+        ))).unwrap();
+        assert_eq!(matched.len(), 1);
+        assert_eq!((matched[0].name.as_str(), matched[0].job.as_ref().unwrap().name.as_str()), ("deploy", "build"));
+    }
+    #[test]
+    fn pipeline_event_from_env_test() {
+        let envs = hashmap!{
+            "DEPLO_CIRCLECI_TRIGGER_SOURCE" => "scheduled_pipeline",
+            "DEPLO_CIRCLECI_SCHEDULE_NAME" => "",
+            "DEPLO_CIRCLECI_SCHEDULE" => "0 3 * * *",
+            "DEPLO_CIRCLECI_SYSTEM_DISPATCH" => "",
+        };
+        let event = PipelineEvent::from_vars(|k| envs.get(k).map(|v| v.to_string()));
+        let ci = circleci("");
+        let matched = ci.filter_workflows(Some(ci::WorkflowTrigger::EventPayload(
+            serde_json::to_string(&event).unwrap()
+        ))).unwrap();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].name, "cron");
+        assert_eq!(matched[0].context["schedule"].resolve(), "nightly");
+    }
+    #[test]
+    fn project_api_url_test() {
+        let ci = circleci("");
+        let root = tempfile::tempdir().unwrap();
+        crate::ci::tests::load_vcs_at(&ci.config, root.path());
+        assert_eq!(ci.project_api_url("/envvar").unwrap(), "https://circleci.com/api/v2/project/gh/o/r/envvar");
+        let value = config::Value::new;
+        ci.config.borrow_mut().vcs = config::vcs::Account::Gitlab {
+            email: value("foo@example.com"), account: value("foo"), key: value("bar"), signing: None
+        };
+        let err = ci.project_api_url("/envvar").unwrap_err().to_string();
+        assert!(err.contains("circleci does not support gitlab as vcs"), "{}", err);
+    }
+    #[test]
+    fn run_job_without_job_test() {
+        let ci = circleci("");
+        let workflow = config::runtime::Workflow {
+            name: "cron".to_string(), context: HashMap::new(), job: None, exec: config::runtime::ExecOptions::default()
+        };
+        let err = ci.run_job(&workflow).unwrap_err().to_string();
+        assert!(err.contains("workflow cron has no job to run remotely"), "{}", err);
+    }
+    #[test]
+    fn generate_config_test() {
+        let ci = circleci(r#"
+[jobs.build]
+on = { workflows = ["integrate"] }
+runner = { os = "linux", class = "large" }
+timeout = "10m"
+command = "cargo build"
+[jobs.deploy]
+on = { workflows = ["deploy"] }
+runner = { image = "node:20" }
+workdir = "web"
+depends = ["build"]
+command = "npm run deploy"
+"#);
+        let root = tempfile::tempdir().unwrap();
+        crate::ci::tests::load_vcs_at(&ci.config, root.path());
+        let path = root.path().join(".circleci/config.yml");
+        // existing config prevents secrets from being synced
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "").unwrap();
+        ci.generate_config(false).unwrap();
+        let generated = fs::read_to_string(&path).unwrap().replace(config::DEPLO_VERSION, "{DEPLO_VERSION}");
+        // snapshot of generated config. update it when templates are changed
+        assert_eq!(generated, include_str!("../../res/test/circleci-config.yml"));
+    }
+}
@@ -12,7 +12,7 @@ use chrono::{Utc, Duration};
 use log;
 use maplit::hashmap;
 use serde::{Deserialize, Serialize};
-use serde_json::Value as JsonValue;
+use serde_json::{json, Value as JsonValue};
 
 use crate::config;
 use crate::config::value;
@@ -24,10 +24,9 @@ use crate::util::{
     MultilineFormatString,rm,
     sorted_key_iter,
     merge_hashmap,
-    randombytes_as_string,
-    escape
+    randombytes_as_string
 };
-use crate::vcs::github::AppTokenGenerator;
+use crate::vcs::github::{api, AppTokenGenerator};
 
 lazy_static! {
     pub static ref DEPLO_GHACTION_MODULE_VERSIONS: HashMap<String, String> = hashmap! {
@@ -201,11 +200,6 @@ struct RepositoryPublicKeyResponse {
 struct RepositorySecret {
     pub name: String
 }
-#[derive(Deserialize)]
-struct RepositorySecretsResponse {
-    // total_count: u64,
-    pub secrets: Vec<RepositorySecret>,
-}
 
 pub struct GhAction<S: shell::Shell = shell::Default> {
     pub config: config::Container,
@@ -223,69 +217,52 @@ lazy_static! {
     };
 }
 impl<S: shell::Shell> GhAction<S> {
+    fn api(&self) -> Result<api::Client<'_, S>, Box<dyn Error>> {
+        let (token, auth_type) = self.get_token()?;
+        Ok(api::Client::new(&self.shell, token, auth_type))
+    }
     fn set_secret_base(&self, key: &str, value: &str, path: &str) -> Result<(), Box<dyn Error>> {
         let config = self.config.borrow();
         let user_and_repo = config.modules.vcs().user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
-        let pkey_response = &self.shell.exec(shell::args![
-                "curl", format!(
-                    "https://api.github.com/repos/{}/{}/{}/secrets/public-key",
-                    user_and_repo.0, user_and_repo.1, path
-                ),
-                "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token)
-            ], shell::no_env(), shell::no_cwd(), &shell::capture()
-        )?;
-        let public_key_info = match serde_json::from_str::<RepositoryPublicKeyResponse>(pkey_response) {
+        let client = self.api()?;
+        let public_key_info = match client.get(&format!(
+            "/repos/{}/{}/{}/secrets/public-key", user_and_repo.0, user_and_repo.1, path
+        )).and_then(|r| r.json::<RepositoryPublicKeyResponse>()) {
             Ok(v) => v,
             Err(e) => {
-                log::error!("fail to get public key to encode secret: {}", pkey_response);
-                return escalate!(Box::new(e))
+                log::error!("fail to get public key to encode secret: {}", e);
+                return escalate!(e)
             }
         };
-        let json = format!("{{\"encrypted_value\":\"{}\",\"key_id\":\"{}\"}}", 
-            //get value from env to unescapse
-            seal(value, &public_key_info.key)?,
-            public_key_info.key_id
-        );
-        // TODO_PATH: use Path to generate path of /dev/null
-        let status = self.shell.exec(shell::args!(
-            "curl", "-X", "PUT",
-            format!(
-                "https://api.github.com/repos/{}/{}/{}/secrets/{}",
-                user_and_repo.0, user_and_repo.1, path, key
-            ),
-            "-H", "Content-Type: application/json",
-            "-H", "Accept: application/json",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-d", json, "-w", "%{http_code}", "-o", "/dev/null"
-        ), shell::no_env(), shell::no_cwd(), &shell::capture())?.parse::<u32>()?;
-        if status >= 200 && status < 300 {
+        let response = client.request("PUT", &format!(
+            "/repos/{}/{}/{}/secrets/{}", user_and_repo.0, user_and_repo.1, path, key
+        ), Some(api::Body::Json(&json!({
+            "encrypted_value": seal(value, &public_key_info.key)?,
+            "key_id": public_key_info.key_id
+        }))))?;
+        if response.is_success() {
             Ok(())
         } else {
             return escalate!(Box::new(ci::CIError {
-                cause: format!("fail to set secret to GitHub Actions {} with status code:{}", path, status)
+                cause: format!(
+                    "fail to set secret to GitHub Actions {} with status code:{} {}", path, response.status, response.body
+                )
             }));
         }
     }
     fn delete_secret_base(&self, key: &str, path: &str) -> Result<(), Box<dyn Error>> {
         let config = self.config.borrow();
         let user_and_repo = config.modules.vcs().user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
-        let status = self.shell.exec(shell::args!(
-            "curl", "-X", "DELETE",
-            format!(
-                "https://api.github.com/repos/{}/{}/{}/secrets/{}",
-                user_and_repo.0, user_and_repo.1, path, key
-            ),
-            "-H", "Accept: application/json",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-w", "%{http_code}", "-o", "/dev/null"
-        ), shell::no_env(), shell::no_cwd(), &shell::capture())?.parse::<u32>()?;
-        if (status >= 200 && status < 300) || status == 404 {
+        let response = self.api()?.request("DELETE", &format!(
+            "/repos/{}/{}/{}/secrets/{}", user_and_repo.0, user_and_repo.1, path, key
+        ), None)?;
+        if response.is_success() || response.status == 404 {
             Ok(())
         } else {
             return escalate!(Box::new(ci::CIError {
-                cause: format!("fail to delete secret from GitHub Actions with status code:{}", status)
+                cause: format!(
+                    "fail to delete secret from GitHub Actions with status code:{} {}", response.status, response.body
+                )
             }));
         }
     }
@@ -380,7 +357,7 @@ impl<S: shell::Shell> GhAction<S> {
                 config::workflow::Workflow::Cron { schedules, .. } => {
                     schedule_entries.push(sorted_key_iter(schedules).map(|(_,v)| { format!("- cron: {}", v) }).collect::<Vec<_>>())
                 },
-                config::workflow::Workflow::Deploy|config::workflow::Workflow::Integrate => {},
+                config::workflow::Workflow::Deploy(..)|config::workflow::Workflow::Integrate(..) => {},
                 config::workflow::Workflow::Dispatch{inputs,manual, ..} => {
                     if manual.unwrap_or(false) {
                         if name == "main" {
@@ -582,7 +559,7 @@ impl<S: shell::Shell> GhAction<S> {
         }
     }
     fn generate_job_envs<'a>(&self, job: &'a config::job::Job) -> Vec<String> {
-        let lines = match job.depends {
+        let mut lines = match job.depends {
             Some(ref depends) => {
                 let mut envs = vec![];
                 for d in depends {
@@ -594,8 +571,15 @@ impl<S: shell::Shell> GhAction<S> {
                 }
                 envs
             },
-            None => return vec![]
+            None => vec![]
         };
+        // quoted so that values like 1.70 are not treated as number
+        for (k, v) in sorted_key_iter(&job.matrix_envs()) {
+            lines.push(format!("{}: \"{}\"", k, v.resolve().replace('"', "\\\"")));
+        }
+        if lines.is_empty() {
+            return vec![];
+        }
         format!(include_str!("../../res/ci/ghaction/envs.yml.tmpl"),
             envs = MultilineFormatString{
                 strings: &lines,
@@ -628,6 +612,13 @@ impl<S: shell::Shell> GhAction<S> {
             None => vec![]
         }
     }
+    // deplo enforces timeout and retry by itself, so job timeout of github actions only covers whole of them
+    fn generate_timeout(&self, job: &config::job::Job) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(match job.max_duration()? {
+            Some(d) => vec![format!("timeout-minutes: {}", d.as_secs().div_ceil(60))],
+            None => vec![]
+        })
+    }
     fn generate_container_setting<'a>(&self, runner: &'a config::job::Runner) -> Vec<String> {
         match runner {
             config::job::Runner::Machine{ .. } => vec![],
@@ -740,6 +731,22 @@ impl<S: shell::Shell> GhAction<S> {
     ) -> Result<(), Box<dyn Error>> {
         let update_workflow_yml_path = format!(
             "{}/.github/workflows/deplo-update{}.yml", repository_root, config_post_fix);
+        let runner = config::job::Runner::Machine{
+            os: config::job::RunnerOS::Linux, image: None, class: None, local_fallback: None, no_fallback: None
+        };
+        let checkout_steps = self.generate_checkout_steps("update", account, checkout, &Some(config::job::CheckoutOption {
+            fetch_depth: Some(2), lfs: None, token: None, submodules: None,
+        }));
+        // stale branch gc shares schedule with update check
+        let gc_job = match &self.config.borrow().branch_gc {
+            Some(older_than) => format!(
+                include_str!("../../res/ci/ghaction/gc.yml.tmpl"),
+                fetchcli = MultilineFormatString{ strings: &self.generate_fetchcli_steps(&runner), postfix: None },
+                checkout = MultilineFormatString{ strings: &checkout_steps, postfix: None },
+                older_than = older_than.resolve()
+            ),
+            None => "".to_string()
+        };
         fs::write(&update_workflow_yml_path,
             format!(
                 include_str!("../../res/ci/ghaction/update.yml.tmpl"),
@@ -749,17 +756,14 @@ impl<S: shell::Shell> GhAction<S> {
                 common_envs = MultilineFormatString{ strings: &common_envs(&self.account_name), postfix: None },
                 secrets = MultilineFormatString{ strings: secrets, postfix: None },
                 fetchcli = MultilineFormatString{
-                    strings: &self.generate_fetchcli_steps(&config::job::Runner::Machine{
-                        os: config::job::RunnerOS::Linux, image: None, class: None, local_fallback: None, no_fallback: None }
-                    ),
+                    strings: &self.generate_fetchcli_steps(&runner),
                     postfix: None
                 },
                 checkout = MultilineFormatString{
-                    strings: &self.generate_checkout_steps("update", account, checkout, &Some(config::job::CheckoutOption {
-                        fetch_depth: Some(2), lfs: None, token: None, submodules: None,
-                    })),
+                    strings: &checkout_steps,
                     postfix: None
                 },
+                gc_job = gc_job,
             )
         )?;
         Ok(())
@@ -951,6 +955,10 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
                     },
                     config::job::Runner::Container{..} => "ubuntu-latest".to_string(),
                 },
+                timeout = MultilineFormatString{
+                    strings: &self.generate_timeout(job)?,
+                    postfix: None
+                },
                 native_configs = MultilineFormatString{
                     strings: &self.generate_native_configs(&job),
                     postfix: None
@@ -1127,11 +1135,11 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
                     }
                     match workflow_event.event_name.as_str() {
                         "push" => match v {
-                            config::workflow::Workflow::Deploy => matched_names.push(name),
+                            config::workflow::Workflow::Deploy(..) => matched_names.push(name),
                             _ => {}
                         },
                         "pull_request" => match v {
-                            config::workflow::Workflow::Integrate => matched_names.push(name),
+                            config::workflow::Workflow::Integrate(..) => matched_names.push(name),
                             _ => {}
                         },
                         "schedule" => match v {
@@ -1180,7 +1188,7 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
                 let mut matches = vec![];
                 for name in matched_names {
                     match config.workflows.get(&name).expect(&format!("workflow {} not found", name)) {
-                        config::workflow::Workflow::Deploy|config::workflow::Workflow::Integrate => {
+                        config::workflow::Workflow::Deploy(..)|config::workflow::Workflow::Integrate(..) => {
                             matches.push(config::runtime::Workflow::with_context(
                                 name, hashmap!{}
                             ))
@@ -1269,7 +1277,7 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
     fn run_job(&self, job_config: &config::runtime::Workflow) -> Result<String, Box<dyn Error>> {
         let config = self.config.borrow();
         let user_and_repo = config.modules.vcs().user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
+        let client = self.api()?;
         let payload = ClientPayload::try_new(job_config)?;
         let mut inputs = hashmap!{
             "id" => payload.job_id.clone(),
@@ -1292,55 +1300,40 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
             Some(v) => v.clone(),
             None => config.modules.vcs().commit_hash(None)?
         };
-        let response = self.shell.exec(shell::args![
-            "curl", "-f", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", "Accept: application/vnd.github.v3+json", 
-            format!(
-                "https://api.github.com/repos/{}/{}/actions/workflows/{}/dispatches", 
-                user_and_repo.0, user_and_repo.1, config::DEPLO_SYSTEM_WORKFLOW_ID,
-            ),
-            "-d", format!(r#"{{
-                "ref": "{remote_ref}",
-                "inputs": {inputs}
-            }}"#, 
-                remote_ref = if commit.starts_with("refs") {
-                    commit
-                } else {
-                    match config.modules.vcs().search_remote_ref(&commit)? {
-                        Some(v) => v,
-                        None => return escalate!(Box::new(ci::CIError {
-                            cause: format!("remote ref for {} not found", commit),
-                        }))
-                    }
-                },
-                inputs = serde_json::to_string(&inputs)?
-            )
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        log::trace!("response: [{}]", response);
+        let remote_ref = if commit.starts_with("refs") {
+            commit
+        } else {
+            match config.modules.vcs().search_remote_ref(&commit)? {
+                Some(v) => v,
+                None => return escalate!(Box::new(ci::CIError {
+                    cause: format!("remote ref for {} not found", commit),
+                }))
+            }
+        };
+        let response = client.post(&format!(
+            "/repos/{}/{}/actions/workflows/{}/dispatches", 
+            user_and_repo.0, user_and_repo.1, config::DEPLO_SYSTEM_WORKFLOW_ID,
+        ), &json!({
+            "ref": remote_ref,
+            "inputs": inputs
+        }))?;
+        log::trace!("response: [{}]", response.body);
         log::debug!("wait for remote job to start.");
         let mut count = 0;
         // we have 1 minutes buffer to search for created workflow
         // to be tolerant of clock skew
         let start = Utc::now().checked_sub_signed(Duration::minutes(1)).unwrap();
         loop {
-            let response = self.shell.exec(shell::args![
-                "curl", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token), 
-                "-H", "Accept: application/vnd.github.v3+json", 
-                format!(
-                    "https://api.github.com/repos/{}/{}/actions/runs?event=workflow_dispatch&created={}",
-                    user_and_repo.0, user_and_repo.1,
-                    format!(">{}", start.to_rfc3339())
-                )
-            ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-            log::trace!("current workflows by remote execution: {}", response);
-            let workflows = serde_json::from_str::<PartialWorkflows>(&response)?;
+            let response = client.get(&format!(
+                "/repos/{}/{}/actions/runs?event=workflow_dispatch&created={}",
+                user_and_repo.0, user_and_repo.1,
+                url::form_urlencoded::byte_serialize(format!(">{}", start.to_rfc3339()).as_bytes()).collect::<String>()
+            ))?;
+            log::trace!("current workflows by remote execution: {}", response.body);
+            let workflows = response.json::<PartialWorkflows>()?;
             if workflows.workflow_runs.len() > 0 {
                 for wf in workflows.workflow_runs {
-                    let response = self.shell.exec(shell::args![
-                        "curl", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token), 
-                        "-H", "Accept: application/vnd.github.v3+json", wf.jobs_url
-                    ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-                    let parsed = serde_json::from_str::<PartialJobs>(&response)?;
+                    let parsed = client.get(&wf.jobs_url)?.json::<PartialJobs>()?;
                     if parsed.jobs.len() > 0 && parsed.jobs[0].name.contains(&payload.job_id) {
                         log::info!("remote job started at: {}", wf.url);
                         return Ok(wf.id.to_string());
@@ -1362,16 +1355,9 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
     fn check_job_finished(&self, job_id: &str) -> Result<Option<String>, Box<dyn Error>> {
         let config = self.config.borrow();
         let user_and_repo = config.modules.vcs().user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
-        let response = self.shell.exec(shell::args![
-            "curl", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", "Accept: application/vnd.github.v3+json",
-            format!(
-                "https://api.github.com/repos/{}/{}/actions/runs/{}",
-                user_and_repo.0, user_and_repo.1, job_id
-            )
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        let parsed = serde_json::from_str::<PartialWorkflow>(&response)?;
+        let parsed = self.api()?.get(&format!(
+            "/repos/{}/{}/actions/runs/{}", user_and_repo.0, user_and_repo.1, job_id
+        ))?.json::<PartialWorkflow>()?;
         if parsed.status == "completed" {
             return Ok(None);
         }
@@ -1509,13 +1495,12 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
     fn generate_token(&self, token_config: &ci::TokenConfig) -> Result<String, Box<dyn Error>> {
         match token_config {
             ci::TokenConfig::OIDC{audience: aud} => {
-                let response = self.shell.exec(shell::args![
-                    "curl", "-H",
-                    shell::fmtargs!("Authorization: Bearer {}", value::Value::new_env("ACTIONS_ID_TOKEN_REQUEST_TOKEN")),
-                    "-H", "Accept: application/vnd.github.v3+json",
-                    shell::fmtargs!("{}&audience={}", value::Value::new_env("ACTIONS_ID_TOKEN_REQUEST_URL"), aud)
-                ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-                let parsed = serde_json::from_str::<WebIdentityTokenResponse>(&response)?;
+                let client = api::Client::new(
+                    &self.shell, value::Value::new_env("ACTIONS_ID_TOKEN_REQUEST_TOKEN"), "Bearer"
+                );
+                let parsed = client.get(&format!(
+                    "{}&audience={}", value::Value::new_env("ACTIONS_ID_TOKEN_REQUEST_URL").resolve(), aud
+                ))?.json::<WebIdentityTokenResponse>()?;
                 Ok(parsed.value)
             }
         }
@@ -1540,21 +1525,12 @@ impl<S: shell::Shell> ci::CI for GhAction<S> {
     fn list_secret_name(&self) -> Result<Vec<String>, Box<dyn Error>> {
         let config = self.config.borrow();
         let user_and_repo = config.modules.vcs().user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
-        let response = match serde_json::from_str::<RepositorySecretsResponse>(
-            &self.shell.exec(shell::args![
-            "curl", format!(
-                "https://api.github.com/repos/{}/{}/actions/secrets",
-                user_and_repo.0, user_and_repo.1
-            ),
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", "Accept: application/json"
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?) {
-            Ok(v) => v,
-            Err(e) => return escalate!(Box::new(e))
-        };
+        let secrets = self.api()?.paginate(&format!(
+            "/repos/{}/{}/actions/secrets?per_page=100", user_and_repo.0, user_and_repo.1
+        ), Some("secrets"))?;
         Ok(
-            response.secrets.iter().map(|s| s.name.clone()).collect()
+            secrets.into_iter().map(|s| serde_json::from_value::<RepositorySecret>(s).map(|s| s.name))
+                .collect::<Result<Vec<String>, _>>()?
         )
     }
     fn set_secret(&self, key: &str, value: &str, targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
@@ -1587,68 +1563,41 @@ impl<S: shell::Shell> GhAction<S> {
     fn set_var_base(&self, key: &str, value: &str, target: &str) -> Result<(), Box<dyn Error>> {
         let config = self.config.borrow();
         let user_and_repo = config.modules.vcs().user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
+        let client = self.api()?;
+        let var_path = format!(
+            "/repos/{}/{}/{}/variables/{}", user_and_repo.0, user_and_repo.1, target, key
+        );
         if value.is_empty() {
-            let status = self.shell.exec(shell::args!(
-                "curl", "-X", "DELETE",
-                format!(
-                    "https://api.github.com/repos/{}/{}/{}/variables/{}",
-                    user_and_repo.0, user_and_repo.1, target, key
-                ),
-                "-H", "Accept: application/json",
-                "-H", "X-GitHub-Api-Version: 2022-11-28",
-                "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-                "-w", "%{http_code}", "-o", "/dev/null"
-            ), shell::no_env(), shell::no_cwd(), &shell::capture())?.parse::<u32>()?;
-            if (status >= 200 && status < 300) || status == 404 {
+            let response = client.request("DELETE", &var_path, None)?;
+            if response.is_success() || response.status == 404 {
                 return Ok(());
             }
             return escalate!(Box::new(ci::CIError {
-                cause: format!("fail to delete variable from GitHub Actions {} with status code:{}", target, status)
+                cause: format!(
+                    "fail to delete variable from GitHub Actions {} with status code:{} {}", target, response.status, response.body
+                )
             }));
         }
-        let json = format!("{{\"name\":\"{}\",\"value\":\"{}\"}}", 
-            key, escape(value)
-        );
+        let body = json!({"name": key, "value": value});
         // Check if the variable already exists
-        let check_status = self.shell.exec(shell::args!(
-            "curl", "-X", "GET",
-            format!(
-                "https://api.github.com/repos/{}/{}/{}/variables/{}",
-                user_and_repo.0, user_and_repo.1, target, key
-            ),
-            "-H", "Content-Type: application/json",
-            "-H", "Accept: application/json",
-            "-H", "X-GitHub-Api-Version: 2022-11-28",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-w", "%{http_code}", "-o", "/dev/null"
-        ), shell::no_env(), shell::no_cwd(), &shell::capture())?.parse::<u32>()?;
+        let check_status = client.request("GET", &var_path, None)?.status;
         log::debug!("check status: {}", check_status);
-        let (method, url) = if check_status != 200 { 
+        let (method, path) = if check_status != 200 { 
             ("POST", format!(
-                "https://api.github.com/repos/{}/{}/{}/variables",
+                "/repos/{}/{}/{}/variables",
                 user_and_repo.0, user_and_repo.1, target
             ))
         } else {
-            ("PATCH", format!(
-                "https://api.github.com/repos/{}/{}/{}/variables/{}",
-                user_and_repo.0, user_and_repo.1, target, key
-            ))
+            ("PATCH", var_path)
         };
-        // TODO_PATH: use Path to generate path of /dev/null
-        let status = self.shell.exec(shell::args!(
-            "curl", "-X", method, url,
-            "-H", "Content-Type: application/json",
-            "-H", "Accept: application/json",
-            "-H", "X-GitHub-Api-Version: 2022-11-28",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-d", json, "-w", "%{http_code}", "-o", "/dev/null"
-        ), shell::no_env(), shell::no_cwd(), &shell::capture())?.parse::<u32>()?;
-        if status >= 200 && status < 300 {
+        let response = client.request(method, &path, Some(api::Body::Json(&body)))?;
+        if response.is_success() {
             Ok(())
         } else {
             return escalate!(Box::new(ci::CIError {
-                cause: format!("fail to set variable to GitHub Actions {} with status code:{}", target, status)
+                cause: format!(
+                    "fail to set variable to GitHub Actions {} with status code:{} {}", target, response.status, response.body
+                )
             }));
         }
     }
@@ -0,0 +1,1126 @@
+use std::fs;
+use std::io::Write;
+use std::error::Error;
+use std::result::Result;
+use std::collections::HashMap;
+
+use maplit::hashmap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use crate::config;
+use crate::ci;
+use crate::shell;
+use crate::vcs::gitlab::parse_remote_url;
+use crate::util::{
+    escalate,
+    MultilineFormatString,rm,
+    sorted_key_iter,
+    randombytes_as_string
+};
+
+// dotenv file that is reported as `artifacts:reports:dotenv`.
+// variables written to the file are exported to the jobs which needs the job that writes it.
+// note that gitlab limits size of the file (5KB on gitlab.com) and number of the variables.
+const DEPLO_GITLAB_OUTPUT_FILE: &str = ".deplo-gitlab-output.env";
+// image to run deplo-boot and deplo-halt. it should contains git and curl.
+const DEPLO_GITLAB_DEFAULT_IMAGE: &str = "buildpack-deps:stable-scm";
+// description prefix of pipeline schedules that is managed by deplo
+const DEPLO_GITLAB_SCHEDULE_PREFIX: &str = "deplo:";
+// pipeline status that indicates pipeline is no longer running
+const FINISHED_PIPELINE_STATUSES: [&str; 4] = ["success", "failed", "canceled", "skipped"];
+
+// event payload of gitlab ci. generated from predefined variables and
+// the variables that deplo passes to trigger/schedule the pipeline.
+#[derive(Serialize, Deserialize)]
+struct PipelineEvent {
+    // CI_PIPELINE_SOURCE (push, merge_request_event, schedule, trigger, pipeline, api, web, ...)
+    pub source: String,
+    // DEPLO_GITLAB_SCHEDULE: cron expression of the pipeline schedule
+    pub schedule: Option<String>,
+    // DEPLO_GITLAB_SYSTEM_DISPATCH: json payload for running job remotely
+    pub system_dispatch: Option<String>,
+    // DEPLO_GITLAB_DISPATCH: name of dispatch workflow
+    pub dispatch: Option<String>,
+    // DEPLO_GITLAB_DISPATCH_INPUTS: json inputs of dispatch workflow
+    pub inputs: Option<String>,
+}
+impl PipelineEvent {
+    fn from_env() -> Result<Self, Box<dyn Error>> {
+        Self::from_vars(|k| std::env::var(k).ok())
+    }
+    // env is given as function, to make it possible to test without process env
+    fn from_vars<F: Fn(&str) -> Option<String>>(env: F) -> Result<Self, Box<dyn Error>> {
+        let var = |k: &str| env(k).filter(|v| !v.is_empty());
+        Ok(Self {
+            source: match var("CI_PIPELINE_SOURCE") {
+                Some(v) => v,
+                None => return escalate!(Box::new(ci::CIError {
+                    cause: "CI_PIPELINE_SOURCE should set if on gitlab ci or no argument for workflow (-w) passed".to_string()
+                }))
+            },
+            schedule: var("DEPLO_GITLAB_SCHEDULE"),
+            system_dispatch: var("DEPLO_GITLAB_SYSTEM_DISPATCH"),
+            dispatch: var("DEPLO_GITLAB_DISPATCH"),
+            inputs: var("DEPLO_GITLAB_DISPATCH_INPUTS"),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct PartialPipeline {
+    pub id: u64,
+    pub status: String,
+    pub web_url: String,
+}
+#[derive(Deserialize)]
+struct PartialVariable {
+    pub key: String,
+    pub masked: bool,
+}
+#[derive(Deserialize)]
+struct PartialSchedule {
+    pub id: u64,
+    pub description: String,
+    pub cron: String,
+}
+
+// convert glob pattern of release target into regex that can be used in gitlab rules.
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::new();
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '.'|'+'|'('|')'|'{'|'}'|'|'|'^'|'$'|'\\'|'/' => {
+                regex.push('\\');
+                regex.push(c);
+            },
+            _ => regex.push(c)
+        }
+    }
+    regex
+}
+
+fn urlencode(s: &str) -> String {
+    url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
+}
+
+// status code, response headers and response body
+type Response = (u32, HashMap<String, String>, String);
+
+pub struct GitlabCI<S: shell::Shell = shell::Default> {
+    pub config: config::Container,
+    pub account_name: String,
+    pub shell: S,
+}
+
+impl<S: shell::Shell> GitlabCI<S> {
+    fn scheduled_env_name(job_name: &str) -> String {
+        format!("DEPLO_GITLAB_SCHEDULED_{}", job_name.replace("-", "_").to_uppercase())
+    }
+    fn account<'a>(&self, config: &'a config::Config) -> Result<&'a config::ci::Account, Box<dyn Error>> {
+        match config.ci.get(&self.account_name) {
+            Some(v) => Ok(v),
+            None => escalate!(Box::new(ci::CIError {
+                cause: format!("ci account {} should defined in Deplo.toml", self.account_name)
+            }))
+        }
+    }
+    fn get_token(&self) -> Result<config::Value, Box<dyn Error>> {
+        let config = self.config.borrow();
+        match self.account(&config)? {
+            config::ci::Account::Gitlab { key, .. } => Ok(key.clone()),
+            _ => escalate!(Box::new(ci::CIError {
+                cause: "should have gitlab CI config but other config provided".to_string()
+            }))
+        }
+    }
+    fn get_trigger_token(&self) -> Result<config::Value, Box<dyn Error>> {
+        let config = self.config.borrow();
+        match self.account(&config)? {
+            config::ci::Account::Gitlab { trigger_token, .. } => match trigger_token {
+                Some(v) => Ok(v.clone()),
+                // job token can trigger the pipeline of the same project
+                None => if config::Config::is_running_on_ci() {
+                    Ok(config::Value::new_env("CI_JOB_TOKEN"))
+                } else {
+                    escalate!(Box::new(ci::CIError {
+                        cause: format!("ci.{}.trigger_token is required to run job remotely", self.account_name)
+                    }))
+                }
+            },
+            _ => escalate!(Box::new(ci::CIError {
+                cause: "should have gitlab CI config but other config provided".to_string()
+            }))
+        }
+    }
+    // returns (server_url, project_path)
+    fn project(&self) -> Result<(String, String), Box<dyn Error>> {
+        match (std::env::var("CI_SERVER_URL"), std::env::var("CI_PROJECT_PATH")) {
+            (Ok(s), Ok(p)) => Ok((s, p)),
+            _ => parse_remote_url(&self.shell.output_of(shell::args!(
+                "git", "remote", "get-url", "origin"
+            ), shell::no_env(), shell::no_cwd())?)
+        }
+    }
+    fn project_api_url(&self, path: &str) -> Result<String, Box<dyn Error>> {
+        let (server_url, project_path) = self.project()?;
+        Ok(format!("{}/api/v4/projects/{}{}", server_url, urlencode(&project_path), path))
+    }
+    // returns status code and response body
+    fn request(
+        &self, method: &str, api_url: &str, body: Option<&JsonValue>
+    ) -> Result<(u32, String), Box<dyn Error>> {
+        let (status, _, response) = self.request_with_headers(method, api_url, body)?;
+        Ok((status, response))
+    }
+    // returns status code, response headers (with lower cased name) and response body
+    fn request_with_headers(
+        &self, method: &str, api_url: &str, body: Option<&JsonValue>
+    ) -> Result<Response, Box<dyn Error>> {
+        // headers (includes token) and body are passed via file, not to expose them in process arguments
+        let mut header_file = tempfile::NamedTempFile::new()?;
+        writeln!(header_file, "Authorization: Bearer {}", self.get_token()?.resolve())?;
+        writeln!(header_file, "Content-Type: application/json")?;
+        header_file.flush()?;
+        let response_header_file = tempfile::NamedTempFile::new()?;
+        let response_body_file = tempfile::NamedTempFile::new()?;
+        let mut body_file = tempfile::NamedTempFile::new()?;
+        let mut args = shell::args![
+            "curl", "-sS", "-X", method.to_string(), api_url.to_string(),
+            "-H", format!("@{}", header_file.path().to_string_lossy()),
+            "-D", response_header_file.path().to_string_lossy().to_string(),
+            "-o", response_body_file.path().to_string_lossy().to_string(),
+            "-w", "%{http_code}"
+        ];
+        if let Some(b) = body {
+            body_file.write_all(serde_json::to_string(b)?.as_bytes())?;
+            body_file.flush()?;
+            args.push(shell::arg!("--data-binary"));
+            args.push(shell::arg!(format!("@{}", body_file.path().to_string_lossy())));
+        }
+        let status = self.shell.exec(args, shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        let status = match status.trim().parse::<u32>() {
+            Ok(s) => s,
+            Err(_) => return escalate!(Box::new(ci::CIError {
+                cause: format!("invalid status code from gitlab api: {}", status)
+            }))
+        };
+        let headers = fs::read_to_string(response_header_file.path())?.lines()
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+            .collect();
+        Ok((status, headers, fs::read_to_string(response_body_file.path())?))
+    }
+    // returns all items of list api, by following X-Next-Page header
+    fn paginate<T: serde::de::DeserializeOwned>(&self, api_url: &str) -> Result<Vec<T>, Box<dyn Error>> {
+        let sep = if api_url.contains('?') { "&" } else { "?" };
+        let mut items = vec![];
+        let mut page = "1".to_string();
+        loop {
+            let url = format!("{}{}per_page=100&page={}", api_url, sep, page);
+            let (status, headers, response) = self.request_with_headers("GET", &url, None)?;
+            if !(200..300).contains(&status) {
+                return escalate!(Box::new(ci::CIError {
+                    cause: format!("GET {} fails with status {}: {}", url, status, response)
+                }));
+            }
+            items.append(&mut serde_json::from_str::<Vec<T>>(&response)?);
+            match headers.get("x-next-page") {
+                Some(p) if !p.is_empty() => page = p.clone(),
+                _ => return Ok(items)
+            }
+        }
+    }
+    // same as request but treats non 2xx status as error
+    fn call(
+        &self, method: &str, api_url: &str, body: Option<&JsonValue>
+    ) -> Result<String, Box<dyn Error>> {
+        let (status, response) = self.request(method, api_url, body)?;
+        if !(200..300).contains(&status) {
+            return escalate!(Box::new(ci::CIError {
+                cause: format!("{} {} fails with status {}: {}", method, api_url, status, response)
+            }));
+        }
+        Ok(response)
+    }
+    fn split_status(output: &str) -> Result<(u32, String), Box<dyn Error>> {
+        // output is `$body\n$status`. if body is empty, leading newline is trimmed by shell.
+        let (body, status) = match output.rsplit_once('\n') {
+            Some((b, s)) => (b.to_string(), s),
+            None => ("".to_string(), output)
+        };
+        match status.trim().parse::<u32>() {
+            Ok(s) => Ok((s, body)),
+            Err(_) => escalate!(Box::new(ci::CIError {
+                cause: format!("invalid response from gitlab api: {}", output)
+            }))
+        }
+    }
+    // append variable to dotenv artifact. it will be exported to subsequent jobs.
+    fn append_output(&self, key: &str, val: &str) -> Result<(), Box<dyn Error>> {
+        let path = match std::env::var("CI_PROJECT_DIR") {
+            Ok(v) => format!("{}/{}", v, DEPLO_GITLAB_OUTPUT_FILE),
+            Err(_) => DEPLO_GITLAB_OUTPUT_FILE.to_string()
+        };
+        let mut f = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(f, "{}={}", key, val)?;
+        Ok(())
+    }
+    fn set_variable(&self, key: &str, val: &str, masked: bool) -> Result<(), Box<dyn Error>> {
+        let url = self.project_api_url(&format!("/variables/{}", key))?;
+        if val.is_empty() {
+            let (status, response) = self.request("DELETE", &url, None)?;
+            if (200..300).contains(&status) || status == 404 {
+                return Ok(());
+            }
+            return escalate!(Box::new(ci::CIError {
+                cause: format!("fail to delete variable {} from GitLab CI with status code:{} {}", key, status, response)
+            }));
+        }
+        let body = json!({
+            "key": key, "value": val, "masked": masked, "raw": true
+        });
+        let (check_status, _) = self.request("GET", &url, None)?;
+        if check_status == 200 {
+            self.call("PUT", &url, Some(&body))?;
+        } else {
+            self.call("POST", &self.project_api_url("/variables")?, Some(&body))?;
+        }
+        Ok(())
+    }
+    fn list_variables(&self) -> Result<Vec<PartialVariable>, Box<dyn Error>> {
+        self.paginate(&self.project_api_url("/variables")?)
+    }
+    // pipeline schedules cannot be declared in .gitlab-ci.yml. create them with api instead.
+    // each schedule passes its cron expression with DEPLO_GITLAB_SCHEDULE to filter cron workflows.
+    fn sync_schedules(&self, config: &config::Config) -> Result<(), Box<dyn Error>> {
+        let mut schedules = hashmap!{};
+        for (name, v) in sorted_key_iter(config.workflows.as_map()) {
+            if let config::workflow::Workflow::Cron{ schedules: s, .. } = v {
+                for (schedule_name, cron) in sorted_key_iter(s) {
+                    schedules.insert(
+                        format!("{}{}/{}", DEPLO_GITLAB_SCHEDULE_PREFIX, name, schedule_name),
+                        cron.resolve()
+                    );
+                }
+            }
+        }
+        let existing = self.paginate::<PartialSchedule>(&self.project_api_url("/pipeline_schedules")?)?;
+        for s in &existing {
+            if !s.description.starts_with(DEPLO_GITLAB_SCHEDULE_PREFIX) {
+                continue;
+            }
+            match schedules.get(&s.description) {
+                Some(cron) if *cron == s.cron => {},
+                _ => {
+                    log::debug!("remove pipeline schedule {}", s.description);
+                    self.call("DELETE", &self.project_api_url(&format!("/pipeline_schedules/{}", s.id))?, None)?;
+                }
+            }
+        }
+        if schedules.is_empty() {
+            return Ok(())
+        }
+        let project = serde_json::from_str::<JsonValue>(&self.call("GET", &self.project_api_url("")?, None)?)?;
+        let default_branch = match project["default_branch"].as_str() {
+            Some(v) => v.to_string(),
+            None => return escalate!(Box::new(ci::CIError {
+                cause: format!("project has no default branch: {}", project)
+            }))
+        };
+        for (description, cron) in sorted_key_iter(&schedules) {
+            if existing.iter().any(|s| s.description == *description && s.cron == *cron) {
+                continue;
+            }
+            log::debug!("create pipeline schedule {} {}", description, cron);
+            let created = serde_json::from_str::<JsonValue>(&self.call(
+                "POST", &self.project_api_url("/pipeline_schedules")?, Some(&json!({
+                    "description": description, "ref": default_branch, "cron": cron
+                }))
+            )?)?;
+            self.call("POST", &self.project_api_url(&format!(
+                "/pipeline_schedules/{}/variables", created["id"]
+            ))?, Some(&json!({
+                "key": "DEPLO_GITLAB_SCHEDULE", "value": cron
+            })))?;
+        }
+        Ok(())
+    }
+    fn generate_rules(&self, config: &config::Config) -> Vec<String> {
+        let target_regex = |tag: bool| {
+            let patterns = sorted_key_iter(&config.release_targets)
+                .filter(|v| if tag { v.1.is_tag() } else { v.1.is_branch() })
+                .map(|(_,v)| v.paths().iter().map(|p| glob_to_regex(&p.resolve())).collect::<Vec<_>>())
+                .collect::<Vec<_>>().concat();
+            if !patterns.is_empty() { Some(format!("/^({})$/", patterns.join("|"))) } else { None }
+        };
+        let mut rules = vec![];
+        if let Some(branches) = target_regex(false) {
+            rules.push(format!(
+                "- if: $CI_PIPELINE_SOURCE == \"merge_request_event\" && $CI_MERGE_REQUEST_TARGET_BRANCH_NAME =~ {}",
+                branches
+            ));
+            rules.push(format!("- if: $CI_PIPELINE_SOURCE == \"push\" && $CI_COMMIT_BRANCH =~ {}", branches));
+        }
+        if let Some(tags) = target_regex(true) {
+            rules.push(format!("- if: $CI_PIPELINE_SOURCE == \"push\" && $CI_COMMIT_TAG =~ {}", tags));
+        }
+        rules.push("- if: $CI_PIPELINE_SOURCE =~ /^(schedule|trigger|pipeline|api|web)$/".to_string());
+        rules
+    }
+    // dotenv variables are only defined when dependent job sets outputs.
+    // deplo run expects user outputs of dependent jobs are always defined.
+    fn generate_job_envs(&self, depends: &Option<Vec<config::Value>>) -> Vec<String> {
+        match depends {
+            Some(ds) => ds.iter().map(|d| {
+                let key = ci::OutputKind::User.env_name_for_job(&d.resolve());
+                format!("- export {}=\"${{{}:-}}\"", key, key)
+            }).collect(),
+            None => vec![]
+        }
+    }
+    fn generate_needs(&self, depends: &Option<Vec<config::Value>>) -> Vec<String> {
+        let mut needs = vec!["- job: deplo-boot".to_string()];
+        if let Some(ds) = depends {
+            for d in ds {
+                needs.push(format!("- job: {}", d));
+            }
+        }
+        needs
+    }
+    fn generate_runner(&self, runner: &config::job::Runner) -> Vec<String> {
+        match runner {
+            config::job::Runner::Machine{ os, image, class, .. } => {
+                let mut lines = vec![];
+                if let Some(v) = image {
+                    lines.push(format!("image: {}", v));
+                }
+                lines.push(format!("tags: [\"{}\"]", match class {
+                    Some(v) => v.resolve(),
+                    // runner tags of gitlab.com hosted runners
+                    None => (match os {
+                        config::job::RunnerOS::Linux => "saas-linux-small-amd64",
+                        config::job::RunnerOS::Windows => "saas-windows-medium-amd64",
+                        config::job::RunnerOS::MacOS => "saas-macos-medium-m1",
+                    }).to_string()
+                }));
+                lines
+            },
+            config::job::Runner::Container{ image, .. } => vec![format!("image: {}", image)],
+        }
+    }
+    fn generate_variables(&self, options: &config::job::CheckoutOption) -> Vec<String> {
+        let mut vars = vec![];
+        if let Some(v) = options.fetch_depth {
+            vars.push(format!("GIT_DEPTH: \"{}\"", v));
+        }
+        match options.submodules {
+            Some(config::job::SubmoduleCheckoutType::Checkout(true)) => {
+                vars.push("GIT_SUBMODULE_STRATEGY: normal".to_string());
+            },
+            Some(config::job::SubmoduleCheckoutType::Recursive) => {
+                vars.push("GIT_SUBMODULE_STRATEGY: recursive".to_string());
+            },
+            _ => {}
+        }
+        if !options.lfs.unwrap_or(true) {
+            vars.push("GIT_LFS_SKIP_SMUDGE: \"1\"".to_string());
+        }
+        if vars.is_empty() {
+            return vec![]
+        }
+        let mut lines = vec!["variables:".to_string()];
+        lines.extend(vars.into_iter().map(|v| format!("  {}", v)));
+        lines
+    }
+    fn generate_caches(&self, job: &config::job::Job) -> Vec<String> {
+        match job.caches {
+            Some(ref c) => {
+                let mut lines = vec!["cache:".to_string()];
+                for (_, cache) in sorted_key_iter(c) {
+                    let keys = cache.keys.iter().map(config::Value::resolve_to_string).collect::<Vec<_>>();
+                    lines.extend(format!(
+                        include_str!("../../res/ci/gitlab/cache.yml.tmpl"),
+                        key = keys[0],
+                        fallback_keys = keys[1..].iter().map(|k| format!("\"{}\"", k)).collect::<Vec<_>>().join(","),
+                        paths = MultilineFormatString{
+                            strings: &cache.paths.iter().map(|p| format!("- \"{}\"", p)).collect::<Vec<_>>(),
+                            postfix: None
+                        }
+                    ).split("\n").map(|s| format!("  {}", s)));
+                }
+                lines
+            },
+            None => vec![]
+        }
+    }
+    fn generate_native_configs(&self, job: &config::job::Job) -> Vec<String> {
+        match job.options {
+            Some(ref o) => match o.get("native_configs") {
+                Some(v) => if v.is_table() {
+                    v.as_yaml().split("\n").map(|s| s.to_string()).collect::<Vec<String>>()
+                } else {
+                    log::warn!("native_configs option should be a table. ignore it");
+                    vec![]
+                }
+                None => vec![]
+            },
+            None => vec![]
+        }
+    }
+    // deplo enforces timeout and retry by itself, so job timeout of gitlab only covers whole of them
+    fn generate_timeout(&self, job: &config::job::Job) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(match job.max_duration()? {
+            Some(d) => vec![format!("timeout: {}m", d.as_secs().div_ceil(60))],
+            None => vec![]
+        })
+    }
+    fn generate_fetchcli_steps(&self, runner: &config::job::Runner) -> Vec<String> {
+        let (path, uname, ext) = match runner {
+            config::job::Runner::Machine{ref os, ..} => match os {
+                config::job::RunnerOS::Windows => ("/usr/bin/deplo", "Windows", ".exe"),
+                config::job::RunnerOS::Linux => ("/usr/local/bin/deplo", "Linux-$(uname -m)", ""),
+                v => ("/usr/local/bin/deplo", v.uname(), "")
+            },
+            config::job::Runner::Container{..} => ("/usr/local/bin/deplo", "Linux-$(uname -m)", "")
+        };
+        format!(include_str!("../../res/ci/gitlab/fetchcli.yml.tmpl"),
+            deplo_cli_path = path,
+            download_url = format!(
+                "{}/{}/deplo-{}{}",
+                config::DEPLO_RELEASE_URL_BASE, config::DEPLO_VERSION, uname, ext
+            )
+        ).split("\n").map(|s| s.to_string()).collect::<Vec<String>>()
+    }
+}
+
+impl<S: shell::Shell> ci::CI for GitlabCI<S> {
+    fn new(config: &config::Container, account_name: &str) -> Result<GitlabCI<S>, Box<dyn Error>> {
+        return Ok(GitlabCI::<S> {
+            config: config.clone(),
+            account_name: account_name.to_string(),
+            shell: S::new(config),
+        });
+    }
+    fn account_name(&self) -> &str {
+        return &self.account_name
+    }
+    fn runs_on_service(&self) -> bool {
+        match std::env::var("DEPLO_CI_ACCOUNT_NAME") {
+            Ok(v) if !v.is_empty() => self.account_name == v,
+            _ => false,
+        }
+    }
+    fn restore_cache(&self, _submodule: bool) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn generate_config(&self, reinit: bool) -> Result<(), Box<dyn Error>> {
+        let config = self.config.borrow();
+        let jobs = config.jobs.as_map().iter().filter(
+            |(_,v)| v.is_enabled_for_account(&self.account_name)
+        ).collect::<HashMap<_,_>>();
+        if jobs.len() == 0 {
+            log::info!(
+                "no jobs defined for the account {}. skip gitlab ci config generation",
+                self.account_name);
+            return Ok(());
+        }
+        let repository_root = config.modules.vcs().repository_root()?;
+        let create_main = config.ci.is_main("GitlabCI");
+        // gitlab only reads .gitlab-ci.yml. config for other accounts should be included from it.
+        // TODO_PATH: use Path to generate path of /.gitlab/...
+        let gitlab_yml_path = match self.account_name.as_str() {
+            "default" => format!("{}/.gitlab-ci.yml", repository_root),
+            _ => {
+                fs::create_dir_all(format!("{}/.gitlab", repository_root))?;
+                format!("{}/.gitlab/deplo-{}.gitlab-ci.yml", repository_root, self.account_name)
+            }
+        };
+        let previously_no_file = !rm(&gitlab_yml_path);
+        if previously_no_file || reinit {
+            // sync dotenv secrets with ci system
+            for (k, v) in sorted_key_iter(&config::secret::vars()?) {
+                let targets = config::secret::targets(k.as_str());
+                (self as &dyn ci::CI).set_secret(k, v, &targets)?;
+                log::debug!("set secret value of {}", k);
+            }
+            for (k, v) in sorted_key_iter(&config::var::vars()?) {
+                let targets = config::var::targets(k);
+                (self as &dyn ci::CI).set_var(k, v, &targets)?;
+                log::debug!("set variable value of {}", k);
+            }
+            if create_main {
+                self.sync_schedules(&config)?;
+            }
+        }
+        // generate job entries
+        let mut job_descs = Vec::new();
+        let mut halt_needs = vec!["- job: deplo-boot".to_string()];
+        for (name, job) in sorted_key_iter(&jobs) {
+            // halt should run even if some of jobs are not created
+            halt_needs.push(format!("- job: {}", name));
+            halt_needs.push("  optional: true".to_string());
+            let checkout = config::job::CheckoutOption {
+                fetch_depth: Some(2), lfs: None, token: None, submodules: None
+            }.merge(&match config.checkout.as_ref() {
+                Some(v) => match job.checkout.as_ref() {
+                    Some(vv) => v.merge(vv),
+                    None => v.clone()
+                },
+                None => job.checkout.clone().unwrap_or_else(config::job::CheckoutOption::default)
+            });
+            let lines = format!(
+                include_str!("../../res/ci/gitlab/job.yml.tmpl"),
+                name = name,
+                needs = MultilineFormatString{
+                    strings: &self.generate_needs(&job.depends),
+                    postfix: None
+                },
+                runner = MultilineFormatString{
+                    strings: &self.generate_runner(&job.runner).into_iter().chain(
+                        self.generate_timeout(job)?.into_iter()
+                    ).chain(
+                        self.generate_native_configs(job).into_iter()
+                    ).collect::<Vec<_>>(),
+                    postfix: None
+                },
+                variables = MultilineFormatString{
+                    strings: &self.generate_variables(&checkout),
+                    postfix: None
+                },
+                caches = MultilineFormatString{
+                    strings: &self.generate_caches(job),
+                    postfix: None
+                },
+                scheduled_env = Self::scheduled_env_name(name),
+                job_envs = MultilineFormatString{
+                    strings: &self.generate_job_envs(&job.depends),
+                    postfix: None
+                },
+                output_file = DEPLO_GITLAB_OUTPUT_FILE,
+                fetchcli = MultilineFormatString{
+                    strings: &self.generate_fetchcli_steps(&job.runner),
+                    postfix: None
+                }
+            ).split("\n").map(|s| s.to_string()).collect::<Vec<String>>();
+            job_descs = job_descs.into_iter().chain(lines.into_iter()).collect();
+        }
+        fs::write(&gitlab_yml_path, format!(
+            include_str!("../../res/ci/gitlab/main.yml.tmpl"),
+            rules = MultilineFormatString{
+                strings: &self.generate_rules(&config),
+                postfix: None
+            },
+            common_envs = MultilineFormatString{
+                strings: &vec![format!("DEPLO_CI_ACCOUNT_NAME: \"{}\"", self.account_name)],
+                postfix: None
+            },
+            image = DEPLO_GITLAB_DEFAULT_IMAGE,
+            output_file = DEPLO_GITLAB_OUTPUT_FILE,
+            fetchcli = MultilineFormatString{
+                strings: &self.generate_fetchcli_steps(&config::job::Runner::Container{
+                    image: config::Value::new(DEPLO_GITLAB_DEFAULT_IMAGE), inputs: None
+                }),
+                postfix: None
+            },
+            halt_needs = MultilineFormatString{
+                strings: &halt_needs,
+                postfix: None
+            },
+            jobs = MultilineFormatString{
+                strings: &job_descs,
+                postfix: None
+            }
+        ))?;
+        if self.account_name != "default" {
+            log::info!("include {} from .gitlab-ci.yml to enable jobs for account {}", gitlab_yml_path, self.account_name);
+        }
+        Ok(())
+    }
+    fn pr_url_from_env(&self) -> Result<Option<String>, Box<dyn Error>> {
+        match (std::env::var("CI_MERGE_REQUEST_IID"), std::env::var("CI_MERGE_REQUEST_PROJECT_URL")) {
+            (Ok(iid), Ok(project_url)) if !iid.is_empty() => Ok(Some(
+                format!("{}/-/merge_requests/{}", project_url, iid)
+            )),
+            _ => Ok(None)
+        }
+    }
+    fn schedule_job(&self, job_name: &str) -> Result<(), Box<dyn Error>> {
+        if config::Config::is_running_on_ci() {
+            self.append_output(&Self::scheduled_env_name(job_name), "true")?;
+        } else {
+            log::debug!("schedule_job: {}", job_name);
+        }
+        Ok(())
+    }
+    fn mark_need_cleanup(&self, job_name: &str) -> Result<(), Box<dyn Error>> {
+        if config::Config::is_running_on_ci() {
+            self.append_output("DEPLO_GITLAB_NEED_CLEANUP", "true")?;
+        } else {
+            log::debug!("mark_need_cleanup: {}", job_name);
+        }
+        Ok(())
+    }
+    fn filter_workflows(
+        &self, trigger: Option<ci::WorkflowTrigger>
+    ) -> Result<Vec<config::runtime::Workflow>, Box<dyn Error>> {
+        let resolved_trigger = match trigger {
+            Some(t) => t,
+            // on gitlab ci, event payload is generated from predefined variables
+            None => ci::WorkflowTrigger::EventPayload(serde_json::to_string(&PipelineEvent::from_env()?)?)
+        };
+        let payload = match &resolved_trigger {
+            ci::WorkflowTrigger::EventPayload(payload) => payload
+        };
+        let event = serde_json::from_str::<PipelineEvent>(payload)?;
+        let config = self.config.borrow();
+        let mut matches = vec![];
+        for (name, v) in sorted_key_iter(config.workflows.as_map()) {
+            match v {
+                config::workflow::Workflow::Deploy(..) => if event.source == "push" {
+                    matches.push(config::runtime::Workflow::with_context(name.to_string(), hashmap!{}));
+                },
+                config::workflow::Workflow::Integrate(..) => if event.source == "merge_request_event" {
+                    matches.push(config::runtime::Workflow::with_context(name.to_string(), hashmap!{}));
+                },
+                config::workflow::Workflow::Cron{schedules, ..} => if event.source == "schedule" {
+                    match event.schedule {
+                        Some(ref schedule) => match schedules.iter().find_map(|(k, v)| {
+                            if v.resolve().as_str() == schedule.as_str() { Some(k) } else { None }
+                        }) {
+                            Some(schedule_name) => matches.push(config::runtime::Workflow::with_context(
+                                name.to_string(), hashmap!{ "schedule".to_string() => config::AnyValue::new(schedule_name) }
+                            )),
+                            None => {}
+                        },
+                        None => log::warn!("pipeline schedule does not have DEPLO_GITLAB_SCHEDULE variable")
+                    }
+                },
+                config::workflow::Workflow::Repository{events, ..} => {
+                    let matched_events = events.iter()
+                        .filter(|(_,vs)| vs.iter().find(|t| t.resolve() == event.source).is_some())
+                        .map(|(k,_)| k.as_str()).collect::<Vec<&str>>();
+                    if matched_events.len() > 0 {
+                        matches.push(config::runtime::Workflow::with_context(
+                            name.to_string(), hashmap!{
+                                "events".to_string() => config::AnyValue::new_from_vec(&matched_events)
+                            }
+                        ))
+                    }
+                },
+                config::workflow::Workflow::Dispatch{inputs, ..} => {
+                    if name == config::DEPLO_SYSTEM_WORKFLOW_NAME {
+                        if let Some(ref system_dispatch) = event.system_dispatch {
+                            matches.push(config::runtime::Workflow::with_system_dispatch(
+                                // input format collectness is checked by this deserialize
+                                &serde_json::from_str(system_dispatch)?
+                            ));
+                        }
+                    } else if let Some(ref dispatch) = event.dispatch {
+                        if *dispatch == *name || *dispatch == name.replace("_", "-") {
+                            let client_payload = match event.inputs {
+                                Some(ref v) => serde_json::from_str::<JsonValue>(v)?,
+                                None => json!({})
+                            };
+                            inputs.verify(&client_payload); // panic!s when schema does not matched
+                            matches.push(config::runtime::Workflow::with_context(
+                                name.to_string(), serde_json::from_value(client_payload)?
+                            ));
+                        } else {
+                            log::debug!("dispatch name does not match {} != {}", dispatch, name)
+                        }
+                    }
+                },
+                config::workflow::Workflow::Module(c) => {
+                    if let Some(event_payload) = c.value(|v| {
+                        log::debug!("check module workflow [{}] matches with setting {:?}",
+                            v.uses.to_string(), v.with);
+                        config.modules.workflow(&v.uses).filter_event(payload, &v.with)
+                    })? {
+                        matches.push(config::runtime::Workflow::with_context(
+                            name.to_string(), serde_json::from_str(&event_payload)?
+                        ));
+                    } else {
+                        log::debug!("module workflow '{}' does not match with event payload", name);
+                    }
+                }
+            }
+        }
+        Ok(matches)
+    }
+    fn run_job(&self, job_config: &config::runtime::Workflow) -> Result<String, Box<dyn Error>> {
+        let config = self.config.borrow();
+        let trigger_token = self.get_trigger_token()?;
+        let mut inputs = hashmap!{
+            "id" => randombytes_as_string!(16),
+            "workflow" => job_config.name.clone(),
+            "context" => serde_json::to_string(&job_config.context)?,
+            "exec" => serde_json::to_string(&job_config.exec)?,
+            "job" => match job_config.job {
+                Some(ref j) => j.name.clone(),
+                None => return escalate!(Box::new(ci::CIError {
+                    cause: format!("workflow {} has no job to run remotely", job_config.name)
+                }))
+            },
+        };
+        match job_config.job {
+            Some(ref j) => match j.command {
+                Some(ref c) => match c.args {
+                    Some(ref a) => { inputs.insert("command", a.join(" ")); },
+                    None => {}
+                },
+                None => {}
+            },
+            None => {}
+        }
+        let commit = match &job_config.exec.revision {
+            Some(v) => v.clone(),
+            None => config.modules.vcs().commit_hash(None)?
+        };
+        let remote_ref = if commit.starts_with("refs") {
+            commit
+        } else {
+            match config.modules.vcs().search_remote_ref(&commit)? {
+                Some(v) => v,
+                None => return escalate!(Box::new(ci::CIError {
+                    cause: format!("remote ref for {} not found", commit),
+                }))
+            }
+        };
+        // trigger api only accepts branch or tag name as ref
+        let ref_name = remote_ref.trim_start_matches("refs/heads/").trim_start_matches("refs/tags/");
+        // token is passed via file, not to expose it in process arguments
+        let mut token_file = tempfile::NamedTempFile::new()?;
+        write!(token_file, "{}", trigger_token.resolve())?;
+        token_file.flush()?;
+        let output = self.shell.exec(shell::args![
+            "curl", "-sS", "-X", "POST", self.project_api_url("/trigger/pipeline")?,
+            "--form", format!("token=<{}", token_file.path().to_string_lossy()),
+            "--form-string", format!("ref={}", ref_name),
+            "--form-string", format!("variables[DEPLO_GITLAB_SYSTEM_DISPATCH]={}", serde_json::to_string(&inputs)?),
+            "--form-string", format!("variables[DEPLO_OVERWRITE_EXEC_OPTIONS]={}", inputs["exec"]),
+            "-w", "\n%{http_code}"
+        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        let (status, response) = Self::split_status(&output)?;
+        if !(200..300).contains(&status) {
+            return escalate!(Box::new(ci::CIError {
+                cause: format!("fail to trigger pipeline with status {}: {}", status, response)
+            }));
+        }
+        let pipeline = serde_json::from_str::<PartialPipeline>(&response)?;
+        log::info!("remote job started at: {}", pipeline.web_url);
+        Ok(pipeline.id.to_string())
+    }
+    fn check_job_finished(&self, job_id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let pipeline = serde_json::from_str::<PartialPipeline>(&self.call(
+            "GET", &self.project_api_url(&format!("/pipelines/{}", job_id))?, None
+        )?)?;
+        if FINISHED_PIPELINE_STATUSES.contains(&pipeline.status.as_str()) {
+            return Ok(None);
+        }
+        Ok(Some(pipeline.status))
+    }
+    fn job_output(&self, job_name: &str, kind: ci::OutputKind, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        match std::env::var(&kind.env_name_for_job(job_name)) {
+            Ok(value) => {
+                log::debug!("job_output: got env {}={}", &kind.env_name_for_job(job_name), value);
+                if value.is_empty() {
+                    return Ok(None);
+                }
+                let decoded = match base64::decode(value.to_string()) {
+                    Ok(decoded) => match String::from_utf8(decoded) {
+                        Ok(v) => v,
+                        Err(e) => return escalate!(Box::new(ci::CIError {
+                            cause: format!("output value[{}] is not utf8 string: {:?}", value, e),
+                        }))
+                    },
+                    Err(e) => return escalate!(Box::new(ci::CIError {
+                        cause: format!("output value[{}] is not valid base64 string: {:?}", value, e),
+                    }))
+                };
+                match serde_json::from_str::<HashMap<String, String>>(&decoded)?.get(key) {
+                    Some(v) => Ok(Some(v.to_string())),
+                    None => Ok(None),
+                }
+            },
+            Err(e) => {
+                log::debug!("job_output: fail to got env {} {:?}", &kind.env_name_for_job(job_name), e);
+                Ok(None)
+            }
+        }
+    }
+    fn set_job_output(&self, job_name: &str, kind: ci::OutputKind, outputs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+        let text = serde_json::to_string(&outputs)?;
+        let base64_text = base64::encode(text.as_bytes());
+        if config::Config::is_running_on_ci() {
+            self.append_output(&kind.env_name_for_job(job_name), &base64_text)?;
+        } else {
+            std::env::set_var(&kind.env_name_for_job(job_name), &base64_text);
+        }
+        Ok(())
+    }
+    fn set_job_env(&self, envs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+        // gitlab ci has no way to change environment of subsequent script lines from the process,
+        // so only current process environment is updated.
+        for (k, v) in envs {
+            std::env::set_var(k, v);
+        }
+        Ok(())
+    }
+    fn process_env(&self) -> Result<HashMap<&str, String>, Box<dyn Error>> {
+        let mut envs = hashmap!{
+            "DEPLO_CI_TYPE" => "GitlabCI".to_string(),
+        };
+        if config::Config::is_running_on_ci() {
+            envs.insert(config::DEPLO_RUNNING_ON_CI_ENV_KEY, "true".to_string());
+        }
+        // get from env
+        for (target, src) in hashmap!{
+            "DEPLO_CI_ID" => "CI_PIPELINE_ID",
+            "DEPLO_CI_CURRENT_COMMIT_ID" => "CI_COMMIT_SHA",
+            "DEPLO_CI_TAG_NAME" => "CI_COMMIT_TAG",
+        } {
+            match std::env::var(src) {
+                Ok(v) if !v.is_empty() => {
+                    envs.insert(target, v);
+                },
+                _ => {}
+            };
+        };
+        // on merged results pipeline, CI_COMMIT_SHA is merge commit of the merge request and its target.
+        match std::env::var("CI_MERGE_REQUEST_SOURCE_BRANCH_SHA") {
+            Ok(v) if !v.is_empty() => {
+                envs.insert("DEPLO_CI_CURRENT_COMMIT_ID", v);
+            },
+            _ => {}
+        };
+        match std::env::var("CI_MERGE_REQUEST_SOURCE_BRANCH_NAME") {
+            Ok(v) if !v.is_empty() => {
+                envs.insert("DEPLO_CI_BRANCH_NAME", v);
+            },
+            _ => match std::env::var("CI_COMMIT_BRANCH") {
+                Ok(v) if !v.is_empty() => {
+                    envs.insert("DEPLO_CI_BRANCH_NAME", v);
+                },
+                _ => {}
+            }
+        };
+        if let Some(v) = self.pr_url_from_env()? {
+            envs.insert("DEPLO_CI_PULL_REQUEST_URL", v);
+        }
+        Ok(envs)
+    }
+    fn generate_token(&self, token_config: &ci::TokenConfig) -> Result<String, Box<dyn Error>> {
+        match token_config {
+            // gitlab ci cannot issue id token at runtime. it should be declared with `id_tokens`
+            // in job config and deplo expects the token is stored in DEPLO_GITLAB_ID_TOKEN_$AUDIENCE
+            ci::TokenConfig::OIDC{audience} => {
+                let key = format!(
+                    "DEPLO_GITLAB_ID_TOKEN_{}",
+                    audience.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect::<String>()
+                );
+                match std::env::var(&key) {
+                    Ok(v) => Ok(v),
+                    Err(_) => escalate!(Box::new(ci::CIError {
+                        cause: format!("id token for audience {} is not available. declare {} with id_tokens", audience, key)
+                    }))
+                }
+            }
+        }
+    }
+    fn job_env(&self) -> HashMap<String, config::Value> {
+        hashmap!{}
+    }
+    fn list_secret_name(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.list_variables()?.into_iter().filter(|v| v.masked).map(|v| v.key).collect())
+    }
+    fn set_secret(&self, key: &str, val: &str, _targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
+        self.set_variable(key, val, true)
+    }
+    fn set_var(&self, key: &str, val: &str, _targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
+        self.set_variable(key, val, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::CI;
+
+    fn gitlab(account_name: &str, jobs: &str) -> GitlabCI {
+        let container = config::Config::with(Some(&format!(r#"
+version = 1
+project_name = "test"
+[release_targets]
+nightly = {{ patterns = ["main"] }}
+prod = {{ tag = true, patterns = ["v*"] }}
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "gitlab"
+key = "key"
+[ci.sub]
+type = "gitlab"
+key = "key"
+[workflows]
+cron = {{ schedules = {{ nightly = "0 3 * * *" }} }}
+repository = {{ events = {{ manual = ["web"] }} }}
+release = {{ manual = true, inputs = {{}} }}
+[jobs]
+{}
+"#, jobs))).unwrap();
+        {
+            let mut config = container.borrow_mut();
+            config.workflows.setup().unwrap();
+            config.jobs.setup().unwrap();
+        }
+        <GitlabCI as ci::CI>::new(&container, account_name).unwrap()
+    }
+    fn workflows(ci: &GitlabCI, event: JsonValue) -> Vec<(String, JsonValue)> {
+        ci.filter_workflows(Some(ci::WorkflowTrigger::EventPayload(event.to_string()))).unwrap().into_iter().map(
+            |w| (w.name, serde_json::to_value(&w.context).unwrap())
+        ).collect()
+    }
+
+    #[test]
+    fn filter_workflows_test() {
+        let ci = gitlab("default", "");
+        let wf = |name: &str, context: JsonValue| (name.to_string(), context);
+        assert_eq!(workflows(&ci, json!({"source": "push"})), vec![wf("deploy", json!({}))]);
+        assert_eq!(workflows(&ci, json!({"source": "merge_request_event"})), vec![wf("integrate", json!({}))]);
+        assert_eq!(
+            workflows(&ci, json!({"source": "schedule", "schedule": "0 3 * * *"})),
+            vec![wf("cron", json!({"schedule": "nightly"}))]
+        );
+        assert!(workflows(&ci, json!({"source": "schedule", "schedule": "0 4 * * *"})).is_empty());
+        assert!(workflows(&ci, json!({"source": "schedule"})).is_empty());
+        assert_eq!(workflows(&ci, json!({"source": "web"})), vec![wf("repository", json!({"events": ["manual"]}))]);
+        assert_eq!(
+            workflows(&ci, json!({"source": "trigger", "dispatch": "release", "inputs": r#"{"target":"prod"}"#})),
+            vec![wf("release", json!({"target": "prod"}))]
+        );
+        assert!(workflows(&ci, json!({"source": "trigger", "dispatch": "unknown"})).is_empty());
+    }
+    #[test]
+    fn pipeline_event_from_env_test() {
+        let ci = gitlab("default", "");
+        let event = |envs: HashMap<&str, &str>| PipelineEvent::from_vars(|k| envs.get(k).map(|v| v.to_string()));
+        let err = event(hashmap!{ "DEPLO_GITLAB_SCHEDULE" => "0 3 * * *" }).map(|_| ()).unwrap_err().to_string();
+        assert!(err.contains("CI_PIPELINE_SOURCE should set"), "{}", err);
+        let matched = |envs: HashMap<&str, &str>| ci.filter_workflows(Some(ci::WorkflowTrigger::EventPayload(
+            serde_json::to_string(&event(envs).unwrap()).unwrap()
+        ))).unwrap().into_iter().map(|w| w.name).collect::<Vec<_>>();
+        assert_eq!(matched(hashmap!{ "CI_PIPELINE_SOURCE" => "merge_request_event", "DEPLO_GITLAB_DISPATCH" => "" }), vec!["integrate"]);
+        assert_eq!(matched(hashmap!{ "CI_PIPELINE_SOURCE" => "schedule", "DEPLO_GITLAB_SCHEDULE" => "0 3 * * *" }), vec!["cron"]);
+        assert_eq!(matched(hashmap!{ "CI_PIPELINE_SOURCE" => "trigger", "DEPLO_GITLAB_DISPATCH" => "release" }), vec!["release"]);
+    }
+    #[test]
+    fn sync_schedules_test() {
+        use std::sync::{Arc, Mutex};
+        use crate::shell::mock;
+        let ci = gitlab("default", "");
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorder = requests.clone();
+        let curl = mock::curl(move |method, url, body| {
+            let path = url.trim_start_matches("https://gitlab.com/api/v4/projects/o%2Fr");
+            recorder.lock().unwrap().push((method.to_string(), path.to_string(), body));
+            let (headers, response) = match (method, path) {
+                ("GET", "/pipeline_schedules?per_page=100&page=1") => (
+                    hashmap!{ "X-Next-Page".to_string() => "2".to_string() },
+                    r#"[{"id": 1, "description": "manual", "cron": "0 0 * * *"}]"#
+                ),
+                ("GET", "/pipeline_schedules?per_page=100&page=2") => (
+                    hashmap!{ "X-Next-Page".to_string() => "".to_string() },
+                    r#"[{"id": 2, "description": "deplo:cron/nightly", "cron": "0 4 * * *"},
+                        {"id": 3, "description": "deplo:cron/hourly", "cron": "0 * * * *"}]"#
+                ),
+                ("GET", "") => (mock::no_headers(), r#"{"default_branch": "main"}"#),
+                ("POST", "/pipeline_schedules") => (mock::no_headers(), r#"{"id": 4}"#),
+                _ => (mock::no_headers(), "{}")
+            };
+            (200, headers, response.to_string())
+        });
+        let ci = GitlabCI {
+            config: ci.config.clone(),
+            account_name: ci.account_name.clone(),
+            shell: mock::Mock::with(&ci.config, move |args, envs| if args[0] == "git" {
+                Ok("https://gitlab.com/o/r.git".to_string())
+            } else {
+                curl(args, envs)
+            })
+        };
+        ci.sync_schedules(&ci.config.borrow()).unwrap();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.iter().map(|(m, p, _)| format!("{} {}", m, p)).collect::<Vec<_>>(), vec![
+            "GET /pipeline_schedules?per_page=100&page=1",
+            "GET /pipeline_schedules?per_page=100&page=2",
+            // schedules on later page are also reconciled
+            "DELETE /pipeline_schedules/2",
+            "DELETE /pipeline_schedules/3",
+            "GET ",
+            "POST /pipeline_schedules",
+            "POST /pipeline_schedules/4/variables",
+        ]);
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(requests[5].2.as_ref().unwrap()).unwrap(),
+            json!({"description": "deplo:cron/nightly", "ref": "main", "cron": "0 3 * * *"})
+        );
+        // token is not passed as process argument
+        assert!(ci.shell.commands.borrow().iter().flatten().all(|a| !a.contains("key")));
+    }
+    #[test]
+    fn generate_config_test() {
+        let jobs = r#"
+[jobs.build]
+on = { workflows = ["integrate"] }
+runner = { image = "rust:1.80" }
+timeout = "10m"
+command = "cargo build"
+[jobs.deploy]
+on = { workflows = ["deploy"] }
+runner = { image = "node:20" }
+depends = ["build"]
+command = "npm run deploy"
+[jobs.lint]
+on = { workflows = ["integrate"] }
+account = "sub"
+runner = { image = "node:20" }
+command = "npm run lint"
+"#;
+        let root = tempfile::tempdir().unwrap();
+        let generate = |account_name: &str, path: &str| {
+            let ci = gitlab(account_name, jobs);
+            crate::ci::tests::load_vcs_at(&ci.config, root.path());
+            let path = root.path().join(path);
+            // existing config prevents secrets and schedules from being synced
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+            ci.generate_config(false).unwrap();
+            fs::read_to_string(&path).unwrap().replace(config::DEPLO_VERSION, "{DEPLO_VERSION}")
+        };
+        // snapshot of generated config. update it when templates are changed
+        assert_eq!(generate("default", ".gitlab-ci.yml"), include_str!("../../res/test/gitlab-ci.yml"));
+        // config for other accounts only contains jobs for the account
+        let sub = generate("sub", ".gitlab/deplo-sub.gitlab-ci.yml");
+        assert!(sub.contains("DEPLO_CI_ACCOUNT_NAME: \"sub\""), "{}", sub);
+        assert!(sub.contains("lint:") && !sub.contains("build:"), "{}", sub);
+    }
+
+    #[test]
+    fn glob_to_regex_test() {
+        let re = |p: &str| regex::Regex::new(&format!("^({})$", glob_to_regex(p))).unwrap();
+        assert!(re("main").is_match("main"));
+        assert!(!re("main").is_match("maintenance"));
+        assert!(re("release/*").is_match("release/1.0"));
+        assert!(!re("release/*").is_match("hotfix/1.0"));
+        assert!(re("[0-9]*").is_match("1.0.0"));
+        assert!(!re("[0-9]*").is_match("v1.0.0"));
+        assert_eq!(glob_to_regex("v1.*"), "v1\\..*");
+        assert_eq!(glob_to_regex("release/*"), "release\\/.*");
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::error::Error;
+use std::path::{Path,PathBuf};
+use std::result::Result;
+use std::collections::{HashMap};
+
+use maplit::hashmap;
+
+use crate::config;
+use crate::ci;
+use crate::shell;
+use crate::util::{escalate,path_join};
+
+// ci implementation for running jobs on local machine.
+// job outputs and bookkeeping are stored under $deplo_data_path/local/$DEPLO_CI_ID, so that
+// separated deplo processes of the same run (eg. jobs on fallback container, deplo halt) can share them.
+// each job writes its outputs to jobs/$job_name, like circleci workspace.
+// the directory is removed by cleanup after deplo boot halts the run.
+pub struct Local<S: shell::Shell = shell::Default> {
+    pub config: config::Container,
+    pub account_name: String,
+    pub shell: S,
+}
+
+fn run_dir(config: &config::Config) -> Result<PathBuf, Box<dyn Error>> {
+    let run_id = match std::env::var("DEPLO_CI_ID") {
+        Ok(v) if !v.is_empty() => v,
+        _ => return escalate!(Box::new(ci::CIError {
+            cause: "DEPLO_CI_ID should be set to store data of local run".to_string()
+        }))
+    };
+    Ok(path_join(vec![config.deplo_data_path()?, PathBuf::from("local"), PathBuf::from(run_id)]))
+}
+// remove data of current local run. called after all jobs of the run are halted
+pub fn cleanup(config: &config::Config) -> Result<(), Box<dyn Error>> {
+    let path = run_dir(config)?;
+    match fs::remove_dir_all(&path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => escalate!(Box::new(e))
+    }
+}
+fn read_output(path: &Path, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let text = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => {
+            log::debug!("job_output: fail to read output {} {:?}", path.display(), e);
+            return Ok(None);
+        }
+    };
+    Ok(serde_json::from_str::<HashMap<String, String>>(&text)?.get(key).cloned())
+}
+fn write_output(path: &Path, outputs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+    // same job may run again in the run (eg. deplo i), so outputs are merged
+    let mut merged = match fs::read_to_string(path) {
+        Ok(v) => serde_json::from_str::<HashMap<String, String>>(&v)?,
+        Err(_) => hashmap!{}
+    };
+    for (k, v) in outputs {
+        merged.insert(k.to_string(), v.to_string());
+    }
+    fs::write(path, serde_json::to_string(&merged)?)?;
+    Ok(())
+}
+
+impl<S: shell::Shell> Local<S> {
+    fn job_dir(&self, job_name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let path = path_join(vec![
+            run_dir(&self.config.borrow())?, PathBuf::from("jobs"), PathBuf::from(job_name)
+        ]);
+        fs::create_dir_all(&path)?;
+        Ok(path)
+    }
+    fn output_path(&self, job_name: &str, kind: ci::OutputKind) -> Result<PathBuf, Box<dyn Error>> {
+        Ok(path_join(vec![self.job_dir(job_name)?, PathBuf::from(format!("{}.json", kind.to_str()))]))
+    }
+    fn unsupported<T>(&self, what: &str) -> Result<T, Box<dyn Error>> {
+        escalate!(Box::new(ci::CIError {
+            cause: format!("{} is not supported by local ci account {}", what, self.account_name)
+        }))
+    }
+}
+
+impl<S: shell::Shell> ci::CI for Local<S> {
+    fn new(config: &config::Container, account_name: &str) -> Result<Local<S>, Box<dyn Error>> {
+        return Ok(Local::<S> {
+            config: config.clone(),
+            account_name: account_name.to_string(),
+            shell: S::new(config),
+        });
+    }
+    fn account_name(&self) -> &str {
+        return &self.account_name
+    }
+    // never detected as CI service. config::Config::local_ci returns this account for local execution
+    fn runs_on_service(&self) -> bool {
+        false
+    }
+    fn restore_cache(&self, _submodule: bool) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn generate_config(&self, _reinit: bool) -> Result<(), Box<dyn Error>> {
+        log::debug!("local ci account {} does not need config file", self.account_name);
+        Ok(())
+    }
+    fn pr_url_from_env(&self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(None)
+    }
+    // jobs are run by the deplo process itself, and halt is always invoked after that
+    fn schedule_job(&self, job_name: &str) -> Result<(), Box<dyn Error>> {
+        log::debug!("schedule_job: {}", job_name);
+        Ok(())
+    }
+    fn mark_need_cleanup(&self, job_name: &str) -> Result<(), Box<dyn Error>> {
+        log::debug!("mark_need_cleanup: {}", job_name);
+        Ok(())
+    }
+    fn filter_workflows(
+        &self, trigger: Option<ci::WorkflowTrigger>
+    ) -> Result<Vec<config::runtime::Workflow>, Box<dyn Error>> {
+        match trigger {
+            Some(t) => self.unsupported(&format!("workflow trigger {}", t.to_string())),
+            None => escalate!(Box::new(ci::CIError {
+                cause: "workflow should be specified with -w for local execution".to_string()
+            }))
+        }
+    }
+    fn run_job(&self, _job_config: &config::runtime::Workflow) -> Result<String, Box<dyn Error>> {
+        self.unsupported("running job remotely")
+    }
+    fn check_job_finished(&self, _job_id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.unsupported("waiting remote job")
+    }
+    fn job_output(&self, job_name: &str, kind: ci::OutputKind, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        read_output(&self.output_path(job_name, kind)?, key)
+    }
+    fn set_job_output(&self, job_name: &str, kind: ci::OutputKind, outputs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+        write_output(&self.output_path(job_name, kind)?, outputs)
+    }
+    fn set_job_env(&self, envs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+        for (k, v) in envs {
+            std::env::set_var(k, v);
+        }
+        Ok(())
+    }
+    fn process_env(&self) -> Result<HashMap<&str, String>, Box<dyn Error>> {
+        Ok(hashmap!{
+            "DEPLO_CI_TYPE" => "Local".to_string(),
+        })
+    }
+    fn generate_token(&self, _token_config: &ci::TokenConfig) -> Result<String, Box<dyn Error>> {
+        self.unsupported("token generation")
+    }
+    fn job_env(&self) -> HashMap<String, config::Value> {
+        hashmap!{}
+    }
+    // secrets and vars of local execution are read from .env file
+    fn list_secret_name(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+    fn set_var(&self, _key: &str, _value: &str, _targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
+        self.unsupported("setting var")
+    }
+    fn set_secret(&self, _key: &str, _val: &str, _targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
+        self.unsupported("setting secret")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_output_merges_existing_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("user.json");
+        write_output(&path, hashmap!{"a" => "1", "b" => "2"}).unwrap();
+        write_output(&path, hashmap!{"b" => "3", "c" => "4"}).unwrap();
+        assert_eq!(Some("1".to_string()), read_output(&path, "a").unwrap());
+        assert_eq!(Some("3".to_string()), read_output(&path, "b").unwrap());
+        assert_eq!(Some("4".to_string()), read_output(&path, "c").unwrap());
+        assert_eq!(None, read_output(&path, "d").unwrap());
+    }
+    #[test]
+    fn read_output_returns_none_for_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(None, read_output(&dir.path().join("system.json"), "a").unwrap());
+    }
+}
@@ -0,0 +1,235 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::error::Error;
+
+use maplit::hashmap;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value as JsonValue};
+
+use crate::ci;
+use crate::config;
+use crate::module;
+use crate::shell;
+use crate::util::{escalate};
+
+// ci implementation that delegates each method to ci entrypoint of the module
+pub struct ModuleRunner<S: shell::Shell = shell::Default> {
+    config: config::Container,
+    account_name: String,
+    module_key: String,
+    with: Option<HashMap<String, config::AnyValue>>,
+    shell: S,
+    // runs_on_service and job_env are fetched when the module is loaded, so that failure of them stops deplo early.
+    // process_env is called many times and its result never change
+    runs_on_service: bool,
+    job_env: HashMap<String, String>,
+    process_env: OnceCell<HashMap<String, String>>
+}
+impl<S: shell::Shell> ModuleRunner<S> {
+    fn with(config: &config::Container, account_name: &str, shell: S) -> Result<Self, Box<dyn Error>> {
+        let (module_key, with) = match config.borrow().ci.get(account_name) {
+            Some(config::ci::Account::Module(c)) => c.value(|v| (v.uses.to_string(), v.with.clone())),
+            Some(account) => return escalate!(Box::new(ci::CIError {
+                cause: format!("should have module config for {} but {}", account_name, account)
+            })),
+            None => return escalate!(Box::new(ci::CIError {
+                cause: format!("ci account {} should defined in Deplo.toml", account_name)
+            }))
+        };
+        let mut runner = ModuleRunner::<S> {
+            config: config.clone(),
+            account_name: account_name.to_string(),
+            module_key, with, shell,
+            runs_on_service: false,
+            job_env: hashmap!{},
+            process_env: OnceCell::new()
+        };
+        runner.runs_on_service = runner.call("runs_on_service", json!({}))?;
+        runner.job_env = runner.call("job_env", json!({}))?;
+        Ok(runner)
+    }
+    fn call<R: DeserializeOwned>(&self, method: &str, request: JsonValue) -> Result<R, Box<dyn Error>> {
+        let c = self.config.borrow();
+        let module = c.modules.repos().get(&self.module_key);
+        module.call(
+            module::EntryPointType::CI, &self.shell, method, &request,
+            hashmap!{ "DEPLO_MODULE_CI_ACCOUNT_NAME" => shell::arg!(self.account_name.as_str()) },
+            &self.with
+        )
+    }
+}
+
+impl<S: shell::Shell> ci::CI for ModuleRunner<S> {
+    fn new(config: &config::Container, account_name: &str) -> Result<ModuleRunner<S>, Box<dyn Error>> {
+        Self::with(config, account_name, S::new(config))
+    }
+    fn account_name(&self) -> &str {
+        &self.account_name
+    }
+    fn runs_on_service(&self) -> bool {
+        self.runs_on_service
+    }
+    fn restore_cache(&self, submodule: bool) -> Result<(), Box<dyn Error>> {
+        self.call("restore_cache", json!({"submodule": submodule}))
+    }
+    fn generate_config(&self, reinit: bool) -> Result<(), Box<dyn Error>> {
+        self.call("generate_config", json!({"reinit": reinit}))
+    }
+    fn pr_url_from_env(&self) -> Result<Option<String>, Box<dyn Error>> {
+        self.call("pr_url_from_env", json!({}))
+    }
+    fn schedule_job(&self, job_name: &str) -> Result<(), Box<dyn Error>> {
+        self.call("schedule_job", json!({"job_name": job_name}))
+    }
+    fn mark_need_cleanup(&self, job_name: &str) -> Result<(), Box<dyn Error>> {
+        self.call("mark_need_cleanup", json!({"job_name": job_name}))
+    }
+    fn run_job(&self, job_config: &config::runtime::Workflow) -> Result<String, Box<dyn Error>> {
+        self.call("run_job", json!({"workflow": job_config}))
+    }
+    fn check_job_finished(&self, job_id: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.call("check_job_finished", json!({"job_id": job_id}))
+    }
+    fn set_secret(&self, key: &str, val: &str, targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
+        self.call("set_secret", json!({"key": key, "value": val, "targets": targets}))
+    }
+    fn set_var(&self, key: &str, val: &str, targets: &Option<Vec<String>>) -> Result<(), Box<dyn Error>> {
+        self.call("set_var", json!({"key": key, "value": val, "targets": targets}))
+    }
+    fn list_secret_name(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.call("list_secret_name", json!({}))
+    }
+    fn job_env(&self) -> HashMap<String, config::Value> {
+        self.job_env.iter().map(|(k, v)| (k.to_string(), config::Value::new(v))).collect()
+    }
+    fn process_env(&self) -> Result<HashMap<&str, String>, Box<dyn Error>> {
+        if self.process_env.get().is_none() {
+            let envs = self.call::<HashMap<String, String>>("process_env", json!({}))?;
+            let _ = self.process_env.set(envs);
+        }
+        Ok(self.process_env.get().unwrap().iter().map(|(k, v)| (k.as_str(), v.to_string())).collect())
+    }
+    fn generate_token(&self, token_config: &ci::TokenConfig) -> Result<String, Box<dyn Error>> {
+        match token_config {
+            ci::TokenConfig::OIDC{audience} => self.call("generate_token", json!({
+                "type": "oidc", "audience": audience
+            }))
+        }
+    }
+    fn filter_workflows(
+        &self, trigger: Option<ci::WorkflowTrigger>
+    ) -> Result<Vec<config::runtime::Workflow>, Box<dyn Error>> {
+        self.call("filter_workflows", json!({
+            "trigger": match trigger {
+                Some(ci::WorkflowTrigger::EventPayload(payload)) => json!({
+                    "type": "event_payload", "payload": payload
+                }),
+                None => JsonValue::Null
+            }
+        }))
+    }
+    fn set_job_output(
+        &self, job_name: &str, kind: ci::OutputKind, outputs: HashMap<&str, &str>
+    ) -> Result<(), Box<dyn Error>> {
+        self.call("set_job_output", json!({
+            "job_name": job_name, "kind": kind.to_str(), "outputs": outputs
+        }))
+    }
+    fn job_output(
+        &self, job_name: &str, kind: ci::OutputKind, key: &str
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        self.call("job_output", json!({
+            "job_name": job_name, "kind": kind.to_str(), "key": key
+        }))
+    }
+    fn set_job_env(&self, envs: HashMap<&str, &str>) -> Result<(), Box<dyn Error>> {
+        self.call("set_job_env", json!({"envs": envs}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci::CI;
+    use crate::shell::mock;
+
+    // ci module at root/ci-module with mock shell that answers the method by the responder
+    fn runner<F>(root: &std::path::Path, responder: F) -> Result<ModuleRunner<mock::Mock>, Box<dyn Error>>
+    where F: Fn(&str, JsonValue) -> String + 'static {
+        std::fs::create_dir_all(root.join("ci-module")).unwrap();
+        std::fs::write(root.join("ci-module/Deplo.Module.toml"), r#"
+version = "0.1.0"
+name = "ci-module"
+author = { email = "foo@example.com" }
+entrypoints = { ci = { linux = ["./ci.sh"] } }
+"#).unwrap();
+        let container = config::Config::with(Some(r#"
+version = 1
+project_name = "test"
+[release_targets]
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "module"
+uses = { path = "ci-module" }
+with = { region = "tokyo" }
+[workflows]
+[jobs]
+"#)).unwrap();
+        container.borrow_mut().runtime.repository_root = root.to_path_buf();
+        container.create_module_repos()?;
+        container.load_module(&crate::module::Source::Local{ path: config::Value::new("ci-module") })?;
+        let shell = mock::Mock::with(&container, move |args, envs| {
+            if args[0] == "uname" {
+                return Ok("Linux".to_string());
+            }
+            assert_eq!(&args[..2], &["./ci.sh", "ci"]);
+            assert_eq!(envs["DEPLO_MODULE_CI_ACCOUNT_NAME"], "default");
+            assert_eq!(envs["DEPLO_MODULE_OPTION_STRING"], r#"{"region":"tokyo"}"#);
+            Ok(responder(&args[2], serde_json::from_str(&envs["DEPLO_MODULE_REQUEST"]).unwrap()))
+        });
+        ModuleRunner::with(&container, "default", shell)
+    }
+    #[test]
+    fn module_protocol_test() {
+        let root = tempfile::tempdir().unwrap();
+        let ci = runner(root.path(), |method, request| match method {
+            "runs_on_service" => r#"{"result": true}"#.to_string(),
+            "job_env" => r#"{"result": {"FOO": "bar"}}"#.to_string(),
+            "set_secret" => {
+                assert_eq!(request, json!({"key": "TOKEN", "value": "secret", "targets": ["build"]}));
+                "{}".to_string()
+            },
+            "job_output" => {
+                assert_eq!(request, json!({"job_name": "build", "kind": "user", "key": "out"}));
+                r#"{"result": null}"#.to_string()
+            },
+            "list_secret_name" => r#"{"error": "permission denied"}"#.to_string(),
+            "check_job_finished" => "not a json".to_string(),
+            _ => panic!("unexpected method {} {}", method, request)
+        }).unwrap();
+        assert!(ci.runs_on_service());
+        assert_eq!(ci.job_env()["FOO"].resolve(), "bar");
+        ci.set_secret("TOKEN", "secret", &Some(vec!["build".to_string()])).unwrap();
+        assert_eq!(ci.job_output("build", ci::OutputKind::User, "out").unwrap(), None);
+        let err = ci.list_secret_name().unwrap_err().to_string();
+        assert!(err.contains("fails to process ci.list_secret_name: permission denied"), "{}", err);
+        let err = ci.check_job_finished("1").unwrap_err().to_string();
+        assert!(err.contains("returns invalid response for ci.check_job_finished"), "{}", err);
+        // methods called when the module is loaded are called only once
+        assert_eq!(ci.shell.executed(&["runs_on_service"]).len(), 1);
+        assert_eq!(ci.shell.executed(&["job_env"]).len(), 1);
+    }
+    #[test]
+    fn module_load_fails_test() {
+        let root = tempfile::tempdir().unwrap();
+        let err = runner(root.path(), |method, _| match method {
+            "runs_on_service" => r#"{"result": false}"#.to_string(),
+            _ => r#"{"error": "no credential"}"#.to_string()
+        }).err().unwrap().to_string();
+        assert!(err.contains("fails to process ci.job_env: no credential"), "{}", err);
+    }
+}
@@ -14,6 +14,7 @@ use crate::shell;
 use crate::module::repos::Repository as ModuleRepository;
 use crate::util::{make_absolute, escalate, path_join, randombytes_as_string};
 
+pub mod changelog;
 pub mod ci;
 pub mod job;
 pub mod module;
@@ -33,6 +34,7 @@ pub const DEPLO_SYSTEM_WORKFLOW_NAME: &'static str = "Deplo System";
 pub const DEPLO_SYSTEM_WORKFLOW_ID: &'static str = "deplo-system.yml";
 pub const DEPLO_MODULE_EVENT_TYPE: &'static str = "deplo-send-module-payload";
 pub const DEPLO_VCS_TEMPORARY_WORKSPACE_NAME: &'static str = "deplo-tmp-workspace";
+pub const DEPLO_CHANGESET_FILE_NAME: &'static str = "changeset.json";
 pub const DEPLO_RUNNING_ON_CI_ENV_KEY: &'static str = "CI";
 
 pub type Value = value::Value;
@@ -115,11 +117,15 @@ pub struct Config {
     pub checkout: Option<job::CheckoutOption>,
     #[serde(default = "default_update_check_schedule")]
     pub update_check_schedule: Value,
+    /// if set, deplo vcs gc runs with update_check_schedule and deletes
+    /// deplo-auto-commits-* branches older than the duration (eg. "7d")
+    pub branch_gc: Option<Value>,
     pub release_targets: HashMap<String, release_target::ReleaseTarget>,
     pub vcs: vcs::Account,
     pub ci: ci::Accounts,
     pub workflows: workflow::Workflows,
     pub jobs: job::Jobs,
+    pub changelog: Option<changelog::Changelog>,
 
     // config that get from args
     #[serde(skip)]
@@ -151,7 +157,23 @@ impl Container {
         c.modules.repos = Some(repos);
         Ok(())
     }
-    pub fn setup_vcs_modules(&self, _repos: &mut ModuleRepository) -> Result<(), Box<dyn Error>> {
+    // load module into module repository and returns its key.
+    // module root path should be resolved before taking repository out of config,
+    // because it may call vcs module that is stored in the repository.
+    pub fn load_module(&self, src: &crate::module::Source) -> Result<String, Box<dyn Error>> {
+        let module_root = self.borrow().deplo_module_root_path()?;
+        let mut repos = self.borrow_mut().modules.repos.take().expect("module repository should be created");
+        let r = repos.load(&self.borrow(), &module_root, src);
+        self.borrow_mut().modules.repos = Some(repos);
+        r
+    }
+    pub fn setup_vcs_modules(&self) -> Result<(), Box<dyn Error>> {
+        module::config_for::<crate::vcs::ModuleDescription, _, (), Box<dyn Error>>(|configs| {
+            for c in configs {
+                self.load_module(&c.uses)?;
+            }
+            Ok(())
+        })?;
         let vcs = crate::vcs::factory(self)?;
         {
             let mut c = self.borrow_mut();
@@ -167,7 +189,13 @@ impl Container {
         }
         Ok(())
     }
-    pub fn setup_ci_modules(&self, _repos: &mut ModuleRepository) -> Result<(), Box<dyn Error>> {
+    pub fn setup_ci_modules(&self) -> Result<(), Box<dyn Error>> {
+        module::config_for::<crate::ci::ModuleDescription, _, (), Box<dyn Error>>(|configs| {
+            for c in configs {
+                self.load_module(&c.uses)?;
+            }
+            Ok(())
+        })?;
         let mut ci = hashmap!{};
         for (k, _) in self.borrow().ci.as_map() {
             ci.insert(k.to_string(), crate::ci::factory(self, &k)?);
@@ -186,48 +214,53 @@ impl Container {
         }
         Ok(())
     }
-    pub fn setup_modules(&self, repos: &mut ModuleRepository) -> Result<(), Box<dyn Error>> {
+    pub fn setup_modules(&self) -> Result<(), Box<dyn Error>> {
         let mut steps = hashmap!{};
         let mut workflows = hashmap!{};
-        {
-            // borrow config
-            let config = self.borrow();
-            // load step modules
-            module::config_for::<crate::step::ModuleDescription, _, (), Box<dyn Error>>(|configs| {
-                for c in configs {
-                    steps.insert(c.uses.to_string(), crate::step::factory(
-                        self, repos.load(&config, &c.uses)?
-                    )?);
-                }
-                Ok(())
-            })?;
-            // load workflow modules
-            module::config_for::<crate::workflow::ModuleDescription, _, (), Box<dyn Error>>(|configs| {
-                for c in configs {
-                    workflows.insert(c.uses.to_string(), crate::workflow::factory(
-                        self, repos.load(&config, &c.uses)?
-                    )?);
-                }
-                Ok(())
-            })?;
-        }
+        // load step modules
+        module::config_for::<crate::step::ModuleDescription, _, (), Box<dyn Error>>(|configs| {
+            for c in configs {
+                steps.insert(c.uses.to_string(), crate::step::factory(
+                    self, self.load_module(&c.uses)?
+                )?);
+            }
+            Ok(())
+        })?;
+        // load workflow modules
+        module::config_for::<crate::workflow::ModuleDescription, _, (), Box<dyn Error>>(|configs| {
+            for c in configs {
+                workflows.insert(c.uses.to_string(), crate::workflow::factory(
+                    self, self.load_module(&c.uses)?
+                )?);
+            }
+            Ok(())
+        })?;
         // store modules
         let mut c = self.borrow_mut();
         c.modules.steps = steps;
         c.modules.workflows = workflows;
         Ok(())
     }
-    pub fn prepare_workflow(&self) -> Result<(), Box<dyn Error>> {
+    pub fn prepare_workflow(&self, runtime_workflow_config: &runtime::Workflow) -> Result<(), Box<dyn Error>> {
         // vcs: init diff data on the fly
         let diff = {
             let config = self.borrow();
+            let diff_base = config.workflows.get(&runtime_workflow_config.name).and_then(|w| w.diff_base());
             let vcs = config.modules.vcs();
-            vcs.make_diff()?
+            vcs.make_diff(diff_base.as_ref(), runtime_workflow_config.exec.release_target.as_deref())?
+        };
+        // expose change set to jobs, so that job script does not need to run git diff again
+        let changeset_path = {
+            let config = self.borrow();
+            let path = config.deplo_data_path()?.join(DEPLO_CHANGESET_FILE_NAME);
+            fs::write(&path, serde_json::to_string(&diff)?)?;
+            path.to_string_lossy().to_string()
         };
         {
             let mut config_mut = self.borrow_mut();
             let vcs = config_mut.modules.vcs_mut();
             vcs.init_diff(diff)?;
+            config_mut.set_process_envs(hashmap!{ "DEPLO_CI_CHANGESET_PATH" => Some(changeset_path) });
         };
         Ok(())
     }
@@ -244,9 +277,24 @@ impl Config {
         c.runtime = runtime::Config::default();
         return Ok(Self::wrap(c));
     }
-    fn setup(&mut self) {
-        self.workflows.setup();
-        self.jobs.setup();
+    fn setup(&mut self) -> Result<(), Box<dyn Error>> {
+        self.workflows.setup()?;
+        self.jobs.setup()
+    }
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        for (name, target) in &self.release_targets {
+            target.validate(name)?;
+        }
+        self.jobs.validate(&self.runtime.repository_root)
+    }
+    // local type ci account, which is used instead of the account of each job when not running on CI
+    pub fn local_ci<'a>(&'a self) -> Option<&'a Box<dyn crate::ci::CI + 'a>> {
+        if Self::is_running_on_ci() {
+            return None;
+        }
+        self.ci.as_map().iter()
+            .find(|(_, a)| matches!(a, ci::Account::Local{..}))
+            .map(|(k, _)| self.modules.ci_for(k))
     }
     pub fn ci_by_env<'a>(&'a self) -> &'a Box<dyn crate::ci::CI + 'a> {
         // the cli is invoked from deplo job script: use the job name to find the ci module
@@ -307,7 +355,10 @@ impl Config {
         let mut penvs = hashmap!{
             // on local, CI ID should be inherited from parent, if exists.
             // on CI DEPLO_CI_ID replaced with CI specific environment variable that represents canonical ID
-            "DEPLO_CI_ID".to_string() => Some(random_id),
+            "DEPLO_CI_ID".to_string() => Some(match std::env::var("DEPLO_CI_ID") {
+                Ok(v) if !v.is_empty() && !Self::is_running_on_ci() => v,
+                _ => random_id
+            }),
             // other CI process env should be calculated, because user may call deplo on non-CI environment.
             // on CI, some of these variables may replaced by CI specific way, by return values of ci.process_env
             "DEPLO_CI_TYPE".to_string() => Some(ci_type),
@@ -336,19 +387,17 @@ impl Config {
         let c = {
             let mut config = src.load_as::<Config>()?;
             config.runtime = runtime_config;
-            config.setup();
+            config.setup()?;
+            config.validate()?;
             Self::wrap(config)
         };
-        let mut repos = ModuleRepository::new(&c);
+        // module repository is stored in config first, because ci/vcs modules are used during loading other modules
+        c.create_module_repos()?;
         // 3. load modules phase 1 (necessary for setup other modules)
-        c.setup_vcs_modules(&mut repos)?;
-        c.setup_ci_modules(&mut repos)?;
+        c.setup_vcs_modules()?;
+        c.setup_ci_modules()?;
         // 4. load modules phase 2 (modules not loaded during phase 1)
-        c.setup_modules(&mut repos)?;
-        {
-            let mut config = c.borrow_mut();
-            config.modules.repos = Some(repos);
-        }
+        c.setup_modules()?;
         return Ok(c);
     }
     pub fn is_running_on_ci() -> bool {
@@ -386,7 +435,12 @@ impl Config {
     }
     pub fn deplo_data_path(&self) -> Result<PathBuf, Box<dyn Error>> {
         let base = self.data_dir();
-        let path = make_absolute(base, self.modules.vcs().repository_root()?);
+        let root = match &self.modules.vcs {
+            Some(vcs) => vcs.repository_root()?,
+            // vcs module itself is being loaded
+            None => self.runtime.repository_root.to_string_lossy().to_string()
+        };
+        let path = make_absolute(base, root);
         self.check_and_create_dir(path)
     }
     pub fn deplo_module_root_path(&self) -> Result<PathBuf, Box<dyn Error>> {
@@ -473,4 +527,178 @@ mod tests {
 
         assert_eq!(config.borrow().update_check_schedule.resolve(), "17 3 * * *");
     }
+
+    #[test]
+    fn matrix_jobs_are_expanded() {
+        let container = Config::with(Some(r#"
+version = 1
+project_name = "test"
+[secrets]
+[vars]
+[release_targets]
+nightly = { patterns = ["main"] }
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "ghaction"
+account = "foo"
+key = "bar"
+[workflows]
+integrate = {}
+[jobs.build]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+matrix = { os = ["linux", "macos"], rust = ["stable", "1.70"], exclude = [{ os = "macos", rust = "1.70" }], include = [{ os = "windows", rust = "stable" }] }
+command = "cargo build"
+[jobs.release]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+depends = ["build"]
+command = "echo release"
+[jobs.doc]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+depends = ["build[os=linux]"]
+command = "echo doc"
+"#)).unwrap();
+        let mut config = container.borrow_mut();
+        config.setup().unwrap();
+        let mut names = config.jobs.as_map().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec![
+            "build-linux-1_70", "build-linux-stable", "build-macos-stable", "build-windows-stable", "doc", "release"
+        ]);
+        let job = config.jobs.find_by_selector("build[os=macos,rust=stable]").unwrap();
+        assert_eq!(job.name, "build-macos-stable");
+        assert!(job.runner_os() == job::RunnerOS::MacOS);
+        assert_eq!(job.matrix_envs()["DEPLO_JOB_MATRIX_RUST"].resolve(), "stable");
+        assert!(config.jobs.find_by_selector("build[os=linux]").is_err());
+        let depends = |name: &str| config.jobs.find(name).unwrap().depends.as_ref().unwrap().iter()
+            .map(|v| v.resolve()).collect::<Vec<_>>();
+        assert_eq!(depends("release").len(), 4);
+        assert_eq!(depends("doc"), vec!["build-linux-1_70", "build-linux-stable"]);
+    }
+    #[test]
+    fn max_duration_of_jobs_covers_retries() {
+        let container = Config::with(Some(r#"
+version = 1
+project_name = "test"
+[secrets]
+[vars]
+[release_targets]
+nightly = { patterns = ["main"] }
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "ghaction"
+account = "foo"
+key = "bar"
+[workflows]
+integrate = {}
+[jobs.fetch]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+steps = [
+    { command = "curl -fsSLO https://example.com/a", timeout = "1m", retry = { max = 2, backoff = "exponential", interval = "10s" } },
+    { command = "tar xzf a", timeout = "30s" },
+]
+retry = { max = 1 }
+[jobs.deploy]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+timeout = "10m"
+command = "echo deploy"
+[jobs.test]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+steps = [{ command = "cargo test", timeout = "1m" }, { command = "cargo doc" }]
+"#)).unwrap();
+        let config = container.borrow();
+        config.jobs.validate(&config.runtime.repository_root).unwrap();
+        let max_duration = |name: &str| config.jobs.find(name).unwrap().max_duration().unwrap().map(|d| d.as_secs());
+        // step 1: 60 * 3 + 10 + 20, step 2: 30, job: (240 * 2) + 1
+        assert_eq!(max_duration("fetch"), Some(481));
+        assert_eq!(max_duration("deploy"), Some(600));
+        assert_eq!(max_duration("test"), None);
+    }
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let load = |release_target: &str, workflows: &str| {
+            let container = Config::with(Some(&format!(r#"
+version = 1
+project_name = "test"
+[secrets]
+[vars]
+[release_targets]
+nightly = {}
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "ghaction"
+account = "foo"
+key = "bar"
+[workflows]
+{}
+[jobs.build]
+on = {{ workflows = ["integrate"] }}
+runner = {{ os = "linux" }}
+command = "cargo build"
+"#, release_target, workflows))).unwrap();
+            let mut config = container.borrow_mut();
+            config.setup().and_then(|_| config.validate()).map_err(|e| e.to_string())
+        };
+        assert!(load(r#"{ patterns = ["main"] }"#, "integrate = {}").is_ok());
+        let err = load(r#"{ patterns = ["[main"] }"#, "integrate = {}").unwrap_err();
+        assert!(err.contains("release target 'nightly' has invalid pattern '[main'"), "{}", err);
+        let err = load(r#"{ patterns = ["main"] }"#, "foo = {}").unwrap_err();
+        assert!(err.contains("foo does not have valid workflow settings"), "{}", err);
+    }
+    #[test]
+    fn unknown_changed_packages_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("Cargo.toml"), "[workspace]\nmembers = [\"core\"]\n").unwrap();
+        fs::create_dir_all(root.path().join("core")).unwrap();
+        fs::write(root.path().join("core/Cargo.toml"), "[package]\nname = \"core\"\n").unwrap();
+        let load = |packages: &str| {
+            let container = Config::with(Some(&format!(r#"
+version = 1
+project_name = "test"
+[secrets]
+[vars]
+[release_targets]
+nightly = {{ patterns = ["main"] }}
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "ghaction"
+account = "foo"
+key = "bar"
+[workflows]
+integrate = {{}}
+[jobs.build]
+on = {{ changed_packages = {} }}
+runner = {{ os = "linux" }}
+command = "cargo build"
+"#, packages))).unwrap();
+            let mut config = container.borrow_mut();
+            config.runtime.repository_root = root.path().to_path_buf();
+            config.setup().and_then(|_| config.validate()).map_err(|e| e.to_string())
+        };
+        assert!(load(r#"["core"]"#).is_ok());
+        let err = load(r#"["core", "cli"]"#).unwrap_err();
+        assert!(err.contains("job build has invalid trigger"), "{}", err);
+        assert!(err.contains("package cli does not found in workspace"), "{}", err);
+    }
 }
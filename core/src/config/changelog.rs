@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum GroupBy {
+    /// type of conventional commit, like feat or fix
+    #[serde(rename = "type")]
+    Type,
+    /// label of pull request
+    #[serde(rename = "label")]
+    Label,
+}
+#[derive(Serialize, Deserialize)]
+pub struct Section {
+    pub title: config::Value,
+    /// commit types or pull request labels that are listed in this section.
+    /// "breaking" matches with breaking change of conventional commit.
+    pub keys: Vec<config::Value>,
+}
+/// settings for changelog generation.
+/// templates can contain {name} placeholders.
+#[derive(Serialize, Deserialize)]
+pub struct Changelog {
+    pub group_by: Option<GroupBy>,
+    pub sections: Option<Vec<Section>>,
+    /// section title for changes that does not match any section. default is "Other Changes".
+    /// set empty string not to list such changes.
+    pub others: Option<config::Value>,
+    /// whole changelog. {from}, {to}, {sections}
+    pub template: Option<config::Value>,
+    /// each section. {title}, {entries}
+    pub section_template: Option<config::Value>,
+    /// each change. {summary}, {type}, {scope}, {hash}, {short_hash}, {author}, {pr}, {pr_url}, {link}
+    pub entry_template: Option<config::Value>,
+}
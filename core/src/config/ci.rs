@@ -29,6 +29,13 @@ pub enum Account {
     CircleCI {
         key: config::Value,
     },
+    #[serde(rename = "gitlab")]
+    Gitlab {
+        key: config::Value, // personal or project access token with api scope
+        trigger_token: Option<config::Value>, // pipeline trigger token to run job remotely. CI_JOB_TOKEN is used on gitlab ci if omitted
+    },
+    #[serde(rename = "local")]
+    Local {}, // runs jobs on local machine. used instead of other accounts when not running on CI
     #[serde(rename = "module")]
     Module(config::module::ConfigFor<crate::ci::ModuleDescription>)
 }
@@ -45,6 +52,8 @@ impl Account {
             Self::GhAction{..} => "GhAction",
             Self::GhActionApp{..} => "GhAction",
             Self::CircleCI{..} => "CircleCI",
+            Self::Gitlab{..} => "GitlabCI",
+            Self::Local{..} => "Local",
             Self::Module{..} => "Module",
         }
     }
@@ -53,6 +62,8 @@ impl Account {
             Self::GhAction{..} => "GhAction",
             Self::GhActionApp{..} => "GhActionApp",
             Self::CircleCI{..} => "CircleCI",
+            Self::Gitlab{..} => "GitlabCI",
+            Self::Local{..} => "Local",
             Self::Module{..} => "Module",
         }
     }
@@ -63,6 +74,8 @@ impl fmt::Display for Account {
             Self::GhAction{..} => write!(f, "ghaction"),
             Self::GhActionApp{..} => write!(f, "ghaction_app"),
             Self::CircleCI{..} => write!(f, "circleci"),
+            Self::Gitlab{..} => write!(f, "gitlab"),
+            Self::Local{..} => write!(f, "local"),
             Self::Module(c) => c.value(|v| write!(f, "module {}", v.uses.to_string())),
         }
     }    
@@ -5,25 +5,32 @@ use std::fs;
 use std::path::{Path};
 use std::io::Write;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use maplit::hashmap;
 use petgraph;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de::Error as DeserializeError};
 
 use crate::ci;
 use crate::config;
 use crate::shell;
-use crate::util::{escalate,UnitOrListOf,merge_hashmap};
+use crate::util::{defer,escalate,UnitOrListOf,merge_hashmap,parse_duration,render_template,sorted_key_iter};
 use crate::vcs;
 
+pub mod expr;
 pub mod runner;
+pub mod scheduler;
 
 pub const DEPLO_JOB_OUTPUT_TEMPORARY_FILE: &'static str = "deplo-tmp-job-output.json";
 pub const DEPLO_SYSTEM_OUTPUT_COMMIT_BRANCH_NAME: &'static str = "COMMIT_BRANCH";
+pub const DEPLO_SYSTEM_OUTPUT_DEPLOYED_COMMIT: &'static str = "DEPLOYED_COMMIT";
+// prefix of branches that deplo creates to aggregate auto commits of jobs. deplo vcs gc deletes stale ones
+pub const DEPLO_AUTO_COMMITS_BRANCH_PREFIX: &'static str = "deplo-auto-commits-";
+// max attempts to push auto commits with push commit method. between attempts, deplo waits 2^n seconds
+const DEPLO_PUSH_MAX_ATTEMPTS: u32 = 5;
 
 /// represents single cache setting of CI service.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Cache {
     /// hierarchical names of the cache. each CI system uses these names to determine the cache already exists or not.
     pub keys: Vec<config::Value>,
@@ -35,7 +42,7 @@ pub struct Cache {
 /// for example, local env does not install cli that CI service VM environemnt does.
 /// to absorb the difference, you can specify docker image or dockerfile to run the job
 /// FallbackContainer is represent such a configuration.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum ContainerImageSource {
     /// docker image that is used for local execution.
@@ -43,7 +50,7 @@ pub enum ContainerImageSource {
     /// dockerfile that is used for local execution. deplo build docker iamge with the dockerfile.
     DockerFile{ path: config::Value, repo_name: Option<config::Value>, args: Option<HashMap<String, config::Value>> },
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum Input {
     Path(config::Value),
@@ -52,7 +59,7 @@ pub enum Input {
         excludes: Vec<config::Value>
     }
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct FallbackContainer {
     #[serde(flatten)]
     source: ContainerImageSource,
@@ -121,7 +128,7 @@ impl TryFrom<String> for RunnerOS {
 /// machine runner is use VM environment of CI service. less compatibility of local execution but faster.
 /// container runner is use exactly same container for local execution as CI service. 
 /// maximum compability of local execution but additional time to invoke job on CI service (for pulling image).
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum Runner {
     #[serde(rename = "machine")]
@@ -171,13 +178,29 @@ impl fmt::Display for Command {
 /// 2. Push & squash = false => pushed to built branch as thease are
 /// 3. Pull request & aggregate = true => all commits are pushed to single working branch and pull request is made to built branch
 /// 4. Pull request & aggregate = false => each commits are pushed to seprated working branch and separated pull request is made to built branch
-#[derive(Serialize, Deserialize)]
+/// 5. Pull request & branch = "..." => commit is force pushed to the branch and pull request from it is reused (aggregate is ignored)
+///
+/// for push, if built branch is updated during the run, commits are rebased onto it and push is retried.
+/// if rebase conflicts, pull request is made instead.
+/// push with `to` commits files to another branch for each job, like publishing documents to gh-pages.
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "with")]
 pub enum CommitMethod {
     #[serde(rename = "push")]
     Push {
         /// whether pushed commits are squashed.
         squash: Option<bool>,
+        /// branch to push, like "gh-pages". if omitted, commits are pushed to the built branch.
+        /// otherwise, matched files are committed onto the tree of the branch without checkout.
+        /// files are committed even if they are ignored by .gitignore of the built branch.
+        to: Option<config::Value>,
+        /// if true and the branch of `to` does not exist, it is created as orphan branch that only has the files.
+        /// otherwise the branch is created from the built commit.
+        orphan: Option<bool>,
+        /// directory of matched files that is stripped from their paths, like "target/doc"
+        root: Option<config::Value>,
+        /// directory of the branch of `to` that files are placed under, like "docs/latest"
+        prefix: Option<config::Value>,
     },
     #[serde(rename = "pull_request")]
     PullRequest {
@@ -187,23 +210,143 @@ pub enum CommitMethod {
         assignees: Option<Vec<config::Value>>,
         /// whether separated pull request is made for the commit
         aggregate: Option<bool>,
+        /// fixed head branch of the pull request. if set, the branch is force pushed and existing pull request
+        /// from it is updated (or reopened) instead of creating new one. the pull request is closed
+        /// when the job produces no diff. the branch should not be shared with other jobs.
+        branch: Option<config::Value>,
     }
 }
-#[derive(Serialize, Deserialize)]
+/// log_format, pr_title and pr_body are templates that can contain following variables.
+/// {job}, {workflow}, {release_target}, {commit} (built commit), {files} (newline separated changed files),
+/// {run_url} (url of CI run) and {outputs.KEY} (user job output of KEY).
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Commit {
     pub files: Vec<config::Value>,
     pub on: Option<TriggerTarget>,
+    /// commit message
     pub log_format: Option<config::Value>,
+    /// title of the pull request, for with = "pull_request"
+    pub pr_title: Option<config::Value>,
+    /// body of the pull request, for with = "pull_request"
+    pub pr_body: Option<config::Value>,
     #[serde(flatten)]
     pub method: Option<CommitMethod>,
 }
 impl Commit {
-    fn generate_commit_log(&self, name: &str, _: &Job) -> String {
-        //TODO: pass variable to log_format
-        self.log_format.as_ref().map_or_else(
-            || format!("[deplo] update by job {job_name}", job_name = name),
-            |v| v.resolve().to_string()
-        )
+    pub fn generate_commit_log(&self, values: &HashMap<&str, String>) -> String {
+        render_template(&self.log_format.as_ref().map_or_else(
+            || "[deplo] update by job {job}".to_string(), |v| v.resolve()
+        ), values)
+    }
+    fn templates(&self) -> Vec<String> {
+        [&self.log_format, &self.pr_title, &self.pr_body].iter()
+            .filter_map(|v| v.as_ref().map(|v| v.resolve()))
+            .collect()
+    }
+}
+/// how interval between retries grows
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum RetryBackoff {
+    /// waits interval before every retry
+    #[serde(rename = "constant")]
+    Constant,
+    /// waits interval * n before nth retry
+    #[serde(rename = "linear")]
+    Linear,
+    /// waits interval * 2^(n-1) before nth retry
+    #[serde(rename = "exponential")]
+    Exponential,
+}
+/// retry policy of job or step, like `retry = { max = 3, backoff = "exponential", on_exit_codes = [1] }`.
+/// if job has retry policy, its steps run again from the first one.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Retry {
+    /// max number of retries. command runs at most max + 1 times.
+    pub max: u32,
+    /// default is constant.
+    pub backoff: Option<RetryBackoff>,
+    /// base interval between retries like "10s". default is 1s.
+    pub interval: Option<config::Value>,
+    /// failure is retried only if command exits with one of these codes.
+    /// if omitted, any failure including timeout is retried.
+    pub on_exit_codes: Option<Vec<i32>>,
+}
+impl Retry {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.interval()?;
+        Ok(())
+    }
+    fn interval(&self) -> Result<Duration, Box<dyn Error>> {
+        match &self.interval {
+            Some(v) => parse_duration(&v.resolve()),
+            None => Ok(Duration::from_secs(1))
+        }
+    }
+    // wait before nth retry (n starts from 1)
+    fn delay(&self, n: u32) -> Result<Duration, Box<dyn Error>> {
+        let interval = self.interval()?;
+        Ok(match self.backoff.unwrap_or(RetryBackoff::Constant) {
+            RetryBackoff::Constant => interval,
+            RetryBackoff::Linear => interval.saturating_mul(n),
+            RetryBackoff::Exponential => interval.saturating_mul(2u32.saturating_pow(n - 1)),
+        })
+    }
+    fn retryable(&self, e: &(dyn Error + 'static)) -> bool {
+        let codes = match &self.on_exit_codes {
+            Some(v) => v,
+            None => return true
+        };
+        // shell error may be wrapped by escalate!
+        let mut cur = Some(e);
+        while let Some(err) = cur {
+            if let Some(shell::ShellError::ExitStatus{ status, .. }) = err.downcast_ref::<shell::ShellError>() {
+                return status.code().is_some_and(|c| codes.contains(&c));
+            }
+            cur = err.source();
+        }
+        false
+    }
+    /// upper bound of time to run command that takes `once` at most, including waits between retries
+    pub fn max_duration(retry: Option<&Self>, once: Duration) -> Result<Duration, Box<dyn Error>> {
+        let mut total = once;
+        if let Some(r) = retry {
+            for n in 1..=r.max {
+                total += r.delay(n)? + once;
+            }
+        }
+        Ok(total)
+    }
+    /// runs f and retries it according to the policy. `what` describes the target like "step 'build'".
+    /// retry that cannot start before deadline is not done.
+    pub fn run<T>(
+        retry: Option<&Self>, what: &str, deadline: Option<Instant>,
+        mut f: impl FnMut() -> Result<T, Box<dyn Error>>
+    ) -> Result<T, Box<dyn Error>> {
+        let mut n = 0;
+        loop {
+            match f() {
+                Ok(v) => return Ok(v),
+                Err(e) => match retry {
+                    Some(r) if n < r.max && r.retryable(e.as_ref()) => {
+                        n += 1;
+                        let d = r.delay(n)?;
+                        if deadline.is_some_and(|dl| Instant::now() + d >= dl) {
+                            log::warn!("{} fails: {}. no time left to retry", what, e);
+                            return Err(e);
+                        }
+                        log::warn!("{} fails: {}. retry {}/{} after {}s", what, e, n, r.max, d.as_secs());
+                        sleep(d);
+                    },
+                    _ => return Err(e)
+                }
+            }
+        }
+    }
+}
+fn parse_timeout(timeout: &Option<config::Value>) -> Result<Option<Duration>, Box<dyn Error>> {
+    match timeout {
+        Some(v) => Ok(Some(parse_duration(&v.resolve())?)),
+        None => Ok(None)
     }
 }
 #[derive(Serialize, Deserialize, Clone)]
@@ -224,9 +367,23 @@ pub enum StepCommand {
 pub struct Step {
     pub name: Option<String>,
     pub env: Option<HashMap<String, config::Value>>,
+    /// expression like "workflow == 'deploy'". the step is skipped if it is false. see Job::condition
+    #[serde(rename = "if")]
+    pub condition: Option<config::Value>,
+    /// max duration of the step like "10m". the step fails if it does not finish in time.
+    pub timeout: Option<config::Value>,
+    pub retry: Option<Retry>,
     #[serde(flatten)]
     pub command: StepCommand,
 }
+impl Step {
+    pub fn timeout(&self) -> Result<Option<Duration>, Box<dyn Error>> {
+        parse_timeout(&self.timeout)
+    }
+    pub fn display_name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("#{}", index + 1))
+    }
+}
 impl fmt::Display for Step {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.command {
@@ -266,7 +423,7 @@ impl<'a> fmt::Display for StepsDumper<'a> {
         write!(f, "[{}]", self.steps.iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join(","))
     }
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(untagged)]
 pub enum TriggerCondition {
     Cron {
@@ -278,10 +435,7 @@ pub enum TriggerCondition {
     Module {
         when: HashMap<String, config::AnyValue>,
     },
-    Commit {
-        changed: Vec<config::Value>,
-        diff_matcher: Option<config::Value>,
-    },
+    Commit(#[serde(deserialize_with = "deserialize_commit_condition")] CommitCondition),
     Any {
         any: Option<()>
     }
@@ -289,9 +443,9 @@ pub enum TriggerCondition {
 impl TriggerCondition {
     pub fn check_workflow_type(&self, w: &config::workflow::Workflow) -> bool {
         match self {
-            Self::Commit{..} => if let config::workflow::Workflow::Deploy{..} = w {
+            Self::Commit(..) => if let config::workflow::Workflow::Deploy(..) = w {
                 true
-            } else if let config::workflow::Workflow::Integrate{..} = w {
+            } else if let config::workflow::Workflow::Integrate(..) = w {
                 true
             } else {
                 false
@@ -316,7 +470,102 @@ impl TriggerCondition {
         }
     }
 }
-#[derive(Serialize, Deserialize)]
+/// patterns of changed files for each change status.
+/// the trigger matches if any file that has corresponding status matches with the patterns.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommitCondition {
+    /// matches with any of added, modified, deleted or renamed (both new and old path) files
+    pub changed: Option<Vec<config::Value>>,
+    pub added: Option<Vec<config::Value>>,
+    pub modified: Option<Vec<config::Value>>,
+    pub deleted: Option<Vec<config::Value>>,
+    /// matches with both new and old path of renamed files
+    pub renamed: Option<Vec<config::Value>>,
+    pub diff_matcher: Option<config::Value>,
+    /// matches if any file of the workspace packages or their path dependencies changed
+    pub changed_packages: Option<Vec<config::Value>>,
+    /// resolver of the workspace manifest (cargo, npm). if omitted, first one that finds workspace is used.
+    pub package_resolver: Option<config::Value>,
+}
+impl CommitCondition {
+    fn patterns(&self) -> Vec<(&Vec<config::Value>, Option<vcs::FileStatus>)> {
+        let mut patterns = vec![];
+        if let Some(v) = &self.changed { patterns.push((v, None)); }
+        if let Some(v) = &self.added { patterns.push((v, Some(vcs::FileStatus::Added))); }
+        if let Some(v) = &self.modified { patterns.push((v, Some(vcs::FileStatus::Modified))); }
+        if let Some(v) = &self.deleted { patterns.push((v, Some(vcs::FileStatus::Deleted))); }
+        if let Some(v) = &self.renamed { patterns.push((v, Some(vcs::FileStatus::Renamed))); }
+        patterns
+    }
+    pub fn validate(&self, repository_root: &Path) -> Result<(), Box<dyn Error>> {
+        for (patterns, _) in self.patterns() {
+            Trigger::diff_matcher(&self.diff_matcher, patterns)?;
+        }
+        if let Some(r) = &self.package_resolver {
+            if !vcs::workspace::has_resolver(&r.resolve()) {
+                return escalate!(Box::new(config::ConfigError{
+                    cause: format!("unsupported package_resolver: {}", r)
+                }));
+            }
+        }
+        if let Some(v) = &self.changed_packages {
+            let names = v.iter().map(config::Value::resolve_to_string).collect::<Vec<String>>();
+            let resolver = self.package_resolver.as_ref().map(|v| v.resolve());
+            if let Err(e) = vcs::workspace::resolve(repository_root, resolver.as_deref())
+                .and_then(|ws| ws.closure(&names)) {
+                return escalate!(Box::new(config::ConfigError{
+                    cause: format!("invalid changed_packages {:?}: {}", names, e)
+                }));
+            }
+        }
+        Ok(())
+    }
+    fn packages_changed(&self, vcs: &Box<dyn vcs::VCS>) -> bool {
+        let names = match &self.changed_packages {
+            Some(v) => v.iter().map(config::Value::resolve_to_string).collect::<Vec<String>>(),
+            None => return false
+        };
+        let diff = vcs.diff();
+        if diff.everything {
+            return true;
+        }
+        let resolver = self.package_resolver.as_ref().map(|v| v.resolve());
+        match vcs.repository_root().and_then(|root| {
+            vcs::workspace::resolve(Path::new(&root), resolver.as_deref())?.changed(&names, &diff.paths(None))
+        }) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("cannot detect changes of packages {:?}: {}", names, e);
+                false
+            }
+        }
+    }
+    pub fn matches(&self, vcs: &Box<dyn vcs::VCS>) -> bool {
+        self.packages_changed(vcs) || self.patterns().iter().any(|(patterns, status)| {
+            match Trigger::diff_matcher(&self.diff_matcher, patterns) {
+                Ok(dm) => vcs.changed(&dm, *status),
+                // should be rejected by validate when config is loaded
+                Err(e) => {
+                    log::error!("invalid diff pattern {:?}: {}", patterns, e);
+                    false
+                }
+            }
+        })
+    }
+}
+// TriggerCondition is untagged and Any accepts everything,
+// so commit condition without any patterns should be rejected to fall through to other conditions
+fn deserialize_commit_condition<'de, D>(deserializer: D) -> Result<CommitCondition, D::Error>
+where D: Deserializer<'de> {
+    let c = CommitCondition::deserialize(deserializer)?;
+    if c.patterns().is_empty() && c.changed_packages.is_none() {
+        return Err(D::Error::custom(
+            "commit condition should have at least one of changed, added, modified, deleted, renamed or changed_packages"
+        ));
+    }
+    Ok(c)
+}
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TriggerTarget {
     /// name of the workflow that is triggered.
     workflows: Option<Vec<config::Value>>,
@@ -373,7 +622,7 @@ impl TriggerTarget {
         return true;
     }
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Trigger {
     #[serde(flatten)]
     target: TriggerTarget,
@@ -384,12 +633,14 @@ impl Trigger {
     pub fn diff_matcher(
         matcher: &Option<config::Value>, 
         patterns: &Vec<config::Value>
-    ) -> vcs::DiffMatcher {
+    ) -> Result<vcs::DiffMatcher, Box<dyn Error>> {
         let matcher_type = matcher.as_ref().map_or_else(|| "glob".to_string(), |v| v.resolve());
-        match matcher_type.as_str() {
-            "regex" => vcs::DiffMatcher::Regex(patterns.iter().map(config::Value::resolve_to_string).collect()),
-            "glob" => vcs::DiffMatcher::Glob(patterns.iter().map(config::Value::resolve_to_string).collect()),
-            others => panic!("unsupported diff matcher {}", others)
+        vcs::DiffMatcher::new(&matcher_type, &patterns.iter().map(config::Value::resolve_to_string).collect())
+    }
+    pub fn validate(&self, repository_root: &Path) -> Result<(), Box<dyn Error>> {
+        match &self.condition {
+            TriggerCondition::Commit(c) => c.validate(repository_root),
+            _ => Ok(())
         }
     }
     pub fn matches(
@@ -422,13 +673,12 @@ impl Trigger {
             return true;
         }
         match &self.condition {
-            TriggerCondition::Commit{ changed, diff_matcher } => {
+            TriggerCondition::Commit(c) => {
                 // diff pattern matches
-                let dm = Self::diff_matcher(diff_matcher, changed);
-                if !config.modules.vcs().changed(&dm) {
+                if !c.matches(config.modules.vcs()) {
                     log::trace!(
                         "workflow '{}' job '{}' diff pattern {:?} does not match any of changed files in last commit", 
-                        workflow, job.name, changed
+                        workflow, job.name, c
                     );
                     return false;
                 }
@@ -543,10 +793,79 @@ impl CheckoutOption {
         }
     }
 }
-#[derive(Serialize, Deserialize)]
+/// values that the job runs with. the job is expanded to one job per combination of values,
+/// and each of them is named as $job_name-$value1-$value2... (values are ordered by their keys).
+/// values are exposed as DEPLO_JOB_MATRIX_$KEY, and key `os` also changes os of machine type runner.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Matrix {
+    /// combinations that are removed. combination is removed if it has all values of any entry.
+    #[serde(default)]
+    pub exclude: Vec<HashMap<String, config::Value>>,
+    /// combinations that are added after exclude applied.
+    #[serde(default)]
+    pub include: Vec<HashMap<String, config::Value>>,
+    /// key and its values. all combination of these values are generated.
+    #[serde(flatten)]
+    pub values: HashMap<String, Vec<config::Value>>,
+}
+impl Matrix {
+    fn contains(combination: &[(String, String)], entry: &HashMap<String, config::Value>) -> bool {
+        entry.iter().all(|(k, v)| combination.iter().any(|(ck, cv)| ck == k && *v == *cv))
+    }
+    /// combinations of matrix values. each combination is ordered by keys
+    pub fn combinations(&self) -> Vec<Vec<(String, String)>> {
+        let mut combinations = if self.values.is_empty() {
+            vec![]
+        } else {
+            vec![vec![]]
+        };
+        for (k, vs) in sorted_key_iter(&self.values) {
+            combinations = combinations.into_iter().flat_map(|c| vs.iter().map(move |v| {
+                let mut c: Vec<(String, String)> = c.clone();
+                c.push((k.to_string(), v.resolve()));
+                c
+            })).collect();
+        }
+        combinations.retain(|c| !self.exclude.iter().any(|e| Self::contains(c, e)));
+        for entry in &self.include {
+            let c = sorted_key_iter(entry).map(|(k, v)| (k.to_string(), v.resolve())).collect::<Vec<_>>();
+            if !combinations.contains(&c) {
+                combinations.push(c);
+            }
+        }
+        combinations
+    }
+}
+/// combination of matrix values that the expanded job runs with
+#[derive(Clone, Default)]
+pub struct MatrixEntry {
+    /// name of the job that has matrix setting
+    pub job: String,
+    /// matrix values ordered by their keys
+    pub values: Vec<(String, String)>,
+}
+impl MatrixEntry {
+    pub fn suffix(&self) -> String {
+        self.values.iter().map(|(_, v)| {
+            v.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect::<String>()
+        }).collect::<Vec<_>>().join("-")
+    }
+    pub fn envs(&self) -> HashMap<String, config::Value> {
+        self.values.iter().map(|(k, v)| (
+            format!("DEPLO_JOB_MATRIX_{}", k.replace("-", "_").to_uppercase()), config::Value::new(v)
+        )).collect()
+    }
+    pub fn matches(&self, values: &[(&str, &str)]) -> bool {
+        values.iter().all(|(k, v)| self.values.iter().any(|(mk, mv)| mk == k && mv == v))
+    }
+}
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Job {
     #[serde(skip, default)]
     pub name: String,
+    /// set if the job is expanded from matrix setting
+    #[serde(skip, default)]
+    pub matrix_entry: Option<MatrixEntry>,
     pub account: Option<config::Value>,
     pub on: UnitOrListOf<Trigger>,
     pub runner: Runner,
@@ -557,7 +876,19 @@ pub struct Job {
     pub workdir: Option<config::Value>,
     pub checkout: Option<CheckoutOption>,
     pub caches: Option<HashMap<String, Cache>>,
+    /// dependent jobs. for job that has matrix setting, all expanded jobs are depended,
+    /// or some of them can be specified like "build[os=linux]".
     pub depends: Option<Vec<config::Value>>,
+    pub matrix: Option<Matrix>,
+    /// expression like "workflow == 'deploy' && changed('src/**')". the job is skipped if it is false.
+    /// it is evaluated by deplo when the job starts, and can refer workflow, release_target, env.NAME,
+    /// context.KEY (workflow context) and jobs.NAME.outputs.KEY (output of dependent job).
+    /// functions are changed(glob...), contains, starts_with, ends_with and matches(value, regex).
+    #[serde(rename = "if")]
+    pub condition: Option<config::Value>,
+    /// max duration of steps of the job like "30m". retries of steps are included, but retries of the job are not.
+    pub timeout: Option<config::Value>,
+    pub retry: Option<Retry>,
     pub commit: Option<UnitOrListOf<Commit>>,
     pub options: Option<HashMap<String, config::AnyValue>>,
     // TODO: able to specify steps for tasks
@@ -576,6 +907,44 @@ impl Job {
             Runner::Container{ .. } => RunnerOS::Linux
         }
     }
+    pub fn timeout(&self) -> Result<Option<Duration>, Box<dyn Error>> {
+        parse_timeout(&self.timeout)
+    }
+    /// upper bound of time to run steps of the job including retries. None if some of them has no timeout
+    pub fn max_duration(&self) -> Result<Option<Duration>, Box<dyn Error>> {
+        let once = match self.timeout()? {
+            Some(t) => t,
+            None => match &self.steps {
+                Some(steps) => {
+                    let mut total = Duration::ZERO;
+                    for step in steps {
+                        match step.timeout()? {
+                            Some(t) => total += Retry::max_duration(step.retry.as_ref(), t)?,
+                            None => return Ok(None)
+                        }
+                    }
+                    total
+                },
+                None => return Ok(None)
+            }
+        };
+        Ok(Some(Retry::max_duration(self.retry.as_ref(), once)?))
+    }
+    /// evaluates `if` of the job. true if it is omitted
+    pub fn condition_matches(
+        &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow,
+        envs: &HashMap<String, config::Value>
+    ) -> Result<bool, Box<dyn Error>> {
+        match &self.condition {
+            Some(c) => expr::Expr::parse(&c.resolve())?.eval(&expr::JobContext{
+                config, runtime_workflow_config, envs
+            }),
+            None => Ok(true)
+        }
+    }
+    pub fn matrix_envs(&self) -> HashMap<String, config::Value> {
+        self.matrix_entry.as_ref().map_or_else(HashMap::new, |m| m.envs())
+    }
     pub fn runs_on_machine(&self) -> bool {
         match &self.runner {
             Runner::Machine{ .. } => true,
@@ -603,6 +972,15 @@ impl Job {
         }
     }
     pub fn ci<'a>(&self, config: &'a config::Config) -> &Box<dyn ci::CI + 'a> {
+        // on local, local type account takes over any job,
+        // so that outputs of the job are shared between jobs and deplo halt.
+        match config.local_ci() {
+            Some(ci) => ci,
+            None => self.remote_ci(config)
+        }
+    }
+    // ci of the account that the job runs on. used for running job on CI service from local
+    pub fn remote_ci<'a>(&self, config: &'a config::Config) -> &Box<dyn ci::CI + 'a> {
         let account = self.account_name();
         return config.modules.ci_for(&account);
     }
@@ -654,6 +1032,7 @@ impl Job {
             Some(ref v) => hashmap!{"DEPLO_CI_RELEASE_TARGET".to_string() => config::Value::new(v)},
             None => hashmap!{}
         });
+        let common_envs = merge_hashmap(&common_envs, &self.matrix_envs());
         let mut depend_envs = hashmap!{};
         if config::Config::is_running_on_ci() {
             match &self.depends {
@@ -735,6 +1114,34 @@ struct AggregatedPullRequestOptions {
     labels: Vec<config::Value>,
     assignees: Vec<config::Value>,
 }
+// commits of jobs that are pushed together by Jobs::push_job_result_branches
+struct JobResultCommits {
+    name: String,
+    // jobs which make the commits, and branches that contain each of them
+    jobs: Vec<String>,
+    branches: Vec<String>,
+    method: CommitMethod,
+}
+// how auto commits of JobResultCommits are finally delivered. shown in report of deplo halt
+enum CommitDelivery {
+    Pushed { name: String, branch: String, attempts: u32 },
+    PullRequest { name: String, head: String, base: String },
+    // pushing is given up and pull request is opened instead
+    FallbackPullRequest { name: String, head: String, base: String, reason: String },
+}
+impl fmt::Display for CommitDelivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pushed{name, branch, attempts} => write!(
+                f, "{}: pushed to {} ({} attempt{})", name, branch, attempts, if *attempts > 1 { "s" } else { "" }
+            ),
+            Self::PullRequest{name, head, base} => write!(f, "{}: pull request {} => {}", name, head, base),
+            Self::FallbackPullRequest{name, head, base, reason} => write!(
+                f, "{}: pull request {} => {} opened instead of push ({})", name, head, base, reason
+            ),
+        }
+    }
+}
 pub struct MatchOptions {
     check_condition: bool
 }
@@ -782,9 +1189,9 @@ impl<'a> DependencyGraph<'a> {
         }
         Self(dag, nodes, tail)
     }
-    pub fn traverse<F>(
-        &self, start_job: Option<&str>, proc: F
-    ) -> Result<(), Box<dyn Error>> where F: Fn(&'a str, &'a Job) -> Result<(), Box<dyn Error>> {
+    // jobs that start_job depends on (directly or indirectly) and start_job itself, ordered by dependency.
+    // if start_job is None, all jobs are returned.
+    pub fn sorted(&self, start_job: Option<&str>) -> Vec<&'a Job> {
         // traversing dependency graph by dfs(post order), to run jobs ordered by dependency
         let mut visitor = petgraph::visit::DfsPostOrder::new(
             &self.0,
@@ -793,12 +1200,21 @@ impl<'a> DependencyGraph<'a> {
                 |name| *self.1.get(&name.to_string()).expect(&format!("job '{}' not found", name))
             )
         );
+        let mut jobs = vec![];
         while let Some(n) = visitor.next(&self.0) {
             match self.0[n].job {
-                Some(j) => proc(&j.name, j)?,
+                Some(j) => jobs.push(j),
                 None => {}
             }
         }
+        jobs
+    }
+    pub fn traverse<F>(
+        &self, start_job: Option<&str>, proc: F
+    ) -> Result<(), Box<dyn Error>> where F: Fn(&'a str, &'a Job) -> Result<(), Box<dyn Error>> {
+        for j in self.sorted(start_job) {
+            proc(&j.name, j)?;
+        }
         Ok(())
     }
 }
@@ -806,12 +1222,133 @@ impl<'a> DependencyGraph<'a> {
 #[derive(Serialize, Deserialize)]
 pub struct Jobs(HashMap<String, Job>);
 impl Jobs {
-    pub fn setup(&mut self) {
+    pub fn setup(&mut self) -> Result<(), Box<dyn Error>> {
         let map = &mut self.0;
         for (k, v) in map.iter_mut() {
             let name = &mut v.name;
             *name = k.to_string();
         }
+        self.expand_matrix()
+    }
+    // replace jobs that have matrix setting with jobs for each combination of matrix values,
+    // and dependencies to them with expanded jobs.
+    fn expand_matrix(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut names = self.0.iter().filter(|(_, j)| j.matrix.is_some()).map(|(k, _)| k.to_string()).collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let job = self.0.remove(&name).expect("job should exist");
+            let combinations = job.matrix.as_ref().expect("job should have matrix").combinations();
+            if combinations.is_empty() {
+                return escalate!(Box::new(config::ConfigError{
+                    cause: format!("matrix of job {} does not have any combination", name)
+                }));
+            }
+            for values in combinations {
+                let entry = MatrixEntry { job: name.clone(), values };
+                let mut expanded = job.clone();
+                expanded.name = format!("{}-{}", name, entry.suffix());
+                if let (Some((_, os)), Runner::Machine{ os: runner_os, .. }) = (
+                    entry.values.iter().find(|(k, _)| k == "os"), &mut expanded.runner
+                ) {
+                    *runner_os = RunnerOS::from_str(os)?;
+                }
+                expanded.matrix_entry = Some(entry);
+                if self.0.contains_key(&expanded.name) {
+                    return escalate!(Box::new(config::ConfigError{
+                        cause: format!("job {} expanded from matrix of {} already exists", expanded.name, name)
+                    }));
+                }
+                self.0.insert(expanded.name.clone(), expanded);
+            }
+        }
+        let mut depends = hashmap!{};
+        for (name, job) in self.as_map() {
+            if let Some(ds) = &job.depends {
+                let mut resolved = vec![];
+                for d in ds {
+                    match self.select(&d.resolve()) {
+                        Ok(jobs) => resolved.extend(jobs.iter().map(|j| config::Value::new(&j.name))),
+                        Err(e) => return escalate!(Box::new(config::ConfigError{
+                            cause: format!("invalid dependency of job {}: {}", name, e)
+                        }))
+                    }
+                }
+                depends.insert(name.to_string(), resolved);
+            }
+        }
+        for (name, ds) in depends {
+            self.0.get_mut(&name).expect("job should exist").depends = Some(ds);
+        }
+        Ok(())
+    }
+    /// jobs that matches selector. selector is job name, or job name with matrix values like "build[os=linux,rust=beta]".
+    /// for job that has matrix setting, all expanded jobs match with its name.
+    pub fn select(&self, selector: &str) -> Result<Vec<&Job>, Box<dyn Error>> {
+        if let Some(j) = self.find(selector) {
+            return Ok(vec![j]);
+        }
+        let (name, values) = match selector.strip_suffix(']').and_then(|s| s.split_once('[')) {
+            Some((name, values)) => (name, values.split(',').filter(|v| !v.is_empty()).map(|kv| {
+                kv.split_once('=').ok_or_else(|| Box::new(config::ConfigError{
+                    cause: format!("matrix value should be key=value form but {} in {}", kv, selector)
+                }))
+            }).collect::<Result<Vec<_>, _>>()?),
+            None => (selector, vec![])
+        };
+        let mut jobs = self.as_map().values().filter(|j| {
+            j.matrix_entry.as_ref().is_some_and(|m| m.job == name && m.matches(&values))
+        }).collect::<Vec<_>>();
+        if jobs.is_empty() {
+            return escalate!(Box::new(config::ConfigError{
+                cause: format!("no job matches with {}", selector)
+            }));
+        }
+        jobs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(jobs)
+    }
+    /// single job that matches selector. see select for the format of selector
+    pub fn find_by_selector(&self, selector: &str) -> Result<&Job, Box<dyn Error>> {
+        let jobs = self.select(selector)?;
+        if jobs.len() > 1 {
+            return escalate!(Box::new(config::ConfigError{
+                cause: format!(
+                    "{} matches multiple jobs {}. specify matrix values like {}[key=value,...]",
+                    selector, jobs.iter().map(|j| j.name.as_str()).collect::<Vec<_>>().join(","),
+                    jobs[0].matrix_entry.as_ref().map_or_else(|| selector, |m| m.job.as_str())
+                )
+            }));
+        }
+        Ok(jobs[0])
+    }
+    pub fn validate(&self, repository_root: &Path) -> Result<(), Box<dyn Error>> {
+        for (name, job) in self.as_map() {
+            for t in &job.on {
+                if let Err(e) = t.validate(repository_root) {
+                    return escalate!(Box::new(config::ConfigError{
+                        cause: format!("job {} has invalid trigger: {}", name, e)
+                    }));
+                }
+            }
+            for condition in job.steps.iter().flatten().map(|s| &s.condition).chain([&job.condition]).flatten() {
+                if let Err(e) = expr::Expr::parse(&condition.resolve()) {
+                    return escalate!(Box::new(config::ConfigError{
+                        cause: format!("job {} has invalid if: {}", name, e)
+                    }));
+                }
+            }
+            let mut policies = vec![(job.timeout(), job.retry.as_ref())];
+            for step in job.steps.iter().flatten() {
+                policies.push((step.timeout(), step.retry.as_ref()));
+            }
+            for (timeout, retry) in policies {
+                if let Err(e) = timeout.and_then(|_| retry.map_or(Ok(()), |r| r.validate())) {
+                    return escalate!(Box::new(config::ConfigError{
+                        cause: format!("job {} has invalid timeout or retry: {}", name, e)
+                    }));
+                }
+            }
+        }
+        Ok(())
     }
     pub fn as_map(&self) -> &HashMap<String, Job> {
         &self.0
@@ -859,6 +1396,44 @@ impl Jobs {
             Err(_) => ci.job_output(job_name, ci::OutputKind::User, key)
         }
     }
+    /// values of template variables for log_format, pr_title and pr_body of commit settings.
+    pub fn commit_template_values(
+        &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow,
+        job_names: &[String], commit: &str, files: &[String]
+    ) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        let mut values = hashmap!{
+            "job".to_string() => job_names.join(","),
+            "workflow".to_string() => runtime_workflow_config.name.clone(),
+            "release_target".to_string() => runtime_workflow_config.exec.release_target.clone().unwrap_or_default(),
+            "commit".to_string() => commit.to_string(),
+            "run_url".to_string() => runner::Runner::run_url().unwrap_or_default(),
+            "files".to_string() => files.join("\n"),
+        };
+        // job outputs cannot be listed, so only keys that templates refer are resolved
+        let re = regex::Regex::new(r"\{outputs\.([a-zA-Z0-9_\-]+)\}").unwrap();
+        for job_name in job_names {
+            let job = match self.as_map().get(job_name) {
+                Some(j) => j,
+                None => continue
+            };
+            let templates = job.commit_setting_from_config(config, runtime_workflow_config)
+                .map_or(vec![], |c| c.templates());
+            for key in templates.iter().flat_map(|t| re.captures_iter(t).map(|c| c[1].to_string())) {
+                let name = format!("outputs.{}", key);
+                if values.contains_key(&name) {
+                    continue
+                }
+                // job that does not set any output does not have output file
+                if let Some(v) = self.user_output(config, job_name, &key).unwrap_or_else(|e| {
+                    log::debug!("fail to get output {} of job {}: {}", key, job_name, e);
+                    None
+                }) {
+                    values.insert(name, v);
+                }
+            }
+        }
+        Ok(values)
+    }
     fn system_output(
         &self, config: &config::Config, job: &Job, key: &str
     ) -> Result<Option<String>, Box<dyn Error>> {
@@ -881,21 +1456,65 @@ impl Jobs {
         Ok(())
     }
     fn push_job_result_branches(
-        &self, config: &config::Config, branches_and_options: &(String, Vec<String>, CommitMethod)
-    ) -> Result<(), Box<dyn Error>> {
+        &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow,
+        commits: &JobResultCommits
+    ) -> Result<Option<CommitDelivery>, Box<dyn Error>> {
         let vcs = config.modules.vcs();
         let job_id = std::env::var("DEPLO_CI_ID").unwrap();
         let current_branch = match std::env::var("DEPLO_CI_BRANCH_NAME") {
             Ok(v) => v,
             Err(_) => {
                 log::debug!("current HEAD is not branch. skip push_job_result_branches");
-                return Ok(())
+                return Ok(None)
             }
         };
         let current_ref = std::env::var("DEPLO_CI_CURRENT_COMMIT_ID").unwrap();
-        let (name, branches, options) = branches_and_options;
+        let JobResultCommits { name, jobs, branches, method: options } = commits;
+        let mut delivery = None;
+        if let CommitMethod::Push{to: Some(to), orphan, root, prefix, ..} = options {
+            if branches.is_empty() {
+                return Ok(None);
+            }
+            // files are committed onto another branch, so working tree is not changed
+            let to = to.resolve();
+            let (root, prefix) = (root.as_ref().map(|v| v.resolve()), prefix.as_ref().map(|v| v.resolve()));
+            let mut option = hashmap!{};
+            if let Some(v) = &root { option.insert("root", v.as_str()); }
+            if let Some(v) = &prefix { option.insert("prefix", v.as_str()); }
+            if orphan.unwrap_or(false) { option.insert("orphan", "true"); }
+            let mut attempts = 0;
+            for b in branches {
+                vcs.fetch_branch(b)?;
+                let source = vcs.commit_hash(Some("FETCH_HEAD"))?;
+                // commit is re-created on latest remote branch by each attempt
+                attempts += match self.push_with_retry(&to, || {
+                    if !vcs.push_files(&source, &to, &option)? {
+                        log::info!("files of {} are not changed on {}, skip push", b, to);
+                    }
+                    Ok(())
+                }) {
+                    Ok(n) => n,
+                    Err(reason) => return escalate!(Box::new(config::ConfigError {
+                        cause: format!("fail to push files of {} to {}: {}", b, to, reason)
+                    }))
+                };
+            }
+            if !config::Config::is_running_on_ci() {
+                for b in branches {
+                    vcs.delete_branch(vcs::RefType::Remote, b)?;
+                }
+            }
+            return Ok(Some(CommitDelivery::Pushed { name: name.clone(), branch: to, attempts }));
+        }
         if branches.len() > 0 {
-            let working_branch = format!("deplo-auto-commits-{}-{}", job_id, name);
+            let stable_branch = match options {
+                CommitMethod::PullRequest{branch: Some(b), ..} => Some(b.resolve()),
+                _ => None
+            };
+            let working_branch = match &stable_branch {
+                Some(b) => b.clone(),
+                None => format!("{}{}-{}", DEPLO_AUTO_COMMITS_BRANCH_PREFIX, job_id, name)
+            };
             vcs.checkout(&current_ref, Some(&working_branch))?;
             for b in branches {
                 vcs.fetch_branch(&b)?;
@@ -904,9 +1523,6 @@ impl Jobs {
             }
             let result_head_ref = match options {
                 CommitMethod::PullRequest{labels,assignees,..} => {
-                    vcs.push_branch(&working_branch, &working_branch, &hashmap!{
-                        "new" => "true",
-                    })?;
                     let mut pr_opts_for_vcs = hashmap!{};
                     match labels {
                         Some(v) => { pr_opts_for_vcs.insert("labels", serde_json::to_string(&v)?); },
@@ -916,21 +1532,119 @@ impl Jobs {
                         Some(v) => { pr_opts_for_vcs.insert("assignees", serde_json::to_string(&v)?); },
                         None => {}
                     };
-                    vcs.pr(
-                        &format!("[deplo] auto commit by job [{}]", job_id),
-                        &working_branch, &current_branch, 
-                        &pr_opts_for_vcs.iter().map(|(k,v)| (*k,v.as_str())).collect()
+                    let files = vcs.diff_files(&format!("{}..{}", current_ref, working_branch))?.files
+                        .into_iter().map(|f| f.path).collect::<Vec<String>>();
+                    let values = self.commit_template_values(
+                        config, runtime_workflow_config, jobs, &current_ref, &files
                     )?;
+                    let values = values.iter().map(|(k, v)| (k.as_str(), v.clone())).collect::<HashMap<_, _>>();
+                    // first setting found in the jobs is used
+                    let settings = jobs.iter()
+                        .filter_map(|j| self.as_map().get(j))
+                        .filter_map(|j| j.commit_setting_from_config(config, runtime_workflow_config))
+                        .collect::<Vec<_>>();
+                    let title = match settings.iter().find_map(|c| c.pr_title.as_ref()) {
+                        Some(t) => render_template(&t.resolve(), &values),
+                        None => format!("[deplo] auto commit by job [{}]", job_id)
+                    };
+                    if let Some(body) = settings.iter().find_map(|c| c.pr_body.as_ref()) {
+                        pr_opts_for_vcs.insert("body", render_template(&body.resolve(), &values));
+                    }
+                    match &stable_branch {
+                        Some(b) => {
+                            if !pr_opts_for_vcs.contains_key("body") {
+                                // body is updated by each run, to show what is changed
+                                let changes = vcs.log(&format!("{}..{}", current_ref, working_branch))?;
+                                pr_opts_for_vcs.insert("body", format!(
+                                    "auto commits on {}\n\n{}", current_ref,
+                                    changes.iter().map(|c| format!("- {}", c.subject)).collect::<Vec<_>>().join("\n")
+                                ));
+                            }
+                            let pr_opts = pr_opts_for_vcs.iter().map(|(k,v)| (*k,v.as_str())).collect();
+                            // reopen before force push, because github refuses to reopen
+                            // the pull request whose head is force pushed after it is closed
+                            let reused = match vcs.find_pr(b, &current_branch)? {
+                                Some(pr) => match vcs.update_pr(&pr.url, &title, &pr_opts) {
+                                    Ok(_) => {
+                                        log::info!("update pull request {}", pr.url);
+                                        true
+                                    },
+                                    Err(e) if !pr.open => {
+                                        log::warn!("fail to reopen pull request {}: {}, create new one", pr.url, e);
+                                        false
+                                    },
+                                    Err(e) => return Err(e)
+                                },
+                                None => false
+                            };
+                            vcs.push_branch(&working_branch, b, &hashmap!{
+                                "new" => "true", "force" => "true",
+                                // commits after base are pushed as verified, if vcs supports
+                                "base" => current_ref.as_str(),
+                            })?;
+                            if !reused {
+                                vcs.pr(&title, b, &current_branch, &pr_opts)?;
+                            }
+                            delivery = Some(CommitDelivery::PullRequest {
+                                name: name.clone(), head: b.clone(), base: current_branch.clone()
+                            });
+                        },
+                        None => {
+                            vcs.push_branch(&working_branch, &working_branch, &hashmap!{
+                                "new" => "true",
+                                // commits after base are pushed as verified, if vcs supports
+                                "base" => current_ref.as_str(),
+                            })?;
+                            vcs.pr(
+                                &title, &working_branch, &current_branch,
+                                &pr_opts_for_vcs.iter().map(|(k,v)| (*k,v.as_str())).collect()
+                            )?;
+                            delivery = Some(CommitDelivery::PullRequest {
+                                name: name.clone(), head: working_branch.clone(), base: current_branch.clone()
+                            });
+                        }
+                    }
                     // if pushed successfully, back to original branch
                     &current_ref
                 },
-                CommitMethod::Push{squash} => {
+                CommitMethod::Push{squash, ..} => {
                     if squash.unwrap_or(true) && branches.len() > 1 {
                         vcs.squash_branch(branches.len())?;
                     }
-                    vcs.push_branch(&working_branch, &current_branch, &hashmap!{})?;
-                    // if pushed successfully, move current branch HEAD to pushed HEAD
-                    &working_branch
+                    match self.push_with_retry(&current_branch, || {
+                        vcs.push_branch(&working_branch, &current_branch, &hashmap!{})
+                    }) {
+                        Ok(attempts) => {
+                            delivery = Some(CommitDelivery::Pushed {
+                                name: name.clone(), branch: current_branch.clone(), attempts
+                            });
+                            // if pushed successfully, move current branch HEAD to pushed HEAD
+                            &working_branch
+                        },
+                        Err(reason) => {
+                            // working branch is restored to the state before failed rebase,
+                            // so it can be merged into current branch via pull request.
+                            log::warn!("give up pushing to {}: {}, open pull request instead", current_branch, reason);
+                            vcs.push_branch(&working_branch, &working_branch, &hashmap!{
+                                "new" => "true",
+                                // commits after base are pushed as verified, if vcs supports
+                                "base" => current_ref.as_str(),
+                            })?;
+                            let body = format!(
+                                "deplo fails to push auto commits of {} to {} directly: {}\n\nplease merge them manually",
+                                jobs.join(","), current_branch, reason
+                            );
+                            vcs.pr(
+                                &format!("[deplo] auto commit by job [{}] (fail to push {})", job_id, current_branch),
+                                &working_branch, &current_branch, &hashmap!{ "body" => body.as_str() }
+                            )?;
+                            delivery = Some(CommitDelivery::FallbackPullRequest {
+                                name: name.clone(), head: working_branch.clone(),
+                                base: current_branch.clone(), reason
+                            });
+                            &current_ref
+                        }
+                    }
                 }
             };
             // only local execution need to recover repository status
@@ -942,14 +1656,38 @@ impl Jobs {
                 }
             }
         }
-        Ok(())
-    }    
+        Ok(delivery)
+    }
+    // call push until it succeeds. if remote branch is updated by another run concurrently,
+    // vcs fetches and rebases onto it at next attempt. returns count of attempts if pushed,
+    // or the reason to give up pushing if rebase conflicts or all attempts fail.
+    fn push_with_retry<F>(&self, remote_branch: &str, push: F) -> Result<u32, String>
+    where F: Fn() -> Result<(), Box<dyn Error>> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match push() {
+                Ok(_) => return Ok(attempts),
+                // retrying never resolves conflict
+                Err(e) if vcs::ConflictError::caused(e.as_ref()) => return Err(e.to_string()),
+                Err(e) => if attempts >= DEPLO_PUSH_MAX_ATTEMPTS {
+                    return Err(format!("push fails {} times: {}", attempts, e));
+                } else {
+                    let backoff = Duration::from_secs(1 << attempts);
+                    log::warn!("push to {} fails: {}, retry after {:?}", remote_branch, e, backoff);
+                    sleep(backoff);
+                }
+            }
+        }
+    }
     fn aggregate_commits(
         &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow
-    ) -> Result<Vec<(String, Vec<String>, CommitMethod)>, Box<dyn Error>> {
-        let mut commits: Vec<(String, Vec<String>, CommitMethod)> = vec![];
+    ) -> Result<Vec<JobResultCommits>, Box<dyn Error>> {
+        let mut commits: Vec<JobResultCommits> = vec![];
         let mut aggregated_push_branches = vec![];
+        let mut aggregated_push_jobs = vec![];
         let mut aggregated_pr_branches = vec![];
+        let mut aggregated_pr_jobs = vec![];
         let mut aggregated_pr_opts = AggregatedPullRequestOptions {
             labels: vec![], assignees: vec![],
         };
@@ -961,23 +1699,52 @@ impl Jobs {
                     match job.commit_setting_from_config(config, runtime_workflow_config)
                              .expect("commit_setting_from_config should success").method {
                         Some(ref options) => match options {
-                            CommitMethod::Push{squash} => {
+                            CommitMethod::Push{to: Some(to), orphan, root, prefix, ..} => {
+                                // files are committed onto the branch for each job
+                                commits.push(JobResultCommits {
+                                    name: format!("{}-push-{}", job_name, to), jobs: vec![job_name.clone()],
+                                    branches: vec![v], method: CommitMethod::Push{
+                                        squash: Some(false), to: Some(to.clone()), orphan: *orphan,
+                                        root: root.clone(), prefix: prefix.clone()
+                                    }
+                                });
+                            },
+                            CommitMethod::Push{squash, to: None, ..} => {
                                 if squash.unwrap_or(true) {
                                     aggregated_push_branches.push(v);
+                                    aggregated_push_jobs.push(job_name.clone());
                                 } else {
-                                    commits.push((format!("{}-push", job_name), vec![v], CommitMethod::Push{squash: Some(false)}));
+                                    commits.push(JobResultCommits {
+                                        name: format!("{}-push", job_name), jobs: vec![job_name.clone()],
+                                        branches: vec![v], method: CommitMethod::Push{
+                                            squash: Some(false), to: None, orphan: None, root: None, prefix: None
+                                        }
+                                    });
                                 }
                             },
-                            CommitMethod::PullRequest{labels, assignees, aggregate} => {
+                            CommitMethod::PullRequest{labels, assignees, branch: Some(b), ..} => {
+                                commits.push(JobResultCommits {
+                                    name: format!("{}-pr", job_name), jobs: vec![job_name.clone()],
+                                    branches: vec![v], method: CommitMethod::PullRequest{
+                                        labels: labels.clone(), assignees: assignees.clone(),
+                                        aggregate: Some(false), branch: Some(b.clone())
+                                    }
+                                });
+                            },
+                            CommitMethod::PullRequest{labels, assignees, aggregate, branch: None} => {
                                 if aggregate.unwrap_or(false) {
                                     aggregated_pr_branches.push(v);
+                                    aggregated_pr_jobs.push(job_name.clone());
                                     aggregated_pr_opts.labels = [aggregated_pr_opts.labels, labels.clone().unwrap_or(vec![])].concat();
                                     aggregated_pr_opts.assignees = [aggregated_pr_opts.assignees, assignees.clone().unwrap_or(vec![])].concat();
                                 } else {
-                                    commits.push((format!("{}-pr", job_name), vec![v], CommitMethod::PullRequest{
-                                        labels: labels.as_ref().map(|v| v.clone()), assignees: assignees.as_ref().map(|v| v.clone()),
-                                        aggregate: Some(false)
-                                    }));
+                                    commits.push(JobResultCommits {
+                                        name: format!("{}-pr", job_name), jobs: vec![job_name.clone()],
+                                        branches: vec![v], method: CommitMethod::PullRequest{
+                                            labels: labels.as_ref().map(|v| v.clone()), assignees: assignees.as_ref().map(|v| v.clone()),
+                                            aggregate: Some(false), branch: None
+                                        }
+                                    });
                                 }
                             }
                         },
@@ -987,11 +1754,15 @@ impl Jobs {
                         None => if runtime_workflow_config.name == "integrate" {
                             // aggregated by default
                             aggregated_push_branches.push(v);
+                            aggregated_push_jobs.push(job_name.clone());
                         } else {
                             // made single PR by default
-                            commits.push((format!("{}-pr", job_name), vec![v], CommitMethod::PullRequest{
-                                labels: None, assignees: None, aggregate: Some(false)
-                            }));
+                            commits.push(JobResultCommits {
+                                name: format!("{}-pr", job_name), jobs: vec![job_name.clone()],
+                                branches: vec![v], method: CommitMethod::PullRequest{
+                                    labels: None, assignees: None, aggregate: Some(false), branch: None
+                                }
+                            });
                         }
                     }
                 },
@@ -1000,20 +1771,29 @@ impl Jobs {
                 }
             };
         }
-        commits.push(("aggregate-push".to_string(), aggregated_push_branches, CommitMethod::Push{squash: Some(true)}));
-        commits.push(("aggregate-pr".to_string(), aggregated_pr_branches, CommitMethod::PullRequest{
-            labels: if aggregated_pr_opts.labels.len() > 0 { Some(aggregated_pr_opts.labels) } else { None }, 
-            assignees: if aggregated_pr_opts.assignees.len() > 0 { Some(aggregated_pr_opts.assignees) } else { None }, 
-            aggregate: Some(true)
-        }));
+        commits.push(JobResultCommits {
+            name: "aggregate-push".to_string(), jobs: aggregated_push_jobs,
+            branches: aggregated_push_branches, method: CommitMethod::Push{
+                squash: Some(true), to: None, orphan: None, root: None, prefix: None
+            }
+        });
+        commits.push(JobResultCommits {
+            name: "aggregate-pr".to_string(), jobs: aggregated_pr_jobs,
+            branches: aggregated_pr_branches, method: CommitMethod::PullRequest{
+                labels: if aggregated_pr_opts.labels.len() > 0 { Some(aggregated_pr_opts.labels) } else { None }, 
+                assignees: if aggregated_pr_opts.assignees.len() > 0 { Some(aggregated_pr_opts.assignees) } else { None }, 
+                aggregate: Some(true), branch: None
+            }
+        });
         Ok(commits)
     }    
     pub fn halt(
         &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow
     ) -> Result<(), Box<dyn Error>> {
-        for branches_and_options in self.aggregate_commits(config, runtime_workflow_config)? {
-            match self.push_job_result_branches(config, &branches_and_options) {
-                Ok(_) => {},
+        let mut deliveries = vec![];
+        for commits in self.aggregate_commits(config, runtime_workflow_config)? {
+            match self.push_job_result_branches(config, runtime_workflow_config, &commits) {
+                Ok(d) => deliveries.extend(d),
                 Err(e) => {
                     log::error!("push_job_result_branches fails: back to original branch");
                     let vcs = config.modules.vcs();
@@ -1024,8 +1804,52 @@ impl Jobs {
                 }
             }
         }
+        if !deliveries.is_empty() {
+            log::info!("auto commits report:");
+            for d in &deliveries {
+                match d {
+                    CommitDelivery::FallbackPullRequest{..} => log::warn!("  {}", d),
+                    _ => log::info!("  {}", d)
+                }
+            }
+        }
+        self.record_deploy(config, runtime_workflow_config)?;
         crate::util::try_debug!("deplo-halt", config.ci_by_env(), runtime_workflow_config.exec, false);
         Ok(())
+    }
+    fn record_deploy(
+        &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow
+    ) -> Result<(), Box<dyn Error>> {
+        let diff_base = config.workflows.get(&runtime_workflow_config.name).and_then(|w| w.diff_base());
+        let release_target = match (diff_base, &runtime_workflow_config.exec.release_target) {
+            (Some(vcs::DiffBase::LastDeploy), Some(rt)) => rt,
+            _ => return Ok(())
+        };
+        // all jobs that should run for current changes need to finish successfully
+        let mut commit = None;
+        for (job_name, job) in self.as_map() {
+            if !job.matches_current_trigger(config, runtime_workflow_config) {
+                continue
+            }
+            match self.system_output(config, &job, DEPLO_SYSTEM_OUTPUT_DEPLOYED_COMMIT)? {
+                Some(v) => if commit.as_ref().map_or(false, |c| *c != v) {
+                    log::warn!("ci fin: jobs deployed different commits ({} and {}), skip recording deploy", commit.unwrap(), v);
+                    return Ok(())
+                } else {
+                    commit = Some(v)
+                },
+                None => {
+                    log::info!("ci fin: job {} does not finish deploy, skip recording deploy", job_name);
+                    return Ok(())
+                }
+            }
+        }
+        let commit = match commit {
+            Some(v) => v,
+            None => config.modules.vcs().commit_hash(None)?
+        };
+        log::info!("ci fin: record {} as last deploy of {}", commit, release_target);
+        config.modules.vcs().mark_deployed(release_target, &commit)
     }    
     fn wait_job(
         &self, job_id: &str, job_name: &str, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow
@@ -1035,7 +1859,7 @@ impl Jobs {
             Some(job) => job,
             None => return escalate!(Box::new(config::ConfigError{cause: format!("no such job: [{}]", job_name)})),
         };
-        let ci = job.ci(config);
+        let ci = job.remote_ci(config);
         let progress = !runtime_workflow_config.exec.silent;
         let mut timeout = runtime_workflow_config.exec.timeout;
         loop {
@@ -1069,8 +1893,18 @@ impl Jobs {
     pub fn run(
         &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow, shell: &impl shell::Shell
     ) -> Result<(), Box<dyn Error>> {
+        defer!{ Self::cleanup_local_run(config); }
         let job_name = &runtime_workflow_config.job.as_ref().expect("should have job setting").name;
-        if runtime_workflow_config.exec.follow_dependency {
+        let parallel = if runtime_workflow_config.exec.follow_dependency {
+            scheduler::Scheduler::parallel(config, runtime_workflow_config)
+        } else {
+            None
+        };
+        if let Some(s) = parallel {
+            s.run(&self.as_dg().sorted(Some(job_name)).into_iter().filter(|job| {
+                job.matches_current_trigger(config, runtime_workflow_config)
+            }).collect::<Vec<_>>())?;
+        } else if runtime_workflow_config.exec.follow_dependency {
             self.as_dg().traverse(Some(job_name), |name, job| {
                 if !job.matches_current_trigger(config, runtime_workflow_config) {
                     log::debug!("run: job '{}' skipped because does not match trigger", name);
@@ -1083,11 +1917,7 @@ impl Jobs {
                 Ok(())
             })?;            
         } else {
-            let job = config.jobs.find(job_name).expect(&format!("job '{}' does not exist", job_name));
-            match job.run(shell, config, runtime_workflow_config)? {
-                Some(job_id) => self.wait_job(&job_id, job_name, config, runtime_workflow_config)?,
-                None => {}
-            };
+            self.run_job(config, runtime_workflow_config, job_name, shell)?;
         }
         if !config::Config::is_running_on_ci() {
             log::debug!("if not running on CI, all jobs should be finished");
@@ -1095,10 +1925,51 @@ impl Jobs {
         }
         Ok(())
     }
+    // outputs of the run stored by local ci account are no longer used after the run finishes
+    fn cleanup_local_run(config: &config::Config) {
+        if config.local_ci().is_some() {
+            if let Err(e) = ci::local::cleanup(config) {
+                log::warn!("fail to cleanup data of local run: {}", e);
+            }
+        }
+    }
+    // run single job without its dependencies, and wait for it if the job runs on remote
+    pub fn run_job(
+        &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow,
+        job_name: &str, shell: &impl shell::Shell
+    ) -> Result<(), Box<dyn Error>> {
+        let job = match self.find(job_name) {
+            Some(j) => j,
+            None => return escalate!(Box::new(config::ConfigError{cause: format!("no such job: [{}]", job_name)})),
+        };
+        // other jobs may run concurrently on the same working tree.
+        // job that makes auto commit resets the working tree, so it should run exclusively.
+        let _lock = scheduler::WorkspaceLock::acquire(
+            job.commit_setting_from_config(config, runtime_workflow_config).is_some()
+        )?;
+        if let Some(job_id) = job.run(shell, config, runtime_workflow_config)? {
+            self.wait_job(&job_id, job_name, config, runtime_workflow_config)?;
+        }
+        Ok(())
+    }
     pub fn boot(
         &self, config: &config::Config, runtime_workflow_config: &config::runtime::Workflow, shell: &impl shell::Shell
     ) -> Result<(), Box<dyn Error>> {
+        defer!{ Self::cleanup_local_run(config); }
         let ci = config.ci_by_env();
+        if let Some(s) = scheduler::Scheduler::parallel(config, runtime_workflow_config) {
+            let jobs = self.as_dg().sorted(None).into_iter().filter(|job| {
+                job.matches_current_trigger(config, runtime_workflow_config)
+            }).collect::<Vec<_>>();
+            // parallel scheduler requires local ci account
+            let local = config.local_ci().expect("local ci account should exist");
+            for job in &jobs {
+                local.schedule_job(&job.name)?;
+            }
+            s.run(&jobs)?;
+            log::debug!("if not running on CI, all jobs should be finished");
+            return self.halt(config, runtime_workflow_config);
+        }
         // TODO: support follow_dependency of remote job running.
         // if runtime_workflow_config.job has some value and follow_dependency, 
         // we use Some(runtime_workflow_config.job.name) as first argument of traverse,
@@ -1114,6 +1985,10 @@ impl Jobs {
             if config::Config::is_running_on_ci() {
                 ci.schedule_job(name)?;
             } else {
+                // local ci account records scheduled jobs as CI services do
+                if let Some(local) = config.local_ci() {
+                    local.schedule_job(name)?;
+                }
                 match job.run(shell, config, runtime_workflow_config)? {
                     Some(job_id) => self.wait_job(&job_id, name, config, runtime_workflow_config)?,
                     None => {}
@@ -1129,3 +2004,118 @@ impl Jobs {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn push_with_retry_gives_up_on_conflict() {
+        let jobs = Jobs(hashmap!{});
+        let calls = Cell::new(0);
+        let result = jobs.push_with_retry("main", || {
+            calls.set(calls.get() + 1);
+            escalate!(Box::new(vcs::ConflictError{ cause: "fail to rebase onto latest main".to_string() }))
+        });
+        assert!(result.unwrap_err().contains("fail to rebase onto latest main"));
+        assert_eq!(calls.get(), 1);
+        // other errors are retried
+        let result = jobs.push_with_retry("main", || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                escalate!(Box::new(shell::ShellError::OtherFailure{
+                    cmd: "git push".to_string(), cause: "remote rejected".to_string()
+                }))
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result, Ok(2));
+    }
+    fn retry(max: u32, interval: &str, on_exit_codes: Option<Vec<i32>>) -> Retry {
+        Retry { max, backoff: None, interval: Some(config::Value::new(interval)), on_exit_codes }
+    }
+    fn exit_with(code: i32) -> Box<dyn Error> {
+        let status = std::process::Command::new("sh").args(["-c", &format!("exit {}", code)]).status().unwrap();
+        Box::new(shell::ShellError::ExitStatus{ status, cmd: "step".to_string(), stderr: "".to_string() })
+    }
+    #[test]
+    fn retry_run_attempts_at_most_max_plus_one() {
+        let r = retry(2, "0", None);
+        let calls = Cell::new(0);
+        let result: Result<(), Box<dyn Error>> = Retry::run(Some(&r), "step 'a'", None, || {
+            calls.set(calls.get() + 1);
+            Err(exit_with(1))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 3);
+        // succeeds on 2nd attempt
+        calls.set(0);
+        let result = Retry::run(Some(&r), "step 'a'", None, || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 2 { Err(exit_with(1)) } else { Ok(calls.get()) }
+        });
+        assert_eq!(result.unwrap(), 2);
+        // no retry policy
+        calls.set(0);
+        let result: Result<(), Box<dyn Error>> = Retry::run(None, "step 'a'", None, || {
+            calls.set(calls.get() + 1);
+            Err(exit_with(1))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+    #[test]
+    fn retry_run_filters_by_exit_codes() {
+        let r = retry(3, "0", Some(vec![2]));
+        let calls = Cell::new(0);
+        // retryable code, then non retryable code
+        let result: Result<(), Box<dyn Error>> = Retry::run(Some(&r), "step 'a'", None, || {
+            calls.set(calls.get() + 1);
+            Err(exit_with(if calls.get() < 2 { 2 } else { 1 }))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 2);
+        // escalated exit status is also recognized
+        calls.set(0);
+        let result: Result<(), Box<dyn Error>> = Retry::run(Some(&r), "step 'a'", None, || {
+            calls.set(calls.get() + 1);
+            escalate!(exit_with(2))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 4);
+        // errors other than exit status (eg. timeout) are not retried
+        calls.set(0);
+        let result: Result<(), Box<dyn Error>> = Retry::run(Some(&r), "step 'a'", None, || {
+            calls.set(calls.get() + 1);
+            Err(Box::new(shell::ShellError::Timeout{ cmd: "step".to_string(), timeout: Duration::from_secs(1) }))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+    #[test]
+    fn retry_run_stops_at_deadline() {
+        let r = retry(3, "1s", None);
+        let calls = Cell::new(0);
+        let start = Instant::now();
+        let result: Result<(), Box<dyn Error>> = Retry::run(
+            Some(&r), "step 'a'", Some(start + Duration::from_millis(500)), || {
+                calls.set(calls.get() + 1);
+                Err(exit_with(1))
+            }
+        );
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+    #[test]
+    fn run_job_rejects_unknown_job() {
+        let container = config::Config::with(None).unwrap();
+        let shell = shell::new_default(&container);
+        let config = container.borrow();
+        let workflow = config::runtime::Workflow::with_context("deploy".to_string(), hashmap!{});
+        let err = config.jobs.run_job(&config, &workflow, "missing", &shell).unwrap_err().to_string();
+        assert!(err.contains("no such job: [missing]"), "{}", err);
+    }
+}
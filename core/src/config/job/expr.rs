@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use regex::Regex;
+
+use crate::config;
+use crate::config::job;
+use crate::util::escalate;
+
+// small expression language for `if` of jobs and steps, like
+// `workflow == "deploy" && (changed("core/**") || env.FORCE == "true")`.
+// it is evaluated by deplo itself, so that the condition behaves same on local and every CI.
+//
+// expr := and ('||' and)*
+// and  := not ('&&' not)*
+// not  := '!' not | cmp
+// cmp  := term (('==' | '!=') term)?
+// term := string | number | 'true' | 'false' | 'null' | '(' expr ')' | name '(' (expr (',' expr)*)? ')' | name ('.' name)*
+
+/// value of expression. numbers are treated as strings, so `env.COUNT == 3` compares "3" with env value.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    String(String),
+}
+impl Value {
+    /// null, false and empty string are false. others (including "false" and "0") are true
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::Null => false,
+            Self::Bool(b) => *b,
+            Self::String(s) => !s.is_empty(),
+        }
+    }
+    fn equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) => true,
+            (Self::Null, _) | (_, Self::Null) => false,
+            (a, b) => a.to_string() == b.to_string()
+        }
+    }
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, ""),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// resolves variables and changed files that expression refers
+pub trait Context {
+    /// value of variable like ["env", "HOME"]. path is already validated by parser
+    fn var(&self, path: &[String]) -> Result<Value, Box<dyn Error>>;
+    /// true if any changed file matches with one of glob patterns
+    fn changed(&self, patterns: &[String]) -> Result<bool, Box<dyn Error>>;
+}
+
+#[derive(Debug)]
+enum Node {
+    Literal(Value),
+    Var(Vec<String>),
+    Call(String, Vec<Node>),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Eq(Box<Node>, Box<Node>),
+    Ne(Box<Node>, Box<Node>),
+}
+
+#[derive(PartialEq, Debug)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Op(&'static str),
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+impl<'a> Parser<'a> {
+    fn error<T>(&self, cause: &str) -> Result<T, Box<dyn Error>> {
+        escalate!(Box::new(config::ConfigError{
+            cause: format!("invalid expression `{}`: {}", self.src, cause)
+        }))
+    }
+    fn tokenize(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut chars: Peekable<Chars> = self.src.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '"' || c == '\'' {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(e) => s.push(e),
+                            None => return self.error("unterminated string")
+                        },
+                        Some(q) if q == c => break,
+                        Some(ch) => s.push(ch),
+                        None => return self.error("unterminated string")
+                    }
+                }
+                self.tokens.push(Token::Str(s));
+            } else if c.is_ascii_digit() {
+                let mut s = String::new();
+                while let Some(&d) = chars.peek() {
+                    if !d.is_ascii_digit() && d != '.' {
+                        break;
+                    }
+                    s.push(d);
+                    chars.next();
+                }
+                self.tokens.push(Token::Num(s));
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let mut s = String::new();
+                while let Some(&d) = chars.peek() {
+                    // job names often contain '-'
+                    if !d.is_ascii_alphanumeric() && d != '_' && d != '-' {
+                        break;
+                    }
+                    s.push(d);
+                    chars.next();
+                }
+                self.tokens.push(Token::Ident(s));
+            } else {
+                chars.next();
+                let next = chars.peek().copied();
+                let op = match (c, next) {
+                    ('=', Some('=')) => "==",
+                    ('!', Some('=')) => "!=",
+                    ('&', Some('&')) => "&&",
+                    ('|', Some('|')) => "||",
+                    ('!', _) => "!",
+                    ('(', _) => "(",
+                    (')', _) => ")",
+                    (',', _) => ",",
+                    ('.', _) => ".",
+                    _ => return self.error(&format!("unexpected character '{}'", c))
+                };
+                if op.len() > 1 {
+                    chars.next();
+                }
+                self.tokens.push(Token::Op(op));
+            }
+        }
+        Ok(())
+    }
+    fn peek_op(&self, op: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Op(o)) if *o == op)
+    }
+    fn expect_op(&mut self, op: &str) -> Result<(), Box<dyn Error>> {
+        if !self.peek_op(op) {
+            return self.error(&format!("'{}' is expected", op));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+    fn parse_or(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.parse_and()?;
+        while self.peek_op("||") {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+        }
+        Ok(node)
+    }
+    fn parse_and(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.parse_not()?;
+        while self.peek_op("&&") {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.parse_not()?));
+        }
+        Ok(node)
+    }
+    fn parse_not(&mut self) -> Result<Node, Box<dyn Error>> {
+        if self.peek_op("!") {
+            self.pos += 1;
+            return Ok(Node::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_cmp()
+    }
+    fn parse_cmp(&mut self) -> Result<Node, Box<dyn Error>> {
+        let lhs = self.parse_term()?;
+        if self.peek_op("==") {
+            self.pos += 1;
+            Ok(Node::Eq(Box::new(lhs), Box::new(self.parse_term()?)))
+        } else if self.peek_op("!=") {
+            self.pos += 1;
+            Ok(Node::Ne(Box::new(lhs), Box::new(self.parse_term()?)))
+        } else {
+            Ok(lhs)
+        }
+    }
+    fn parse_term(&mut self) -> Result<Node, Box<dyn Error>> {
+        let token = match self.tokens.get(self.pos) {
+            Some(t) => t,
+            None => return self.error("unexpected end of expression")
+        };
+        self.pos += 1;
+        match token {
+            Token::Str(s) | Token::Num(s) => Ok(Node::Literal(Value::String(s.clone()))),
+            Token::Op("(") => {
+                let node = self.parse_or()?;
+                self.expect_op(")")?;
+                Ok(node)
+            },
+            Token::Op(op) => self.error(&format!("unexpected '{}'", op)),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                "null" => Ok(Node::Literal(Value::Null)),
+                _ => {
+                    let name = name.clone();
+                    if self.peek_op("(") {
+                        self.pos += 1;
+                        self.parse_call(name)
+                    } else {
+                        self.parse_var(name)
+                    }
+                }
+            }
+        }
+    }
+    fn parse_call(&mut self, name: String) -> Result<Node, Box<dyn Error>> {
+        let mut args = vec![];
+        if !self.peek_op(")") {
+            args.push(self.parse_or()?);
+            while self.peek_op(",") {
+                self.pos += 1;
+                args.push(self.parse_or()?);
+            }
+        }
+        self.expect_op(")")?;
+        let valid = match name.as_str() {
+            "changed" => !args.is_empty(),
+            "contains" | "starts_with" | "ends_with" | "matches" => args.len() == 2,
+            _ => return self.error(&format!("unknown function {}", name))
+        };
+        if !valid {
+            return self.error(&format!("wrong number of arguments for {}", name));
+        }
+        if name == "matches" {
+            if let Node::Literal(Value::String(re)) = &args[1] {
+                if let Err(e) = Regex::new(re) {
+                    return self.error(&format!("invalid regex {}: {}", re, e));
+                }
+            }
+        }
+        Ok(Node::Call(name, args))
+    }
+    fn parse_var(&mut self, name: String) -> Result<Node, Box<dyn Error>> {
+        let mut path = vec![name];
+        while self.peek_op(".") {
+            self.pos += 1;
+            match self.tokens.get(self.pos) {
+                Some(Token::Ident(s)) | Some(Token::Num(s)) => path.push(s.clone()),
+                _ => return self.error("name is expected after '.'")
+            }
+            self.pos += 1;
+        }
+        let valid = match path[0].as_str() {
+            "workflow" | "release_target" => path.len() == 1,
+            "env" => path.len() == 2,
+            "context" => path.len() >= 2,
+            "jobs" => path.len() == 4 && path[2] == "outputs",
+            _ => return self.error(&format!("unknown variable {}", path[0]))
+        };
+        if !valid {
+            return self.error(&format!(
+                "invalid reference {}. use workflow, release_target, env.NAME, context.KEY or jobs.NAME.outputs.KEY",
+                path.join(".")
+            ));
+        }
+        Ok(Node::Var(path))
+    }
+}
+
+/// parsed expression
+pub struct Expr {
+    src: String,
+    root: Node,
+}
+impl Expr {
+    pub fn parse(src: &str) -> Result<Self, Box<dyn Error>> {
+        let mut p = Parser { src, tokens: vec![], pos: 0 };
+        p.tokenize()?;
+        if p.tokens.is_empty() {
+            return p.error("empty expression");
+        }
+        let root = p.parse_or()?;
+        if p.pos < p.tokens.len() {
+            return p.error(&format!("unexpected {:?}", p.tokens[p.pos]));
+        }
+        Ok(Self { src: src.to_string(), root })
+    }
+    /// evaluates the expression and returns whether its value is truthy
+    pub fn eval(&self, ctx: &impl Context) -> Result<bool, Box<dyn Error>> {
+        let v = Self::eval_node(&self.root, ctx)?;
+        log::debug!("expression `{}` is evaluated to {:?}", self.src, v);
+        Ok(v.is_truthy())
+    }
+    fn eval_node(node: &Node, ctx: &impl Context) -> Result<Value, Box<dyn Error>> {
+        Ok(match node {
+            Node::Literal(v) => v.clone(),
+            Node::Var(path) => ctx.var(path)?,
+            Node::Not(n) => Value::Bool(!Self::eval_node(n, ctx)?.is_truthy()),
+            // like shell, right hand side is not evaluated if result is determined by left hand side
+            Node::And(l, r) => Value::Bool(Self::eval_node(l, ctx)?.is_truthy() && Self::eval_node(r, ctx)?.is_truthy()),
+            Node::Or(l, r) => Value::Bool(Self::eval_node(l, ctx)?.is_truthy() || Self::eval_node(r, ctx)?.is_truthy()),
+            Node::Eq(l, r) => Value::Bool(Self::eval_node(l, ctx)?.equals(&Self::eval_node(r, ctx)?)),
+            Node::Ne(l, r) => Value::Bool(!Self::eval_node(l, ctx)?.equals(&Self::eval_node(r, ctx)?)),
+            Node::Call(name, args) => {
+                let mut values = vec![];
+                for a in args {
+                    values.push(Self::eval_node(a, ctx)?.to_string());
+                }
+                Value::Bool(match name.as_str() {
+                    "changed" => ctx.changed(&values)?,
+                    "contains" => values[0].contains(&values[1]),
+                    "starts_with" => values[0].starts_with(&values[1]),
+                    "ends_with" => values[0].ends_with(&values[1]),
+                    "matches" => Regex::new(&values[1])?.is_match(&values[0]),
+                    _ => unreachable!("function {} should be rejected by parser", name)
+                })
+            }
+        })
+    }
+}
+
+/// context to evaluate `if` of job and step with current workflow
+pub struct JobContext<'a> {
+    pub config: &'a config::Config,
+    pub runtime_workflow_config: &'a config::runtime::Workflow,
+    /// envs of the job (and the step). process envs are used if not found
+    pub envs: &'a HashMap<String, config::Value>,
+}
+impl<'a> Context for JobContext<'a> {
+    fn var(&self, path: &[String]) -> Result<Value, Box<dyn Error>> {
+        let optional = |v: Option<String>| v.map_or(Value::Null, Value::String);
+        Ok(match path[0].as_str() {
+            "workflow" => Value::String(self.runtime_workflow_config.name.clone()),
+            "release_target" => optional(self.runtime_workflow_config.exec.release_target.clone()),
+            "env" => optional(self.envs.get(&path[1]).map(|v| v.resolve()).or_else(|| std::env::var(&path[1]).ok())),
+            "context" => {
+                let mut v = self.runtime_workflow_config.context.get(&path[1]).cloned();
+                for k in &path[2..] {
+                    v = v.and_then(|v| v.index(k));
+                }
+                optional(v.map(|v| v.resolve()))
+            },
+            "jobs" => {
+                if self.config.jobs.find(&path[1]).is_none() {
+                    return escalate!(Box::new(config::ConfigError{
+                        cause: format!("job {} referred by expression does not exist", path[1])
+                    }));
+                }
+                optional(self.config.jobs.user_output(self.config, &path[1], &path[3])?)
+            },
+            _ => unreachable!("variable {} should be rejected by parser", path[0])
+        })
+    }
+    fn changed(&self, patterns: &[String]) -> Result<bool, Box<dyn Error>> {
+        let matcher = job::Trigger::diff_matcher(
+            &None, &patterns.iter().map(|p| config::Value::new(p)).collect()
+        )?;
+        Ok(self.config.modules.vcs().changed(&matcher, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestContext;
+    impl Context for TestContext {
+        fn var(&self, path: &[String]) -> Result<Value, Box<dyn Error>> {
+            Ok(match path.join(".").as_str() {
+                "workflow" => Value::String("deploy".to_string()),
+                "env.EMPTY" => Value::String("".to_string()),
+                "env.COUNT" => Value::String("3".to_string()),
+                "jobs.build-linux.outputs.version" => Value::String("1.2.0".to_string()),
+                _ => Value::Null
+            })
+        }
+        fn changed(&self, patterns: &[String]) -> Result<bool, Box<dyn Error>> {
+            Ok(patterns.iter().any(|p| p == "core/*"))
+        }
+    }
+    fn eval(src: &str) -> bool {
+        Expr::parse(src).unwrap_or_else(|e| panic!("parse {}: {}", src, e)).eval(&TestContext).unwrap()
+    }
+
+    #[test]
+    fn eval_expression() {
+        assert!(eval(r#"workflow == "deploy""#));
+        assert!(eval("workflow != 'integrate'"));
+        assert!(!eval("release_target"));
+        assert!(eval("release_target == null"));
+        assert!(!eval("env.EMPTY"));
+        assert!(eval("env.COUNT == 3"));
+        assert!(eval("!env.UNDEFINED && workflow == 'deploy'"));
+        assert!(eval("env.EMPTY || (workflow == 'deploy' && changed('docs/*', 'core/*'))"));
+        assert!(!eval("changed('docs/*') || false"));
+        assert!(eval("starts_with(jobs.build-linux.outputs.version, '1.') && !contains(workflow, 'x')"));
+        assert!(eval(r#"matches(jobs.build-linux.outputs.version, "^\\d+\\.\\d+\\.0$")"#));
+    }
+    #[test]
+    fn reject_invalid_expression() {
+        for src in [
+            "", "workflow ==", "(workflow", "workflow = 'a'", "'unterminated", "unknown",
+            "env", "env.A.B", "jobs.a.result", "undefined('a')", "contains('a')", "matches(workflow, '(')",
+            "workflow workflow"
+        ] {
+            assert!(Expr::parse(src).is_err(), "{} should be rejected", src);
+        }
+    }
+}
@@ -2,13 +2,14 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
+use std::time::Instant;
 
 use maplit::hashmap;
 
 use crate::config;
 use crate::config::job;
 use crate::shell;
-use crate::util::{defer, merge_hashmap, rm};
+use crate::util::{defer, escalate, merge_hashmap, rm};
 use crate::vcs;
 
 pub struct Runner<'a> {
@@ -57,6 +58,9 @@ impl<'a> Runner<'a> {
                 (vec![job::Step{
                     name: None,
                     env: None,
+                    condition: None,
+                    timeout: None,
+                    retry: None,
                     command: job::StepCommand::Eval{
                         command: config::Value::new(c),
                         workdir: None,
@@ -70,6 +74,9 @@ impl<'a> Runner<'a> {
                 (vec![job::Step{
                     name: None,
                     env: None,
+                    condition: None,
+                    timeout: None,
+                    retry: None,
                     command: job::StepCommand::Eval{
                         command: c.clone(),
                         workdir: None,
@@ -84,6 +91,9 @@ impl<'a> Runner<'a> {
                 (vec![job::Step{
                     name: None,
                     env: None,
+                    condition: None,
+                    timeout: None,
+                    retry: None,
                     command: job::StepCommand::Eval{
                         command: c.clone(),
                         workdir: None,
@@ -105,42 +115,142 @@ impl<'a> Runner<'a> {
             None => format!("deplo job run-steps {} -p '{}'", job_name, payload)
         }
     }
+    fn run_step(
+        &self, shell: &impl shell::Shell, shell_settings: &shell::Settings,
+        job_envs: &HashMap<String, config::Value>, job: &config::job::Job, step: &job::Step
+    ) -> Result<(), Box<dyn Error>> {
+        let empty_envs = hashmap!{};
+        match &step.command {
+            job::StepCommand::Eval{shell: sh, command, workdir} => {
+                shell.eval(
+                    command.as_str(),
+                    &sh.as_ref().map_or_else(|| job.shell.as_ref().map(|v| v.resolve()), |v| Some(v.as_str().to_string())),
+                    shell::mctoa(merge_hashmap(job_envs, step.env.as_ref().unwrap_or(&empty_envs))),
+                    &workdir.as_ref().or(job.workdir.as_ref()),
+                    shell_settings
+                )?;
+            },
+            job::StepCommand::Exec{exec, workdir} => {
+                shell.exec(exec.iter().map(|v| shell::arg!(v)),
+                    shell::mctoa(merge_hashmap(job_envs, step.env.as_ref().unwrap_or(&empty_envs))),
+                    &workdir.as_ref().or(job.workdir.as_ref()),
+                    shell_settings
+                )?;
+            },
+            job::StepCommand::Module(c) => {
+                c.value(|v| {
+                    self.config.modules.step(&v.uses).run(
+                        shell_settings, job_envs, &v.with
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
     pub fn run_steps(
         &self, shell: &impl shell::Shell, shell_settings: &shell::Settings,
         runtime_workflow_config: &config::runtime::Workflow,
         job: &config::job::Job, steps: &Vec<job::Step>
     ) -> Result<Option<String>, Box<dyn Error>> {
-        let empty_envs = hashmap!{};
         let job_envs = job.env(self.config, runtime_workflow_config);
-        for step in steps {
-            match &step.command {
-                job::StepCommand::Eval{shell: sh, command, workdir} => {
-                    shell.eval(
-                        command.as_str(),
-                        &sh.as_ref().map_or_else(|| job.shell.as_ref().map(|v| v.resolve()), |v| Some(v.as_str().to_string())),
-                        shell::mctoa(merge_hashmap(&job_envs, step.env.as_ref().map_or_else(|| &empty_envs, |v| v))),
-                        &workdir.as_ref().map_or_else(|| job.workdir.as_ref(), |v| Some(&v)),
-                        shell_settings
-                    )?;
-                },
-                job::StepCommand::Exec{exec, workdir} => {
-                    shell.exec(exec.iter().map(|v| shell::arg!(v)),
-                        shell::mctoa(merge_hashmap(&job_envs, step.env.as_ref().map_or_else(|| &empty_envs, |v| v))),
-                        &workdir.as_ref().map_or_else(|| job.workdir.as_ref(), |v| Some(&v)),
-                        shell_settings
-                    )?;
-                },
-                job::StepCommand::Module(c) => {
-                    c.value(|v| {
-                        self.config.modules.step(&v.uses).run(
-                            shell_settings, &job_envs, &v.with
-                        )
-                    })?;
+        // timeout and retry of the job are not applied to adhoc command and shell
+        let (job_timeout, job_retry) = match runtime_workflow_config.command() {
+            job::Command::Job => (job.timeout()?, job.retry.as_ref()),
+            _ => (None, None)
+        };
+        job::Retry::run(job_retry, &format!("job '{}'", job.name), None, || {
+            let deadline = job_timeout.map(|t| Instant::now() + t);
+            for (i, step) in steps.iter().enumerate() {
+                let what = format!("step '{}' of job '{}'", step.display_name(i), job.name);
+                if let Some(c) = &step.condition {
+                    let envs = merge_hashmap(&job_envs, step.env.as_ref().unwrap_or(&hashmap!{}));
+                    if !job::expr::Expr::parse(&c.resolve())?.eval(&job::expr::JobContext{
+                        config: self.config, runtime_workflow_config, envs: &envs
+                    })? {
+                        log::info!("skip {} because `{}` is false", what, c);
+                        continue;
+                    }
                 }
+                job::Retry::run(step.retry.as_ref(), &what, deadline, || {
+                    // step is killed when job timeout comes
+                    let remaining = match deadline {
+                        Some(d) => match d.checked_duration_since(Instant::now()) {
+                            Some(r) if !r.is_zero() => Some(r),
+                            _ => return escalate!(Box::new(config::ConfigError{
+                                cause: format!(
+                                    "job '{}' timeout after {}s", job.name, job_timeout.unwrap_or_default().as_secs()
+                                )
+                            }))
+                        },
+                        None => None
+                    };
+                    let mut settings = shell_settings.clone();
+                    settings.timeout(match (step.timeout()?, remaining) {
+                        (Some(t), Some(r)) => Some(t.min(r)),
+                        (t, r) => t.or(r)
+                    });
+                    self.run_step(shell, &settings, &job_envs, job, step)
+                })?;
             }
-        };
+            Ok(())
+        })?;
         Ok(None)
     }
+    // context of commit status for the job. None if commit_status option is not enabled
+    fn commit_status_context(&self) -> Option<String> {
+        let job = self.job;
+        let option = job.options.as_ref().and_then(|o| o.get("commit_status"))?;
+        match option.as_str() {
+            // custom context. {job} is replaced with job name
+            Some(_) => Some(option.resolve().replace("{job}", &job.name)),
+            None => option.value.as_bool().unwrap_or(false).then(|| format!("deplo/{}", job.name))
+        }
+    }
+    // url of running CI job, which is linked from commit status
+    pub fn run_url() -> Option<String> {
+        if let Ok(url) = std::env::var("CI_JOB_URL") {
+            return Some(url);
+        }
+        if let Ok(url) = std::env::var("CIRCLE_BUILD_URL") {
+            return Some(url);
+        }
+        match (std::env::var("GITHUB_SERVER_URL"), std::env::var("GITHUB_REPOSITORY"), std::env::var("GITHUB_RUN_ID")) {
+            (Ok(server), Ok(repo), Ok(run_id)) => Some(format!("{}/{}/actions/runs/{}", server, repo, run_id)),
+            _ => None
+        }
+    }
+    // commit that status is set to. for pull request, HEAD may be merge commit of the PR and its base
+    // (eg. refs/pull/N/merge of github actions) that is not shown on the PR page.
+    // so status is set to head commit of the PR, which is given by CI as DEPLO_CI_CURRENT_COMMIT_ID.
+    fn commit_for_status(&self, runtime_workflow_config: &config::runtime::Workflow) -> Result<String, Box<dyn Error>> {
+        let vcs = self.config.modules.vcs();
+        if let Some(ref rev) = runtime_workflow_config.exec.revision {
+            return vcs.commit_hash(Some(rev));
+        }
+        if let (vcs::RefType::Pull, _) = vcs.current_ref()? {
+            match std::env::var("DEPLO_CI_CURRENT_COMMIT_ID") {
+                Ok(v) if !v.is_empty() => return Ok(v),
+                _ => log::warn!("DEPLO_CI_CURRENT_COMMIT_ID is not set, commit status is set to HEAD")
+            }
+        }
+        vcs.commit_hash(None)
+    }
+    fn set_commit_status(
+        &self, runtime_workflow_config: &config::runtime::Workflow, state: vcs::CommitState, summary: &str
+    ) {
+        let context = match self.commit_status_context() {
+            Some(v) => v,
+            None => return
+        };
+        let vcs = self.config.modules.vcs();
+        // failing to report status should not fail the job itself
+        let result = self.commit_for_status(runtime_workflow_config).and_then(|commit| {
+            vcs.set_commit_status(&commit, &context, state, Self::run_url().as_deref(), summary)
+        });
+        if let Err(e) = result {
+            log::warn!("fail to set commit status {} of job '{}': {}", state, self.job.name, e);
+        }
+    }
     pub fn run(
         &self, shell: &impl shell::Shell, runtime_workflow_config: &config::runtime::Workflow
     ) -> Result<Option<String>, Box<dyn Error>> {
@@ -153,6 +263,31 @@ impl<'a> Runner<'a> {
             },
             None => {}
         }
+        // like timeout and retry, condition is not applied to adhoc command and shell
+        if let (job::Command::Job, Some(c)) = (runtime_workflow_config.command(), &job.condition) {
+            if !job.condition_matches(config, runtime_workflow_config, &job.env(config, runtime_workflow_config))? {
+                log::info!("skip job '{}' because `{}` is false", job.name, c);
+                return Ok(None);
+            }
+        }
+        self.set_commit_status(
+            runtime_workflow_config, vcs::CommitState::Pending, &format!("job '{}' is running", job.name)
+        );
+        match self.execute(shell, runtime_workflow_config) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                self.set_commit_status(
+                    runtime_workflow_config, vcs::CommitState::Failure, &format!("job '{}' fails: {}", job.name, e)
+                );
+                Err(e)
+            }
+        }
+    }
+    fn execute(
+        &self, shell: &impl shell::Shell, runtime_workflow_config: &config::runtime::Workflow
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let config = self.config;
+        let job = self.job;
         let exec = &runtime_workflow_config.exec;
         // apply exec settings to current workspace.
         let command = runtime_workflow_config.command();
@@ -166,13 +301,12 @@ impl<'a> Runner<'a> {
             }
         };
         let (steps, cmd_for_container) = self.create_steps(&command);
-        let ci = job.ci(&config);
         if exec.remote {
             log::debug!(
                 "force running job '{}' on remote with steps {} at {}",
                 job.name, job::StepsDumper{steps: &steps}, exec.revision.as_ref().unwrap_or(&"".to_string())
             );
-            return Ok(Some(ci.run_job(&runtime_workflow_config)?));
+            return Ok(Some(job.remote_ci(config).run_job(runtime_workflow_config)?));
         }
         // adjust revision with command line argument
         self.adjust_commit_hash(&exec.revision.as_ref().map(|v| v.as_str()))?;
@@ -182,7 +316,7 @@ impl<'a> Runner<'a> {
                 let current_os = shell.detect_os()?;
                 // if deplo is not runnning on CI, we respect configuration no_fallback.
                 // if set to false or ommitted (and not running on CI), we use fallback even if os type is matched
-                if os == current_os && (ci.runs_on_service() || no_fallback.unwrap_or(false)) {
+                if os == current_os && (job.remote_ci(config).runs_on_service() || no_fallback.unwrap_or(false)) {
                     log::debug!("runner os '{}' is same as current os '{}' and runs on CI or no_fallback is set to true", os, current_os);
                     if let Some(p) = config.setup_deplo_cli(os, shell)? {
                         let parent = p.parent().expect(&format!("path should not be root {}", p.display()));
@@ -261,7 +395,7 @@ impl<'a> Runner<'a> {
                         "running job '{}' on remote with steps {} at {}: its target os={} which cannot fallback to local execution",
                         job.name, job::StepsDumper{steps: &steps}, exec.revision.as_ref().unwrap_or(&"".to_string()), os
                     );
-                    let ci = job.ci(&config);
+                    let ci = job.remote_ci(config);
                     return Ok(Some(ci.run_job(&runtime_workflow_config)?));
     
                 }
@@ -299,25 +433,56 @@ impl<'a> Runner<'a> {
         let config = self.config;
         let job_name = &job.name;
         let mut system_job_outputs = hashmap!{};
+        // record the commit deployed by this job, so that deplo halt can record last deploy of the release target
+        if config.workflows.get(&runtime_workflow_config.name).and_then(|w| w.diff_base()) == Some(vcs::DiffBase::LastDeploy) {
+            system_job_outputs.insert(
+                config::job::DEPLO_SYSTEM_OUTPUT_DEPLOYED_COMMIT, config.modules.vcs().commit_hash(None)?
+            );
+        }
         match job.commit_setting_from_config(&config, runtime_workflow_config) {
             Some(commit) => {
                 let vcs = config.modules.vcs();
                 match vcs.current_ref()? {
                     (vcs::RefType::Branch|vcs::RefType::Pull, _) => {
                         let branch_name = format!(
-                            "deplo-auto-commits-{}-tmp-{}", 
+                            "{}{}-tmp-{}", config::job::DEPLO_AUTO_COMMITS_BRANCH_PREFIX,
                             crate::util::env::var_or_die("DEPLO_CI_ID"),
                             job_name
                         );
+                        let patterns = commit.files.iter().map(|v| v.as_str()).collect::<Vec<&str>>();
+                        let options = if matches!(&commit.method, Some(job::CommitMethod::Push{to: Some(_), ..})) {
+                            // generated files for other branch (eg. documents for gh-pages) are usually ignored
+                            hashmap!{"include_ignored" => "true"}
+                        } else {
+                            hashmap!{}
+                        };
+                        let values = config.jobs.commit_template_values(
+                            config, runtime_workflow_config, std::slice::from_ref(job_name),
+                            &vcs.commit_hash(None)?, &vcs.files_to_push(&patterns, &options)?
+                        )?;
                         if vcs.push_diff(
                             // basically the branch_name does not exists in remote,
                             // we need to add refs/heads to create it automatically
                             &format!("refs/heads/{}", branch_name), 
-                            &commit.generate_commit_log(job_name, &job),
-                            &commit.files.iter().map(|v| v.as_str()).collect::<Vec<&str>>(),
-                            &hashmap!{}
+                            &commit.generate_commit_log(&values.iter().map(|(k, v)| (k.as_str(), v.clone())).collect()),
+                            &patterns, &options
                         )? {
                             system_job_outputs.insert(config::job::DEPLO_SYSTEM_OUTPUT_COMMIT_BRANCH_NAME, branch_name);
+                        } else if let Some(job::CommitMethod::PullRequest{ branch: Some(b), .. }) = &commit.method {
+                            // nothing to be merged anymore, so pull request from the fixed branch is closed
+                            if let Ok(base) = std::env::var("DEPLO_CI_BRANCH_NAME") {
+                                match vcs.find_pr(&b.resolve(), &base) {
+                                    Ok(Some(pr)) if pr.open => {
+                                        log::info!("job {} produces no diff, close pull request {}", job_name, pr.url);
+                                        vcs.close_pr(&pr.url, &serde_json::json!({
+                                            "message": format!("closed by deplo because job {} produces no diff", job_name)
+                                        }))?;
+                                    },
+                                    Ok(_) => {},
+                                    // job itself succeeds, so failure of the housekeeping does not fail it
+                                    Err(e) => log::error!("fail to find pull request from {}: {}", b.resolve(), e)
+                                }
+                            }
                         }
                     },
                     (ty, b) => {
@@ -336,6 +501,7 @@ impl<'a> Runner<'a> {
                 job_name, crate::ci::OutputKind::System, 
                 system_job_outputs.iter().map(|(k,v)| (*k, v.as_str())).collect()
             )?;
+            // also makes deplo halt run to record deploy
             ci.mark_need_cleanup(job_name)?;
         }
         match fs::read(Path::new(config::job::DEPLO_JOB_OUTPUT_TEMPORARY_FILE)) {
@@ -347,6 +513,63 @@ impl<'a> Runner<'a> {
             },
             Err(_) => {}
         }
+        self.set_commit_status(
+            runtime_workflow_config, vcs::CommitState::Success, &format!("job '{}' succeeded", job_name)
+        );
         Ok(())
     }    
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_status_context_test() {
+        let container = config::Config::with(Some(r#"
+version = 1
+project_name = "test"
+[secrets]
+[vars]
+[release_targets]
+nightly = { patterns = ["main"] }
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "ghaction"
+account = "foo"
+key = "bar"
+[workflows]
+integrate = {}
+[jobs.enabled]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+command = "echo enabled"
+options = { commit_status = true }
+[jobs.custom]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+command = "echo custom"
+options = { commit_status = "ci/{job}/check" }
+[jobs.disabled]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+command = "echo disabled"
+options = { commit_status = false }
+[jobs.none]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+command = "echo none"
+"#)).unwrap();
+        let mut config = container.borrow_mut();
+        config.setup().unwrap();
+        let context = |name: &str| Runner::new(config.jobs.find(name).unwrap(), &config).commit_status_context();
+        assert_eq!(context("enabled").as_deref(), Some("deplo/enabled"));
+        assert_eq!(context("custom").as_deref(), Some("ci/custom/check"));
+        assert_eq!(context("disabled"), None);
+        assert_eq!(context("none"), None);
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::Duration;
+
+use crate::config;
+use crate::config::job;
+use crate::util::{defer, escalate, path_join};
+
+// environment variable that tells child deplo process the path of workspace lock file
+const DEPLO_SCHEDULER_LOCK_PATH: &str = "DEPLO_SCHEDULER_LOCK_PATH";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// destination of output of concurrently running job
+#[derive(Clone)]
+enum LogSink {
+    Prefix(String),
+    File(Arc<Mutex<fs::File>>),
+}
+impl LogSink {
+    fn write(&self, line: &[u8], is_stderr: bool) {
+        // error of writing log should not stop the job
+        let _ = match self {
+            Self::Prefix(prefix) => if is_stderr {
+                let mut e = std::io::stderr().lock();
+                e.write_all(prefix.as_bytes()).and_then(|_| e.write_all(line)).and_then(|_| e.write_all(b"\n"))
+            } else {
+                let mut o = std::io::stdout().lock();
+                o.write_all(prefix.as_bytes()).and_then(|_| o.write_all(line)).and_then(|_| o.write_all(b"\n"))
+            },
+            Self::File(f) => {
+                let mut f = f.lock().unwrap();
+                f.write_all(line).and_then(|_| f.write_all(b"\n"))
+            }
+        };
+    }
+    fn pump<R: Read + Send + 'static>(&self, src: R, is_stderr: bool) -> JoinHandle<()> {
+        let sink = self.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(src).split(b'\n') {
+                match line {
+                    Ok(l) => sink.write(&l, is_stderr),
+                    Err(_) => break
+                }
+            }
+        })
+    }
+}
+
+// job that is started by scheduler
+trait Task {
+    /// returns Some(true) if the task succeeded, Some(false) if failed, None if still running
+    fn poll(&mut self) -> Result<Option<bool>, Box<dyn Error>>;
+    fn finish(self);
+}
+
+struct RunningJob {
+    name: String,
+    child: Child,
+    pumps: Vec<JoinHandle<()>>,
+}
+impl Task for RunningJob {
+    fn poll(&mut self) -> Result<Option<bool>, Box<dyn Error>> {
+        Ok(self.child.try_wait()?.map(|status| {
+            if !status.success() {
+                log::error!("job '{}' exits with {}", self.name, status);
+            }
+            status.success()
+        }))
+    }
+    fn finish(self) {
+        for p in self.pumps {
+            let _ = p.join();
+        }
+    }
+}
+
+/// held by child deplo process while it runs a job.
+/// jobs share single working tree, and a job that makes auto commit resets the working tree
+/// and commits whole of its modification. so such a job holds the lock exclusively,
+/// and other jobs hold it shared, to run concurrently with each other.
+/// lock is released by OS when the process exits, so it never becomes stale.
+pub struct WorkspaceLock {
+    _file: fs::File,
+}
+impl WorkspaceLock {
+    /// returns None if current process is not run by scheduler
+    pub fn acquire(exclusive: bool) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = match std::env::var(DEPLO_SCHEDULER_LOCK_PATH) {
+            Ok(v) if !v.is_empty() => PathBuf::from(v),
+            _ => return Ok(None)
+        };
+        let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(&path)?;
+        log::debug!("acquire workspace lock {} (exclusive = {})", path.display(), exclusive);
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(Some(Self { _file: file }))
+    }
+}
+
+/// runs jobs concurrently on local machine, by respecting their dependencies.
+/// config::Container is not thread safe, so each job runs in separated deplo process (`deplo job run`)
+/// that has its own config, and scheduler only manages these processes.
+/// job outputs are passed between processes via local ci account.
+pub struct Scheduler<'a> {
+    config: &'a config::Config,
+    runtime_workflow_config: &'a config::runtime::Workflow,
+}
+impl<'a> Scheduler<'a> {
+    /// returns scheduler if jobs should run concurrently. otherwise jobs should run one by one
+    pub fn parallel(
+        config: &'a config::Config, runtime_workflow_config: &'a config::runtime::Workflow
+    ) -> Option<Self> {
+        let exec = &runtime_workflow_config.exec;
+        if exec.jobs <= 1 || config::Config::is_running_on_ci() {
+            return None;
+        }
+        if !matches!(runtime_workflow_config.command(), job::Command::Job) {
+            log::warn!("--jobs is ignored because adhoc command or shell is specified");
+            return None;
+        }
+        if config.local_ci().is_none() {
+            log::warn!("--jobs is ignored because local ci account, which is required to pass job outputs, is not configured");
+            return None;
+        }
+        Some(Self { config, runtime_workflow_config })
+    }
+    fn child_workflow(&self, job_name: &str) -> config::runtime::Workflow {
+        let mut w = self.runtime_workflow_config.clone();
+        // keep command of the job that is specified by user
+        w.job = match w.job {
+            Some(j) if j.name == job_name => Some(j),
+            _ => Some(config::runtime::Job { name: job_name.to_string(), command: None })
+        };
+        // revision is already checked out by scheduler
+        w.exec.revision = None;
+        w.exec.follow_dependency = false;
+        w.exec.jobs = 1;
+        // stdin of child process is not a terminal, so debugger cannot run
+        w.exec.debug = config::runtime::StartDebugOn::Never;
+        w
+    }
+    fn log_sink(&self, job_name: &str) -> Result<LogSink, Box<dyn Error>> {
+        match &self.runtime_workflow_config.exec.log_dir {
+            Some(d) => {
+                fs::create_dir_all(d)?;
+                let path = path_join(vec![Path::new(d), Path::new(&format!("{}.log", job_name))]);
+                log::info!("output of job '{}' is written to {}", job_name, path.display());
+                Ok(LogSink::File(Arc::new(Mutex::new(fs::File::create(path)?))))
+            },
+            None => Ok(LogSink::Prefix(format!("[{}] ", job_name)))
+        }
+    }
+    fn spawn(&self, job: &job::Job, lock_path: &Path) -> Result<RunningJob, Box<dyn Error>> {
+        let payload = serde_json::to_string(&self.child_workflow(&job.name))?;
+        let sink = self.log_sink(&job.name)?;
+        let mut child = Command::new(std::env::current_exe()?)
+            .args(self.config.runtime.cli_args())
+            .args(["job", "run", &job.name, "-p", &payload])
+            .env(DEPLO_SCHEDULER_LOCK_PATH, lock_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let pumps = vec![
+            sink.pump(child.stdout.take().expect("stdout should be piped"), false),
+            sink.pump(child.stderr.take().expect("stderr should be piped"), true),
+        ];
+        Ok(RunningJob { name: job.name.clone(), child, pumps })
+    }
+    /// run jobs that should be ordered by dependency, like DependencyGraph::sorted returns.
+    /// dependencies which are not contained in jobs are regarded as finished.
+    /// if a job fails and keep_going is not set, jobs that are not started yet are canceled.
+    pub fn run(&self, jobs: &[&'a job::Job]) -> Result<(), Box<dyn Error>> {
+        let exec = &self.runtime_workflow_config.exec;
+        let vcs = self.config.modules.vcs();
+        // each job should not checkout revision by itself, because jobs share working tree
+        if let Some(ref rev) = exec.revision {
+            log::debug!("change commit hash to {}", rev);
+            vcs.checkout(rev, Some(config::DEPLO_VCS_TEMPORARY_WORKSPACE_NAME))?;
+        }
+        defer!{
+            if exec.revision.is_some() {
+                vcs.checkout_previous().unwrap();
+            }
+        };
+        let lock_dir = tempfile::tempdir()?;
+        let lock_path = lock_dir.path().join("workspace.lock");
+        schedule(jobs, exec.jobs, exec.keep_going, |j| self.spawn(j, &lock_path))
+    }
+}
+
+fn schedule<'a, T: Task>(
+    jobs: &[&'a job::Job], max_jobs: usize, keep_going: bool,
+    mut spawn: impl FnMut(&'a job::Job) -> Result<T, Box<dyn Error>>
+) -> Result<(), Box<dyn Error>> {
+    let names = jobs.iter().map(|j| j.name.as_str()).collect::<HashSet<_>>();
+    let mut pending = jobs.to_vec();
+    let mut running: Vec<(&'a job::Job, T)> = vec![];
+    let mut succeeded = HashSet::new();
+    let mut failed = vec![];
+    let mut skipped = vec![];
+    let mut stopping = false;
+    loop {
+        let mut progressed = false;
+        let mut i = 0;
+        while !stopping && i < pending.len() && running.len() < max_jobs {
+            let j = pending[i];
+            let deps = j.depends.iter().flatten()
+                .map(|d| d.resolve())
+                .filter(|d| names.contains(d.as_str()))
+                .collect::<Vec<_>>();
+            if deps.iter().any(|d| failed.contains(d) || skipped.contains(d)) {
+                log::warn!("job '{}' is skipped because its dependency fails", j.name);
+                skipped.push(j.name.clone());
+                pending.remove(i);
+                progressed = true;
+            } else if deps.iter().all(|d| succeeded.contains(d)) {
+                log::info!("start job '{}'", j.name);
+                match spawn(j) {
+                    Ok(t) => running.push((j, t)),
+                    Err(e) => {
+                        log::error!("fail to start job '{}': {}", j.name, e);
+                        failed.push(j.name.clone());
+                        stopping = !keep_going;
+                    }
+                }
+                pending.remove(i);
+                progressed = true;
+            } else {
+                i += 1;
+            }
+        }
+        if running.is_empty() {
+            if stopping || pending.is_empty() {
+                break;
+            } else if !progressed {
+                return escalate!(Box::new(config::ConfigError{
+                    cause: format!(
+                        "dependency of jobs {} cannot be resolved",
+                        pending.iter().map(|j| j.name.as_str()).collect::<Vec<_>>().join(",")
+                    )
+                }));
+            }
+            continue;
+        }
+        sleep(POLL_INTERVAL);
+        let mut i = 0;
+        while i < running.len() {
+            let success = match running[i].1.poll()? {
+                Some(s) => s,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let (j, t) = running.remove(i);
+            t.finish();
+            if success {
+                log::info!("job '{}' finished", j.name);
+                succeeded.insert(j.name.clone());
+            } else {
+                failed.push(j.name.clone());
+                if !keep_going && !stopping && !running.is_empty() {
+                    // like make, running jobs are not killed because processes started by them may be left.
+                    log::warn!(
+                        "waiting for unfinished jobs {}",
+                        running.iter().map(|(j, _)| j.name.as_str()).collect::<Vec<_>>().join(",")
+                    );
+                }
+                stopping = stopping || !keep_going;
+            }
+        }
+    }
+    let canceled = pending.iter().map(|j| j.name.as_str()).collect::<Vec<_>>();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    let mut cause = format!("job(s) {} failed", failed.join(","));
+    if !skipped.is_empty() {
+        cause.push_str(&format!(", {} skipped", skipped.join(",")));
+    }
+    if !canceled.is_empty() {
+        cause.push_str(&format!(", {} canceled", canceled.join(",")));
+    }
+    escalate!(Box::new(config::ConfigError{ cause }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // task that finishes after polled specified times
+    struct FakeTask {
+        name: String,
+        polls: u32,
+        success: bool,
+        events: Rc<RefCell<Vec<String>>>,
+    }
+    impl Task for FakeTask {
+        fn poll(&mut self) -> Result<Option<bool>, Box<dyn Error>> {
+            if self.polls > 0 {
+                self.polls -= 1;
+                return Ok(None);
+            }
+            Ok(Some(self.success))
+        }
+        fn finish(self) {
+            self.events.borrow_mut().push(format!("end {}", self.name));
+        }
+    }
+
+    fn jobs(defs: &[(&str, &[&str])]) -> Vec<job::Job> {
+        let container = config::Config::with(None).unwrap();
+        let config = container.borrow();
+        let base = config.jobs.as_map().values().next().expect("default config should have job").clone();
+        defs.iter().map(|(name, depends)| {
+            let mut j = base.clone();
+            j.name = name.to_string();
+            j.depends = Some(depends.iter().map(|d| config::Value::new(d)).collect());
+            j
+        }).collect()
+    }
+    fn run(
+        jobs: &[job::Job], max_jobs: usize, keep_going: bool, failures: &[&str]
+    ) -> (Result<(), String>, Vec<String>) {
+        let events = Rc::new(RefCell::new(vec![]));
+        let refs = jobs.iter().collect::<Vec<_>>();
+        let result = schedule(&refs, max_jobs, keep_going, |j| {
+            events.borrow_mut().push(format!("start {}", j.name));
+            Ok(FakeTask {
+                name: j.name.clone(), polls: 2, success: !failures.contains(&j.name.as_str()), events: events.clone()
+            })
+        }).map_err(|e| e.to_string());
+        let events = events.borrow().clone();
+        (result, events)
+    }
+
+    #[test]
+    fn schedule_jobs_by_dependency() {
+        let jobs = jobs(&[("a", &[]), ("b", &[]), ("c", &["a", "b"]), ("d", &["c", "not_scheduled"])]);
+        let (result, events) = run(&jobs, 2, false, &[]);
+        assert!(result.is_ok());
+        // independent jobs run concurrently, and dependent job starts after all of its dependencies finish
+        assert_eq!(events, vec!["start a", "start b", "end a", "end b", "start c", "end c", "start d", "end d"]);
+        // number of concurrent jobs is limited
+        let (_, events) = run(&jobs, 1, false, &[]);
+        assert_eq!(events[..4], ["start a", "end a", "start b", "end b"]);
+    }
+
+    #[test]
+    fn schedule_stops_at_failure() {
+        let jobs = jobs(&[("a", &[]), ("b", &[]), ("c", &["a"])]);
+        // not started jobs are canceled
+        let (result, events) = run(&jobs, 1, false, &["a"]);
+        let err = result.unwrap_err();
+        assert!(err.contains("job(s) a failed, b,c canceled"), "{}", err);
+        assert_eq!(events, vec!["start a", "end a"]);
+        // with keep_going, only jobs depending on failed job are skipped
+        let (result, events) = run(&jobs, 1, true, &["a"]);
+        let err = result.unwrap_err();
+        assert!(err.contains("job(s) a failed, c skipped"), "{}", err);
+        assert_eq!(events, vec!["start a", "end a", "start b", "end b"]);
+        // dependent of skipped job is also skipped
+        let jobs = self::jobs(&[("a", &[]), ("b", &["a"]), ("c", &["b"])]);
+        let (result, _) = run(&jobs, 2, true, &["a"]);
+        assert!(result.unwrap_err().contains("job(s) a failed, b,c skipped"));
+    }
+
+    #[test]
+    fn schedule_rejects_unresolvable_dependency() {
+        let jobs = jobs(&[("a", &[]), ("b", &["c"]), ("c", &["b"])]);
+        let (result, events) = run(&jobs, 2, false, &[]);
+        let err = result.unwrap_err();
+        assert!(err.contains("dependency of jobs b,c cannot be resolved"), "{}", err);
+        assert_eq!(events, vec!["start a", "end a"]);
+    }
+}
@@ -1,6 +1,10 @@
+use std::error::Error;
+
+use glob::Pattern;
 use serde::{Deserialize, Serialize};
 
 use crate::config;
+use crate::util::escalate;
 
 #[derive(Serialize, Deserialize)]
 pub struct ReleaseTarget {
@@ -17,4 +21,18 @@ impl ReleaseTarget {
     pub fn is_tag(&self) -> bool {
         return self.tag.unwrap_or(false)
     }
+    pub fn matches(&self, ref_name: &str) -> bool {
+        // patterns are checked by validate on loading
+        self.patterns.iter().any(|p| Pattern::new(&p.resolve()).is_ok_and(|p| p.matches(ref_name)))
+    }
+    pub fn validate(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        for p in &self.patterns {
+            if let Err(e) = Pattern::new(&p.resolve()) {
+                return escalate!(Box::new(config::ConfigError{
+                    cause: format!("release target '{}' has invalid pattern '{}': {}", name, p, e)
+                }));
+            }
+        }
+        Ok(())
+    }
 }
@@ -9,7 +9,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::args::{Args};
 use crate::config;
-use crate::util::{merge_hashmap, find_repository_root};
+use crate::util::{escalate, merge_hashmap, find_repository_root};
 
 /// remote execution payload
 #[derive(Deserialize)]
@@ -96,8 +96,20 @@ pub struct ExecOptions {
     pub follow_dependency: bool,
     pub silent: bool,
     pub timeout: Option<u64>,
+    /// max number of jobs that run concurrently on local machine
+    #[serde(default = "ExecOptions::default_jobs")]
+    pub jobs: usize,
+    /// if true, continue to run jobs that do not depend on failed job. otherwise stop all jobs at first failure
+    #[serde(default)]
+    pub keep_going: bool,
+    /// if set, output of each job that runs concurrently is written to $log_dir/$job_name.log
+    #[serde(default)]
+    pub log_dir: Option<String>,
 }
 impl ExecOptions {
+    fn default_jobs() -> usize {
+        1
+    }
     pub fn default() -> Self {
         Self {
             envs: hashmap!{},
@@ -110,6 +122,9 @@ impl ExecOptions {
             follow_dependency: false,
             silent: false,
             timeout: None,
+            jobs: Self::default_jobs(),
+            keep_going: false,
+            log_dir: None,
         }
     }
     pub fn new<A: Args>(args: &A, config: &config::Container, has_job_config: bool) -> Result<Self, Box<dyn Error>> {
@@ -119,13 +134,15 @@ impl ExecOptions {
         // on remote running on CI, verbosity should configured with same value as cli specified,
         // via envvar DEPLO_OVERWRITE_EXEC_OPTIONS(JSON)'s verbosity.
         instance.verbosity = config.borrow().runtime.verbosity;
-        instance.apply(args, config, has_job_config);
+        instance.apply(args, config, has_job_config)?;
         Ok(instance)
     }
     pub fn with_json(json: &str) -> Self {
         serde_json::from_str(json).expect(&format!("exec options should be valid json but {}", json))
     }
-    pub fn apply<A: Args>(&mut self, args: &A, config: &config::Container, has_job_config: bool) {
+    pub fn apply<A: Args>(
+        &mut self, args: &A, config: &config::Container, has_job_config: bool
+    ) -> Result<(), Box<dyn Error>> {
         // merge parameters from command line args, basically value does not change if cli arg not specified.
         self.envs = merge_hashmap(&self.envs, &args.map_of("env"));
         self.revision = match args.value_of("revision") {
@@ -175,6 +192,21 @@ impl ExecOptions {
             // so we set the option false if it does not has job config.
         };
         self.silent = args.get_flag("silent");
+        self.jobs = match args.value_of("jobs") {
+            Some(v) => match v.parse::<usize>() {
+                Ok(n) if n >= 1 => n,
+                _ => return escalate!(Box::new(config::ConfigError{
+                    cause: format!("value of `jobs` should be a positive number but {}", v)
+                }))
+            },
+            None => self.jobs
+        };
+        self.keep_going = args.get_flag("keep_going");
+        self.log_dir = match args.value_of("log_dir") {
+            Some(v) => Some(v.to_string()),
+            None => self.log_dir.clone()
+        };
+        Ok(())
     }
     pub fn debug_should_start(&self, job: &str, job_failure: bool) -> bool {
         if self.debug.should_start(job_failure) {
@@ -224,20 +256,23 @@ pub struct Job {
 impl Job {
     pub fn new<A: Args>(
         args: &A, config: &config::Container
-    ) -> Self {
-        let name = args.value_of("job").map(|v| v.to_string()).expect("job name should be specified");
-        let command = Command::new_or_none(
-            args,
-            config.borrow().jobs.find(&name).expect(&format!("config for job {} does not exist", name))
-        );
-        Self { name, command }
+    ) -> Result<Self, Box<dyn Error>> {
+        let selector = match args.value_of("job") {
+            Some(v) => v,
+            None => return escalate!(Box::new(config::ConfigError{
+                cause: "job name should be specified".to_string()
+            }))
+        };
+        let config = config.borrow();
+        let job = Self::find(&config, selector)?;
+        let command = Command::new_or_none(args, job);
+        Ok(Self { name: job.name.clone(), command })
     }
     pub fn apply<A: Args>(
         &mut self, args: &A, config: &config::Container
-    ) {
-        match args.value_of("job").map(|v| v.to_string()) {
-            Some(v) => self.name = v,
-            None => {},
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(v) = args.value_of("job") {
+            self.name = Self::find(&config.borrow(), v)?.name.clone();
         }
         match config.borrow().jobs.find(&self.name) {
             Some(j) => match Command::new_or_none(args, j) {
@@ -246,6 +281,16 @@ impl Job {
             },
             None => {}
         };
+        Ok(())
+    }
+    // job of matrix can be specified like job[key=value,...]
+    fn find<'a>(config: &'a config::Config, selector: &str) -> Result<&'a config::job::Job, Box<dyn Error>> {
+        match config.jobs.find_by_selector(selector) {
+            Ok(j) => Ok(j),
+            Err(e) => escalate!(Box::new(config::ConfigError{
+                cause: format!("invalid job {}: {}", selector, e)
+            }))
+        }
     }
 }
 
@@ -269,7 +314,7 @@ impl Workflow {
             // directly specify workflow_name and context
             Some(v) => return Ok(Self {
                 name: v.to_string(),
-                job: if has_job_config { Some(Job::new(args, config)) } else { None },
+                job: if has_job_config { Some(Job::new(args, config)?) } else { None },
                 context: match args.value_of("workflow_context") {
                     Some(v) => match fs::read_to_string(Path::new(v)) {
                         Ok(s) => {
@@ -332,7 +377,7 @@ impl Workflow {
                 }
                 let mut v = matches.remove(0);
                 log::debug!("determined workflow name: {}", v.name);
-                v.apply(args, config, has_job_config);
+                v.apply(args, config, has_job_config)?;
                 Ok(v)
             }
         }
@@ -356,14 +401,17 @@ impl Workflow {
     pub fn with_payload(payload: &str) -> Result<Self, Box<dyn Error>> {
         Ok(serde_json::from_str(payload)?)
     }
-    pub fn apply<A: Args>(&mut self, args: &A, config: &config::Container, has_job_config: bool) {
-        self.exec.apply(args, config, has_job_config);
+    pub fn apply<A: Args>(
+        &mut self, args: &A, config: &config::Container, has_job_config: bool
+    ) -> Result<(), Box<dyn Error>> {
+        self.exec.apply(args, config, has_job_config)?;
         if has_job_config { 
             match self.job.as_mut() {
-                Some(j) => j.apply(args, config),
-                None => self.job = Some(Job::new(args, config))
+                Some(j) => j.apply(args, config)?,
+                None => self.job = Some(Job::new(args, config)?)
             };
         }
+        Ok(())
     }
     pub fn command(&self) -> config::job::Command {
         match &self.job {
@@ -433,6 +481,25 @@ impl Config {
             debug_options: args.map_of("debug")
         }
     }
+    // command line arguments to invoke child deplo process with same runtime configuration.
+    // workdir is not included because child process inherits current directory.
+    pub fn cli_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-c".to_string(), self.config_path.clone(),
+            "-v".to_string(), self.verbosity.to_string()
+        ];
+        if let Some(ref v) = self.dotenv_path {
+            args.extend(["-e".to_string(), v.clone()]);
+        }
+        if let Some(ref v) = self.ci {
+            args.extend(["--ci".to_string(), v.clone()]);
+        }
+        for (k, v) in &self.debug_options {
+            // --debug takes multiple values, so value should be attached to prevent subcommand from taken as value
+            args.push(format!("--debug={}={}", k, v));
+        }
+        args
+    }
     pub fn apply(&self) -> Result<(), Box<dyn Error>> {
         self.load_dotenv()?;
         Self::setup_logger(self.verbosity);
@@ -497,3 +564,95 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cli args given as name => value. flags are given with value "true"
+    struct TestArgs {
+        values: HashMap<&'static str, &'static str>,
+        path: Vec<&'static str>,
+    }
+    impl TestArgs {
+        fn new(values: HashMap<&'static str, &'static str>) -> Self {
+            Self { values, path: vec![] }
+        }
+    }
+    impl Args for TestArgs {
+        fn create() -> Result<Self, Box<dyn Error>> {
+            Ok(Self::new(hashmap!{}))
+        }
+        fn subcommand(&self) -> Option<(&str, Self)> {
+            None
+        }
+        fn values_of(&self, name: &str) -> Option<Vec<&str>> {
+            self.values.get(name).map(|v| vec![*v])
+        }
+        fn command_path(&self) -> &Vec<&str> {
+            &self.path
+        }
+        fn get_flag(&self, name: &str) -> bool {
+            self.values.get(name).is_some_and(|v| *v == "true")
+        }
+        fn value_of(&self, name: &str) -> Option<&str> {
+            self.values.get(name).copied()
+        }
+    }
+
+    #[test]
+    fn exec_options_jobs_test() {
+        let config = config::Config::with(None).unwrap();
+        let options = |jobs: &'static str| ExecOptions::new(
+            &TestArgs::new(hashmap!{ "jobs" => jobs, "release_target" => "nightly" }), &config, false
+        );
+        assert_eq!(options("4").unwrap().jobs, 4);
+        for invalid in ["0", "-1", "many"] {
+            let err = options(invalid).map(|_| ()).unwrap_err().to_string();
+            assert!(err.contains("value of `jobs` should be a positive number"), "{}", err);
+        }
+    }
+    #[test]
+    fn job_selector_test() {
+        let config = config::Config::with(Some(r#"
+version = 1
+project_name = "test"
+[release_targets]
+nightly = { patterns = ["main"] }
+[vcs]
+type = "github"
+account = "foo"
+key = "bar"
+email = "foo@example.com"
+[ci.default]
+type = "ghaction"
+account = "foo"
+key = "bar"
+[workflows]
+integrate = {}
+[jobs.build]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+matrix = { os = ["linux", "macos"] }
+command = "cargo build"
+[jobs.lint]
+on = { workflows = ["integrate"] }
+runner = { os = "linux" }
+command = "cargo clippy"
+"#)).unwrap();
+        config.borrow_mut().jobs.setup().unwrap();
+        let job = |selector: &'static str| Job::new(&TestArgs::new(hashmap!{ "job" => selector }), &config);
+        assert_eq!(job("build[os=macos]").unwrap().name, "build-macos");
+        for (selector, cause) in [("build", "matches multiple jobs"), ("nothing", "invalid job nothing")] {
+            let err = job(selector).map(|_| ()).unwrap_err().to_string();
+            assert!(err.contains(cause), "{}: {}", selector, err);
+        }
+        assert!(Job::new(&TestArgs::new(hashmap!{}), &config).is_err());
+        // invalid selector is reported, instead of being used as job name as it is
+        let mut lint = job("lint").unwrap();
+        assert!(lint.apply(&TestArgs::new(hashmap!{ "job" => "build[os=windows]" }), &config).is_err());
+        assert_eq!(lint.name, "lint");
+        lint.apply(&TestArgs::new(hashmap!{ "job" => "build[os=linux]" }), &config).unwrap();
+        assert_eq!(lint.name, "build-linux");
+    }
+}
@@ -4,11 +4,29 @@ use serde::{Deserialize, Serialize};
 
 use crate::config;
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SigningFormat {
+    #[serde(rename = "ssh")]
+    Ssh,
+    #[serde(rename = "gpg")]
+    Gpg,
+}
+
+/// key to sign the commits that deplo creates (job outputs, squashed commits).
+/// key should not have passphrase.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Signing {
+    pub format: SigningFormat,
+    /// private key itself (not path), usually given as secret like "${DEPLO_SIGNING_KEY}"
+    pub key: config::Value,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct LocalFallback {
     pub account: config::Value,
     pub key: config::Value,
     pub email: config::Value,
+    pub signing: Option<Signing>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -18,19 +36,23 @@ pub enum Account {
     Github {
         email: config::Value,
         account: config::Value,
-        key: config::Value
+        key: config::Value,
+        signing: Option<Signing>
     },
     #[serde(rename = "github_app")]
     GithubApp {
         app_id: config::Value,
         pkey: config::Value,
-        local_fallback: Option<LocalFallback>
+        local_fallback: Option<LocalFallback>,
+        /// create commits through github api, to make them verified as the app
+        verified_commits: Option<bool>
     },
     #[serde(rename = "gitlab")]
     Gitlab {
         email: config::Value,
         account: config::Value,
-        key: config::Value
+        key: config::Value,
+        signing: Option<Signing>
     },
     #[serde(rename = "module")]
     Module(config::module::ConfigFor<crate::vcs::ModuleDescription>)
@@ -1,16 +1,27 @@
 use std::collections::{HashMap};
+use std::error::Error;
 use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
 
 use crate::config;
+use crate::util::escalate;
+use crate::vcs;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct WorkflowExtension {
     pub release_target: Option<String>,
     pub priority: Option<i64>,
     pub inherit_from: Option<Vec<String>>,
+    pub diff_base: Option<vcs::DiffBase>,
+}
+/// settings of builtin workflows (deploy and integrate).
+/// unknown fields are denied so that other kind of workflow does not deserialized as this.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BuiltinWorkflow {
+    pub diff_base: Option<vcs::DiffBase>,
 }
 #[derive(Serialize, Deserialize)]
 pub enum InputValueType {
@@ -119,20 +130,26 @@ impl InputSchemaSet {
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Workflow {
-    Deploy,
-    Integrate,
+    // [workflows.deploy] and [workflows.integrate] are always deserialized as Deploy,
+    // Workflows::setup moves the settings to corresponding workflow.
+    Deploy(BuiltinWorkflow),
+    #[serde(skip_deserializing)]
+    Integrate(BuiltinWorkflow),
     Cron {
         schedules: HashMap<String, config::Value>,
-        priority: Option<i64>
+        priority: Option<i64>,
+        diff_base: Option<vcs::DiffBase>
     },
     Repository {
         events: HashMap<String, Vec<config::Value>>,
-        priority: Option<i64>
+        priority: Option<i64>,
+        diff_base: Option<vcs::DiffBase>
     },
     Dispatch {
         manual: Option<bool>,
         inputs: InputSchemaSet,
-        priority: Option<i64>
+        priority: Option<i64>,
+        diff_base: Option<vcs::DiffBase>
     },
     Module(config::module::ConfigFor<crate::workflow::ModuleDescription, WorkflowExtension>),
 }
@@ -146,6 +163,16 @@ impl Workflow {
             _ => 0,
         }
     }
+    pub fn diff_base(&self) -> Option<vcs::DiffBase> {
+        match self {
+            Self::Deploy(b) => b.diff_base,
+            Self::Integrate(b) => b.diff_base,
+            Self::Cron{diff_base, ..} => *diff_base,
+            Self::Repository{diff_base, ..} => *diff_base,
+            Self::Dispatch{diff_base, ..} => *diff_base,
+            Self::Module(m) => m.ext().diff_base,
+        }
+    }
     pub fn inherits(&self, workflow_name: &str) -> bool {
         // check if workflow matches with the name
         match self {
@@ -183,8 +210,8 @@ impl Workflow {
 impl fmt::Display for Workflow {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Deploy => write!(f, "deploy"),
-            Self::Integrate => write!(f, "integrate"),
+            Self::Deploy(..) => write!(f, "deploy"),
+            Self::Integrate(..) => write!(f, "integrate"),
             Self::Cron{schedules,..} => write!(f, "cron:{:?}", schedules),
             Self::Repository{events,..} => write!(f, "repository:{:?}", events),
             Self::Dispatch{manual,..} => write!(f, "dispatch:{:?}", manual),
@@ -196,41 +223,60 @@ impl fmt::Display for Workflow {
 #[derive(Serialize, Deserialize)]
 pub struct Workflows(HashMap<String, Workflow>);
 impl Workflows {
-    fn insert_or_die(&mut self, name: &str, value: Workflow) {
+    fn insert_or_die(&mut self, name: &str, value: Workflow) -> Result<(), Box<dyn Error>> {
         if self.0.contains_key(name) {
-            panic!("{} is reserved workflow name", name);
+            return escalate!(Box::new(config::ConfigError{
+                cause: format!("{} is reserved workflow name", name)
+            }));
         }
         self.0.insert(name.to_string(), value);
+        Ok(())
     }
-    pub fn setup(&mut self) {
-        self.insert_or_die("deploy", Workflow::Deploy);
-        self.insert_or_die("integrate", Workflow::Integrate);
+    pub fn setup(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut builtin = |name: &str| match self.0.remove(name) {
+            Some(Workflow::Deploy(b)) => Ok(b),
+            Some(_) => escalate!(Box::new(config::ConfigError{
+                cause: format!("{} is reserved workflow name", name)
+            })),
+            None => Ok(BuiltinWorkflow::default())
+        };
+        let (deploy, integrate) = (builtin("deploy")?, builtin("integrate")?);
+        for (name, wf) in &self.0 {
+            if let Workflow::Deploy(..) = wf {
+                return escalate!(Box::new(config::ConfigError{
+                    cause: format!("{} does not have valid workflow settings", name)
+                }));
+            }
+        }
+        self.insert_or_die("deploy", Workflow::Deploy(deploy))?;
+        self.insert_or_die("integrate", Workflow::Integrate(integrate))?;
         self.insert_or_die(config::DEPLO_SYSTEM_WORKFLOW_NAME, Workflow::Dispatch {
-            manual: Some(false), inputs: InputSchemaSet(HashMap::new()), priority: None
-        });
+            manual: Some(false), inputs: InputSchemaSet(HashMap::new()), priority: None, diff_base: None
+        })?;
         let mut cron_found = None;
         let mut repo_found = None;
         for (name, wf) in &self.0 {
             match wf {
-                Workflow::Cron{..} => if cron_found.is_some() {
-                    panic!(
+                Workflow::Cron{..} => if let Some(found) = cron_found {
+                    return escalate!(Box::new(config::ConfigError{ cause: format!(
                         "{} is cron workflow but you cannot define it twice (already have {}) for now",
-                        name, cron_found.unwrap()
-                    );
+                        name, found
+                    )}));
                 } else {
                     cron_found = Some(name);
                 },
-                Workflow::Repository{..} => if repo_found.is_some() {
-                    panic!(
+                Workflow::Repository{..} => if let Some(found) = repo_found {
+                    return escalate!(Box::new(config::ConfigError{ cause: format!(
                         "{} is repository event workflow but you cannot define it twice (already have {}) for now",
-                        name, repo_found.unwrap()
-                    );
+                        name, found
+                    )}));
                 } else {
                     repo_found = Some(name);
                 },
                 _ => {}
             }
         }
+        Ok(())
     }
     pub fn as_map(&self) -> &HashMap<String, Workflow> {
         &self.0
@@ -5,8 +5,8 @@ use std::fmt;
 use std::path::Path;
 
 use maplit::hashmap;
-use serde::{Deserialize, Serialize};
-use serde_json;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{self, Value as JsonValue};
 use toml;
 
 use crate::config;
@@ -189,6 +189,45 @@ impl Module {
             }))
         }
     }
+    // invoke method of ci/vcs entrypoint. request is passed as json via DEPLO_MODULE_REQUEST,
+    // and entrypoint should print {"result": ...} or {"error": "..."} to stdout. see docs/module.md
+    pub fn call<'a,E,S,K,R>(
+        &'a self, ep: EntryPointType, shell: &S, method: &str, request: &JsonValue,
+        envs: E, option: &Option<HashMap<String, config::AnyValue>>
+    ) -> Result<R, Box<dyn Error>>
+    where
+        E: IntoIterator<Item = (K, shell::Arg<'a>)>,
+        S: shell::Shell,
+        K: AsRef<OsStr>,
+        R: DeserializeOwned
+    {
+        let mut envs = envs.into_iter().map(
+            |(k, v)| (k.as_ref().to_string_lossy().to_string(), v)
+        ).collect::<HashMap<String, shell::Arg<'a>>>();
+        // request may contain secrets (eg. set_secret), so mask it and keep it out of process arguments
+        envs.insert("DEPLO_MODULE_REQUEST".to_string(), shell::protected_arg!(&serde_json::to_string(request)?));
+        let ep_name = ep.to_string();
+        let output = self.run(
+            ep, shell, &shell::capture(), shell::args![method.to_string()], envs, option
+        )?;
+        let response = match serde_json::from_str::<Response>(&output) {
+            Ok(r) => r,
+            Err(e) => return escalate!(Box::new(ModuleError{
+                cause: format!("module {} returns invalid response for {}.{}: {} ({})", self.name, ep_name, method, output, e)
+            }))
+        };
+        match response.error {
+            Some(cause) => escalate!(Box::new(ModuleError{
+                cause: format!("module {} fails to process {}.{}: {}", self.name, ep_name, method, cause)
+            })),
+            None => Ok(serde_json::from_value::<R>(response.result.unwrap_or(JsonValue::Null))?)
+        }
+    }
+}
+#[derive(Deserialize)]
+struct Response {
+    result: Option<JsonValue>,
+    error: Option<String>
 }
 
 pub fn empty_env<'a>() -> HashMap<String, shell::Arg<'a>> {
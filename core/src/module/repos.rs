@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
+use std::path::Path;
 
 use maplit::hashmap;
 use regex::Regex;
@@ -36,15 +37,15 @@ impl<S: shell::Shell> Repository<S> {
         self.get(&key)
     }
     pub fn load(
-        &mut self, config: &config::Config, src: &module::Source
+        &mut self, config: &config::Config, module_root: &Path, src: &module::Source
     ) -> Result<String, Box<dyn Error>> {
-        self.mods.insert(src.to_string(), Self::fetch(config, src, &self.shell)?);
+        self.mods.insert(src.to_string(), Self::fetch(config, module_root, src, &self.shell)?);
         Ok(src.to_string())
     }
     fn fetch(
-        config: &config::Config, src: &module::Source, shell: &S
+        config: &config::Config, module_root: &Path, src: &module::Source, shell: &S
     ) -> Result<module::Module, Box<dyn Error>> {
-        let mut module_path = config.deplo_module_root_path()?;
+        let mut module_path = module_root.to_path_buf();
         let (url, ver) = match src {
             module::Source::Std(name) => {
                 let re = Regex::new(r"([^/@]+)/([^/@]+)@([^/@]+)").unwrap();
@@ -5,6 +5,7 @@ use std::convert::AsRef;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::path;
+use std::time::Duration;
 
 use glob::glob;
 use maplit::hashmap;
@@ -14,6 +15,8 @@ use crate::config;
 use crate::util::{escalate,make_absolute,docker_mount_path,path_join,join_vector};
 
 pub mod native;
+#[cfg(test)]
+pub mod mock;
 
 pub trait ArgTrait {
     fn value(&self) -> String;
@@ -155,7 +158,8 @@ pub struct Settings {
     interactive: bool,
     silent: bool,
     env_inherit: bool,
-    paths: Option<Vec<String>>
+    paths: Option<Vec<String>>,
+    timeout: Option<Duration>
 }
 impl Settings {
     pub fn paths(&mut self, paths: Vec<String>) -> &mut Self {
@@ -173,6 +177,11 @@ impl Settings {
         self.env_inherit = false;
         self
     }
+    // command is killed if it does not finish in timeout
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
 }
 
 #[derive(Eq, PartialEq, Hash)]
@@ -459,6 +468,10 @@ pub enum ShellError {
         cmd: String,
         cause: String
     },
+    Timeout {
+        cmd: String,
+        timeout: Duration
+    },
 }
 impl fmt::Display for ShellError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -466,7 +479,8 @@ impl fmt::Display for ShellError {
             Self::ExitStatus { status, stderr, cmd } => {
                 write!(f, "cmd:{}, {}, stedrr:{}", cmd, status, stderr)
             },
-            Self::OtherFailure { cmd, cause } => write!(f, "cmd:{}, err:{}", cmd, cause)
+            Self::OtherFailure { cmd, cause } => write!(f, "cmd:{}, err:{}", cmd, cause),
+            Self::Timeout { cmd, timeout } => write!(f, "cmd:{}, timeout after {:?}", cmd, timeout)
         }
     }
 }
@@ -508,19 +522,19 @@ pub const ENV_INHERIT_DEFAULT: bool = true;
 pub const ENV_INHERIT_DEFAULT: bool = false;
 
 pub fn capture() -> Settings {
-    return Settings{ capture: true, interactive: false, silent: false, env_inherit: ENV_INHERIT_DEFAULT, paths: None };
+    return Settings{ capture: true, interactive: false, silent: false, env_inherit: ENV_INHERIT_DEFAULT, paths: None, timeout: None };
 }
 pub fn no_capture() -> Settings {
-    return Settings{ capture: false, interactive: false, silent: false, env_inherit: ENV_INHERIT_DEFAULT, paths: None };
+    return Settings{ capture: false, interactive: false, silent: false, env_inherit: ENV_INHERIT_DEFAULT, paths: None, timeout: None };
 }
 pub fn capture_inherit() -> Settings {
-    return Settings{ capture: true, interactive: false, silent: false, env_inherit: true, paths: None };
+    return Settings{ capture: true, interactive: false, silent: false, env_inherit: true, paths: None, timeout: None };
 }
 pub fn interactive() -> Settings {
-    return Settings{ capture: false, interactive: true, silent: false, env_inherit: ENV_INHERIT_DEFAULT, paths: None };
+    return Settings{ capture: false, interactive: true, silent: false, env_inherit: ENV_INHERIT_DEFAULT, paths: None, timeout: None };
 }
 pub fn silent() -> Settings {
-    return Settings{ capture: true, interactive: false, silent: true, env_inherit: ENV_INHERIT_DEFAULT, paths: None };
+    return Settings{ capture: true, interactive: false, silent: true, env_inherit: ENV_INHERIT_DEFAULT, paths: None, timeout: None };
 }
 
 pub fn sheban_of<'a>(script: &'a str, fallback: &'a str) -> &'a str {
@@ -549,4 +563,27 @@ mod tests {
             "cat", "/tmp/large-text.json"
         ), crate::shell::no_env(), crate::shell::no_cwd(), &crate::shell::capture()).unwrap();
     }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn timeout_kills_process_group_test() {
+        let shell = crate::shell::new_default(&crate::config::Config::with(None).unwrap());
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let mut settings = crate::shell::capture();
+        settings.timeout(Some(std::time::Duration::from_secs(1)));
+        // background process is a grandchild, which is not killed by killing the direct child only
+        let started = std::time::Instant::now();
+        let r = shell.exec(args!(
+            "sh", "-c", format!("sleep 100 & echo $! > {}; wait", pid_file.display())
+        ), crate::shell::no_env(), crate::shell::no_cwd(), &settings);
+        assert!(matches!(r, Err(ShellError::Timeout{..})));
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // killed process may remain as zombie until it is reaped by init
+        let alive = match std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+            Ok(stat) => !stat.contains(") Z "),
+            Err(_) => false
+        };
+        assert!(!alive, "grandchild process {} should be killed", pid.trim());
+    }
 }
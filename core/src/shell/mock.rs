@@ -0,0 +1,143 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::convert::AsRef;
+
+use maplit::hashmap;
+
+use crate::config;
+use crate::shell;
+
+type Handler = Box<dyn Fn(&[String], &HashMap<String, String>) -> Result<String, shell::ShellError>>;
+
+// shell implementation for tests. records executed commands and returns output of the handler
+pub struct Mock {
+    pub config: config::Container,
+    pub commands: RefCell<Vec<Vec<String>>>,
+    handler: Handler,
+}
+impl Mock {
+    pub fn with<F>(config: &config::Container, handler: F) -> Self
+    where F: Fn(&[String], &HashMap<String, String>) -> Result<String, shell::ShellError> + 'static {
+        Self { config: config.clone(), commands: RefCell::new(vec![]), handler: Box::new(handler) }
+    }
+    // commands that contain all of the words
+    pub fn executed(&self, words: &[&str]) -> Vec<Vec<String>> {
+        self.commands.borrow().iter().filter(
+            |c| words.iter().all(|w| c.iter().any(|a| a == w))
+        ).cloned().collect()
+    }
+    fn run<'b, I, J, K>(&self, args: I, envs: J) -> Result<String, shell::ShellError>
+    where
+        I: IntoIterator<Item = shell::Arg<'b>>,
+        J: IntoIterator<Item = (K, shell::Arg<'b>)>,
+        K: AsRef<OsStr>
+    {
+        let args = args.into_iter().map(|a| a.value()).collect::<Vec<_>>();
+        let envs = envs.into_iter().map(
+            |(k, v)| (k.as_ref().to_string_lossy().to_string(), v.value())
+        ).collect::<HashMap<_, _>>();
+        self.commands.borrow_mut().push(args.clone());
+        (self.handler)(&args, &envs)
+    }
+}
+impl shell::Shell for Mock {
+    fn new(config: &config::Container) -> Self {
+        Self::with(config, |args, _| Err(shell::ShellError::OtherFailure{
+            cmd: args.join(" "), cause: "no handler for mock shell".to_string()
+        }))
+    }
+    fn set_cwd<P: shell::ArgTrait>(&mut self, _dir: &Option<P>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn set_env(&mut self, _key: &str, _val: String) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+    fn config(&self) -> &config::Container {
+        &self.config
+    }
+    fn output_of<'b, I, J, K, P>(
+        &self, args: I, envs: J, _cwd: &Option<P>
+    ) -> Result<String, shell::ShellError>
+    where
+        I: IntoIterator<Item = shell::Arg<'b>>,
+        J: IntoIterator<Item = (K, shell::Arg<'b>)>,
+        K: AsRef<OsStr>, P: shell::ArgTrait
+    {
+        self.run(args, envs)
+    }
+    fn exec<'b, I, J, K, P>(
+        &self, args: I, envs: J, _cwd: &Option<P>, _settings: &shell::Settings
+    ) -> Result<String, shell::ShellError>
+    where
+        I: IntoIterator<Item = shell::Arg<'b>>,
+        J: IntoIterator<Item = (K, shell::Arg<'b>)>,
+        K: AsRef<OsStr>, P: shell::ArgTrait
+    {
+        self.run(args, envs)
+    }
+}
+
+// handler that emulates curl invoked by api clients.
+// responder receives method, url and request body, and returns status, response headers and body.
+pub fn curl<F>(responder: F) -> impl Fn(&[String], &HashMap<String, String>) -> Result<String, shell::ShellError>
+where F: Fn(&str, &str, Option<String>) -> (u16, HashMap<String, String>, String) {
+    move |args, _| {
+        let value_of = |flag: &str| args.iter().position(|a| a == flag).map(|i| args[i + 1].clone());
+        let method = value_of("-X").unwrap_or_else(|| "GET".to_string());
+        let body = value_of("--data-binary").and_then(|v| std::fs::read_to_string(v.trim_start_matches('@')).ok());
+        let url = match url_of(args) {
+            Some(v) => v,
+            None => return Err(shell::ShellError::OtherFailure{
+                cmd: args.join(" "), cause: "no url given to curl".to_string()
+            })
+        };
+        let (status, headers, response) = responder(&method, url, body);
+        if let Some(path) = value_of("-D") {
+            let dump = headers.iter().map(|(k, v)| format!("{}: {}\r\n", k, v)).collect::<String>();
+            std::fs::write(path, format!("HTTP/1.1 {}\r\n{}\r\n", status, dump)).unwrap();
+        }
+        if let Some(path) = value_of("-o") {
+            std::fs::write(path, response).unwrap();
+        }
+        Ok(status.to_string())
+    }
+}
+// curl options that take a value
+const CURL_VALUE_OPTIONS: &[&str] = &[
+    "-X", "-H", "-D", "-o", "-w", "-u", "-d", "-F", "-T",
+    "--data", "--data-binary", "--form", "--header", "--output", "--upload-file", "--user", "--write-out"
+];
+// first argument that is neither an option nor its value
+fn url_of(args: &[String]) -> Option<&str> {
+    let mut rest = args.iter().skip(1);
+    while let Some(a) = rest.next() {
+        if CURL_VALUE_OPTIONS.contains(&a.as_str()) {
+            rest.next();
+        } else if !a.starts_with('-') {
+            return Some(a);
+        }
+    }
+    None
+}
+pub fn no_headers() -> HashMap<String, String> {
+    hashmap!{}
+}
+
+mod tests {
+    #[test]
+    fn curl_url_test() {
+        let args = |a: &[&str]| a.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let url = "https://example.com/api";
+        for a in [
+            args(&["curl", "-sS", "-X", "POST", url, "-H", "@headers"]),
+            args(&["curl", "-sS", "-f", "-u", "token:", url]),
+            args(&["curl", "-sSL", url, "-o", "out", "-w", "%{http_code}"]),
+            args(&["curl", "-H", "@headers", "-D", "dump", "--data-binary", "@body", url]),
+        ] {
+            assert_eq!(super::url_of(&a), Some(url), "{:?}", a);
+        }
+        assert_eq!(super::url_of(&args(&["curl", "-sS", "-X", "GET"])), None);
+    }
+}
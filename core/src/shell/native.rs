@@ -1,4 +1,4 @@
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Output, Stdio};
 use std::collections::HashMap;
 use std::borrow::Cow;
 use std::error::Error;
@@ -7,6 +7,8 @@ use std::path::{Path};
 use std::ffi::OsStr;
 use std::fs::File;
 use std::convert::AsRef;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use maplit::hashmap;
 use tempfile::tempfile;
@@ -80,16 +82,17 @@ impl<'a> shell::Shell for Native {
         if !settings.interactive && settings.silent {
             // regardless for the value of `capture`, always capture value
             let mut adjusted_settings = shell::capture();
+            adjusted_settings.timeout(settings.timeout);
             let (mut cmd, mut ct, cmdstr) = self.create_command(
                 args, envs, cwd, match &settings.paths {
                     Some(paths) => adjusted_settings.paths(paths.clone()),
                     None => &mut adjusted_settings
                 }
             );
-            return Native::run_as_child(&mut cmd, &mut ct, cmdstr);
+            return Native::run_as_child(&mut cmd, &mut ct, cmdstr, settings.timeout);
         } else {
             let (mut cmd, mut ct, cmdstr) = self.create_command(args, envs, cwd, settings);
-            return Native::run_as_child(&mut cmd, &mut ct, cmdstr);
+            return Native::run_as_child(&mut cmd, &mut ct, cmdstr, settings.timeout);
         }
     }
 }
@@ -134,6 +137,14 @@ impl Native {
             c.env_clear();
         }
         c.args(&raw_args[1..]);
+        // command with timeout runs as a leader of new process group,
+        // so that processes spawned by the command are also killed on timeout.
+        // (as a side effect, it does not receive signals from terminal like Ctrl+C)
+        #[cfg(unix)]
+        if settings.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            c.process_group(0);
+        }
         c.envs(&self.envs);
         c.envs(envs_map.iter().map(|(k,v)| (k, v.value())));
         let cwd_used = match cwd {
@@ -158,7 +169,9 @@ impl Native {
         let ct = if settings.capture {
             // windows std::process::Command does not work well with huge (>1kb) output piping.
             // see https://github.com/rust-lang/rust/issues/45572 for detail
-            if cfg!(windows) {
+            // also if timeout is set, process is polled instead of wait_with_output, which reads pipes.
+            // so output should go to temp file, otherwise the process may block by filled pipe.
+            if cfg!(windows) || settings.timeout.is_some() {
                 let v = CaptureTarget {
                     stdout: tempfile().unwrap(),
                     stderr: tempfile().unwrap(),
@@ -246,10 +259,56 @@ impl Native {
             })
         }
     }
-    fn run_as_child(cmd: &mut Command, ct: &mut Option<CaptureTarget>, cmdstr: String) -> Result<String,shell::ShellError> {
+    // kill the process group that child leads. see create_command
+    #[cfg(unix)]
+    fn kill_child(process: &mut Child) -> Result<(), std::io::Error> {
+        if unsafe { libc::killpg(process.id() as libc::pid_t, libc::SIGKILL) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    fn kill_child(process: &mut Child) -> Result<(), std::io::Error> {
+        process.kill()
+    }
+    fn wait_child(mut process: Child, timeout: Option<Duration>, cmdstr: &str) -> Result<Output, shell::ShellError> {
+        let timeout = match timeout {
+            Some(t) => t,
+            None => return process.wait_with_output().map_err(|err| shell::ShellError::OtherFailure{
+                cause: format!("wait process error {:?}", err),
+                cmd: cmdstr.to_string()
+            })
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            match process.try_wait() {
+                Ok(Some(_)) => return process.wait_with_output().map_err(|err| shell::ShellError::OtherFailure{
+                    cause: format!("wait process error {:?}", err),
+                    cmd: cmdstr.to_string()
+                }),
+                Ok(None) => if Instant::now() >= deadline {
+                    log::error!("command timeout after {:?}, kill it: {}", timeout, cmdstr);
+                    if let Err(err) = Self::kill_child(&mut process) {
+                        log::error!("fail to kill process {}: {:?}", process.id(), err);
+                    }
+                    let _ = process.wait();
+                    return Err(shell::ShellError::Timeout{ cmd: cmdstr.to_string(), timeout });
+                } else {
+                    sleep(Duration::from_millis(100));
+                },
+                Err(err) => return Err(shell::ShellError::OtherFailure{
+                    cause: format!("wait process error {:?}", err),
+                    cmd: cmdstr.to_string()
+                })
+            }
+        }
+    }
+    fn run_as_child(
+        cmd: &mut Command, ct: &mut Option<CaptureTarget>, cmdstr: String, timeout: Option<Duration>
+    ) -> Result<String,shell::ShellError> {
         match cmd.spawn() {
             Ok(process) => {
-                match process.wait_with_output() { 
+                match Self::wait_child(process, timeout, &cmdstr) { 
                     Ok(output) => {
                         if output.status.success() {
                             match ct {
@@ -284,12 +343,22 @@ impl Native {
                                 }
                             }
                         } else {
-                            let stderr = match String::from_utf8(output.stderr) {
-                                Ok(s) => s,
-                                Err(err) => return Err(shell::ShellError::OtherFailure{
-                                    cause: format!("read stdout error: non printable characters {:?}", err),
-                                    cmd: cmdstr
-                                })
+                            let stderr = match ct {
+                                Some(v) => {
+                                    let mut buf = String::new();
+                                    v.read_stderr(&mut buf).map_err(|e| shell::ShellError::OtherFailure{
+                                        cause: format!("cannot read from stderr tempfile error {:?}", e),
+                                        cmd: cmdstr.clone()
+                                    })?;
+                                    buf
+                                },
+                                None => match String::from_utf8(output.stderr) {
+                                    Ok(s) => s,
+                                    Err(err) => return Err(shell::ShellError::OtherFailure{
+                                        cause: format!("read stdout error: non printable characters {:?}", err),
+                                        cmd: cmdstr
+                                    })
+                                }
                             };
                             return match output.status.code() {
                                 Some(_) => {
@@ -313,10 +382,7 @@ impl Native {
                             }
                         }
                     },
-                    Err(err) => Err(shell::ShellError::OtherFailure{
-                        cause: format!("wait process error {:?}", err),
-                        cmd: cmdstr
-                    })
+                    Err(err) => Err(err)
                 }
             },
             Err(err) => Err(shell::ShellError::OtherFailure{
@@ -106,9 +106,21 @@ pub fn envsubst(src: &str) -> String {
     return content.to_string()
 }
 
+// replace {name} in template with values. unknown names are kept as it is.
+// name can contain . and - to refer nested value like {outputs.key}
+pub fn render_template(template: &str, values: &HashMap<&str, String>) -> String {
+    let re = Regex::new(r"\{([a-zA-Z0-9_.\-]+)\}").unwrap();
+    re.replace_all(template, |caps: &Captures| {
+        match values.get(&caps[1]) {
+            Some(v) => v.to_string(),
+            None => caps[0].to_string()
+        }
+    }).to_string()
+}
+
 // serde
 use serde::{Deserialize, Serialize};
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum UnitOrListOf<T> {
     Unit(T),
@@ -427,6 +439,31 @@ pub fn find_repository_root() -> Result<PathBuf, Box<dyn Error>> {
     }
 }
 
+// parse duration like "90s", "30m", "12h", "7d". number without unit is seconds
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, Box<dyn Error>> {
+    let s = s.trim();
+    let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit '{}' in {}", unit, s).into())
+    };
+    match num.parse::<u64>() {
+        Ok(n) => Ok(std::time::Duration::from_secs(n * secs)),
+        Err(_) => Err(format!("invalid duration {}", s).into())
+    }
+}
+
+// hex encoded sha256 of the file content
+pub fn sha256_file(path: &Path) -> Result<String, Box<dyn Error>> {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
 // hashmap utils
 use crc::{Crc, CRC_64_ECMA_182};
 
@@ -741,4 +778,24 @@ mod tests {
             assert_eq!(docker_mount_path(input), expect.to_string());
         }
     }
+
+    #[test]
+    fn parse_duration_test() {
+        assert_eq!(parse_duration("90").unwrap().as_secs(), 90);
+        assert_eq!(parse_duration("30m").unwrap().as_secs(), 30 * 60);
+        assert_eq!(parse_duration("12h").unwrap().as_secs(), 12 * 3600);
+        assert_eq!(parse_duration("7d").unwrap().as_secs(), 7 * 86400);
+        assert!(parse_duration("7w").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+    #[test]
+    fn render_template_test() {
+        let values = hashmap!{
+            "job" => "docs".to_string(), "outputs.version" => "1.2.0".to_string()
+        };
+        assert_eq!(
+            render_template("[{job}] release {outputs.version} {files} { job }", &values),
+            "[docs] release 1.2.0 {files} { job }"
+        );
+    }
 }
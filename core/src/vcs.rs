@@ -1,18 +1,348 @@
 use std::error::Error;
 use std::collections::HashMap;
 use std::fmt;
+use std::fs;
+use std::path::Path;
 
 use glob::Pattern;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::{Value as JsonValue};
 
 use crate::config;
 use crate::module;
 use crate::config::value::Value;
+use crate::util::{self, escalate};
 
+pub struct GitignorePattern {
+    regex: Regex,
+    negated: bool,
+    // pattern ends with '/' only matches directory
+    dir_only: bool,
+}
+impl GitignorePattern {
+    pub fn new(pattern: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = |cause: &str| Box::new(VCSError {
+            cause: format!("invalid gitignore pattern '{}': {}", pattern, cause)
+        });
+        let (negated, p) = match pattern.strip_prefix('!') {
+            Some(p) => (true, p),
+            // \! and \# are literal
+            None => (false, if pattern.starts_with("\\!") || pattern.starts_with("\\#") { &pattern[1..] } else { pattern })
+        };
+        let (dir_only, p) = match p.strip_suffix('/') {
+            Some(p) => (true, p),
+            None => (false, p)
+        };
+        // pattern that contains separator at the beginning or middle is relative to repository root,
+        // otherwise it matches at any level.
+        let anchored = p.contains('/');
+        let p = p.strip_prefix('/').unwrap_or(p);
+        if p.is_empty() {
+            return Err(invalid("empty pattern"));
+        }
+        let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+        let segments = p.split('/').collect::<Vec<&str>>();
+        for (i, segment) in segments.iter().enumerate() {
+            let last = i == segments.len() - 1;
+            if *segment == "**" {
+                // leading and middle ** matches zero or more directories, trailing ** matches everything inside
+                regex.push_str(if last { ".*" } else { "(?:.*/)?" });
+                continue
+            }
+            let mut chars = segment.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '*' => regex.push_str("[^/]*"),
+                    '?' => regex.push_str("[^/]"),
+                    '[' => {
+                        let mut class = String::from("[");
+                        let mut closed = false;
+                        if let Some(c) = chars.next() {
+                            match c {
+                                '!' | '^' => class.push('^'),
+                                ']' => class.push_str("\\]"),
+                                c => class.push_str(&regex::escape(&c.to_string()))
+                            }
+                        }
+                        for c in chars.by_ref() {
+                            if c == ']' {
+                                closed = true;
+                                break
+                            }
+                            // keep range expression like a-z
+                            if c == '-' { class.push('-') } else { class.push_str(&regex::escape(&c.to_string())) }
+                        }
+                        if !closed {
+                            return Err(invalid("unclosed character class"));
+                        }
+                        class.push(']');
+                        regex.push_str(&class);
+                    },
+                    '\\' => match chars.next() {
+                        Some(c) => regex.push_str(&regex::escape(&c.to_string())),
+                        None => return Err(invalid("trailing backslash"))
+                    },
+                    c => regex.push_str(&regex::escape(&c.to_string()))
+                }
+            }
+            if !last {
+                regex.push('/');
+            }
+        }
+        regex.push('$');
+        Ok(Self { regex: Regex::new(&regex).map_err(|e| invalid(&e.to_string()))?, negated, dir_only })
+    }
+    // like gitignore, pattern that matches one of parent directories also matches the file
+    fn matches(&self, path: &str) -> bool {
+        let mut dirs = path.match_indices('/').map(|(i, _)| &path[..i]);
+        (!self.dir_only && self.regex.is_match(path)) || dirs.any(|d| self.regex.is_match(d))
+    }
+}
 pub enum DiffMatcher {
-    Glob(Vec<String>),
-    Regex(Vec<String>)
+    Glob(Vec<Pattern>),
+    Regex(Vec<Regex>),
+    Gitignore(Vec<GitignorePattern>),
+}
+impl DiffMatcher {
+    pub fn new(matcher_type: &str, patterns: &Vec<String>) -> Result<Self, Box<dyn Error>> {
+        match matcher_type {
+            "glob" => Ok(Self::Glob(patterns.iter().map(|p| Pattern::new(p).map_err(|e| Box::new(VCSError {
+                cause: format!("invalid glob pattern '{}': {}", p, e)
+            }) as Box<dyn Error>)).collect::<Result<Vec<_>,_>>()?)),
+            "regex" => Ok(Self::Regex(patterns.iter().map(|p| Regex::new(p).map_err(|e| Box::new(VCSError {
+                cause: format!("invalid regex pattern '{}': {}", p, e)
+            }) as Box<dyn Error>)).collect::<Result<Vec<_>,_>>()?)),
+            "gitignore" => Ok(Self::Gitignore(
+                patterns.iter().map(|p| GitignorePattern::new(p)).collect::<Result<Vec<_>,_>>()?
+            )),
+            others => escalate!(Box::new(VCSError {
+                cause: format!("unsupported diff matcher {}", others)
+            }))
+        }
+    }
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Glob(patterns) => patterns.iter().any(|p| p.matches(path)),
+            Self::Regex(patterns) => patterns.iter().any(|p| p.is_match(path)),
+            // last matched pattern decides whether the path is included or excluded (by negation)
+            Self::Gitignore(patterns) => patterns.iter().rev().find(|p| p.matches(path)).map_or(false, |p| !p.negated)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Debug)]
+pub enum FileStatus {
+    #[serde(rename = "added")]
+    Added,
+    #[serde(rename = "modified")]
+    Modified,
+    #[serde(rename = "deleted")]
+    Deleted,
+    #[serde(rename = "renamed")]
+    Renamed,
+}
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added => write!(f, "added"),
+            Self::Modified => write!(f, "modified"),
+            Self::Deleted => write!(f, "deleted"),
+            Self::Renamed => write!(f, "renamed"),
+        }
+    }
+}
+/// which commit is used as base of diff that decides changed files of workflow
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Debug)]
+pub enum DiffBase {
+    /// merge base with target branch of pull request
+    #[serde(rename = "merge_base")]
+    MergeBase,
+    #[serde(rename = "previous_commit")]
+    PreviousCommit,
+    /// last tag that matches with patterns of the release target
+    #[serde(rename = "last_release_tag")]
+    LastReleaseTag,
+    /// last commit that is recorded as successfully deployed for the release target
+    #[serde(rename = "last_deploy")]
+    LastDeploy,
+}
+impl fmt::Display for DiffBase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MergeBase => write!(f, "merge_base"),
+            Self::PreviousCommit => write!(f, "previous_commit"),
+            Self::LastReleaseTag => write!(f, "last_release_tag"),
+            Self::LastDeploy => write!(f, "last_deploy"),
+        }
+    }
+}
+/// single commit of git log
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    pub hash: String,
+    pub author: String,
+    // committer date in RFC3339 format
+    pub date: String,
+    pub subject: String,
+    pub body: String,
+}
+/// file attached to release
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReleaseAsset {
+    pub name: String,
+    // url for browser
+    pub url: String,
+    pub size: Option<u64>,
+    pub content_type: Option<String>,
+    // url to download the asset with api. None if url can be used for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+}
+/// pull request found by its head branch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PullRequest {
+    // url for browser, which can be passed to merge_pr, close_pr and update_pr
+    pub url: String,
+    pub open: bool,
+}
+/// ref that records last successfully deployed commit of the release target
+pub fn deployed_ref_path(release_target: &str) -> String {
+    format!("refs/deplo/deployed/{}", release_target)
+}
+/// compare tag names by treating numbers in them as numeric value, like v1.9.0 < v1.10.0
+pub fn compare_tag_name(a: &str, b: &str) -> std::cmp::Ordering {
+    let re = regex::Regex::new(r"\d+|\D+").unwrap();
+    let (ca, cb) = (
+        re.find_iter(a).map(|m| m.as_str()).collect::<Vec<_>>(),
+        re.find_iter(b).map(|m| m.as_str()).collect::<Vec<_>>()
+    );
+    for (x, y) in ca.iter().zip(cb.iter()) {
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(n), Ok(m)) => n.cmp(&m),
+            _ => x.cmp(y)
+        };
+        if ordering != std::cmp::Ordering::Equal {
+            return ordering;
+        }
+    }
+    ca.len().cmp(&cb.len())
+}
+/// find last tag that matches with the release target from output of GitFeatures::tags.
+/// tags are ordered by compare_tag_name. if current_tag is given, the tag and tags after it are ignored.
+/// returns (hash, ref path).
+pub fn last_release_tag(
+    tags: &Vec<Vec<String>>, target: &config::release_target::ReleaseTarget, current_tag: Option<&str>
+) -> Option<(String, String)> {
+    tags.iter()
+        // skip empty line and peeled entry of annotated tag (refs/tags/v1^{})
+        .filter(|tag| tag.len() >= 2 && !tag[1].ends_with("^{}"))
+        .map(|tag| (tag, tag[1].replace("refs/tags/", "")))
+        .filter(|(_, name)| target.matches(name))
+        .filter(|(_, name)| match current_tag {
+            Some(c) => compare_tag_name(name, c) == std::cmp::Ordering::Less,
+            None => true
+        })
+        .max_by(|(_, a), (_, b)| compare_tag_name(a, b))
+        .map(|(tag, _)| (tag[0].to_string(), tag[1].to_string()))
+}
+/// download release asset and verify it with checksum asset ($name.sha256), if exists
+pub fn download_verified_release_asset(
+    vcs: &dyn VCS, tag: &str, name: &str, path: &str
+) -> Result<(), Box<dyn Error>> {
+    let assets = vcs.list_release_assets((tag, false))?;
+    let asset = match assets.iter().find(|a| a.name == name) {
+        Some(a) => a,
+        None => return escalate!(Box::new(VCSError {
+            cause: format!("release {} does not have asset {}", tag, name)
+        }))
+    };
+    vcs.download_release_asset(asset, path)?;
+    let checksum_name = format!("{}.sha256", name);
+    let checksum_asset = match assets.iter().find(|a| a.name == checksum_name) {
+        Some(a) => a,
+        None => {
+            log::debug!("no checksum asset {}, skip verification", checksum_name);
+            return Ok(());
+        }
+    };
+    let checksum_file = tempfile::NamedTempFile::new()?;
+    vcs.download_release_asset(checksum_asset, &checksum_file.path().to_string_lossy())?;
+    // same format as output of sha256sum: "$hash  $file_name"
+    let expect = fs::read_to_string(checksum_file.path())?
+        .split_whitespace().next().unwrap_or("").to_lowercase();
+    let actual = util::sha256_file(Path::new(path))?;
+    if expect != actual {
+        fs::remove_file(path)?;
+        return escalate!(Box::new(VCSError {
+            cause: format!("checksum of {} mismatch: expect {} but {}", name, expect, actual)
+        }));
+    }
+    log::info!("checksum of {} verified", name);
+    Ok(())
+}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileChange {
+    pub status: FileStatus,
+    pub path: String,
+    // path before rename. only set when status is renamed
+    pub old_path: Option<String>,
+}
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ChangeSet {
+    // if true, every file should be treated as changed (eg. first release tag)
+    pub everything: bool,
+    pub files: Vec<FileChange>,
+}
+impl ChangeSet {
+    pub fn all() -> Self {
+        Self { everything: true, files: vec![] }
+    }
+    // parse output of `git diff --name-status -z`
+    pub fn from_name_status(output: &str) -> Result<Self, Box<dyn Error>> {
+        let mut files = vec![];
+        let mut fields = output.split('\0').filter(|f| !f.is_empty());
+        while let Some(code) = fields.next() {
+            let mut next_path = || fields.next().map(|p| p.to_string()).ok_or_else(|| Box::new(VCSError {
+                cause: format!("diff status {} does not have path: {}", code, output)
+            }));
+            // rename and copy have score like R100 and C075, and followed by old and new path
+            let change = match &code[..1] {
+                "A" => FileChange { status: FileStatus::Added, path: next_path()?, old_path: None },
+                "M" | "T" => FileChange { status: FileStatus::Modified, path: next_path()?, old_path: None },
+                "D" => FileChange { status: FileStatus::Deleted, path: next_path()?, old_path: None },
+                "R" => {
+                    let old_path = next_path()?;
+                    FileChange { status: FileStatus::Renamed, path: next_path()?, old_path: Some(old_path) }
+                },
+                // copied file is new file for the commit
+                "C" => {
+                    next_path()?;
+                    FileChange { status: FileStatus::Added, path: next_path()?, old_path: None }
+                },
+                _ => {
+                    log::debug!("ignore diff status {} for {:?}", code, next_path()?);
+                    continue
+                }
+            };
+            files.push(change);
+        }
+        Ok(Self { everything: false, files })
+    }
+    // paths of files that have the status. if status is None, returns paths of all changed files.
+    // for renamed file, both old and new path are returned.
+    pub fn paths(&self, status: Option<FileStatus>) -> Vec<&str> {
+        let mut paths = vec![];
+        for f in &self.files {
+            if status.map_or(true, |s| s == f.status) {
+                paths.push(f.path.as_str());
+                if let Some(p) = &f.old_path {
+                    paths.push(p.as_str());
+                }
+            }
+        }
+        paths
+    }
 }
 
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
@@ -35,6 +365,22 @@ impl fmt::Display for RefType {
     }
 }
 
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum CommitState {
+    Pending,
+    Success,
+    Failure,
+}
+impl fmt::Display for CommitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::Success => write!(f, "success"),
+            Self::Failure => write!(f, "failure"),
+        }
+    }
+}
+
 pub trait VCS {
     fn new(config: &config::Container) -> Result<Self, Box<dyn Error>> where Self : Sized;
     fn get_token(&self) -> Result<(Value, &str), Box<dyn Error>>;
@@ -66,6 +412,12 @@ pub trait VCS {
     fn search_pr(
         &self, filters: &Vec<String>
     ) -> Result<String, Box<dyn Error>>;
+    // latest pull request from head_branch to base_branch. None if it does not exist or already merged
+    fn find_pr(&self, head_branch: &str, base_branch: &str) -> Result<Option<PullRequest>, Box<dyn Error>>;
+    // update title, body, labels and assignees of the pull request. closed one is reopened
+    fn update_pr(
+        &self, url: &str, title: &str, option: &HashMap<&str, &str>
+    ) -> Result<(), Box<dyn Error>>;
     fn label(
         &self, name: &str, color: Option<&str>
     ) -> Result<(), Box<dyn Error>>;
@@ -77,53 +429,51 @@ pub trait VCS {
     fn release_assets(
         &self, target_ref: (&str, bool), asset_file_path: &str, opts: &JsonValue
     ) -> Result<String, Box<dyn Error>>;
-    fn make_diff(&self) -> Result<String, Box<dyn Error>>;
-    fn init_diff(&mut self, diff: String) -> Result<(), Box<dyn Error>>;
+    fn list_release_assets(&self, target_ref: (&str, bool)) -> Result<Vec<ReleaseAsset>, Box<dyn Error>>;
+    // download asset returned by list_release_assets to the path. authorization is added for private repository
+    fn download_release_asset(&self, asset: &ReleaseAsset, path: &str) -> Result<(), Box<dyn Error>>;
+    fn make_diff(&self, base: Option<&DiffBase>, release_target: Option<&str>) -> Result<ChangeSet, Box<dyn Error>>;
+    // files changed in the range, like "a..b" or "a...b"
+    fn diff_files(&self, expression: &str) -> Result<ChangeSet, Box<dyn Error>>;
+    fn mark_deployed(&self, release_target: &str, commit: &str) -> Result<(), Box<dyn Error>>;
+    // same format as GitFeatures::tags
+    fn tags(&self) -> Result<Vec<Vec<String>>, Box<dyn Error>>;
+    // same format as GitFeatures::branches
+    fn branches(&self) -> Result<Vec<Vec<String>>, Box<dyn Error>>;
+    // head branches of open pull requests, which are created in this repository
+    fn open_pr_branches(&self) -> Result<Vec<String>, Box<dyn Error>>;
+    // commits of the range, like "v1.0.0..v1.1.0", or single commit like "$hash^!". newer commit comes first
+    fn log(&self, expression: &str) -> Result<Vec<LogEntry>, Box<dyn Error>>;
+    // create lightweight tag that points the commit and push it to remote
+    fn push_tag(&self, tag_name: &str, commit: &str) -> Result<(), Box<dyn Error>>;
+    // show status of the commit on pull request and commit page. context identifies each status
+    fn set_commit_status(
+        &self, commit: &str, context: &str, state: CommitState, url: Option<&str>, summary: &str
+    ) -> Result<(), Box<dyn Error>>;
+    fn init_diff(&mut self, diff: ChangeSet) -> Result<(), Box<dyn Error>>;
+    // files that push_diff commits with same patterns and option
+    fn files_to_push(
+        &self, patterns: &[&str], option: &HashMap<&str, &str>
+    ) -> Result<Vec<String>, Box<dyn Error>>;
     fn push_diff(
         &self, remote_branch: &str, msg: &str, patterns: &Vec<&str>, option: &HashMap<&str, &str>
     ) -> Result<bool, Box<dyn Error>>;
-    fn diff<'b>(&'b self) -> &'b Vec<String>;
+    // push a commit that adds files changed by source commit onto remote_branch, without checkout.
+    // option can have "root", "prefix" and "orphan". returns false if there is nothing to push
+    fn push_files(
+        &self, source: &str, remote_branch: &str, option: &HashMap<&str, &str>
+    ) -> Result<bool, Box<dyn Error>>;
+    fn diff<'b>(&'b self) -> &'b ChangeSet;
 
     // derived interfaces
     fn pick_fetched_head(&self) -> Result<(), Box<dyn Error>> { self.pick_ref("FETCH_HEAD") }
     fn checkout_previous(&self) -> Result<(), Box<dyn Error>> { self.checkout("-", None) }
-    fn changed<'b>(&'b self, matcher: &DiffMatcher) -> bool {
-        let difflines = self.diff();
-        if difflines.len() == 1 && difflines[0] == "*" {
-            // this specifal pattern indicates everything changed
+    fn changed<'b>(&'b self, matcher: &DiffMatcher, status: Option<FileStatus>) -> bool {
+        let diff = self.diff();
+        if diff.everything {
             return true;
         }
-        match matcher {
-            DiffMatcher::Glob(patterns) => {
-                for pattern in patterns {
-                    match Pattern::new(pattern){
-                        Ok(gp) => for diff in difflines {
-                            if gp.matches(diff) {
-                                return true
-                            }
-                        },
-                        Err(err) => {
-                            panic!("glob pattern[{}] is invalid expression err:{:?}", pattern, err);
-                        }
-                    }
-                }
-            },
-            DiffMatcher::Regex(patterns) => {
-                for pattern in patterns {
-                    match Regex::new(pattern) {
-                        Ok(re) => for diff in difflines {
-                            if re.is_match(diff) {
-                                return true
-                            }
-                        },
-                        Err(err) => {
-                            panic!("regex pattern[{}] is invalid expression err:{:?}", pattern, err);
-                        }
-                    }
-                }
-            }
-        }
-        false
+        diff.paths(status).iter().any(|p| matcher.matches(p))
     }
 }
 #[derive(Clone)]
@@ -146,10 +496,44 @@ impl Error for VCSError {
         None
     }
 }
+// returned when local commits cannot be rebased onto remote branch without manual merge
+#[derive(Debug)]
+pub struct ConflictError {
+    pub(crate) cause: String
+}
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cause)
+    }
+}
+impl Error for ConflictError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+impl ConflictError {
+    // conflict error may be wrapped by escalate!, so search it through the chain of sources
+    pub fn caused(e: &(dyn Error + 'static)) -> bool {
+        let mut cur = Some(e);
+        while let Some(err) = cur {
+            if err.is::<ConflictError>() {
+                return true;
+            }
+            cur = err.source();
+        }
+        false
+    }
+}
 
 // subcommands
 pub mod git;
 pub mod github;
+pub mod gitlab;
+pub mod changelog;
+pub mod gc;
+mod runner;
+pub mod version;
+pub mod workspace;
 
 // factorys
 fn factory_by<'a, T: VCS + 'a>(
@@ -166,8 +550,82 @@ pub fn factory<'a>(
         config::vcs::Account::Github {..} | config::vcs::Account::GithubApp {..} => {
             return factory_by::<github::Github>(config);
         },
-        _ => return Err(Box::new(VCSError {
-            cause: format!("add factory matching pattern for [{}]", config.borrow().vcs)
-        }))
+        config::vcs::Account::Gitlab {..} => {
+            return factory_by::<gitlab::Gitlab>(config);
+        },
+        config::vcs::Account::Module {..} => {
+            return factory_by::<runner::ModuleRunner>(config);
+        }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gitignore_matcher_test() {
+        let m = |patterns: &[&str]| DiffMatcher::new(
+            "gitignore", &patterns.iter().map(|p| p.to_string()).collect()
+        ).unwrap();
+        let dm = m(&["core/**", "!core/**/*.md"]);
+        assert!(dm.matches("core/src/main.rs"));
+        assert!(!dm.matches("core/README.md"));
+        assert!(!dm.matches("core/docs/a/b.md"));
+        assert!(!dm.matches("cli/src/main.rs"));
+        // unanchored pattern matches at any level, and matches contents of matched directory
+        let dm = m(&["*.toml", "target"]);
+        assert!(dm.matches("Cargo.toml"));
+        assert!(dm.matches("core/Cargo.toml"));
+        assert!(dm.matches("core/target/debug/deplo"));
+        assert!(!dm.matches("core/targets.rs"));
+        // anchored pattern and * does not cross directories
+        let dm = m(&["/src/*.rs", "docs/"]);
+        assert!(dm.matches("src/main.rs"));
+        assert!(!dm.matches("src/bin/main.rs"));
+        assert!(!dm.matches("core/src/main.rs"));
+        assert!(dm.matches("docs/module.md"));
+        assert!(!dm.matches("docs"));
+        // ** in the middle matches zero or more directories
+        let dm = m(&["a/**/b", "v[0-9].?", "\\!important"]);
+        assert!(dm.matches("a/b"));
+        assert!(dm.matches("a/x/y/b"));
+        assert!(dm.matches("v1.x"));
+        assert!(!dm.matches("va.x"));
+        assert!(dm.matches("!important"));
+        // later pattern overrides earlier one
+        let dm = m(&["!*.md", "*.md"]);
+        assert!(dm.matches("README.md"));
+
+        assert!(DiffMatcher::new("gitignore", &vec!["src/[a-z".to_string()]).is_err());
+        assert!(DiffMatcher::new("gitignore", &vec!["!".to_string()]).is_err());
+        assert!(DiffMatcher::new("regex", &vec!["(".to_string()]).is_err());
+        assert!(DiffMatcher::new("glob", &vec!["***a".to_string()]).is_err());
+        assert!(DiffMatcher::new("unknown", &vec![]).is_err());
+    }
+    #[test]
+    fn last_release_tag_test() {
+        let target = toml::from_str::<config::release_target::ReleaseTarget>(
+            "tag = true\npatterns = [\"v*\"]"
+        ).unwrap();
+        let tags = vec![
+            vec!["a1".to_string(), "refs/tags/nightly".to_string()],
+            vec!["b1".to_string(), "refs/tags/v1".to_string()],
+            vec!["b2".to_string(), "refs/tags/v1^{}".to_string()],
+            vec!["c1".to_string(), "refs/tags/v2".to_string()],
+            vec!["d1".to_string(), "refs/tags/v3".to_string()],
+            vec![],
+        ];
+        assert_eq!(last_release_tag(&tags, &target, None), Some(("d1".to_string(), "refs/tags/v3".to_string())));
+        assert_eq!(last_release_tag(&tags, &target, Some("v3")), Some(("c1".to_string(), "refs/tags/v2".to_string())));
+        assert_eq!(last_release_tag(&tags, &target, Some("v2")), Some(("b1".to_string(), "refs/tags/v1".to_string())));
+        assert_eq!(last_release_tag(&tags, &target, Some("v1")), None);
+        // numbers in tag are compared as numeric value, though tags are listed in lexicographic order
+        let tags = ["v1.10.0", "v1.11.0", "v1.9.0", "v1.9.1"].iter()
+            .map(|t| vec![t.to_string(), format!("refs/tags/{}", t)]).collect::<Vec<_>>();
+        assert_eq!(last_release_tag(&tags, &target, None).unwrap().0, "v1.11.0");
+        assert_eq!(last_release_tag(&tags, &target, Some("v1.11.0")).unwrap().0, "v1.10.0");
+        assert_eq!(last_release_tag(&tags, &target, Some("v1.10.0")).unwrap().0, "v1.9.1");
+        assert_eq!(compare_tag_name("v1.9", "v1.10"), std::cmp::Ordering::Less);
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use regex::Regex;
+use serde_json::{Value as JsonValue};
+
+use crate::config;
+use crate::config::changelog::GroupBy;
+use crate::util::render_template;
+use crate::vcs;
+use crate::vcs::version;
+
+const DEFAULT_TEMPLATE: &str = "{sections}";
+const DEFAULT_SECTION_TEMPLATE: &str = "## {title}\n{entries}\n";
+const DEFAULT_ENTRY_TEMPLATE: &str = "- {summary} ({link})";
+const DEFAULT_OTHERS_TITLE: &str = "Other Changes";
+const BREAKING_KEY: &str = "breaking";
+
+#[derive(Clone, Debug)]
+pub struct PullRequest {
+    pub number: u64,
+    pub title: String,
+    pub url: String,
+    pub author: String,
+    pub labels: Vec<String>,
+}
+impl PullRequest {
+    // normalize element of VCS::search_pr result.
+    // github returns issue object, gitlab returns merge request object.
+    fn from_json(v: &JsonValue) -> Option<Self> {
+        let number = v.get("number").or(v.get("iid"))?.as_u64()?;
+        let str_of = |v: Option<&JsonValue>| {
+            v.and_then(|v| v.as_str()).unwrap_or("").to_string()
+        };
+        Some(Self {
+            number,
+            title: str_of(v.get("title")),
+            url: str_of(v.get("html_url").or(v.get("web_url"))),
+            author: str_of(v.get("user").or(v.get("author")).and_then(|u| u.get("login").or(u.get("username")))),
+            labels: v.get("labels").and_then(|v| v.as_array()).map_or(vec![], |labels| {
+                labels.iter().filter_map(|l| {
+                    l.as_str().or(l.get("name").and_then(|n| n.as_str())).map(|v| v.to_string())
+                }).collect()
+            })
+        })
+    }
+    fn merged(v: &JsonValue) -> bool {
+        match v.get("pull_request") {
+            // github: closed without merge has null merged_at
+            Some(pr) => pr.get("merged_at").is_some_and(|v| !v.is_null()),
+            None => v.get("state").and_then(|v| v.as_str()) == Some("merged")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub summary: String,
+    pub ty: Option<String>,
+    pub scope: Option<String>,
+    pub breaking: bool,
+    pub hash: String,
+    pub author: String,
+    pub pr: Option<PullRequest>,
+}
+impl Entry {
+    fn new(commit: &vcs::LogEntry, pr: Option<PullRequest>) -> Self {
+        let title = pr.as_ref().map_or(commit.subject.as_str(), |pr| pr.title.as_str());
+        let (ty, scope, breaking, summary) = parse_conventional(title);
+        Self {
+            summary, ty, scope,
+            breaking: breaking || commit.body.contains("BREAKING CHANGE"),
+            hash: commit.hash.clone(),
+            author: pr.as_ref().map_or(commit.author.clone(), |pr| {
+                if pr.author.is_empty() { commit.author.clone() } else { pr.author.clone() }
+            }),
+            pr
+        }
+    }
+    fn keys(&self, group_by: GroupBy) -> Vec<String> {
+        let mut keys = vec![];
+        if self.breaking {
+            keys.push(BREAKING_KEY.to_string());
+        }
+        match group_by {
+            GroupBy::Type => keys.extend(self.ty.iter().cloned()),
+            GroupBy::Label => keys.extend(self.pr.iter().flat_map(|pr| pr.labels.iter().cloned())),
+        }
+        keys
+    }
+    fn render(&self, template: &str) -> String {
+        let short_hash = self.hash.chars().take(7).collect::<String>();
+        let (pr, pr_url) = self.pr.as_ref().map_or(
+            (String::new(), String::new()), |pr| (format!("#{}", pr.number), pr.url.clone())
+        );
+        let link = if pr.is_empty() { short_hash.clone() } else { pr.clone() };
+        render_template(template, &HashMap::from([
+            ("summary", self.summary.clone()),
+            ("type", self.ty.clone().unwrap_or_default()),
+            ("scope", self.scope.clone().unwrap_or_default()),
+            ("hash", self.hash.clone()),
+            ("short_hash", short_hash),
+            ("author", self.author.clone()),
+            ("pr", pr),
+            ("pr_url", pr_url),
+            ("link", link),
+        ]))
+    }
+}
+
+// parse "type(scope)!: summary". returns (type, scope, breaking, summary)
+fn parse_conventional(title: &str) -> (Option<String>, Option<String>, bool, String) {
+    let re = Regex::new(r"^(\w+)(?:\(([^)]*)\))?(!)?:\s*(.+)$").unwrap();
+    // squash merge appends pull request number to the title
+    let title = Regex::new(r"\s*\(#\d+\)$").unwrap().replace(title.trim(), "").to_string();
+    match re.captures(&title) {
+        Some(c) => (
+            Some(c[1].to_lowercase()),
+            c.get(2).map(|m| m.as_str().to_string()),
+            c.get(3).is_some(),
+            c[4].to_string()
+        ),
+        None => (None, None, false, title)
+    }
+}
+
+// number of pull request that the commit merges.
+// "title (#123)" by squash merge, "Merge pull request #123 from ..." by merge commit of github,
+// and "See merge request group/project!123" in body of merge commit of gitlab.
+fn pr_number(commit: &vcs::LogEntry) -> Option<u64> {
+    [
+        (r"\(#(\d+)\)$", &commit.subject),
+        (r"^Merge pull request #(\d+)", &commit.subject),
+        (r"See merge request \S*!(\d+)", &commit.body),
+    ].iter().find_map(|(pattern, text)| {
+        Regex::new(pattern).unwrap().captures(text).and_then(|c| c[1].parse().ok())
+    })
+}
+
+// merge commits that are not created by pull request (e.g. merging base branch) are not listed
+fn is_merge_noise(commit: &vcs::LogEntry) -> bool {
+    commit.subject.starts_with("Merge branch ") || commit.subject.starts_with("Merge remote-tracking branch ")
+}
+
+fn merged_prs(
+    config: &config::Config, commits: &[vcs::LogEntry]
+) -> Result<HashMap<u64, PullRequest>, Box<dyn Error>> {
+    let numbers = commits.iter().filter_map(pr_number).collect::<HashSet<_>>();
+    if numbers.is_empty() {
+        return Ok(HashMap::new());
+    }
+    // pull requests merged in the range are updated after the oldest commit
+    let since = commits.iter().map(|c| c.date.as_str()).min().unwrap_or("");
+    let filters = match config.vcs {
+        config::vcs::Account::Gitlab{..} => vec![
+            "state=merged".to_string(), format!("updated_after={}", since)
+        ],
+        _ => vec![
+            "state=closed".to_string(), format!("since={}", since)
+        ]
+    };
+    let found = serde_json::from_str::<Vec<JsonValue>>(&config.modules.vcs().search_pr(&filters)?)?;
+    Ok(found.iter()
+        .filter(|v| PullRequest::merged(v))
+        .filter_map(PullRequest::from_json)
+        .filter(|pr| numbers.contains(&pr.number))
+        .map(|pr| (pr.number, pr))
+        .collect())
+}
+
+fn default_sections(group_by: GroupBy) -> Vec<(String, Vec<String>)> {
+    let keys = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    match group_by {
+        GroupBy::Type => vec![
+            ("Breaking Changes".to_string(), keys(&[BREAKING_KEY])),
+            ("Features".to_string(), keys(&["feat"])),
+            ("Bug Fixes".to_string(), keys(&["fix"])),
+            ("Performance Improvements".to_string(), keys(&["perf"])),
+        ],
+        GroupBy::Label => vec![
+            ("Breaking Changes".to_string(), keys(&[BREAKING_KEY])),
+            ("Features".to_string(), keys(&["enhancement", "feature"])),
+            ("Bug Fixes".to_string(), keys(&["bug"])),
+        ]
+    }
+}
+
+/// collect changes of the range from..to and group them into sections.
+/// commits that belong to same pull request are listed as single entry.
+pub fn entries(
+    config: &config::Config, from: &str, to: &str
+) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let commits = config.modules.vcs().log(&format!("{}..{}", from, to))?;
+    let prs = match merged_prs(config, &commits) {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("fail to search pull requests, changelog only uses commits: {}", e);
+            HashMap::new()
+        }
+    };
+    let mut listed = HashSet::new();
+    let mut entries = vec![];
+    for commit in &commits {
+        if is_merge_noise(commit) {
+            continue;
+        }
+        let pr = pr_number(commit).and_then(|n| prs.get(&n).cloned());
+        if let Some(pr) = &pr {
+            if !listed.insert(pr.number) {
+                continue;
+            }
+        }
+        entries.push(Entry::new(commit, pr));
+    }
+    Ok(entries)
+}
+
+/// render changelog of the range from..to with [changelog] settings of Deplo.toml
+pub fn generate(config: &config::Config, from: &str, to: &str) -> Result<String, Box<dyn Error>> {
+    render(config.changelog.as_ref(), &entries(config, from, to)?, from, to)
+}
+
+pub fn render(
+    settings: Option<&config::changelog::Changelog>, entries: &[Entry], from: &str, to: &str
+) -> Result<String, Box<dyn Error>> {
+    let template_of = |v: Option<&config::Value>, default: &str| {
+        v.map_or(default.to_string(), |v| v.resolve())
+    };
+    let group_by = settings.and_then(|s| s.group_by).unwrap_or(GroupBy::Type);
+    let mut sections = match settings.and_then(|s| s.sections.as_ref()) {
+        Some(v) => v.iter().map(|s| (s.title.resolve(), s.keys.iter().map(|k| k.resolve()).collect())).collect(),
+        None => default_sections(group_by)
+    };
+    let others = template_of(settings.and_then(|s| s.others.as_ref()), DEFAULT_OTHERS_TITLE);
+    let entry_template = template_of(settings.and_then(|s| s.entry_template.as_ref()), DEFAULT_ENTRY_TEMPLATE);
+    let section_template = template_of(settings.and_then(|s| s.section_template.as_ref()), DEFAULT_SECTION_TEMPLATE);
+    let template = template_of(settings.and_then(|s| s.template.as_ref()), DEFAULT_TEMPLATE);
+    if !others.is_empty() {
+        sections.push((others.clone(), vec![]));
+    }
+    let mut grouped = vec![vec![]; sections.len()];
+    for entry in entries {
+        let keys = entry.keys(group_by);
+        // first section that has one of keys of the entry. otherwise goes to others, if enabled
+        match sections.iter().position(|(_, section_keys)| section_keys.iter().any(|k| keys.contains(k))) {
+            Some(idx) => grouped[idx].push(entry.render(&entry_template)),
+            None => if !others.is_empty() {
+                grouped[sections.len() - 1].push(entry.render(&entry_template))
+            }
+        }
+    }
+    let rendered = sections.iter().zip(grouped.iter())
+        .filter(|(_, lines)| !lines.is_empty())
+        .map(|((title, _), lines)| render_template(&section_template, &HashMap::from([
+            ("title", title.clone()),
+            ("entries", lines.join("\n")),
+        ])))
+        .collect::<Vec<_>>();
+    Ok(render_template(&template, &HashMap::from([
+        ("from", from.to_string()),
+        ("to", to.to_string()),
+        ("sections", rendered.join("\n")),
+    ])))
+}
+
+/// tag that previous release of the tag is made with.
+/// if the tag is version tag, latest version tag older than it (of release target that matches with the tag, if exists).
+/// otherwise uses release target that matches with the tag if exists, otherwise previous tag of the list.
+pub fn previous_tag(config: &config::Config, tag: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let tags = config.modules.vcs().tags()?;
+    let name_of = |ref_path: &str| ref_path.replace("refs/tags/", "");
+    let target = config.release_targets.values().find(|t| t.matches(tag));
+    if let Some(current) = version::Tag::parse(tag) {
+        return Ok(version::previous(&tags, target, &current).map(|t| t.name));
+    }
+    if let Some(target) = target {
+        return Ok(vcs::last_release_tag(&tags, target, Some(tag)).map(|(_, ref_path)| name_of(&ref_path)));
+    }
+    let mut found = None;
+    for t in &tags {
+        if t.len() < 2 || t[1].ends_with("^{}") {
+            continue
+        }
+        let name = name_of(&t[1]);
+        if name == tag {
+            break
+        }
+        found = Some(name);
+    }
+    Ok(found)
+}
+
+/// option key of VCS::release that is used as release note
+pub fn release_body_key(config: &config::Config) -> &'static str {
+    match config.vcs {
+        config::vcs::Account::Gitlab{..} => "description",
+        _ => "body"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(hash: &str, subject: &str, body: &str) -> vcs::LogEntry {
+        vcs::LogEntry {
+            hash: hash.to_string(), author: "dev".to_string(),
+            date: "2024-01-01T00:00:00+00:00".to_string(),
+            subject: subject.to_string(), body: body.to_string()
+        }
+    }
+    #[test]
+    fn render_test() {
+        assert_eq!(pr_number(&commit("a", "feat: add x (#12)", "")), Some(12));
+        assert_eq!(pr_number(&commit("a", "Merge pull request #3 from a/b", "")), Some(3));
+        assert_eq!(pr_number(&commit("a", "Merge branch 'x' into 'main'", "See merge request g/p!7")), Some(7));
+        assert_eq!(pr_number(&commit("a", "fix: y", "")), None);
+        let pr = PullRequest::from_json(&serde_json::json!({
+            "number": 12, "title": "feat(cli): add x (#12)", "html_url": "https://example.com/pull/12",
+            "user": {"login": "someone"}, "labels": [{"name": "enhancement"}],
+            "pull_request": {"merged_at": "2024-01-01T00:00:00Z"}
+        })).unwrap();
+        assert_eq!(pr.labels, vec!["enhancement".to_string()]);
+        let entries = vec![
+            Entry::new(&commit("1111111111", "feat(cli): add x (#12)", ""), Some(pr)),
+            Entry::new(&commit("2222222222", "fix!: drop y", ""), None),
+            Entry::new(&commit("3333333333", "chore: bump", "BREAKING CHANGE: z"), None),
+            Entry::new(&commit("4444444444", "update readme", ""), None),
+        ];
+        assert_eq!(entries[0].scope.as_deref(), Some("cli"));
+        assert_eq!(entries[0].author, "someone");
+        assert_eq!(
+            render(None, &entries, "v1", "v2").unwrap(),
+            "## Breaking Changes\n- drop y (2222222)\n- bump (3333333)\n\n\
+             ## Features\n- add x (#12)\n\n\
+             ## Other Changes\n- update readme (4444444)\n"
+        );
+        let settings = toml::from_str::<config::changelog::Changelog>(r##"
+            group_by = "label"
+            sections = [{ title = "New", keys = ["enhancement"] }]
+            others = ""
+            template = "# {to}\n{sections}"
+            entry_template = "* {summary} by {author}"
+        "##).unwrap();
+        assert_eq!(render(Some(&settings), &entries, "v1", "v2").unwrap(), "# v2\n## New\n* add x by someone\n");
+    }
+}
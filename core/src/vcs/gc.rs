@@ -0,0 +1,65 @@
+use std::error::Error;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::config;
+use crate::vcs;
+
+pub struct StaleBranch {
+    pub name: String,
+    pub last_commit_at: DateTime<Utc>,
+}
+
+/// auto commit branches that are older than threshold and not used as head of open pull request.
+/// these branches are left when deplo run fails before merging them.
+pub fn stale_branches(
+    config: &config::Config, threshold: Duration
+) -> Result<Vec<StaleBranch>, Box<dyn Error>> {
+    let vcs = config.modules.vcs();
+    let prefix = config::job::DEPLO_AUTO_COMMITS_BRANCH_PREFIX;
+    let candidates = vcs.branches()?.into_iter()
+        .filter(|b| b.len() >= 2)
+        .filter_map(|b| b[1].strip_prefix("refs/heads/").map(|v| v.to_string()))
+        .filter(|name| name.starts_with(prefix))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(vec![]);
+    }
+    let open_pr_branches = vcs.open_pr_branches()?;
+    // fetch all candidates at once to get their commit dates
+    vcs.fetch_object(
+        &format!("refs/heads/{}*", prefix), &format!("refs/remotes/origin/{}*", prefix), None
+    )?;
+    let deadline = Utc::now() - chrono::Duration::from_std(threshold)?;
+    let mut stale = vec![];
+    for name in candidates {
+        if open_pr_branches.contains(&name) {
+            log::debug!("branch {} is used by open pull request, keep it", name);
+            continue;
+        }
+        let last_commit_at = match vcs.log(&format!("refs/remotes/origin/{}^!", name))?.first() {
+            Some(c) => DateTime::parse_from_rfc3339(&c.date)?.with_timezone(&Utc),
+            None => continue
+        };
+        if last_commit_at < deadline {
+            stale.push(StaleBranch { name, last_commit_at });
+        }
+    }
+    Ok(stale)
+}
+
+/// delete stale branches and returns them. if dry_run is true, branches are only listed
+pub fn run(
+    config: &config::Config, threshold: Duration, dry_run: bool
+) -> Result<Vec<StaleBranch>, Box<dyn Error>> {
+    let stale = stale_branches(config, threshold)?;
+    if !dry_run {
+        let vcs = config.modules.vcs();
+        for b in &stale {
+            log::info!("delete stale branch {} (last commit at {})", b.name, b.last_commit_at);
+            vcs.delete_branch(vcs::RefType::Remote, &b.name)?;
+        }
+    }
+    Ok(stale)
+}
@@ -0,0 +1,617 @@
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::collections::HashMap;
+
+use git2::{
+    AutotagOption, BranchType, Commit, Cred, Delta, DescribeOptions, DiffFindOptions, Direction,
+    FetchOptions, IndexAddOption, PushOptions, RemoteCallbacks,
+    Repository, RepositoryOpenFlags, ResetType, Signature, Status, StatusOptions,
+    build::CheckoutBuilder
+};
+
+use crate::config;
+use crate::shell;
+use crate::util::{defer, escalate};
+use crate::vcs;
+use crate::vcs::git::{FilesCommit, GitFeatures, ShellGit, RemoteCredential, ref_from_path, user_and_repo_from_url};
+use crate::vcs::github::AppTokenGenerator;
+
+pub struct LibGit<S: shell::Shell = shell::Default> {
+    // used for the operation that libgit2 does not support (interactive rebase, git-lfs, ...)
+    git: ShellGit<S>,
+    repo: Repository,
+}
+
+impl<S: shell::Shell> LibGit<S> {
+    fn open(git: ShellGit<S>) -> Result<LibGit<S>, Box<dyn Error>> {
+        let repo_path = {
+            let config = git.shell.config().borrow();
+            match config.runtime.workdir.as_ref() {
+                Some(v) => std::path::PathBuf::from(v),
+                None => std::env::current_dir()?
+            }
+        };
+        // stop searching parent directories at HOME, if it is set
+        let ceiling_dirs = std::env::var("HOME").map_or_else(|_| vec![], |home| vec![home]);
+        let repo = match Repository::open_ext(repo_path.as_path(), RepositoryOpenFlags::empty(), &ceiling_dirs) {
+            Ok(repo) => repo,
+            Err(e) => return escalate!(Box::new(vcs::VCSError {
+                cause: format!("fail to open git repository at {}: {}", repo_path.display(), e)
+            }))
+        };
+        Ok(LibGit::<S> { repo, git })
+    }
+    fn credential(&self) -> &RemoteCredential<S> {
+        &self.git.credential
+    }
+    fn signature(&self) -> Result<Signature<'static>, Box<dyn Error>> {
+        let (name, email) = self.credential().committer();
+        Ok(Signature::now(&name, &email)?)
+    }
+    fn callbacks<'a>(&'a self, remote_url: &'a str) -> RemoteCallbacks<'a> {
+        let mut callbacks = RemoteCallbacks::new();
+        // libgit2 calls credentials callback repeatedly while authentication fails
+        let tried = Cell::new(false);
+        callbacks.credentials(move |_, _, _| {
+            if tried.replace(true) {
+                return Err(git2::Error::from_str(&format!("authentication fails for {}", remote_url)));
+            }
+            match self.credential().basic_auth(remote_url) {
+                Ok((username, password)) => Cred::userpass_plaintext(&username, &password),
+                Err(e) => Err(git2::Error::from_str(&e.to_string()))
+            }
+        });
+        callbacks
+    }
+    fn fetch<'a>(
+        &'a self, remote_url: &'a str, refspecs: &[&str], options: &mut FetchOptions<'a>
+    ) -> Result<(), Box<dyn Error>> {
+        options.remote_callbacks(self.callbacks(remote_url));
+        self.repo.remote_anonymous(remote_url)?.fetch(refspecs, Some(options), None)?;
+        Ok(())
+    }
+    fn push(&self, remote_url: &str, refspecs: &[&str]) -> Result<(), Box<dyn Error>> {
+        // libgit2 does not treat rejection of remote as error, so we need to check it with callback
+        let rejected = RefCell::new(vec![]);
+        {
+            let mut callbacks = self.callbacks(remote_url);
+            callbacks.push_update_reference(|refname, status| {
+                if let Some(msg) = status {
+                    rejected.borrow_mut().push(format!("{} ({})", refname, msg));
+                }
+                Ok(())
+            });
+            let mut options = PushOptions::new();
+            options.remote_callbacks(callbacks);
+            self.repo.remote_anonymous(remote_url)?.push(refspecs, Some(&mut options))?;
+        }
+        let rejected = rejected.into_inner();
+        if !rejected.is_empty() {
+            return escalate!(Box::new(vcs::VCSError {
+                cause: format!("push to {} rejected: {}", remote_url, rejected.join(", "))
+            }));
+        }
+        Ok(())
+    }
+    // libgit2 requires fully qualified name for remote ref
+    fn qualify_branch(branch: &str) -> String {
+        if branch.starts_with("refs/") {
+            branch.to_string()
+        } else {
+            format!("refs/heads/{}", branch)
+        }
+    }
+    // same as git add -n, list changes in working tree that matches with pattern
+    fn changes_of(&self, pattern: &str, include_ignored: bool) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut status_options = StatusOptions::new();
+        status_options.pathspec(pattern).include_untracked(true).recurse_untracked_dirs(true)
+            .include_ignored(include_ignored).recurse_ignored_dirs(include_ignored);
+        let status_flags = if include_ignored { Status::IGNORED } else { Status::empty() };
+        Ok(self.repo.statuses(Some(&mut status_options))?.iter()
+            .filter(|e| e.status().intersects(
+                Status::WT_NEW | Status::WT_MODIFIED | Status::WT_DELETED |
+                Status::WT_RENAMED | Status::WT_TYPECHANGE | status_flags
+            ))
+            .filter_map(|e| e.path().ok().map(|p| p.to_string()))
+            .collect::<Vec<String>>())
+    }
+    // empty expression means HEAD, like git diff a..
+    fn find_commit(&self, expr: &str) -> Result<Commit<'_>, Box<dyn Error>> {
+        Ok(self.repo.revparse_single(if expr.is_empty() { "HEAD" } else { expr })?.peel_to_commit()?)
+    }
+}
+
+impl<S: shell::Shell> GitFeatures<S> for LibGit<S> {
+    fn from_pat(
+        username: &config::Value, email: &config::Value, key: &config::Value,
+        signing: &Option<config::vcs::Signing>, shell: S
+    ) -> Result<LibGit<S>, Box<dyn Error>> {
+        Self::open(ShellGit::<S>::from_pat(username, email, key, signing, shell)?)
+    }
+    fn from_app(
+        app_token_generator: AppTokenGenerator<S>,
+        shell: S
+    ) -> Result<LibGit<S>, Box<dyn Error>> {
+        Self::open(ShellGit::<S>::from_app(app_token_generator, shell)?)
+    }
+    fn user_and_repo(&self) -> Result<(String, String), Box<dyn Error>> {
+        user_and_repo_from_url(&self.remote_url(None)?)
+    }
+    fn current_ref(&self) -> Result<(vcs::RefType, String), Box<dyn Error>> {
+        // same as git describe --all
+        let described = self.repo.describe(
+            DescribeOptions::new().describe_all()
+        ).and_then(|d| d.format(None));
+        match described.ok().and_then(|ref_path| ref_from_path(&ref_path)) {
+            Some(v) => Ok(v),
+            None => Ok((vcs::RefType::Commit, self.commit_hash(None)?))
+        }
+    }
+    fn delete_branch(&self, remote_url: &str, ref_type: vcs::RefType, ref_path: &str) -> Result<(), Box<dyn Error>> {
+        match ref_type {
+            vcs::RefType::Branch => {
+                self.repo.find_branch(ref_path, BranchType::Local)?.delete()?;
+            },
+            vcs::RefType::Remote => {
+                self.push(remote_url, &[&format!(":{}", Self::qualify_branch(ref_path))])?;
+            }
+            _ => {
+                return escalate!(Box::new(vcs::VCSError {
+                    cause: format!("delete_branch: unsupported ref type: {}/{}", ref_type, ref_path)
+                }))
+            }
+        }
+        Ok(())
+    }
+    fn fetch_branch(&self, remote_url: &str, branch_name: &str) -> Result<(), Box<dyn Error>> {
+        // FETCH_HEAD is updated as git fetch does
+        self.fetch(remote_url, &[branch_name], &mut FetchOptions::new())
+    }
+    fn fetch_object(&self, remote_url: &str, hash: &str, ref_name: &str, depth: Option<usize>) -> Result<(), Box<dyn Error>> {
+        let mut options = FetchOptions::new();
+        // --prune of git fetch is not applied here. libgit2 prunes ref_name itself because
+        // it does not exist in remote refs.
+        options.download_tags(AutotagOption::None);
+        if let Some(d) = depth {
+            options.depth(d as i32);
+        }
+        self.fetch(remote_url, &[&format!("+{}:{}", hash, ref_name)], &mut options)
+    }
+    fn squash_branch(&self, n: usize) -> Result<(), Box<dyn Error>> {
+        self.git.squash_branch(n)
+    }
+    fn checkout(&self, refspec: &str, branch_name: Option<&str>) -> Result<(), Box<dyn Error>> {
+        // "-" means previously checked out branch or commit
+        let (object, reference) = self.repo.revparse_ext(if refspec == "-" { "@{-1}" } else { refspec })?;
+        let commit = object.peel_to_commit()?;
+        match branch_name {
+            Some(b) => {
+                // same as git checkout -B. branch is created or reset to the commit
+                self.repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+                let branch_ref = Self::qualify_branch(b);
+                self.repo.reference(&branch_ref, commit.id(), true, &format!("checkout: moving to {}", b))?;
+                self.repo.set_head(&branch_ref)?;
+            },
+            None => {
+                self.repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))?;
+                match reference {
+                    Some(r) if r.is_branch() => self.repo.set_head(r.name()?)?,
+                    _ => self.repo.set_head_detached(commit.id())?
+                }
+            }
+        };
+        Ok(())
+    }
+    fn commit_hash(&self, expr: Option<&str>) -> Result<String, Box<dyn Error>> {
+        Ok(self.repo.revparse_single(expr.unwrap_or("HEAD"))?.id().to_string())
+    }
+    fn remote_url(&self, remote_name: Option<&str>) -> Result<String, Box<dyn Error>> {
+        Ok(self.repo.find_remote(remote_name.unwrap_or("origin"))?.url()?.to_string())
+    }
+    fn repository_root(&self) -> Result<String, Box<dyn Error>> {
+        match self.repo.workdir() {
+            Some(p) => Ok(p.to_string_lossy().trim_end_matches('/').to_string()),
+            None => escalate!(Box::new(vcs::VCSError {
+                cause: "repository does not have working directory".to_string()
+            }))
+        }
+    }
+    fn diff_files(&self, expression: &str) -> Result<vcs::ChangeSet, Box<dyn Error>> {
+        let mut diff = match expression.split_once("...") {
+            // diff between merge base of a and b, and b
+            Some((a, b)) => {
+                let (a, b) = (self.find_commit(a)?, self.find_commit(b)?);
+                let base = self.repo.find_commit(self.repo.merge_base(a.id(), b.id())?)?;
+                self.repo.diff_tree_to_tree(Some(&base.tree()?), Some(&b.tree()?), None)?
+            },
+            None => match expression.split_once("..") {
+                // diff between a and b
+                Some((a, b)) => self.repo.diff_tree_to_tree(
+                    Some(&self.find_commit(a)?.tree()?), Some(&self.find_commit(b)?.tree()?), None
+                )?,
+                // diff between the commit and working tree
+                None => self.repo.diff_tree_to_workdir_with_index(
+                    Some(&self.find_commit(expression)?.tree()?), None
+                )?
+            }
+        };
+        // same as git diff -M
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+        let path_of = |f: git2::DiffFile| f.path().map(|p| p.to_string_lossy().to_string());
+        let mut files = vec![];
+        for d in diff.deltas() {
+            let (status, path, old_path) = match d.status() {
+                Delta::Added | Delta::Copied => (vcs::FileStatus::Added, path_of(d.new_file()), None),
+                Delta::Modified | Delta::Typechange => (vcs::FileStatus::Modified, path_of(d.new_file()), None),
+                Delta::Deleted => (vcs::FileStatus::Deleted, path_of(d.old_file()), None),
+                Delta::Renamed => (vcs::FileStatus::Renamed, path_of(d.new_file()), path_of(d.old_file())),
+                other => {
+                    log::debug!("ignore diff status {:?} for {:?}", other, path_of(d.new_file()));
+                    continue
+                }
+            };
+            match path {
+                Some(path) => files.push(vcs::FileChange { status, path, old_path }),
+                None => log::debug!("ignore diff {:?} without path", status)
+            }
+        }
+        Ok(vcs::ChangeSet { everything: false, files })
+    }
+    fn tags(&self, remote_url: &str) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        // same as git ls-remote --tags origin
+        let mut remote = self.repo.find_remote("origin")?;
+        let connection = remote.connect_auth(Direction::Fetch, Some(self.callbacks(remote_url)), None)?;
+        Ok(connection.list()?.iter()
+            .filter(|head| head.name().starts_with("refs/tags/"))
+            .map(|head| vec![head.oid().to_string(), head.name().to_string()])
+            .collect())
+    }
+    fn branches(&self, remote_url: &str) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        // same as git ls-remote --heads origin
+        let mut remote = self.repo.find_remote("origin")?;
+        let connection = remote.connect_auth(Direction::Fetch, Some(self.callbacks(remote_url)), None)?;
+        Ok(connection.list()?.iter()
+            .filter(|head| head.name().starts_with("refs/heads/"))
+            .map(|head| vec![head.oid().to_string(), head.name().to_string()])
+            .collect())
+    }
+    fn log(&self, expression: &str) -> Result<Vec<vcs::LogEntry>, Box<dyn Error>> {
+        let mut walk = self.repo.revwalk()?;
+        match (expression.split_once(".."), expression.strip_suffix("^!")) {
+            (Some((a, b)), _) => {
+                walk.push(self.find_commit(b)?.id())?;
+                walk.hide(self.find_commit(a)?.id())?;
+            },
+            // the commit only
+            (None, Some(c)) => {
+                let commit = self.find_commit(c)?;
+                walk.push(commit.id())?;
+                for parent in commit.parent_ids() {
+                    walk.hide(parent)?;
+                }
+            },
+            (None, None) => walk.push(self.find_commit(expression)?.id())?
+        }
+        let mut entries = vec![];
+        for oid in walk {
+            let commit = self.repo.find_commit(oid?)?;
+            // same as %cI of git log
+            let time = commit.committer().when();
+            let date = chrono::FixedOffset::east_opt(time.offset_minutes() * 60)
+                .zip(chrono::DateTime::from_timestamp(time.seconds(), 0))
+                .map(|(offset, t)| t.with_timezone(&offset).to_rfc3339())
+                .unwrap_or_default();
+            entries.push(vcs::LogEntry {
+                hash: commit.id().to_string(),
+                author: commit.author().name()?.to_string(),
+                date,
+                subject: commit.summary()?.unwrap_or("").to_string(),
+                body: commit.body()?.unwrap_or("").trim().to_string()
+            });
+        }
+        Ok(entries)
+    }
+    fn remote_ref(&self, remote_url: &str, ref_path: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let mut remote = self.repo.find_remote("origin")?;
+        let connection = remote.connect_auth(Direction::Fetch, Some(self.callbacks(remote_url)), None)?;
+        Ok(connection.list()?.iter()
+            .find(|head| head.name() == ref_path)
+            .map(|head| head.oid().to_string()))
+    }
+    fn push_ref(&self, remote_url: &str, commit: &str, ref_path: &str) -> Result<(), Box<dyn Error>> {
+        // libgit2 only accepts reference as source of push refspec, so create same name ref locally
+        let oid = self.find_commit(commit)?.id();
+        self.repo.reference(ref_path, oid, true, &format!("push_ref: {}", commit))?;
+        self.push(remote_url, &[&format!("+{}:{}", ref_path, ref_path)])
+    }
+    fn push_tag(&self, remote_url: &str, tag_name: &str, commit: &str) -> Result<(), Box<dyn Error>> {
+        let ref_path = format!("refs/tags/{}", tag_name);
+        // unlike git command, libgit2 updates existing remote tag if it is fast-forward
+        if self.remote_ref(remote_url, &ref_path)?.is_some() {
+            return escalate!(Box::new(vcs::VCSError {
+                cause: format!("push_tag: {} already exists in {}", tag_name, remote_url)
+            }));
+        }
+        let target = self.repo.find_object(self.find_commit(commit)?.id(), None)?;
+        self.repo.tag_lightweight(tag_name, &target, false)?;
+        let pushed = self.push(remote_url, &[&format!("{}:{}", ref_path, ref_path)]);
+        if pushed.is_err() {
+            // remove local tag, otherwise retry fails because the tag already exists locally
+            self.repo.tag_delete(tag_name)?;
+        }
+        pushed
+    }
+    fn cherry_pick(&self, target: &str) -> Result<(), Box<dyn Error>> {
+        if self.git.signs_commit() {
+            // libgit2 does not sign commits by itself
+            return self.git.cherry_pick(target);
+        }
+        let commit = self.repo.revparse_single(target)?.peel_to_commit()?;
+        // index may be updated by git command after it is loaded
+        self.repo.index()?.read(true)?;
+        self.repo.cherrypick(&commit, None)?;
+        let mut index = self.repo.index()?;
+        if index.has_conflicts() {
+            // like git cherry-pick --abort, do not leave the repository in cherry-pick state
+            self.repo.cleanup_state()?;
+            let head = self.repo.head()?.peel_to_commit()?;
+            self.repo.reset(head.as_object(), ResetType::Hard, None)?;
+            return escalate!(Box::new(vcs::VCSError {
+                cause: format!("cherry_pick: {} conflicts with HEAD", target)
+            }));
+        }
+        // like git cherry-pick, keep original author and record committer
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let head = self.repo.head()?.peel_to_commit()?;
+        self.repo.commit(
+            Some("HEAD"), &commit.author(), &self.signature()?,
+            commit.message()?, &tree, &[&head]
+        )?;
+        self.repo.cleanup_state()?;
+        Ok(())
+    }
+    fn push_branch(
+        &self, remote_url: &str, local_ref: &str,
+        remote_branch: &str, options: &HashMap<&str, &str>
+    ) -> Result<(), Box<dyn Error>> {
+        let explicit_lfs = match options.get("explicit_lfs") {
+            Some(v) => !v.is_empty(),
+            None => false
+        };
+        if match options.get("new") {
+            Some(v) => v.is_empty(),
+            None => true
+        } {
+            // if the branch already exists, refresh remote branch with its latest state
+            self.rebase_with_remote_counterpart(remote_url, remote_branch)?;
+        }
+        if explicit_lfs {
+            self.git.push_lfs(remote_url)?;
+        }
+        let force = options.get("force").map_or(false, |v| !v.is_empty());
+        self.push(remote_url, &[&format!(
+            "{}{}:{}", if force { "+" } else { "" }, local_ref, Self::qualify_branch(remote_branch)
+        )])
+    }
+    fn push_diff(
+        &self, remote_url: &str, remote_branch: &str, msg: &str,
+        patterns: &Vec<&str>, options: &HashMap<&str, &str>
+    ) -> Result<bool, Box<dyn Error>> {
+        if match options.get("explicit_lfs") {
+            Some(v) => !v.is_empty(),
+            None => false
+        } || self.git.signs_commit() {
+            // libgit2 does not run filter of git-lfs and does not sign commits,
+            // so files should be added and committed by git command
+            return self.git.push_diff(remote_url, remote_branch, msg, patterns, options);
+        }
+        let original = self.repo.head()?.peel_to_commit()?;
+        defer! {
+            self.repo.reset(original.as_object(), ResetType::Hard, None).unwrap();
+        };
+        let mut index = self.repo.index()?;
+        let mut changed = false;
+        // generated files like documents are often ignored, but should be committed if include_ignored is set
+        let include_ignored = options.get("include_ignored").is_some_and(|v| !v.is_empty());
+        let add_option = if include_ignored { IndexAddOption::FORCE } else { IndexAddOption::DEFAULT };
+        for pattern in patterns {
+            let diff = self.changes_of(pattern, include_ignored)?;
+            if !diff.is_empty() {
+                log::debug!("diff found for {} [{}]", pattern, diff.join("\n"));
+                index.add_all([pattern].iter(), add_option, None)?;
+                index.update_all([pattern].iter(), None)?;
+                changed = true
+            }
+        }
+        if !changed {
+            log::debug!("skip push because no changes for provided pattern [{}]", patterns.join(" "));
+            return Ok(false)
+        }
+        index.write()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let signature = self.signature()?;
+        self.repo.commit(Some("HEAD"), &signature, &signature, msg, &tree, &[&original])?;
+        log::debug!("commit done: [{}]", msg);
+        self.push(remote_url, &[&format!("HEAD:{}", Self::qualify_branch(remote_branch))])?;
+        Ok(true)
+    }
+    fn files_to_push(
+        &self, patterns: &[&str], options: &HashMap<&str, &str>
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let include_ignored = options.get("include_ignored").is_some_and(|v| !v.is_empty());
+        let mut files = vec![];
+        for pattern in patterns {
+            files.extend(self.changes_of(pattern, include_ignored)?);
+        }
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+    fn commit_files(
+        &self, remote_url: &str, source: &str, remote_branch: &str, options: &HashMap<&str, &str>
+    ) -> Result<Option<FilesCommit>, Box<dyn Error>> {
+        // temporary index is needed to build tree, and signing is also needed, so delegates to git command
+        self.git.commit_files(remote_url, source, remote_branch, options)
+    }
+    fn rebase_with_remote_counterpart(
+        &self, url: &str, remote_branch: &str
+    ) -> Result<(), Box<dyn Error>> {
+        self.git.rebase_with_remote_counterpart(url, remote_branch)
+    }
+    fn search_remote_ref(&self, commit: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.git.search_remote_ref(commit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcs::git::tests::TempRepo;
+
+    fn log_of(entries: Vec<vcs::LogEntry>) -> Vec<(String, String, String, String, String)> {
+        entries.into_iter().map(|e| (e.hash, e.author, e.date, e.subject, e.body)).collect()
+    }
+    fn sorted(mut refs: Vec<Vec<String>>) -> Vec<Vec<String>> {
+        refs.sort();
+        refs
+    }
+
+    #[test]
+    fn parity_with_shell_git_test() {
+        let config = config::Config::with(None).unwrap();
+        let repo = TempRepo::new();
+        repo.write("a.txt", "a");
+        let first = repo.commit("first");
+        repo.write("b.txt", "b");
+        repo.commit("second\n\nbody of second");
+        repo.git(&["tag", "v1.0.0"]);
+        repo.write("a.txt", "a2");
+        repo.commit("third");
+        repo.git(&["tag", "-a", "v1.1.0", "-m", "annotated"]);
+        repo.git(&["branch", "feature", &first]);
+        repo.git(&["push", "-q", "origin", "main", "feature", "--tags"]);
+        let shell_git = repo.shell_git(&config);
+        let lib_git = LibGit { git: repo.shell_git(&config), repo: Repository::open(repo.path()).unwrap() };
+        for expr in [None, Some("HEAD~1"), Some("v1.0.0"), Some("feature")] {
+            assert_eq!(shell_git.commit_hash(expr).unwrap(), lib_git.commit_hash(expr).unwrap(), "{:?}", expr);
+        }
+        for expr in ["v1.0.0..HEAD", &format!("{}..main", first), "HEAD~1^!"] {
+            assert_eq!(log_of(shell_git.log(expr).unwrap()), log_of(lib_git.log(expr).unwrap()), "{}", expr);
+        }
+        let tags = sorted(shell_git.tags(&repo.remote_url).unwrap());
+        assert!(tags.iter().any(|t| t[1] == "refs/tags/v1.1.0"));
+        assert_eq!(tags, sorted(lib_git.tags(&repo.remote_url).unwrap()));
+        assert_eq!(
+            sorted(shell_git.branches(&repo.remote_url).unwrap()), sorted(lib_git.branches(&repo.remote_url).unwrap())
+        );
+        for ref_path in ["refs/heads/main", "refs/heads/feature", "refs/tags/v1.0.0", "refs/heads/none", "main"] {
+            assert_eq!(
+                shell_git.remote_ref(&repo.remote_url, ref_path).unwrap(),
+                lib_git.remote_ref(&repo.remote_url, ref_path).unwrap(), "{}", ref_path
+            );
+        }
+    }
+
+    #[test]
+    fn mutation_parity_with_shell_git_test() {
+        let config = config::Config::with(None).unwrap();
+        let repo = TempRepo::new();
+        repo.write("a.txt", "a");
+        repo.write("b.txt", "b");
+        let first = repo.commit("first");
+        repo.git(&["checkout", "-q", "-b", "feature"]);
+        repo.write("a.txt", "a2");
+        repo.write("c.txt", "c");
+        repo.remove("b.txt");
+        repo.commit("change on feature");
+        repo.git(&["checkout", "-q", "main"]);
+        repo.write("d.txt", "d");
+        let main = repo.commit("second");
+        repo.git(&["push", "-q", "origin", "main", "feature"]);
+        let shell_git = repo.shell_git(&config);
+        let lib_git = LibGit { git: repo.shell_git(&config), repo: Repository::open(repo.path()).unwrap() };
+        let reset = || { repo.git(&["checkout", "-q", "-f", "main"]); repo.git(&["reset", "-q", "--hard", &main]); };
+        let tree_of = |expr: &str| repo.git(&["rev-parse", &format!("{}^{{tree}}", expr)]);
+
+        for expr in ["HEAD~1..HEAD", "main..feature", &format!("{}..feature", first)] {
+            assert_eq!(
+                format!("{:?}", shell_git.diff_files(expr).unwrap()),
+                format!("{:?}", lib_git.diff_files(expr).unwrap()), "{}", expr
+            );
+        }
+
+        let mut checked_out = vec![];
+        for (git, branch) in [(&shell_git as &dyn GitFeatures<shell::Default>, "by_shell"), (&lib_git, "by_lib")] {
+            git.checkout("feature", Some(branch)).unwrap();
+            checked_out.push((repo.git(&["symbolic-ref", "--short", "HEAD"]), tree_of("HEAD"), repo.git(&["status", "--porcelain"])));
+            reset();
+        }
+        assert_eq!(checked_out, vec![
+            ("by_shell".to_string(), tree_of("feature"), "".to_string()),
+            ("by_lib".to_string(), tree_of("feature"), "".to_string()),
+        ]);
+
+        let mut picked = vec![];
+        for git in [&shell_git as &dyn GitFeatures<shell::Default>, &lib_git] {
+            git.cherry_pick("feature").unwrap();
+            picked.push((tree_of("HEAD"), repo.git(&["log", "-1", "--format=%an %s", "HEAD"]), repo.git(&["rev-parse", "HEAD~1"])));
+            reset();
+        }
+        assert_eq!(picked[0], picked[1]);
+        assert_eq!(picked[0].2, main);
+
+        let mut pushed = vec![];
+        for (git, branch) in [(&shell_git as &dyn GitFeatures<shell::Default>, "pushed_by_shell"), (&lib_git, "pushed_by_lib")] {
+            repo.write("d.txt", "d2");
+            repo.write("e.txt", "e");
+            repo.write("f.txt", "f");
+            assert!(git.push_diff(&repo.remote_url, branch, "push diff", &vec!["d.txt", "e.txt"], &HashMap::new()).unwrap());
+            // working tree is reset to original commit, except untracked files that are not pushed
+            assert_eq!(repo.git(&["rev-parse", "HEAD"]), main);
+            assert_eq!(repo.git(&["status", "--porcelain"]), "?? f.txt");
+            repo.git(&["fetch", "-q", "origin", &format!("{}:refs/remotes/origin/{}", branch, branch)]);
+            let remote = format!("origin/{}", branch);
+            pushed.push((tree_of(&remote), repo.git(&["log", "-1", "--format=%s", &remote]), repo.git(&["rev-parse", &format!("{}~1", remote)])));
+            repo.remove("f.txt");
+            reset();
+        }
+        assert_eq!(pushed[0], pushed[1]);
+        assert_eq!(pushed[0].2, main);
+    }
+    #[test]
+    fn cherry_pick_conflict_test() {
+        let config = config::Config::with(None).unwrap();
+        let repo = TempRepo::new();
+        repo.write("a.txt", "a");
+        repo.commit("first");
+        repo.git(&["checkout", "-q", "-b", "feature"]);
+        repo.write("a.txt", "a on feature");
+        repo.commit("change on feature");
+        repo.git(&["checkout", "-q", "main"]);
+        repo.write("a.txt", "a on main");
+        let main = repo.commit("change on main");
+        let lib_git = LibGit { git: repo.shell_git(&config), repo: Repository::open(repo.path()).unwrap() };
+        let err = lib_git.cherry_pick("feature").unwrap_err().to_string();
+        assert!(err.contains("conflicts with HEAD"), "{}", err);
+        // repository is not left in cherry-pick state, so that next operation can run
+        assert_eq!(lib_git.repo.state(), git2::RepositoryState::Clean);
+        assert_eq!(repo.git(&["status", "--porcelain"]), "");
+        assert_eq!(repo.git(&["rev-parse", "HEAD"]), main);
+    }
+    #[test]
+    fn push_tag_retry_test() {
+        let config = config::Config::with(None).unwrap();
+        let repo = TempRepo::new();
+        repo.write("a.txt", "a");
+        let first = repo.commit("first");
+        repo.write("a.txt", "a2");
+        let second = repo.commit("second");
+        repo.git(&["push", "-q", "origin", &format!("{}:refs/tags/v1.0.0", first)]);
+        let lib_git = LibGit { git: repo.shell_git(&config), repo: Repository::open(repo.path()).unwrap() };
+        assert!(lib_git.push_tag(&repo.remote_url, "v1.0.0", &second).is_err());
+        assert_eq!(repo.git(&["tag", "-l", "v1.0.0"]), "");
+        repo.git(&["push", "-q", "origin", ":refs/tags/v1.0.0"]);
+        lib_git.push_tag(&repo.remote_url, "v1.0.0", &second).unwrap();
+        assert_eq!(lib_git.remote_ref(&repo.remote_url, "refs/tags/v1.0.0").unwrap(), Some(second));
+    }
+}
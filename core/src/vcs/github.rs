@@ -7,23 +7,27 @@ use std::rc::Rc;
 use std::cell::{RefCell};
 
 use chrono::{DateTime, Utc};
-use glob::Pattern;
 use maplit::hashmap;
 use regex;
 use serde::Deserialize;
 use serde::Serialize;
-use serde_json::{Value as JsonValue};
+use serde_json::{json, Value as JsonValue};
 use jsonwebtoken::{encode, Header, EncodingKey, Algorithm};
 
 use crate::config;
 use crate::util::merge_hashmap;
 use crate::vcs::{self, VCS};
 use crate::shell;
-use crate::util::{escalate,make_escalation,jsonpath,str_to_json,json_to_strmap};
+use crate::util::{defer,escalate,make_escalation,jsonpath,str_to_json,json_to_strmap};
 use crate::config::value::Value;
 
 use super::git;
 
+pub mod api;
+
+// commits to be re-created through api are pushed here, to make their trees available in remote
+const UNVERIFIED_REF_PREFIX: &str = "refs/deplo/unverified/";
+
 #[derive(Serialize, Deserialize)]
 struct Claims {
     iat: u64,  // issued at
@@ -99,16 +103,12 @@ impl<S: shell::Shell> AppTokenGeneratorInner<S> {
 
     fn get_installation_id(
         shell: &impl shell::Shell, jwt: &str, user_and_repo: &(String, String)
-    ) -> Result<String, Box<dyn Error>> {
-        let response = shell.exec(shell::args![
-            "curl", "-sS", "-H", shell::fmtargs!("Authorization: Bearer {}", jwt),
-            "-H", "Accept: application/vnd.github.v3+json",
-            format!("https://api.github.com/repos/{}/{}/installation", user_and_repo.0, user_and_repo.1)
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        let installation_id = jsonpath(&response, "$.id")
-            .expect(&format!("malform installation id response: {:?}", &response))
-            .expect(&format!("no installation id in response: {:?}", &response));
-        Ok(installation_id)
+    ) -> Result<u64, Box<dyn Error>> {
+        let client = api::Client::new(shell, config::Value::new_sensitive(jwt), "Bearer");
+        let installation = client.get(&format!(
+            "/repos/{}/{}/installation", user_and_repo.0, user_and_repo.1
+        ))?.json::<Installation>()?;
+        Ok(installation.id)
     }
 
     fn generate_installation_token(
@@ -116,25 +116,28 @@ impl<S: shell::Shell> AppTokenGeneratorInner<S> {
     ) -> Result<(String, String), Box<dyn Error>> {
         let jwt = Self::generate_jwt(app_id, private_key)?;
         let installation_id = Self::get_installation_id(shell, &jwt, user_and_repo)?;
-        let response = shell.exec(shell::args![
-            "curl", "-sS", "-X", "POST", format!(
-                "https://api.github.com/app/installations/{}/access_tokens",
-                installation_id
-            ),
-            "-H", shell::fmtargs!("Authorization: Bearer {}", jwt),
-            "-H", "Accept: application/vnd.github.v3+json"
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        let token = jsonpath(&response, "$.token")
-            .expect(&format!("malform installation token response: {:?}", &response))
-            .expect(&format!("no token in response: {:?}", &response));
-        let token_expires_at = jsonpath(&response, "$.expires_at")
-            .expect(&format!("malform installation token response: {:?}", &response))
-            .expect(&format!("no expires_at in response: {:?}", &response));
-        Ok((token, token_expires_at))
+        let client = api::Client::new(shell, config::Value::new_sensitive(&jwt), "Bearer");
+        let token = client.post(
+            &format!("/app/installations/{}/access_tokens", installation_id), &json!({})
+        )?.json::<InstallationToken>()?;
+        Ok((token.token, token.expires_at))
     }
 }
 
 
+#[derive(Deserialize)]
+struct Installation {
+    id: u64,
+}
+#[derive(Deserialize)]
+struct InstallationToken {
+    token: String,
+    expires_at: String,
+}
+#[derive(Deserialize)]
+struct PullRequest {
+    issue_url: String,
+}
 #[derive(Serialize, Deserialize)]
 struct MergeResult {
     merged: bool,
@@ -147,7 +150,7 @@ pub struct Github<GIT: git::GitFeatures<S> = git::Git, S: shell::Shell = shell::
     pub git: GIT,
     pub shell: S,
     pub app_token_generator: Option<AppTokenGenerator<S>>,
-    pub diff: Vec<String>
+    pub diff: vcs::ChangeSet
 }
 
 impl<GIT: git::GitFeatures<S>, S: shell::Shell> Github<GIT, S> {
@@ -155,26 +158,22 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> Github<GIT, S> {
         let user_and_repo = (self as &dyn vcs::VCS).user_and_repo()?;
         Ok(format!("https://github.com/{}/{}", user_and_repo.0, user_and_repo.1))
     }
+    fn api(&self) -> Result<api::Client<'_, S>, Box<dyn Error>> {
+        let (token, auth_type) = (self as &dyn vcs::VCS).get_token()?;
+        Ok(api::Client::new(&self.shell, token, auth_type))
+    }
     fn enable_auto_merge_pr(
         &self, url: &str, options: &HashMap<&str, &str>
     ) -> Result<bool, Box<dyn Error>> {
         let user_and_repo = (self as &dyn vcs::VCS).user_and_repo().unwrap();
         let pr_num = url.split('/').last().unwrap_or("");
-        let (key, auth_type) = (self as &dyn vcs::VCS).get_token()?;
+        let client = self.api()?;
         
         // First, get PR ID using GraphQL query
-        let query_pr_id = format!(r#"
-{{"query": "query {{ repository(owner: \"{}\", name: \"{}\") {{ pullRequest(number: {}) {{ id }} }} }}"}}"#,
+        let query_pr_id = format!(
+            r#"query {{ repository(owner: "{}", name: "{}") {{ pullRequest(number: {}) {{ id }} }} }}"#,
             user_and_repo.0, user_and_repo.1, pr_num);
-        
-        let pr_response = self.shell.exec(shell::args![
-            "curl", "-sS", "-X", "POST", "https://api.github.com/graphql",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &key),
-            "-H", "Content-Type: application/json",
-            "-d", query_pr_id
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        
-        let pr_data: serde_json::Value = serde_json::from_str(&pr_response)?;
+        let pr_data = client.post("/graphql", &json!({"query": query_pr_id}))?.json::<JsonValue>()?;
         let pr_id = pr_data["data"]["repository"]["pullRequest"]["id"]
             .as_str()
             .ok_or("Failed to get PR ID")?;
@@ -191,18 +190,13 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> Github<GIT, S> {
         let commit_headline = options.get("commit_title").unwrap_or(&"Auto-merge by deplo");
         let commit_body = options.get("commit_message").unwrap_or(&"");
         
-        let mutation = format!(r#"
-{{"query": "mutation {{ enablePullRequestAutoMerge(input: {{ pullRequestId: \"{}\", mergeMethod: {}, commitHeadline: \"{}\", commitBody: \"{}\" }}) {{ pullRequest {{ autoMergeRequest {{ enabledAt }} }} }} }}"}}"#, 
-            pr_id, merge_method, commit_headline, commit_body);
-        
-        let response = self.shell.exec(shell::args![
-            "curl", "-sS", "-X", "POST", "https://api.github.com/graphql",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &key),
-            "-H", "Content-Type: application/json",
-            "-d", mutation
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        
-        let result: serde_json::Value = serde_json::from_str(&response)?;
+        let mutation = r#"mutation($id: ID!, $method: PullRequestMergeMethod!, $headline: String, $body: String) { enablePullRequestAutoMerge(input: { pullRequestId: $id, mergeMethod: $method, commitHeadline: $headline, commitBody: $body }) { pullRequest { autoMergeRequest { enabledAt } } } }"#;
+        let result = client.post("/graphql", &json!({
+            "query": mutation,
+            "variables": {
+                "id": pr_id, "method": merge_method, "headline": commit_headline, "body": commit_body
+            }
+        }))?.json::<JsonValue>()?;
         if let Some(errors) = result.get("errors") {
             let default_strval = serde_json::Value::String("".to_string());
             // check if errors has entry its path is enablePullRequestAutoMerge and error type is UNPROCESSABLE
@@ -234,6 +228,26 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> Github<GIT, S> {
         let url = self.pushable_remote_url()?;
         executer(&url)
     }
+    fn verified_commits(&self) -> bool {
+        // app_token_generator is not set if local fallback is used
+        self.app_token_generator.is_some() && matches!(
+            &self.config.borrow().vcs,
+            config::vcs::Account::GithubApp{ verified_commits: Some(true), .. }
+        )
+    }
+    fn push_verified(
+        &self, remote_url: &str, base: &str, head: &str, unverified_ref: &str, remote_branch: &str, force: bool
+    ) -> Result<(), Box<dyn Error>> {
+        let user_and_repo = self.user_and_repo()?;
+        let ref_path = format!("refs/heads/{}", remote_branch);
+        let exists = self.git.remote_ref(remote_url, &ref_path)?.is_some();
+        let repo_path = format!("/repos/{}/{}", user_and_repo.0, user_and_repo.1);
+        let client = self.api()?;
+        defer! {
+            delete_ref(&client, &repo_path, unverified_ref);
+        };
+        push_verified_commits(&client, &repo_path, base, head, &ref_path, exists, force)
+    }
     fn get_release(&self, target_ref: (&str, bool)) -> Result<String, Box<dyn Error>> {
         if target_ref.1 {
             return escalate!(Box::new(vcs::VCSError {
@@ -241,14 +255,10 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> Github<GIT, S> {
             }));
         }
         let user_and_repo = self.user_and_repo()?;
-        let (token, auth_type) = (self as &dyn vcs::VCS).get_token()?;
-        let response = self.shell.exec(shell::args![
-            "curl", "--fail", "-sS", format!(
-                "https://api.github.com/repos/{}/{}/releases/tags/{}",
-                user_and_repo.0, user_and_repo.1, target_ref.0
-            ), "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token)
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        return Ok(response);
+        let response = self.api()?.get(&format!(
+            "/repos/{}/{}/releases/tags/{}", user_and_repo.0, user_and_repo.1, target_ref.0
+        ))?;
+        return Ok(response.body);
     }
     fn get_value_from_json_object(&self, json_object: &str, key: &str) -> Result<String, Box<dyn Error>> {
         let mut object: JsonValue = str_to_json(json_object);
@@ -280,12 +290,8 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> Github<GIT, S> {
             if !is_branch && !v.is_tag() {
                 continue;
             }
-            for path in v.paths() {
-                let re = Pattern::new(&path.resolve()).unwrap();
-                match re.matches(&ref_name) {
-                    true => return Some(k.to_string()),
-                    false => {}, 
-                }
+            if v.matches(ref_name) {
+                return Some(k.to_string());
             }
         }
         None
@@ -295,42 +301,95 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> Github<GIT, S> {
         format!("https://github.com/{}/{}/{}", user_and_repo.0, user_and_repo.1, ref_name)
     }
     fn pr_data_from_ref_path(&self, ref_path: &str, json_path: &str) ->Result<String, Box<dyn Error>> {
-        let (token, auth_type) = (self as &dyn vcs::VCS).get_token()?;
         let pr_url = self.url_from_pull_ref(ref_path);
-        let api_url = format!(
-            "https://api.github.com/repos/{pr_part}",
+        let api_path = format!(
+            "/repos/{pr_part}",
             pr_part = &pr_url[19..].replace("/pull/", "/pulls/")
         );
-        let output = self.shell.exec(shell::args![
-            "curl", "-s", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token), 
-            "-H", "Accept: application/vnd.github.v3+json", api_url
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        let output = self.api()?.get(&api_path)?.body;
         Ok(jsonpath(&output, json_path)
             .expect(&format!("malform pulls response: {:?} for json_path {}", &output, json_path))
             .unwrap_or("".to_string()))    
     }
 }
 
+// ref api takes the path with refs/ prefix, like /git/refs/heads/main
+fn delete_ref<S: shell::Shell>(client: &api::Client<'_, S>, repo_path: &str, ref_path: &str) {
+    if let Err(e) = client.delete(&format!("{}/git/{}", repo_path, ref_path)) {
+        log::warn!("fail to delete temporary ref {}: {}", ref_path, e);
+    }
+}
+// re-create commits in base...head (head should be pushed to temporary ref) through api,
+// and update ref_path (like refs/heads/main) to point the last one. github signs commits that are created by app
+// through api, so they are shown as verified.
+fn push_verified_commits<S: shell::Shell>(
+    client: &api::Client<'_, S>, repo_path: &str, base: &str, head: &str,
+    ref_path: &str, exists: bool, force: bool
+) -> Result<(), Box<dyn Error>> {
+    let compare = client.get(&format!("{}/compare/{}...{}", repo_path, base, head))?.json::<JsonValue>()?;
+    let mut parent = match compare["merge_base_commit"]["sha"].as_str() {
+        Some(v) => v.to_string(),
+        None => return escalate!(Box::new(vcs::VCSError {
+            cause: format!("no merge base between {} and {}", base, head)
+        }))
+    };
+    let commits = compare["commits"].as_array().cloned().unwrap_or_default();
+    // compare api returns 250 commits at most
+    if compare["total_commits"].as_u64().is_some_and(|n| n as usize > commits.len()) {
+        return escalate!(Box::new(vcs::VCSError {
+            cause: format!("too many commits to push between {} and {}", base, head)
+        }));
+    }
+    for c in &commits {
+        // author and committer are omitted, to make github sign the commit as the app
+        let created = client.post(&format!("{}/git/commits", repo_path), &json!({
+            "message": c["commit"]["message"],
+            "tree": c["commit"]["tree"]["sha"],
+            "parents": [parent]
+        }))?.json::<JsonValue>()?;
+        log::debug!("re-create commit {} as {}", c["sha"], created["sha"]);
+        parent = match created["sha"].as_str() {
+            Some(v) => v.to_string(),
+            None => return escalate!(Box::new(vcs::VCSError {
+                cause: format!("fail to re-create commit {}", c["sha"])
+            }))
+        };
+    }
+    if exists {
+        client.patch(&format!("{}/git/{}", repo_path, ref_path), &json!({
+            "sha": parent, "force": force
+        }))?;
+    } else {
+        client.post(&format!("{}/git/refs", repo_path), &json!({
+            "ref": ref_path, "sha": parent
+        }))?;
+    }
+    log::info!("push {} verified commits to {}", commits.len(), ref_path);
+    Ok(())
+}
+
 impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
     fn new(config: &config::Container) -> Result<Github<GIT,S>, Box<dyn Error>> {
-        if let config::vcs::Account::Github{ account, key, email } = &config.borrow().vcs {
+        if let config::vcs::Account::Github{ account, key, email, signing } = &config.borrow().vcs {
             return Ok(Github {
                 config: config.clone(),
-                diff: vec!(),
+                diff: vcs::ChangeSet::default(),
                 shell: S::new(config),
                 app_token_generator: None,
-                git: GIT::from_pat(&account, &email, &key,S::new(config))
+                git: GIT::from_pat(account, email, key, signing, S::new(config))?
             });
-        } else if let config::vcs::Account::GithubApp{ app_id, pkey, local_fallback } = &config.borrow().vcs {
+        } else if let config::vcs::Account::GithubApp{ app_id, pkey, local_fallback, .. } = &config.borrow().vcs {
             // Use local_fallback when running locally and fallback is configured
             if !config::Config::is_running_on_ci() {
                 if let Some(fallback) = local_fallback {
                     return Ok(Github {
                         config: config.clone(),
-                        diff: vec!(),
+                        diff: vcs::ChangeSet::default(),
                         shell: S::new(config),
                         app_token_generator: None,
-                        git: GIT::from_pat(&fallback.account, &fallback.email, &fallback.key, S::new(config))
+                        git: GIT::from_pat(
+                            &fallback.account, &fallback.email, &fallback.key, &fallback.signing, S::new(config)
+                        )?
                     });
                 }
             }
@@ -341,11 +400,11 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
             let git = GIT::from_app(
                 AppTokenGenerator { inner: app_token_generator.inner.clone() },
                 S::new(config)
-            );
+            )?;
             app_token_generator.inner.borrow_mut().user_and_repo = git.user_and_repo()?;
             return Ok(Github {
                 config: config.clone(),
-                diff: vec!(),
+                diff: vcs::ChangeSet::default(),
                 shell: S::new(config),
                 app_token_generator: Some(app_token_generator),
                 git: git
@@ -397,7 +456,6 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
             Ok(v) => v,
             Err(_) => {
                 let user_and_repo = self.user_and_repo()?;
-                let (token, auth_type) = self.get_token()?;
                 // create release
                 let mut options = match opts.as_object() {
                     Some(v) => v.clone(),
@@ -406,14 +464,9 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
                     }))
                 };
                 options.insert("tag_name".to_string(), str_to_json(target_ref.0));
-                self.shell.exec(shell::args![
-                    "curl", "-sS", format!(
-                        "https://api.github.com/repos/{}/{}/releases", 
-                        user_and_repo.0, user_and_repo.1
-                    ), 
-                    "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token), 
-                    "-d", serde_json::to_string(&options)?
-                ], shell::no_env(), shell::no_cwd(), &shell::no_capture())?             
+                self.api()?.post(&format!(
+                    "/repos/{}/{}/releases", user_and_repo.0, user_and_repo.1
+                ), &JsonValue::Object(options))?.body
             }
         };
         let upload_url = self.get_upload_url_from_release(&response)?;
@@ -434,36 +487,44 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
             None => Path::new(asset_file_path).file_name().unwrap().to_str().unwrap().to_string()
         };
         let upload_url = format!("{}?name={}", upload_url_base, asset_name);
-        let (token, auth_type) = self.get_token()?;
+        let client = self.api()?;
         let content_type = match opts.get("content-type") {
             Some(v) => v.as_str().unwrap_or("application/octet-stream").to_string(),
             None => "application/octet-stream".to_string()
         };
-        let response = self.shell.exec(shell::args![
-            "curl", "-sS", upload_url_base.replace("uploads.github.com", "api.github.com"),
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token)
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        let response = client.get(&upload_url_base.replace("uploads.github.com", "api.github.com"))?.body;
         match jsonpath(&response, &format!("$.[?(@.name=='{}')]", asset_name))? {
             Some(v) => match opts.get("replace") {
                 Some(_) => {
                     // delete old asset
                     let delete_url = self.get_value_from_json_object(&v, "url")?;
-                    self.shell.exec(shell::args![
-                        "curl", delete_url, "-X", "DELETE", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token)
-                    ], shell::no_env(), shell::no_cwd(), &shell::no_capture())?;
+                    client.delete(&delete_url)?;
                 },
                 // nothing to do, return browser_download_url
                 None => return self.get_value_from_json_object(&v, "browser_download_url")
             },
             None => log::debug!("no asset with name {}, proceed to upload", asset_name),
         };
-        let response = self.shell.exec(shell::args![
-            "curl", "-sS", upload_url, "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", format!("Content-Type: {}", content_type),
-            "--data-binary", format!("@{}", asset_file_path)
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        let response = client.call("POST", &upload_url, Some(api::Body::File {
+            path: asset_file_path, content_type: &content_type
+        }))?.body;
         self.get_value_from_json_object(&response, "browser_download_url")
     }
+    fn list_release_assets(&self, target_ref: (&str, bool)) -> Result<Vec<vcs::ReleaseAsset>, Box<dyn Error>> {
+        // target_ref checked in get_release
+        let assets_url = self.get_value_from_json_object(&self.get_release(target_ref)?, "assets_url")?;
+        Ok(self.api()?.paginate(&assets_url, None)?.iter().map(|a| vcs::ReleaseAsset {
+            name: a["name"].as_str().unwrap_or("").to_string(),
+            url: a["browser_download_url"].as_str().unwrap_or("").to_string(),
+            size: a["size"].as_u64(),
+            content_type: a["content_type"].as_str().map(|v| v.to_string()),
+            // browser_download_url of private repository cannot be accessed with token, so use api url of the asset
+            api_url: a["url"].as_str().map(|v| v.to_string())
+        }).collect())
+    }
+    fn download_release_asset(&self, asset: &vcs::ReleaseAsset, path: &str) -> Result<(), Box<dyn Error>> {
+        self.api()?.download(asset.api_url.as_ref().unwrap_or(&asset.url), Path::new(path))
+    }
     fn rebase_with_remote_counterpart(&self, branch: &str) -> Result<(), Box<dyn Error>> {
         self.git.rebase_with_remote_counterpart(&self.pushable_remote_url()?, branch)
     }
@@ -504,7 +565,7 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
         &self, title: &str, head_branch: &str, base_branch: &str, options: &HashMap<&str, &str>
     ) -> Result<(), Box<dyn Error>> {
         let user_and_repo = self.user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
+        let client = self.api()?;
         let mut body = hashmap!{
             "title" => title, "owner" => &user_and_repo.0, 
             "repo" => &user_and_repo.1,
@@ -515,38 +576,26 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
                 body.insert(k, v);
             }
         }
-        let response = self.shell.exec(shell::args![
-            "curl", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token), 
-            "-H", "Accept: application/vnd.github.v3+json", 
-            format!(
-                "https://api.github.com/repos/{}/{}/pulls", 
-                user_and_repo.0, user_and_repo.1
-            ),
-            "-d", serde_json::to_string(&body)?
-            ], shell::no_env(), shell::no_cwd(), &shell::capture()
-        )?;
-        let issues_api_url = jsonpath(&response, "$.issue_url")
-            .expect(&format!("malform pulls response: {:?}", &response))
-            .expect(&format!("no issue_url in response: {:?}", &response));
+        let issues_api_url = client.post(&format!(
+            "/repos/{}/{}/pulls", user_and_repo.0, user_and_repo.1
+        ), &serde_json::to_value(&body)?)?.json::<PullRequest>()?.issue_url;
         match options.get("labels") {
             Some(labels) => {
                 log::debug!("attach labels({}) to PR via {}", labels, issues_api_url);
-                self.shell.exec(shell::args![
-                    "curl", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token), 
-                    "-H", "Accept: application/vnd.github.v3+json", format!("{}/labels", issues_api_url),
-                    "-d", format!(r#"{{"labels":{}}}"#, labels)
-                ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+                client.post(
+                    &format!("{}/labels", issues_api_url),
+                    &json!({"labels": serde_json::from_str::<JsonValue>(labels)?})
+                )?;
             },
             None => {}
         };
         match options.get("assignees") {
             Some(assignees) => {
                 log::debug!("assign accounts({}) to PR via {}", assignees, issues_api_url);
-                self.shell.exec(shell::args![
-                    "curl", "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token), 
-                    "-H", "Accept: application/vnd.github.v3+json", format!("{}/assignees", issues_api_url),
-                    "-d", format!(r#"{{"assignees":{}}}"#, assignees)
-                ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+                client.post(
+                    &format!("{}/assignees", issues_api_url),
+                    &json!({"assignees": serde_json::from_str::<JsonValue>(assignees)?})
+                )?;
             },
             None => {}
         };
@@ -559,32 +608,23 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
         let options = optmap_src.iter().map(|(k, v)| (*k, v.as_str())).collect::<HashMap<_, _>>();        
         // merge pr with github api
         let user_and_repo = self.user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
+        let client = self.api()?;
         let pr_num = url.split('/').last().unwrap_or("");
         if let Some(message) = options.get("message") {
-            let api_url = format!(
-                "https://api.github.com/repos/{}/{}/issues/{}/comments",
-                user_and_repo.0, user_and_repo.1, pr_num
-            );
-            self.shell.exec(shell::args![
-                "curl", "-sS", "-X", "POST", api_url,
-                "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-                "-H", "Accept: application/vnd.github.v3+json",
-                "-d", format!(r#"{{"body":"{}"}}"#, message)
-            ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+            client.post(&format!(
+                "/repos/{}/{}/issues/{}/comments", user_and_repo.0, user_and_repo.1, pr_num
+            ), &json!({"body": message}))?;
         }            
         // if options are set, approve first
         if options.get("approve").unwrap_or(&"true") == &"true" {
-            let api_url = format!(
-                "https://api.github.com/repos/{}/{}/pulls/{}/reviews",
-                user_and_repo.0, user_and_repo.1, pr_num
-            );
-            self.shell.exec(shell::args![
-                "curl", "-sS", "-X", "POST", api_url,
-                "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-                "-H", "Accept: application/vnd.github.v3+json",
-                "-d", r#"{"event":"APPROVE"}"#
-            ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+            // approval fails if the token is of PR author (eg. PR created by deplo itself).
+            // merge may still succeed if branch protection does not require review, so just warn it.
+            let response = client.request("POST", &format!(
+                "/repos/{}/{}/pulls/{}/reviews", user_and_repo.0, user_and_repo.1, pr_num
+            ), Some(api::Body::Json(&json!({"event": "APPROVE"}))))?;
+            if !response.is_success() {
+                log::warn!("fail to approve PR {} with status {}: {}", url, response.status, response.body);
+            }
         }
         // Check if auto_merge option is requested
         if options.get("auto_merge").unwrap_or(&"false") == &"true" {
@@ -593,10 +633,6 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
                 return Ok(()); // auto-merge enabled and not clean status. PR will wait for condition met
             }
         }
-        let api_url = format!(
-            "https://api.github.com/repos/{}/{}/pulls/{}/merge",
-            user_and_repo.0, user_and_repo.1, pr_num
-        );
         let default_message = format!("deplo version: {}, commit: {}", 
             config::DEPLO_VERSION, config::DEPLO_GIT_HASH);
         let default_commit_title = format!("PR #{} merged by deplo", pr_num);
@@ -605,13 +641,9 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
             "commit_message" => default_message.as_str()
         };
         let body = merge_hashmap(&default_body, &options);
-        let response_text = self.shell.exec(shell::args![
-            "curl", "-sS", "-X", "PUT", api_url,
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", "Accept: application/vnd.github.v3+json",
-            "-d", serde_json::to_string(&body)?
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        let response = serde_json::from_str::<MergeResult>(&response_text)?;
+        let response = client.put(&format!(
+            "/repos/{}/{}/pulls/{}/merge", user_and_repo.0, user_and_repo.1, pr_num
+        ), &serde_json::to_value(&body)?)?.json::<MergeResult>()?;
         if !response.merged {
             return escalate!(Box::new(vcs::VCSError {
                 cause: format!("failed to merge PR {}: {}", url, response.message)
@@ -626,71 +658,115 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
         &self, url: &str, opts: &JsonValue
     ) -> Result<(), Box<dyn Error>> {
         let user_and_repo = self.user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
+        let client = self.api()?;
         let pr_num = url.split('/').last().unwrap_or("");
-        let api_url = format!(
-            "https://api.github.com/repos/{}/{}/pulls/{}",
+        let api_path = format!(
+            "/repos/{}/{}/pulls/{}",
             user_and_repo.0, user_and_repo.1, pr_num
         );
         let optmap_src = json_to_strmap(&opts);
         let options = optmap_src.iter().map(|(k, v)| (*k, v.as_str())).collect::<HashMap<_, _>>();
         // if options["message"] is set, use it as comment to pull request, then close it
         if let Some(message) = options.get("message") {
-            let comment_url = format!("{}/comments", api_url).replace("pulls", "issues");
-            self.shell.exec(shell::args![
-                "curl", "-sS", "-X", "POST", comment_url,
-                "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-                "-H", "Accept: application/vnd.github.v3+json",
-                "-d", format!(r#"{{"body":"{}"}}"#, message)
-            ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+            let comment_path = format!("{}/comments", api_path).replace("pulls", "issues");
+            client.post(&comment_path, &json!({"body": message}))?;
         }
-        self.shell.exec(shell::args![
-            "curl", "-sS", "-X", "PATCH", api_url,
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", "Accept: application/vnd.github.v3+json",
-            "-d", r#"{"state":"closed"}"#
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        client.patch(&api_path, &json!({"state": "closed"}))?;
         Ok(())
     }
     fn search_pr(
         &self, filters: &Vec<String>
     ) -> Result<String, Box<dyn Error>> {
         let user_and_repo = self.user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
-        let mut args = shell::args![
-            "curl", "-fsSL", "-G",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", "Accept: application/vnd.github.v3+json",
-            format!("https://api.github.com/repos/{}/{}/issues", user_and_repo.0, user_and_repo.1)
-        ];
+        // filters are "key=value" form
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
         for filter in filters {
-            args.push(shell::arg!("--data-urlencode"));
-            args.push(shell::arg!(filter.clone()));
+            match filter.split_once('=') {
+                Some((k, v)) => query.append_pair(k, v),
+                None => query.append_key_only(filter)
+            };
         }
-        let response_text = self.shell.exec(args, shell::no_env(), shell::no_cwd(), &shell::capture())?;
-        let issues = serde_json::from_str::<Vec<JsonValue>>(&response_text)?;
+        let issues = self.api()?.paginate(&format!(
+            "/repos/{}/{}/issues?{}", user_and_repo.0, user_and_repo.1, query.finish()
+        ), None)?;
         let prs = issues.into_iter()
             .filter(|issue| issue.get("pull_request").is_some())
             .collect::<Vec<_>>();
         Ok(serde_json::to_string(&prs)?)
     }
+    fn find_pr(&self, head_branch: &str, base_branch: &str) -> Result<Option<vcs::PullRequest>, Box<dyn Error>> {
+        let user_and_repo = self.user_and_repo()?;
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("head", &format!("{}:{}", user_and_repo.0, head_branch))
+            .append_pair("base", base_branch)
+            .append_pair("state", "all")
+            .append_pair("sort", "created")
+            .append_pair("direction", "desc")
+            .append_pair("per_page", "1");
+        let prs = self.api()?.get(&format!(
+            "/repos/{}/{}/pulls?{}", user_and_repo.0, user_and_repo.1, query.finish()
+        ))?.json::<Vec<JsonValue>>()?;
+        Ok(prs.first()
+            // merged pull request cannot be reused
+            .filter(|pr| pr["merged_at"].is_null())
+            .and_then(|pr| pr["html_url"].as_str().map(|url| vcs::PullRequest {
+                url: url.to_string(), open: pr["state"] == "open"
+            })))
+    }
+    fn update_pr(
+        &self, url: &str, title: &str, options: &HashMap<&str, &str>
+    ) -> Result<(), Box<dyn Error>> {
+        let user_and_repo = self.user_and_repo()?;
+        let client = self.api()?;
+        let pr_num = url.rsplit('/').next().unwrap_or("");
+        let mut body = hashmap!{ "title" => title, "state" => "open" };
+        for (k, v) in options {
+            if *k != "labels" && *k != "assignees" {
+                body.insert(k, v);
+            }
+        }
+        client.patch(&format!(
+            "/repos/{}/{}/pulls/{}", user_and_repo.0, user_and_repo.1, pr_num
+        ), &serde_json::to_value(&body)?)?;
+        let issues_api_url = format!("/repos/{}/{}/issues/{}", user_and_repo.0, user_and_repo.1, pr_num);
+        if let Some(labels) = options.get("labels") {
+            // replace labels, to remove ones that are no longer configured
+            client.put(
+                &format!("{}/labels", issues_api_url),
+                &json!({"labels": serde_json::from_str::<JsonValue>(labels)?})
+            )?;
+        }
+        if let Some(assignees) = options.get("assignees") {
+            client.post(
+                &format!("{}/assignees", issues_api_url),
+                &json!({"assignees": serde_json::from_str::<JsonValue>(assignees)?})
+            )?;
+        }
+        Ok(())
+    }
+    fn open_pr_branches(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let user_and_repo = self.user_and_repo()?;
+        let full_name = format!("{}/{}", user_and_repo.0, user_and_repo.1);
+        let prs = self.api()?.paginate(&format!("/repos/{}/pulls?state=open&per_page=100", full_name), None)?;
+        // pull requests from fork have same branch name possibly
+        Ok(prs.iter()
+            .filter(|pr| pr["head"]["repo"]["full_name"] == full_name.as_str())
+            .filter_map(|pr| pr["head"]["ref"].as_str().map(|v| v.to_string()))
+            .collect())
+    }
     fn label(
         &self, name: &str, color: Option<&str>
     ) -> Result<(), Box<dyn Error>> {
         let user_and_repo = self.user_and_repo()?;
-        let (token, auth_type) = self.get_token()?;
         let mut body = serde_json::Map::new();
         body.insert("name".to_string(), JsonValue::String(name.to_string()));
         if let Some(color) = color {
             body.insert("color".to_string(), JsonValue::String(color.trim_start_matches('#').to_string()));
         }
-        self.shell.exec(shell::args![
-            "curl", "-fsSL", "-X", "POST",
-            "-H", shell::fmtargs!("Authorization: {} {}", auth_type, &token),
-            "-H", "Accept: application/vnd.github.v3+json",
-            format!("https://api.github.com/repos/{}/{}/labels", user_and_repo.0, user_and_repo.1),
-            "-d", serde_json::to_string(&JsonValue::Object(body))?
-        ], shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        self.api()?.post(
+            &format!("/repos/{}/{}/labels", user_and_repo.0, user_and_repo.1),
+            &JsonValue::Object(body)
+        )?;
         Ok(())
     }
     fn pr_url_from_env(&self) -> Result<Option<String>, Box<dyn Error>> {
@@ -711,65 +787,344 @@ impl<GIT: git::GitFeatures<S>, S: shell::Shell> vcs::VCS for Github<GIT, S> {
         &self, local_ref: &str, remote_branch: &str, option: &HashMap<&str, &str>
     ) -> Result<(), Box<dyn Error>> {
         self.with_remote_for_push(|remote_url| {
-            self.git.push_branch(remote_url, local_ref, remote_branch, option)
+            if !self.verified_commits() {
+                return self.git.push_branch(remote_url, local_ref, remote_branch, option);
+            }
+            // commits after base are re-created. for new branch, caller should give the base
+            let base = if !matches!(option.get("new"), Some(v) if !v.is_empty()) {
+                self.git.rebase_with_remote_counterpart(remote_url, remote_branch)?;
+                remote_branch
+            } else {
+                match option.get("base") {
+                    Some(v) => *v,
+                    None => {
+                        log::warn!("base of new branch {} is not given, push commits without verification", remote_branch);
+                        return self.git.push_branch(remote_url, local_ref, remote_branch, option);
+                    }
+                }
+            };
+            let unverified_ref = format!("{}{}", UNVERIFIED_REF_PREFIX, remote_branch);
+            let mut unverified_option = option.clone();
+            unverified_option.insert("new", "true");
+            self.git.push_branch(remote_url, local_ref, &unverified_ref, &unverified_option)?;
+            let head = self.git.commit_hash(Some(local_ref))?;
+            let force = option.get("force").is_some_and(|v| !v.is_empty());
+            self.push_verified(remote_url, base, &head, &unverified_ref, remote_branch, force)
         })
     }
+    fn files_to_push(
+        &self, patterns: &[&str], options: &HashMap<&str, &str>
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        self.git.files_to_push(patterns, options)
+    }
     fn push_diff(
         &self, branch: &str, msg: &str, patterns: &Vec<&str>, options: &HashMap<&str, &str>
     ) -> Result<bool, Box<dyn Error>> {
+        // branch may be given as fully qualified ref (refs/heads/...)
+        let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
         self.with_remote_for_push(|remote_url| {
-            self.git.push_diff(remote_url, branch, msg, patterns, options)
+            if !self.verified_commits() {
+                return self.git.push_diff(remote_url, branch, msg, patterns, options);
+            }
+            let base = self.git.commit_hash(None)?;
+            let unverified_ref = format!("{}{}", UNVERIFIED_REF_PREFIX, branch);
+            if !self.git.push_diff(remote_url, &unverified_ref, msg, patterns, options)? {
+                return Ok(false);
+            }
+            // local commit is discarded by push_diff, so take it from remote
+            let head = match self.git.remote_ref(remote_url, &unverified_ref)? {
+                Some(v) => v,
+                None => return escalate!(Box::new(vcs::VCSError {
+                    cause: format!("pushed commit is not found at {}", unverified_ref)
+                }))
+            };
+            self.push_verified(remote_url, &base, &head, &unverified_ref, branch, false)?;
+            Ok(true)
         })
     }
-    fn make_diff(&self) -> Result<String, Box<dyn Error>> {
-        let diff = match self.git.current_ref()? {
-            (vcs::RefType::Branch|vcs::RefType::Remote|vcs::RefType::Pull, _) => {
-                self.git.diff_paths("HEAD^")?
-            },
-            (vcs::RefType::Tag, ref_name) => {
-                let tags = self.with_remote_for_push(|remote_url| {
-                    self.git.tags(remote_url)
-                })?;
-                let index = tags.iter().position(|tag| 
-                    tag[1].replace("refs/tags/", "") == ref_name.as_str()
-                ).ok_or(
-                    make_escalation!(Box::new(vcs::VCSError {
-                        cause: format!("tag {} does not found for list {:?}", ref_name, tags)
-                    }))
-                )?;
-                if index == 0 {
-                    // this is first tag, so treat as it changes everyhing
-                    "*".to_string()
-                } else {
-                    let (src, dst) = (
-                        &tags[index - 1][1].replace("^{}", ""), 
-                        &tags[index][1].replace("^{}", "")
-                    );
-                    // fetch previous tag that does not usually fetched
-                    self.fetch_object(&tags[index - 1][0], src, Some(1))?;
-                    // diffing with previous tag
-                    self.git.diff_paths(&format!("{}..{}", src, dst))?
+    fn push_files(
+        &self, source: &str, remote_branch: &str, option: &HashMap<&str, &str>
+    ) -> Result<bool, Box<dyn Error>> {
+        self.with_remote_for_push(|remote_url| {
+            let git::FilesCommit { local_ref, parent } = match self.git.commit_files(
+                remote_url, source, remote_branch, option
+            )? {
+                Some(v) => v,
+                None => return Ok(false)
+            };
+            match parent {
+                Some(p) if self.verified_commits() => {
+                    let unverified_ref = format!("{}{}", UNVERIFIED_REF_PREFIX, remote_branch);
+                    self.git.push_branch(remote_url, &local_ref, &unverified_ref, &hashmap!{"new" => "true"})?;
+                    let head = self.git.commit_hash(Some(&local_ref))?;
+                    self.push_verified(remote_url, &p, &head, &unverified_ref, remote_branch, false)?;
+                },
+                // orphan commit cannot be re-created by compare api, so pushed as it is
+                _ => self.git.push_branch(
+                    remote_url, &local_ref, &format!("refs/heads/{}", remote_branch), &hashmap!{"new" => "true"}
+                )?
+            }
+            Ok(true)
+        })
+    }
+    fn diff_files(&self, expression: &str) -> Result<vcs::ChangeSet, Box<dyn Error>> {
+        self.git.diff_files(expression)
+    }
+    fn make_diff(
+        &self, base: Option<&vcs::DiffBase>, release_target: Option<&str>
+    ) -> Result<vcs::ChangeSet, Box<dyn Error>> {
+        match (base, release_target) {
+            (Some(vcs::DiffBase::MergeBase), _) => match self.git.current_ref()? {
+                (vcs::RefType::Pull, ref_name) => {
+                    let base = self.pr_data_from_ref_path(&ref_name, "$.base.ref")?;
+                    self.with_remote_for_push(|remote_url| git::diff_with_merge_base(&self.git, remote_url, &base))
+                },
+                (ty, ref_name) => {
+                    log::debug!("{}/{} is not pull request, diffing with previous commit", ty, ref_name);
+                    self.git.diff_files("HEAD^")
                 }
             },
-            (vcs::RefType::Commit, ref_name) => {
-                match self.git.diff_paths("HEAD^") {
-                    Ok(v) => v,
-                    Err(e) => return escalate!(Box::new(vcs::VCSError {
-                        cause: format!(
-                            "current head does not branch or tag {} and cannot get diff with HEAD^ {:?}", 
-                            ref_name, e
-                        )
-                    }))
-                }
-            }
-        };
-        Ok(diff)
-    }    
-    fn init_diff(&mut self, diff: String) -> Result<(), Box<dyn Error>> {
-        self.diff = diff.split('\n').map(|e| e.to_string()).collect();
+            (Some(vcs::DiffBase::PreviousCommit), _) => self.git.diff_files("HEAD^"),
+            (Some(vcs::DiffBase::LastReleaseTag), Some(rt)) => self.with_remote_for_push(|remote_url| {
+                git::diff_with_last_release_tag(&self.git, remote_url, &self.config, rt)
+            }),
+            (Some(vcs::DiffBase::LastDeploy), Some(rt)) => self.with_remote_for_push(|remote_url| {
+                git::diff_with_last_deploy(&self.git, remote_url, rt)
+            }),
+            (Some(b), None) => {
+                log::warn!("diff_base '{}' requires release target but current ref is not for release, fallback to default", b);
+                self.with_remote_for_push(|remote_url| git::default_diff(&self.git, remote_url))
+            },
+            (None, _) => self.with_remote_for_push(|remote_url| git::default_diff(&self.git, remote_url))
+        }
+    }
+    fn tags(&self) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        self.with_remote_for_push(|remote_url| {
+            self.git.tags(remote_url)
+        })
+    }
+    fn branches(&self) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        self.with_remote_for_push(|remote_url| {
+            self.git.branches(remote_url)
+        })
+    }
+    fn log(&self, expression: &str) -> Result<Vec<vcs::LogEntry>, Box<dyn Error>> {
+        self.git.log(expression)
+    }
+    fn push_tag(&self, tag_name: &str, commit: &str) -> Result<(), Box<dyn Error>> {
+        self.with_remote_for_push(|remote_url| {
+            self.git.push_tag(remote_url, tag_name, commit)
+        })
+    }
+    fn set_commit_status(
+        &self, commit: &str, context: &str, state: vcs::CommitState, url: Option<&str>, summary: &str
+    ) -> Result<(), Box<dyn Error>> {
+        let user_and_repo = self.user_and_repo()?;
+        // description is limited to 140 characters
+        let mut body = json!({
+            "state": state.to_string(), "context": context,
+            "description": summary.chars().take(140).collect::<String>()
+        });
+        if let Some(url) = url {
+            body["target_url"] = json!(url);
+        }
+        self.api()?.post(&format!(
+            "/repos/{}/{}/statuses/{}", user_and_repo.0, user_and_repo.1, commit
+        ), &body)?;
+        Ok(())
+    }
+    fn mark_deployed(&self, release_target: &str, commit: &str) -> Result<(), Box<dyn Error>> {
+        self.with_remote_for_push(|remote_url| {
+            self.git.push_ref(remote_url, commit, &vcs::deployed_ref_path(release_target))
+        })
+    }
+    fn init_diff(&mut self, diff: vcs::ChangeSet) -> Result<(), Box<dyn Error>> {
+        self.diff = diff;
         Ok(())
     }
-    fn diff<'b>(&'b self) -> &'b Vec<String> {
+    fn diff<'b>(&'b self) -> &'b vcs::ChangeSet {
         &self.diff
     }
 }
+
+#[cfg(all(test, not(feature="use-hyper")))]
+mod tests {
+    use super::*;
+    use crate::shell::mock;
+
+    fn github(api_shell: mock::Mock) -> Github<git::ShellGit<mock::Mock>, mock::Mock> {
+        let config = api_shell.config.clone();
+        config.borrow_mut().vcs = config::vcs::Account::Github {
+            email: Value::new("mail@address.com"),
+            account: Value::new("umegaya"),
+            key: Value::new("token"),
+            signing: None,
+        };
+        let git_shell = mock::Mock::with(&config, |args, _| match args {
+            [git, config, _, key] if git == "git" && config == "config" && key == "remote.origin.url" => {
+                Ok("https://github.com/o/r.git".to_string())
+            },
+            _ => Ok("".to_string())
+        });
+        Github {
+            config: config.clone(),
+            git: <git::ShellGit<mock::Mock> as git::GitFeatures<mock::Mock>>::from_pat(
+                &Value::new("umegaya"), &Value::new("mail@address.com"), &Value::new("token"), &None, git_shell
+            ).unwrap(),
+            shell: api_shell,
+            app_token_generator: None,
+            diff: vcs::ChangeSet::default()
+        }
+    }
+
+    #[test]
+    fn push_verified_commits_test() {
+        let config = config::Config::with(None).unwrap();
+        for exists in [true, false] {
+            let shell = mock::Mock::with(&config, mock::curl(|_, url, _| {
+                let body = if url.contains("/compare/") {
+                    json!({"merge_base_commit": {"sha": "base"}, "total_commits": 1, "commits": [
+                        {"sha": "c1", "commit": {"message": "msg", "tree": {"sha": "t1"}}}
+                    ]})
+                } else if url.ends_with("/git/commits") {
+                    json!({"sha": "v1"})
+                } else {
+                    json!({})
+                };
+                (200, mock::no_headers(), body.to_string())
+            }));
+            let client = api::Client::new(&shell, Value::new("token"), "Bearer");
+            push_verified_commits(&client, "/repos/o/r", "main", "head", "refs/heads/main", exists, false).unwrap();
+            delete_ref(&client, "/repos/o/r", "refs/deplo/unverified/main");
+            let requests = shell.commands.borrow().iter().map(|c| format!("{} {}", c[3], c[4])).collect::<Vec<_>>();
+            assert_eq!(requests, vec![
+                "GET https://api.github.com/repos/o/r/compare/main...head",
+                "POST https://api.github.com/repos/o/r/git/commits",
+                if exists {
+                    "PATCH https://api.github.com/repos/o/r/git/refs/heads/main"
+                } else {
+                    "POST https://api.github.com/repos/o/r/git/refs"
+                },
+                "DELETE https://api.github.com/repos/o/r/git/refs/deplo/unverified/main",
+            ]);
+        }
+    }
+    #[test]
+    fn verified_push_diff_test() {
+        use std::sync::{Arc, Mutex};
+        let config = config::Config::with(None).unwrap();
+        config.borrow_mut().vcs = config::vcs::Account::GithubApp {
+            app_id: Value::new("1"), pkey: Value::new("pkey"), local_fallback: None, verified_commits: Some(true)
+        };
+        let api_requests = Arc::new(Mutex::new(vec![]));
+        let recorder = api_requests.clone();
+        let api_shell = mock::Mock::with(&config, mock::curl(move |method, url, body| {
+            recorder.lock().unwrap().push(format!("{} {} {}", method, url, body.unwrap_or_default()));
+            let body = if url.contains("/compare/") {
+                json!({"merge_base_commit": {"sha": "base"}, "total_commits": 1, "commits": [
+                    {"sha": "head", "commit": {"message": "msg", "tree": {"sha": "t1"}}}
+                ]})
+            } else if url.ends_with("/git/commits") {
+                json!({"sha": "verified"})
+            } else {
+                json!({})
+            };
+            (200, mock::no_headers(), body.to_string())
+        }));
+        let pushes = Arc::new(Mutex::new(vec![]));
+        let recorder = pushes.clone();
+        let git_shell = mock::Mock::with(&config, move |args, _| match (args[0].as_str(), args.last().unwrap().as_str()) {
+            _ if args.iter().any(|a| a == "ls-remote") => Ok(match args.last().unwrap().as_str() {
+                "refs/deplo/unverified/main" => "head\trefs/deplo/unverified/main".to_string(),
+                _ => "".to_string()
+            }),
+            _ if args.iter().any(|a| a == "push") => {
+                recorder.lock().unwrap().push(args.last().unwrap().clone());
+                Ok("".to_string())
+            },
+            (_, "remote.origin.url") => Ok("https://github.com/o/r.git".to_string()),
+            (_, "HEAD") => Ok("base".to_string()),
+            _ if args[1] == "add" && args[2] == "-n" => Ok("add 'a.txt'".to_string()),
+            _ => Ok("".to_string())
+        });
+        let app_token_generator = AppTokenGenerator::new(
+            mock::Mock::with(&config, |args, _| panic!("unexpected token request {:?}", args)),
+            Value::new("1"), Value::new("pkey"), ("o".to_string(), "r".to_string())
+        );
+        {
+            let mut inner = app_token_generator.inner.borrow_mut();
+            inner.token = Value::new("app-token");
+            inner.token_expires_at = SystemTime::now() + std::time::Duration::from_secs(3600);
+        }
+        let github = Github {
+            config: config.clone(),
+            git: <git::ShellGit<mock::Mock> as git::GitFeatures<mock::Mock>>::from_pat(
+                &Value::new("o"), &Value::new("mail@address.com"), &Value::new("token"), &None, git_shell
+            ).unwrap(),
+            shell: api_shell,
+            app_token_generator: Some(app_token_generator),
+            diff: vcs::ChangeSet::default()
+        };
+        // fully qualified branch name is accepted as well as short name
+        assert!((&github as &dyn vcs::VCS).push_diff(
+            "refs/heads/main", "update", &vec!["a.txt"], &HashMap::new()
+        ).unwrap());
+        assert_eq!(*pushes.lock().unwrap(), vec!["HEAD:refs/deplo/unverified/main"]);
+        let api_requests = api_requests.lock().unwrap();
+        assert_eq!(api_requests.len(), 4, "{:?}", api_requests);
+        assert!(api_requests[2].starts_with("POST https://api.github.com/repos/o/r/git/refs "), "{}", api_requests[2]);
+        assert!(api_requests[2].contains(r#""ref":"refs/heads/main""#), "{}", api_requests[2]);
+        assert_eq!(api_requests[3].trim(), "DELETE https://api.github.com/repos/o/r/git/refs/deplo/unverified/main");
+    }
+    #[test]
+    fn reuse_pull_request_test() {
+        let config = config::Config::with(None).unwrap();
+        let bodies = Rc::new(RefCell::new(vec![]));
+        let recorder = bodies.clone();
+        let shell = mock::Mock::with(&config, mock::curl(move |method, url, body| {
+            if let Some(b) = body {
+                recorder.borrow_mut().push(serde_json::from_str::<JsonValue>(&b).unwrap());
+            }
+            let found = |state: &str, merged_at: JsonValue| json!([{
+                "html_url": "https://github.com/o/r/pull/5", "state": state, "merged_at": merged_at
+            }]);
+            let response = match (method, url) {
+                ("GET", u) if u.contains("head=o%3Adeplo%2Fopen&base=main&state=all") => found("open", JsonValue::Null),
+                ("GET", u) if u.contains("head=o%3Adeplo%2Fclosed&") => found("closed", JsonValue::Null),
+                ("GET", u) if u.contains("head=o%3Adeplo%2Fmerged&") => found("closed", json!("2024-01-01T00:00:00Z")),
+                ("GET", _) => json!([]),
+                _ => json!({})
+            };
+            (200, mock::no_headers(), response.to_string())
+        }));
+        let github = github(shell);
+        let open = github.find_pr("deplo/open", "main").unwrap().unwrap();
+        assert_eq!(open.url, "https://github.com/o/r/pull/5");
+        assert!(open.open);
+        assert!(!github.find_pr("deplo/closed", "main").unwrap().unwrap().open);
+        // merged pull request cannot be reused
+        assert!(github.find_pr("deplo/merged", "main").unwrap().is_none());
+        assert!(github.find_pr("deplo/none", "main").unwrap().is_none());
+        github.update_pr(&open.url, "new title", &hashmap!{
+            "labels" => r#"["docs"]"#, "assignees" => r#"["umegaya"]"#, "body" => "new body"
+        }).unwrap();
+        github.close_pr(&open.url, &json!({"message": "no diff"})).unwrap();
+        let requests = github.shell.commands.borrow().iter()
+            .map(|c| format!("{} {}", c[3], c[4])).skip(4).collect::<Vec<_>>();
+        assert_eq!(requests, vec![
+            "PATCH https://api.github.com/repos/o/r/pulls/5",
+            "PUT https://api.github.com/repos/o/r/issues/5/labels",
+            "POST https://api.github.com/repos/o/r/issues/5/assignees",
+            "POST https://api.github.com/repos/o/r/issues/5/comments",
+            "PATCH https://api.github.com/repos/o/r/pulls/5",
+        ]);
+        // closed pull request is reopened by update
+        assert_eq!(*bodies.borrow(), vec![
+            json!({"title": "new title", "state": "open", "body": "new body"}),
+            json!({"labels": ["docs"]}),
+            json!({"assignees": ["umegaya"]}),
+            json!({"body": "no diff"}),
+            json!({"state": "closed"}),
+        ]);
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Value as JsonValue};
+
+use crate::config;
+use crate::shell;
+use crate::util::{escalate};
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+const GITHUB_API_VERSION: &str = "2022-11-28";
+// if rate limit resets later than this, request fails instead of waiting for the reset
+const MAX_RATE_LIMIT_WAIT_SECS: u64 = 60;
+const MAX_RATE_LIMIT_RETRY: u32 = 3;
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    pub body: String,
+}
+impl ApiError {
+    // github returns error detail as {"message":"...", "errors": [...]}
+    pub fn message(&self) -> String {
+        match serde_json::from_str::<JsonValue>(&self.body) {
+            Ok(v) => match v.get("message").and_then(|m| m.as_str()) {
+                Some(m) => match v.get("errors") {
+                    Some(errors) => format!("{} {}", m, errors),
+                    None => m.to_string()
+                },
+                None => self.body.clone()
+            },
+            Err(_) => self.body.clone()
+        }
+    }
+}
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} fails with status {}: {}", self.method, self.url, self.status, self.message())
+    }
+}
+impl Error for ApiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+pub enum Body<'a> {
+    Json(&'a JsonValue),
+    File { path: &'a str, content_type: &'a str },
+}
+
+pub struct Response {
+    pub status: u16,
+    // header names are lower cased
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Box<dyn Error>> {
+        Ok(serde_json::from_str::<T>(&self.body)?)
+    }
+    // url of next page, from Link: <https://api.github.com/...?page=2>; rel="next", <...>; rel="last"
+    pub fn next_page(&self) -> Option<String> {
+        self.header("link")?.split(',').find_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            if params.split(';').any(|p| p.trim() == "rel=\"next\"") {
+                Some(url.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            } else {
+                None
+            }
+        })
+    }
+    // returns duration to wait if the request is rejected by rate limit
+    fn rate_limit_wait(&self) -> Option<Duration> {
+        if self.status != 403 && self.status != 429 {
+            return None
+        }
+        // secondary rate limit
+        if let Some(v) = self.header("retry-after") {
+            return v.trim().parse::<u64>().ok().map(Duration::from_secs);
+        }
+        // primary rate limit
+        if self.header("x-ratelimit-remaining") == Some("0") {
+            let reset = self.header("x-ratelimit-reset")?.trim().parse::<u64>().ok()?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+            return Some(Duration::from_secs(reset.saturating_sub(now) + 1));
+        }
+        None
+    }
+}
+
+// parse headers dumped by curl -D. header names are lower cased.
+// if curl follows redirect or receives 100 continue, dump contains multiple header blocks.
+// last block is for the response of status.
+#[cfg_attr(feature="use-hyper", allow(dead_code))]
+fn parse_header_dump(dump: &str) -> HashMap<String, String> {
+    match dump.trim_end().rsplit_once("\r\n\r\n") {
+        Some((_, last)) => last,
+        None => dump.trim_end()
+    }.lines().filter_map(|l| l.split_once(':')).map(|(k, v)| (
+        k.trim().to_lowercase(), v.trim().to_string()
+    )).collect()
+}
+
+pub struct Client<'a, S: shell::Shell = shell::Default> {
+    // used to run curl if use-hyper feature is disabled
+    #[cfg_attr(feature="use-hyper", allow(dead_code))]
+    shell: &'a S,
+    authorization: (config::Value, String),
+}
+impl<'a, S: shell::Shell> Client<'a, S> {
+    pub fn new(shell: &'a S, token: config::Value, auth_type: &str) -> Self {
+        Client {
+            shell,
+            authorization: (token, auth_type.to_string())
+        }
+    }
+    pub fn url(path: &str) -> String {
+        if path.starts_with("https://") || path.starts_with("http://") {
+            path.to_string()
+        } else {
+            format!("{}{}", GITHUB_API_URL, path)
+        }
+    }
+    fn headers(&self, accept: &str) -> Vec<(String, String)> {
+        vec![
+            ("Authorization".to_string(), format!("{} {}", self.authorization.1, self.authorization.0.resolve())),
+            ("Accept".to_string(), accept.to_string()),
+            ("X-GitHub-Api-Version".to_string(), GITHUB_API_VERSION.to_string()),
+            ("User-Agent".to_string(), format!("deplo/{}", config::DEPLO_VERSION)),
+        ]
+    }
+    // send request and returns response regardless of its status.
+    // if the request is rejected by rate limit, it is retried after the limit resets.
+    pub fn request(
+        &self, method: &str, path: &str, body: Option<Body>
+    ) -> Result<Response, Box<dyn Error>> {
+        let url = Self::url(path);
+        let mut headers = self.headers("application/vnd.github.v3+json");
+        match &body {
+            Some(Body::Json(_)) => headers.push(("Content-Type".to_string(), "application/json".to_string())),
+            Some(Body::File { content_type, .. }) => headers.push(("Content-Type".to_string(), content_type.to_string())),
+            None => {}
+        };
+        let mut retry = 0;
+        loop {
+            let response = self.send(method, &url, &headers, &body, None)?;
+            match response.rate_limit_wait() {
+                Some(wait) if retry < MAX_RATE_LIMIT_RETRY && wait.as_secs() <= MAX_RATE_LIMIT_WAIT_SECS => {
+                    log::warn!("{} {} is rate limited, retry after {} seconds", method, url, wait.as_secs());
+                    sleep(wait);
+                    retry += 1;
+                },
+                _ => return Ok(response)
+            }
+        }
+    }
+    // same as request but treats non 2xx status as ApiError
+    pub fn call(
+        &self, method: &str, path: &str, body: Option<Body>
+    ) -> Result<Response, Box<dyn Error>> {
+        let response = self.request(method, path, body)?;
+        if !response.is_success() {
+            return escalate!(Box::new(ApiError {
+                method: method.to_string(), url: Self::url(path),
+                status: response.status, body: response.body
+            }));
+        }
+        Ok(response)
+    }
+    pub fn get(&self, path: &str) -> Result<Response, Box<dyn Error>> {
+        self.call("GET", path, None)
+    }
+    pub fn post(&self, path: &str, body: &JsonValue) -> Result<Response, Box<dyn Error>> {
+        self.call("POST", path, Some(Body::Json(body)))
+    }
+    pub fn put(&self, path: &str, body: &JsonValue) -> Result<Response, Box<dyn Error>> {
+        self.call("PUT", path, Some(Body::Json(body)))
+    }
+    pub fn patch(&self, path: &str, body: &JsonValue) -> Result<Response, Box<dyn Error>> {
+        self.call("PATCH", path, Some(Body::Json(body)))
+    }
+    pub fn delete(&self, path: &str) -> Result<Response, Box<dyn Error>> {
+        self.call("DELETE", path, None)
+    }
+    // download binary content (eg. release asset) to dest.
+    // redirect is followed manually, because redirected url (signed url of storage) rejects Authorization header.
+    pub fn download(&self, path: &str, dest: &Path) -> Result<(), Box<dyn Error>> {
+        let url = Self::url(path);
+        let mut response = self.send("GET", &url, &self.headers("application/octet-stream"), &None, Some(dest))?;
+        if (300..400).contains(&response.status) {
+            let location = match response.header("location") {
+                Some(v) => v.to_string(),
+                None => return escalate!(Box::new(ApiError {
+                    method: "GET".to_string(), url, status: response.status,
+                    body: "redirect without location header".to_string()
+                }))
+            };
+            log::debug!("download redirected to {}", location.split('?').next().unwrap_or(""));
+            response = self.send("GET", &location, &[
+                ("User-Agent".to_string(), format!("deplo/{}", config::DEPLO_VERSION))
+            ], &None, Some(dest))?;
+        }
+        if !response.is_success() {
+            // error response is written to dest
+            let body = std::fs::read_to_string(dest).unwrap_or_default();
+            std::fs::remove_file(dest).ok();
+            return escalate!(Box::new(ApiError {
+                method: "GET".to_string(), url, status: response.status, body
+            }));
+        }
+        Ok(())
+    }
+    // follows Link header and returns items of all pages.
+    // if key is given, items are taken from the array in response object (eg. {"total_count":10,"secrets":[...]})
+    pub fn paginate(&self, path: &str, key: Option<&str>) -> Result<Vec<JsonValue>, Box<dyn Error>> {
+        let mut items = vec![];
+        let mut next = Some(path.to_string());
+        while let Some(url) = next {
+            let response = self.get(&url)?;
+            let parsed = response.json::<JsonValue>()?;
+            let page = match key {
+                Some(k) => parsed.get(k).cloned(),
+                None => Some(parsed)
+            };
+            match page {
+                Some(JsonValue::Array(v)) => items.extend(v),
+                _ => return escalate!(Box::new(ApiError {
+                    method: "GET".to_string(), url: Self::url(&url), status: response.status,
+                    body: format!("response does not contain list of items: {}", response.body)
+                }))
+            }
+            next = response.next_page();
+        }
+        Ok(items)
+    }
+    // if output is given, response body is written to the file instead of Response::body
+    #[cfg(feature="use-hyper")]
+    fn send(
+        &self, method: &str, url: &str, headers: &[(String, String)], body: &Option<Body>, output: Option<&Path>
+    ) -> Result<Response, Box<dyn Error>> {
+        use http_body_util::{BodyExt, Full};
+        use hyper::body::Bytes;
+
+        let payload = match body {
+            Some(Body::Json(v)) => serde_json::to_vec(v)?,
+            Some(Body::File { path, .. }) => std::fs::read(path)?,
+            None => vec![]
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(async {
+            let connector = hyper_rustls::HttpsConnectorBuilder::new()
+                .with_webpki_roots()
+                .https_or_http()
+                .enable_http1()
+                .build();
+            let client = hyper_util::client::legacy::Client::builder(
+                hyper_util::rt::TokioExecutor::new()
+            ).build::<_, Full<Bytes>>(connector);
+            let mut builder = hyper::Request::builder().method(method).uri(url);
+            for (k, v) in headers {
+                builder = builder.header(k, v);
+            }
+            let response = client.request(builder.body(Full::new(Bytes::from(payload)))?).await?;
+            let status = response.status().as_u16();
+            let headers = response.headers().iter().map(|(k, v)| (
+                k.as_str().to_lowercase(), String::from_utf8_lossy(v.as_bytes()).to_string()
+            )).collect::<HashMap<String, String>>();
+            let body = response.into_body().collect().await?.to_bytes();
+            Ok(Response { status, headers, body: match output {
+                Some(path) => {
+                    std::fs::write(path, &body)?;
+                    String::new()
+                },
+                None => String::from_utf8_lossy(&body).to_string()
+            }})
+        })
+    }
+    #[cfg(not(feature="use-hyper"))]
+    fn send(
+        &self, method: &str, url: &str, headers: &[(String, String)], body: &Option<Body>, output: Option<&Path>
+    ) -> Result<Response, Box<dyn Error>> {
+        use std::io::Write;
+        // headers (includes token) and body are passed via file, not to expose them in process arguments
+        let mut header_file = tempfile::NamedTempFile::new()?;
+        for (k, v) in headers {
+            writeln!(header_file, "{}: {}", k, v)?;
+        }
+        header_file.flush()?;
+        let response_header_file = tempfile::NamedTempFile::new()?;
+        let response_body_file = tempfile::NamedTempFile::new()?;
+        let mut body_file = tempfile::NamedTempFile::new()?;
+        let body_path = output.unwrap_or(response_body_file.path());
+        let mut args = shell::args![
+            "curl", "-sS", "-X", method.to_string(), url.to_string(),
+            "-H", format!("@{}", header_file.path().to_string_lossy()),
+            "-D", response_header_file.path().to_string_lossy().to_string(),
+            "-o", body_path.to_string_lossy().to_string(),
+            "-w", "%{http_code}"
+        ];
+        match body {
+            Some(Body::Json(v)) => {
+                body_file.write_all(serde_json::to_string(v)?.as_bytes())?;
+                body_file.flush()?;
+                args.push(shell::arg!("--data-binary"));
+                args.push(shell::arg!(format!("@{}", body_file.path().to_string_lossy())));
+            },
+            Some(Body::File { path, .. }) => {
+                args.push(shell::arg!("--data-binary"));
+                args.push(shell::arg!(format!("@{}", path)));
+            },
+            None => {}
+        };
+        let status = self.shell.exec(args, shell::no_env(), shell::no_cwd(), &shell::capture())?;
+        let headers = parse_header_dump(&std::fs::read_to_string(response_header_file.path())?);
+        Ok(Response {
+            status: match status.trim().parse::<u16>() {
+                Ok(s) => s,
+                Err(_) => return escalate!(Box::new(ApiError {
+                    method: method.to_string(), url: url.to_string(), status: 0,
+                    body: format!("invalid status code from curl: {}", status)
+                }))
+            },
+            headers,
+            body: match output {
+                Some(_) => String::new(),
+                None => std::fs::read_to_string(response_body_file.path())?
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashmap;
+
+    fn response(status: u16, headers: HashMap<&str, &str>) -> Response {
+        Response {
+            status, body: String::new(),
+            headers: headers.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        }
+    }
+
+    #[test]
+    fn parse_header_dump_test() {
+        let headers = parse_header_dump(concat!(
+            "HTTP/1.1 100 Continue\r\n\r\n",
+            "HTTP/2 200\r\n",
+            "Link: <https://api.github.com/repositories/1/pulls?page=2>; rel=\"next\", ",
+            "<https://api.github.com/repositories/1/pulls?page=5>; rel=\"last\"\r\n",
+            "Retry-After: 30\r\n\r\n"
+        ));
+        assert_eq!(headers.len(), 2);
+        let r = Response { status: 200, headers, body: String::new() };
+        assert_eq!(r.header("Retry-After"), Some("30"));
+        assert_eq!(r.next_page().unwrap(), "https://api.github.com/repositories/1/pulls?page=2");
+    }
+
+    #[test]
+    fn next_page_test() {
+        let r = response(200, hashmap!{
+            "link" => "<https://api.github.com/x?page=1>; rel=\"prev\", <https://api.github.com/x?page=3>; rel=\"next\""
+        });
+        assert_eq!(r.next_page().unwrap(), "https://api.github.com/x?page=3");
+        // last page
+        assert!(response(200, hashmap!{
+            "link" => "<https://api.github.com/x?page=1>; rel=\"first\", <https://api.github.com/x?page=2>; rel=\"prev\""
+        }).next_page().is_none());
+        assert!(response(200, hashmap!{}).next_page().is_none());
+    }
+
+    #[test]
+    fn rate_limit_wait_test() {
+        // secondary rate limit
+        assert_eq!(response(403, hashmap!{"retry-after" => "30"}).rate_limit_wait(), Some(Duration::from_secs(30)));
+        assert_eq!(response(429, hashmap!{"retry-after" => " 5 "}).rate_limit_wait(), Some(Duration::from_secs(5)));
+        assert_eq!(response(429, hashmap!{"retry-after" => "soon"}).rate_limit_wait(), None);
+        // primary rate limit waits until reset
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let reset = (now + 10).to_string();
+        let wait = response(403, hashmap!{
+            "x-ratelimit-remaining" => "0", "x-ratelimit-reset" => reset.as_str()
+        }).rate_limit_wait().unwrap();
+        assert!(wait <= Duration::from_secs(11) && wait >= Duration::from_secs(9), "{:?}", wait);
+        // already reset
+        assert_eq!(response(403, hashmap!{
+            "x-ratelimit-remaining" => "0", "x-ratelimit-reset" => "0"
+        }).rate_limit_wait(), Some(Duration::from_secs(1)));
+        // not rate limited
+        assert_eq!(response(403, hashmap!{"x-ratelimit-remaining" => "10"}).rate_limit_wait(), None);
+        assert_eq!(response(200, hashmap!{"retry-after" => "30"}).rate_limit_wait(), None);
+    }
+}
@@ -0,0 +1,332 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::error::Error;
+
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{json, Value as JsonValue};
+
+use crate::config;
+use crate::config::value::Value;
+use crate::module;
+use crate::shell;
+use crate::util::{escalate};
+use crate::vcs;
+
+#[derive(Deserialize)]
+struct Token {
+    token: String,
+    #[serde(rename = "type")]
+    ty: String
+}
+#[derive(Deserialize)]
+struct Ref {
+    #[serde(rename = "type")]
+    ty: String,
+    path: String
+}
+#[derive(Deserialize)]
+struct UserAndRepo {
+    user: String,
+    repo: String
+}
+
+// vcs implementation that delegates each method to vcs entrypoint of the module
+pub struct ModuleRunner<S: shell::Shell = shell::Default> {
+    config: config::Container,
+    module_key: String,
+    with: Option<HashMap<String, config::AnyValue>>,
+    shell: S,
+    token_type: OnceCell<String>,
+    // fetched when the module is loaded, so that failure of it stops deplo early
+    release_target: Option<String>,
+    diff: vcs::ChangeSet
+}
+impl<S: shell::Shell> ModuleRunner<S> {
+    fn with(config: &config::Container, shell: S) -> Result<Self, Box<dyn Error>> {
+        let (module_key, with) = match &config.borrow().vcs {
+            config::vcs::Account::Module(c) => c.value(|v| (v.uses.to_string(), v.with.clone())),
+            _ => return escalate!(Box::new(vcs::VCSError {
+                cause: format!("should have module config but {}", config.borrow().vcs)
+            }))
+        };
+        let mut runner = ModuleRunner::<S> {
+            config: config.clone(),
+            module_key, with, shell,
+            token_type: OnceCell::new(),
+            release_target: None,
+            diff: vcs::ChangeSet::default()
+        };
+        runner.release_target = runner.call("release_target", json!({}))?;
+        Ok(runner)
+    }
+    fn call<R: DeserializeOwned>(&self, method: &str, request: JsonValue) -> Result<R, Box<dyn Error>> {
+        let c = self.config.borrow();
+        let module = c.modules.repos().get(&self.module_key);
+        module.call(
+            module::EntryPointType::VCS, &self.shell, method, &request,
+            module::empty_env(), &self.with
+        )
+    }
+    fn ref_type_from_str(&self, ty: &str) -> Result<vcs::RefType, Box<dyn Error>> {
+        match ty {
+            "branch" => Ok(vcs::RefType::Branch),
+            "remote" => Ok(vcs::RefType::Remote),
+            "tag" => Ok(vcs::RefType::Tag),
+            "pull" => Ok(vcs::RefType::Pull),
+            "commit" => Ok(vcs::RefType::Commit),
+            _ => escalate!(Box::new(vcs::VCSError {
+                cause: format!("module {} returns invalid ref type {}", self.module_key, ty)
+            }))
+        }
+    }
+}
+
+impl<S: shell::Shell> vcs::VCS for ModuleRunner<S> {
+    fn new(config: &config::Container) -> Result<ModuleRunner<S>, Box<dyn Error>> {
+        Self::with(config, S::new(config))
+    }
+    fn get_token(&self) -> Result<(Value, &str), Box<dyn Error>> {
+        let token = self.call::<Token>("get_token", json!({}))?;
+        Ok((Value::new_sensitive(&token.token), self.token_type.get_or_init(|| token.ty)))
+    }
+    fn release_target(&self) -> Option<String> {
+        self.release_target.clone()
+    }
+    fn current_ref(&self) -> Result<(vcs::RefType, String), Box<dyn Error>> {
+        let r = self.call::<Ref>("current_ref", json!({}))?;
+        Ok((self.ref_type_from_str(&r.ty)?, r.path))
+    }
+    fn delete_branch(&self, ref_type: vcs::RefType, ref_path: &str) -> Result<(), Box<dyn Error>> {
+        self.call("delete_branch", json!({"ref_type": ref_type.to_string(), "ref_path": ref_path}))
+    }
+    fn fetch_branch(&self, branch_name: &str) -> Result<(), Box<dyn Error>> {
+        self.call("fetch_branch", json!({"branch_name": branch_name}))
+    }
+    fn fetch_object(&self, hash: &str, ref_name: &str, depth: Option<usize>) -> Result<(), Box<dyn Error>> {
+        self.call("fetch_object", json!({"hash": hash, "ref_name": ref_name, "depth": depth}))
+    }
+    fn squash_branch(&self, n: usize) -> Result<(), Box<dyn Error>> {
+        self.call("squash_branch", json!({"n": n}))
+    }
+    fn commit_hash(&self, expr: Option<&str>) -> Result<String, Box<dyn Error>> {
+        self.call("commit_hash", json!({"expr": expr}))
+    }
+    fn checkout(&self, commit: &str, branch_name: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.call("checkout", json!({"commit": commit, "branch_name": branch_name}))
+    }
+    fn repository_root(&self) -> Result<String, Box<dyn Error>> {
+        self.call("repository_root", json!({}))
+    }
+    fn rebase_with_remote_counterpart(&self, branch: &str) -> Result<(), Box<dyn Error>> {
+        self.call("rebase_with_remote_counterpart", json!({"branch": branch}))
+    }
+    fn search_remote_ref(&self, commit: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.call("search_remote_ref", json!({"commit": commit}))
+    }
+    fn pick_ref(&self, target: &str) -> Result<(), Box<dyn Error>> {
+        self.call("pick_ref", json!({"target": target}))
+    }
+    fn push_branch(
+        &self, local_ref: &str, remote_branch: &str, option: &HashMap<&str, &str>
+    ) -> Result<(), Box<dyn Error>> {
+        self.call("push_branch", json!({
+            "local_ref": local_ref, "remote_branch": remote_branch, "option": option
+        }))
+    }
+    fn pr(
+        &self, title: &str, head_branch: &str, base_branch: &str, option: &HashMap<&str, &str>
+    ) -> Result<(), Box<dyn Error>> {
+        self.call("pr", json!({
+            "title": title, "head_branch": head_branch, "base_branch": base_branch, "option": option
+        }))
+    }
+    fn merge_pr(&self, url: &str, options: &JsonValue) -> Result<(), Box<dyn Error>> {
+        self.call("merge_pr", json!({"url": url, "options": options}))
+    }
+    fn close_pr(&self, url: &str, opts: &JsonValue) -> Result<(), Box<dyn Error>> {
+        self.call("close_pr", json!({"url": url, "options": opts}))
+    }
+    fn search_pr(&self, filters: &Vec<String>) -> Result<String, Box<dyn Error>> {
+        self.call("search_pr", json!({"filters": filters}))
+    }
+    fn find_pr(&self, head_branch: &str, base_branch: &str) -> Result<Option<vcs::PullRequest>, Box<dyn Error>> {
+        self.call("find_pr", json!({"head_branch": head_branch, "base_branch": base_branch}))
+    }
+    fn update_pr(
+        &self, url: &str, title: &str, option: &HashMap<&str, &str>
+    ) -> Result<(), Box<dyn Error>> {
+        self.call("update_pr", json!({"url": url, "title": title, "option": option}))
+    }
+    fn label(&self, name: &str, color: Option<&str>) -> Result<(), Box<dyn Error>> {
+        self.call("label", json!({"name": name, "color": color}))
+    }
+    fn pr_url_from_env(&self) -> Result<Option<String>, Box<dyn Error>> {
+        self.call("pr_url_from_env", json!({}))
+    }
+    fn user_and_repo(&self) -> Result<(String, String), Box<dyn Error>> {
+        let r = self.call::<UserAndRepo>("user_and_repo", json!({}))?;
+        Ok((r.user, r.repo))
+    }
+    fn release(
+        &self, target_ref: (&str, bool), opts: &JsonValue
+    ) -> Result<String, Box<dyn Error>> {
+        self.call("release", json!({
+            "target_ref": target_ref.0, "is_branch": target_ref.1, "options": opts
+        }))
+    }
+    fn release_assets(
+        &self, target_ref: (&str, bool), asset_file_path: &str, opts: &JsonValue
+    ) -> Result<String, Box<dyn Error>> {
+        self.call("release_assets", json!({
+            "target_ref": target_ref.0, "is_branch": target_ref.1,
+            "asset_file_path": asset_file_path, "options": opts
+        }))
+    }
+    fn list_release_assets(&self, target_ref: (&str, bool)) -> Result<Vec<vcs::ReleaseAsset>, Box<dyn Error>> {
+        self.call("list_release_assets", json!({"target_ref": target_ref.0, "is_branch": target_ref.1}))
+    }
+    fn download_release_asset(&self, asset: &vcs::ReleaseAsset, path: &str) -> Result<(), Box<dyn Error>> {
+        self.call("download_release_asset", json!({"asset": asset, "path": path}))
+    }
+    fn make_diff(
+        &self, base: Option<&vcs::DiffBase>, release_target: Option<&str>
+    ) -> Result<vcs::ChangeSet, Box<dyn Error>> {
+        self.call("make_diff", json!({"base": base, "release_target": release_target}))
+    }
+    fn diff_files(&self, expression: &str) -> Result<vcs::ChangeSet, Box<dyn Error>> {
+        self.call("diff_files", json!({"expression": expression}))
+    }
+    fn tags(&self) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        self.call("tags", json!({}))
+    }
+    fn branches(&self) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+        self.call("branches", json!({}))
+    }
+    fn open_pr_branches(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.call("open_pr_branches", json!({}))
+    }
+    fn log(&self, expression: &str) -> Result<Vec<vcs::LogEntry>, Box<dyn Error>> {
+        self.call("log", json!({"expression": expression}))
+    }
+    fn push_tag(&self, tag_name: &str, commit: &str) -> Result<(), Box<dyn Error>> {
+        self.call("push_tag", json!({"tag_name": tag_name, "commit": commit}))
+    }
+    fn set_commit_status(
+        &self, commit: &str, context: &str, state: vcs::CommitState, url: Option<&str>, summary: &str
+    ) -> Result<(), Box<dyn Error>> {
+        self.call("set_commit_status", json!({
+            "commit": commit, "context": context, "state": state.to_string(), "url": url, "summary": summary
+        }))
+    }
+    fn mark_deployed(&self, release_target: &str, commit: &str) -> Result<(), Box<dyn Error>> {
+        self.call("mark_deployed", json!({"release_target": release_target, "commit": commit}))
+    }
+    fn init_diff(&mut self, diff: vcs::ChangeSet) -> Result<(), Box<dyn Error>> {
+        self.diff = diff;
+        Ok(())
+    }
+    fn files_to_push(
+        &self, patterns: &[&str], option: &HashMap<&str, &str>
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        self.call("files_to_push", json!({"patterns": patterns, "option": option}))
+    }
+    fn push_diff(
+        &self, remote_branch: &str, msg: &str, patterns: &Vec<&str>, option: &HashMap<&str, &str>
+    ) -> Result<bool, Box<dyn Error>> {
+        self.call("push_diff", json!({
+            "remote_branch": remote_branch, "message": msg, "patterns": patterns, "option": option
+        }))
+    }
+    fn push_files(
+        &self, source: &str, remote_branch: &str, option: &HashMap<&str, &str>
+    ) -> Result<bool, Box<dyn Error>> {
+        self.call("push_files", json!({
+            "source": source, "remote_branch": remote_branch, "option": option
+        }))
+    }
+    fn diff<'b>(&'b self) -> &'b vcs::ChangeSet {
+        &self.diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::mock;
+    use crate::vcs::VCS;
+
+    // vcs module at root/vcs-module with mock shell that answers the method by the responder
+    fn runner<F>(root: &std::path::Path, responder: F) -> Result<ModuleRunner<mock::Mock>, Box<dyn Error>>
+    where F: Fn(&str, JsonValue) -> String + 'static {
+        std::fs::create_dir_all(root.join("vcs-module")).unwrap();
+        std::fs::write(root.join("vcs-module/Deplo.Module.toml"), r#"
+version = "0.1.0"
+name = "vcs-module"
+author = { email = "foo@example.com" }
+entrypoints = { vcs = { linux = ["./vcs.sh"] } }
+"#).unwrap();
+        let container = config::Config::with(Some(r#"
+version = 1
+project_name = "test"
+[release_targets]
+[vcs]
+type = "module"
+uses = { path = "vcs-module" }
+[ci.default]
+type = "ghaction"
+account = "foo"
+key = "bar"
+[workflows]
+[jobs]
+"#)).unwrap();
+        container.borrow_mut().runtime.repository_root = root.to_path_buf();
+        container.create_module_repos()?;
+        container.load_module(&module::Source::Local{ path: Value::new("vcs-module") })?;
+        let shell = mock::Mock::with(&container, move |args, envs| {
+            if args[0] == "uname" {
+                return Ok("Linux".to_string());
+            }
+            assert_eq!(&args[..2], &["./vcs.sh", "vcs"]);
+            assert!(!envs.contains_key("DEPLO_MODULE_OPTION_STRING"));
+            Ok(responder(&args[2], serde_json::from_str(&envs["DEPLO_MODULE_REQUEST"]).unwrap()))
+        });
+        ModuleRunner::with(&container, shell)
+    }
+    #[test]
+    fn module_protocol_test() {
+        let root = tempfile::tempdir().unwrap();
+        let vcs = runner(root.path(), |method, request| match method {
+            "release_target" => r#"{"result": "nightly"}"#.to_string(),
+            "current_ref" => r#"{"result": {"type": "tag", "path": "v1.0.0"}}"#.to_string(),
+            "find_pr" => {
+                assert_eq!(request, json!({"head_branch": "deplo/docs", "base_branch": "main"}));
+                r#"{"result": {"url": "https://example.com/pr/1", "open": true}}"#.to_string()
+            },
+            "push_tag" => {
+                assert_eq!(request, json!({"tag_name": "v1.1.0", "commit": "abcd"}));
+                "{}".to_string()
+            },
+            "user_and_repo" => r#"{"result": {"user": "suntomi"}}"#.to_string(),
+            "branches" => r#"{"error": "not a repository"}"#.to_string(),
+            "log" => r#"{"result": [{"hash": "abcd"}]}"#.to_string(),
+            _ => panic!("unexpected method {} {}", method, request)
+        }).unwrap();
+        assert_eq!(vcs.release_target(), Some("nightly".to_string()));
+        assert_eq!(vcs.current_ref().unwrap(), (vcs::RefType::Tag, "v1.0.0".to_string()));
+        let pr = vcs.find_pr("deplo/docs", "main").unwrap().unwrap();
+        assert_eq!((pr.url.as_str(), pr.open), ("https://example.com/pr/1", true));
+        vcs.push_tag("v1.1.0", "abcd").unwrap();
+        // result that does not match with return type
+        assert!(vcs.user_and_repo().is_err());
+        assert!(vcs.log("a..b").is_err());
+        let err = vcs.branches().unwrap_err().to_string();
+        assert!(err.contains("fails to process vcs.branches: not a repository"), "{}", err);
+        assert_eq!(vcs.shell.executed(&["release_target"]).len(), 1);
+    }
+    #[test]
+    fn module_load_fails_test() {
+        let root = tempfile::tempdir().unwrap();
+        let err = runner(root.path(), |_, _| r#"{"error": "detached head"}"#.to_string()).err().unwrap().to_string();
+        assert!(err.contains("fails to process vcs.release_target: detached head"), "{}", err);
+    }
+}
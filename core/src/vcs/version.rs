@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::config;
+use crate::util::escalate;
+use crate::vcs;
+use crate::vcs::changelog;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+    // infer from conventional commits since last tag
+    Auto,
+}
+impl FromStr for Bump {
+    type Err = Box<dyn Error>;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            "patch" => Ok(Self::Patch),
+            "auto" => Ok(Self::Auto),
+            _ => escalate!(Box::new(vcs::VCSError {
+                cause: format!("invalid bump level {}: should be one of major, minor, patch, auto", s)
+            }))
+        }
+    }
+}
+impl fmt::Display for Bump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Major => write!(f, "major"),
+            Self::Minor => write!(f, "minor"),
+            Self::Patch => write!(f, "patch"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+// field order is significant for derived Ord
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+impl Version {
+    pub fn bump(&self, bump: Bump) -> Self {
+        match bump {
+            Bump::Major => Self { major: self.major + 1, minor: 0, patch: 0 },
+            Bump::Minor => Self { major: self.major, minor: self.minor + 1, patch: 0 },
+            Bump::Patch | Bump::Auto => Self { patch: self.patch + 1, ..self.clone() },
+        }
+    }
+}
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub name: String,
+    // prefix of version like "v". next tag is created with same prefix
+    pub prefix: String,
+    pub version: Version,
+}
+impl Tag {
+    // pre-release versions like 1.0.0-rc1 are not considered
+    pub fn parse(name: &str) -> Option<Self> {
+        let re = Regex::new(r"^(\D*)(\d+)\.(\d+)\.(\d+)$").unwrap();
+        let c = re.captures(name)?;
+        Some(Self {
+            name: name.to_string(),
+            prefix: c[1].to_string(),
+            version: Version {
+                major: c[2].parse().ok()?, minor: c[3].parse().ok()?, patch: c[4].parse().ok()?
+            }
+        })
+    }
+    pub fn ref_path(&self) -> String {
+        format!("refs/tags/{}", self.name)
+    }
+}
+
+/// latest version tag in output of VCS::tags.
+/// if release target is given, only tags that match with it are considered.
+/// otherwise only tags like 1.2.3 or v1.2.3 are considered.
+pub fn latest(tags: &[Vec<String>], target: Option<&config::release_target::ReleaseTarget>) -> Option<Tag> {
+    version_tags(tags, target)
+        .filter(|tag| target.is_some() || tag.prefix.is_empty() || tag.prefix == "v")
+        .max_by(|a, b| a.version.cmp(&b.version))
+}
+
+/// latest version tag that is older than current in output of VCS::tags.
+/// if release target is given, only tags that match with it are considered.
+/// otherwise only tags that have same prefix as current are considered.
+pub fn previous(
+    tags: &[Vec<String>], target: Option<&config::release_target::ReleaseTarget>, current: &Tag
+) -> Option<Tag> {
+    version_tags(tags, target)
+        .filter(|tag| target.is_some() || tag.prefix == current.prefix)
+        .filter(|tag| tag.version < current.version)
+        .max_by(|a, b| a.version.cmp(&b.version))
+}
+
+fn version_tags<'a>(
+    tags: &'a [Vec<String>], target: Option<&'a config::release_target::ReleaseTarget>
+) -> impl Iterator<Item = Tag> + 'a {
+    tags.iter()
+        // skip empty line and peeled entry of annotated tag (refs/tags/v1^{})
+        .filter(|t| t.len() >= 2 && !t[1].ends_with("^{}"))
+        .map(|t| t[1].replace("refs/tags/", ""))
+        .filter(move |name| target.map_or(true, |t| t.matches(name)))
+        .filter_map(|name| Tag::parse(&name))
+}
+
+/// infer bump level from conventional commits. None if there is no change
+pub fn infer(entries: &[changelog::Entry]) -> Option<Bump> {
+    if entries.is_empty() {
+        None
+    } else if entries.iter().any(|e| e.breaking) {
+        Some(Bump::Major)
+    } else if entries.iter().any(|e| e.ty.as_deref() == Some("feat")) {
+        Some(Bump::Minor)
+    } else {
+        Some(Bump::Patch)
+    }
+}
+
+pub struct Plan {
+    pub current: Option<Tag>,
+    pub bump: Bump,
+    pub next: String,
+    pub commit: String,
+}
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{} => {} ({} bump) at {}",
+            self.current.as_ref().map_or("(no version tag)", |t| t.name.as_str()),
+            self.next, self.bump, self.commit
+        )
+    }
+}
+
+/// decide next version tag for current HEAD
+pub fn plan(
+    config: &config::Config, bump: Bump, release_target: Option<&str>
+) -> Result<Plan, Box<dyn Error>> {
+    let vcs = config.modules.vcs();
+    let target = match release_target {
+        Some(name) => match config.release_targets.get(name) {
+            Some(t) if t.is_tag() => Some(t),
+            Some(_) => return escalate!(Box::new(vcs::VCSError {
+                cause: format!("release target {} is not tag based", name)
+            })),
+            None => return escalate!(Box::new(vcs::VCSError {
+                cause: format!("no such release target {}", name)
+            }))
+        },
+        None => None
+    };
+    let current = latest(&vcs.tags()?, target);
+    let commit = vcs.commit_hash(None)?;
+    let changes = match &current {
+        Some(tag) => {
+            // tag may not be fetched in shallow or tag-less clone
+            vcs.fetch_object(&tag.ref_path(), &tag.ref_path(), None)?;
+            changelog::entries(config, &tag.ref_path(), "HEAD")?
+        },
+        None => vec![]
+    };
+    let bump = match (&current, bump) {
+        (Some(tag), bump) => match infer(&changes) {
+            Some(inferred) => if bump == Bump::Auto { inferred } else { bump },
+            None => return escalate!(Box::new(vcs::VCSError {
+                cause: format!("no change since {}", tag.name)
+            }))
+        },
+        // first release
+        (None, Bump::Auto) => Bump::Minor,
+        (None, bump) => bump
+    };
+    let (prefix, version) = current.as_ref().map_or(
+        (String::new(), Version::default()), |t| (t.prefix.clone(), t.version.clone())
+    );
+    let next = format!("{}{}", prefix, version.bump(bump));
+    if let Some(target) = target {
+        if !target.matches(&next) {
+            return escalate!(Box::new(vcs::VCSError {
+                cause: format!("next tag {} does not match with release target", next)
+            }));
+        }
+    }
+    Ok(Plan { current, bump, next, commit })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_test() {
+        let tags = vec![
+            vec!["a".to_string(), "refs/tags/v1.2.0".to_string()],
+            vec!["b".to_string(), "refs/tags/v1.10.0".to_string()],
+            vec!["c".to_string(), "refs/tags/v1.10.0^{}".to_string()],
+            vec!["d".to_string(), "refs/tags/test-9.0.0".to_string()],
+            vec!["e".to_string(), "refs/tags/v2.0.0-rc1".to_string()],
+            vec![],
+        ];
+        let tag = latest(&tags, None).unwrap();
+        assert_eq!(tag.name, "v1.10.0");
+        assert_eq!(format!("{}{}", tag.prefix, tag.version.bump(Bump::Minor)), "v1.11.0");
+        assert_eq!(tag.version.bump(Bump::Major).to_string(), "2.0.0");
+        assert_eq!(tag.version.bump(Bump::Patch).to_string(), "1.10.1");
+        let target = toml::from_str::<config::release_target::ReleaseTarget>(
+            r#"tag = true
+               patterns = ["test-[0-9]*"]"#
+        ).unwrap();
+        assert_eq!(latest(&tags, Some(&target)).unwrap().name, "test-9.0.0");
+    }
+
+    #[test]
+    fn previous_test() {
+        // listed in lexicographic order like ls-remote
+        let tags = ["v1.10.0", "v1.11.0", "v1.9.0", "v2.0.0", "test-1.9.5"].iter()
+            .map(|t| vec!["hash".to_string(), format!("refs/tags/{}", t)]).collect::<Vec<_>>();
+        let previous_of = |name: &str, target| previous(&tags, target, &Tag::parse(name).unwrap()).map(|t| t.name);
+        assert_eq!(previous_of("v1.10.0", None).as_deref(), Some("v1.9.0"));
+        assert_eq!(previous_of("v2.0.0", None).as_deref(), Some("v1.11.0"));
+        assert_eq!(previous_of("v1.9.0", None), None);
+        // current tag does not need to exist
+        assert_eq!(previous_of("v1.10.5", None).as_deref(), Some("v1.10.0"));
+        let target = toml::from_str::<config::release_target::ReleaseTarget>(
+            r#"tag = true
+               patterns = ["test-*"]"#
+        ).unwrap();
+        assert_eq!(previous_of("test-1.10.0", Some(&target)).as_deref(), Some("test-1.9.5"));
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde_json::{Value as JsonValue};
+
+use crate::util::{escalate};
+use crate::vcs;
+
+/// package in the workspace. path is relative to repository root and empty for root package.
+#[derive(Debug)]
+pub struct Package {
+    pub name: String,
+    pub path: String,
+    // names of workspace packages that this package depends on
+    pub dependencies: Vec<String>,
+}
+/// package dependency graph of the workspace
+#[derive(Debug, Default)]
+pub struct Workspace {
+    pub packages: HashMap<String, Package>,
+    // files that affect all packages, like lock file
+    pub shared_files: Vec<String>,
+}
+impl Workspace {
+    /// names of the packages and all its (transitive) workspace dependencies
+    pub fn closure(&self, names: &Vec<String>) -> Result<HashSet<String>, Box<dyn Error>> {
+        let mut result = HashSet::new();
+        let mut queue = names.iter().map(|v| v.to_string()).collect::<VecDeque<String>>();
+        while let Some(name) = queue.pop_front() {
+            if result.contains(&name) {
+                continue
+            }
+            match self.packages.get(&name) {
+                Some(p) => queue.extend(p.dependencies.iter().cloned()),
+                None => return escalate!(Box::new(vcs::VCSError {
+                    cause: format!(
+                        "package {} does not found in workspace ({})", name,
+                        self.packages.keys().map(|v| v.as_str()).collect::<Vec<&str>>().join(",")
+                    )
+                }))
+            }
+            result.insert(name);
+        }
+        Ok(result)
+    }
+    /// package that the file belongs to. nested package takes precedence.
+    pub fn owner(&self, path: &str) -> Option<&Package> {
+        self.packages.values().filter(|p| {
+            p.path.is_empty() || path.starts_with(&format!("{}/", p.path))
+        }).max_by_key(|p| p.path.len())
+    }
+    /// true if any of paths belongs to the packages or those dependencies
+    pub fn changed(&self, names: &Vec<String>, paths: &Vec<&str>) -> Result<bool, Box<dyn Error>> {
+        let closure = self.closure(names)?;
+        Ok(paths.iter().any(|path| {
+            self.shared_files.iter().any(|f| f == path) ||
+            self.owner(path).map_or(false, |p| closure.contains(&p.name))
+        }))
+    }
+}
+
+/// resolves package dependency graph from manifest files of the workspace
+pub trait Resolver {
+    fn name(&self) -> &'static str;
+    /// returns None if root does not contain the workspace of this type
+    fn resolve(&self, root: &Path) -> Result<Option<Workspace>, Box<dyn Error>>;
+}
+pub fn resolvers() -> Vec<Box<dyn Resolver>> {
+    vec![Box::new(Cargo), Box::new(Npm)]
+}
+pub fn has_resolver(name: &str) -> bool {
+    resolvers().iter().any(|r| r.name() == name)
+}
+type WorkspaceCache = HashMap<(PathBuf, Option<String>), Arc<Workspace>>;
+lazy_static! {
+    // manifests are not changed while the run checks changes of packages, so workspace is resolved once per run
+    static ref G_WORKSPACES: RwLock<WorkspaceCache> = {
+        RwLock::new(HashMap::new())
+    };
+}
+/// resolve workspace at root with the resolver. if name is omitted, first resolver that finds workspace is used.
+/// resolved workspace is cached for the same root and resolver.
+pub fn resolve(root: &Path, name: Option<&str>) -> Result<Arc<Workspace>, Box<dyn Error>> {
+    let key = (normalize(root), name.map(|v| v.to_string()));
+    if let Some(ws) = G_WORKSPACES.read().unwrap().get(&key) {
+        return Ok(ws.clone());
+    }
+    let ws = Arc::new(find(root, name)?);
+    G_WORKSPACES.write().unwrap().insert(key, ws.clone());
+    Ok(ws)
+}
+fn find(root: &Path, name: Option<&str>) -> Result<Workspace, Box<dyn Error>> {
+    for r in resolvers() {
+        if name.map_or(false, |n| n != r.name()) {
+            continue
+        }
+        if let Some(ws) = r.resolve(root)? {
+            log::debug!("resolve workspace by {}: {:?}", r.name(), ws.packages.keys());
+            return Ok(ws)
+        }
+    }
+    escalate!(Box::new(vcs::VCSError {
+        cause: format!("no workspace found at {} with resolver {}", root.display(), name.unwrap_or("any"))
+    }))
+}
+
+// lexically normalize path, without accessing file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {},
+            Component::ParentDir => { result.pop(); },
+            c => result.push(c.as_os_str())
+        }
+    }
+    result
+}
+// path relative to root in slash separated form. None if path is outside of root
+fn relative(root: &Path, path: &Path) -> Option<String> {
+    normalize(path).strip_prefix(root).ok().map(|p| {
+        p.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect::<Vec<String>>().join("/")
+    })
+}
+// expand member globs of workspace manifest into directories
+fn expand_members(root: &Path, patterns: &Vec<String>, excludes: &Vec<String>) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let excludes = excludes.iter().map(|v| normalize(&root.join(v))).collect::<Vec<PathBuf>>();
+    let mut dirs = vec![];
+    for pattern in patterns {
+        for entry in glob::glob(&root.join(pattern).to_string_lossy())? {
+            let dir = normalize(&entry?);
+            if dir.is_dir() && !excludes.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// cargo workspace. packages are members of the workspace and path dependencies of them.
+pub struct Cargo;
+impl Cargo {
+    fn path_dependencies(dir: &Path, manifest: &toml::Value, workspace_deps: &HashMap<String, PathBuf>) -> Vec<PathBuf> {
+        let mut tables = vec![manifest];
+        if let Some(targets) = manifest.get("target").and_then(|v| v.as_table()) {
+            tables.extend(targets.values());
+        }
+        let mut paths = vec![];
+        for t in tables {
+            for key in ["dependencies", "dev-dependencies", "build-dependencies"] {
+                let deps = match t.get(key).and_then(|v| v.as_table()) {
+                    Some(v) => v,
+                    None => continue
+                };
+                for (name, dep) in deps {
+                    if let Some(path) = dep.get("path").and_then(|v| v.as_str()) {
+                        paths.push(normalize(&dir.join(path)));
+                    } else if dep.get("workspace").and_then(|v| v.as_bool()).unwrap_or(false) {
+                        // renamed dependency refers workspace.dependencies with its key
+                        if let Some(path) = workspace_deps.get(name) {
+                            paths.push(path.clone());
+                        }
+                    }
+                }
+            }
+        }
+        paths
+    }
+    fn read_manifest(dir: &Path) -> Result<Option<toml::Value>, Box<dyn Error>> {
+        match fs::read_to_string(dir.join("Cargo.toml")) {
+            Ok(s) => Ok(Some(toml::from_str(&s)?)),
+            Err(_) => Ok(None)
+        }
+    }
+}
+impl Resolver for Cargo {
+    fn name(&self) -> &'static str { "cargo" }
+    fn resolve(&self, root: &Path) -> Result<Option<Workspace>, Box<dyn Error>> {
+        let root = normalize(root);
+        let manifest = match Self::read_manifest(&root)? {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+        let strings = |v: Option<&toml::Value>| v.and_then(|v| v.as_array()).map_or(vec![], |a| {
+            a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect::<Vec<String>>()
+        });
+        let workspace = manifest.get("workspace");
+        let workspace_deps: HashMap<String, PathBuf> = workspace.and_then(|w| w.get("dependencies")).and_then(|v| v.as_table()).map_or(
+            HashMap::new(), |deps| deps.iter().filter_map(|(name, dep)| {
+                dep.get("path").and_then(|v| v.as_str()).map(|p| (name.to_string(), normalize(&root.join(p))))
+            }).collect()
+        );
+        let mut queue = match workspace {
+            Some(w) => expand_members(
+                &root, &strings(w.get("members")), &strings(w.get("exclude"))
+            )?.into_iter().collect::<VecDeque<PathBuf>>(),
+            None => VecDeque::new()
+        };
+        if manifest.get("package").is_some() {
+            queue.push_front(root.clone());
+        }
+        // collect packages by directory, then convert dependency directories into package names
+        let mut packages_by_dir: HashMap<PathBuf, (String, Vec<PathBuf>)> = HashMap::new();
+        while let Some(dir) = queue.pop_front() {
+            if packages_by_dir.contains_key(&dir) {
+                continue
+            }
+            let m = match Self::read_manifest(&dir)? {
+                Some(v) => v,
+                None => {
+                    log::warn!("cargo: no Cargo.toml in {}", dir.display());
+                    continue
+                }
+            };
+            let name = match m.get("package").and_then(|p| p.get("name")).and_then(|v| v.as_str()) {
+                Some(v) => v.to_string(),
+                // virtual manifest
+                None => continue
+            };
+            let deps = Self::path_dependencies(&dir, &m, &workspace_deps);
+            queue.extend(deps.iter().cloned());
+            packages_by_dir.insert(dir, (name, deps));
+        }
+        let mut ws = Workspace {
+            packages: HashMap::new(),
+            shared_files: vec!["Cargo.toml".to_string(), "Cargo.lock".to_string()]
+        };
+        for (dir, (name, deps)) in &packages_by_dir {
+            let path = match relative(&root, dir) {
+                Some(v) => v,
+                None => {
+                    log::debug!("cargo: ignore package {} outside of repository {}", name, dir.display());
+                    continue
+                }
+            };
+            ws.packages.insert(name.to_string(), Package {
+                name: name.to_string(), path,
+                dependencies: deps.iter().filter_map(|d| packages_by_dir.get(d).map(|(n, _)| n.to_string())).collect()
+            });
+        }
+        Ok(Some(ws))
+    }
+}
+/// npm/yarn workspaces (package.json) or pnpm workspace (pnpm-workspace.yaml).
+/// workspace packages depend each other by package name.
+pub struct Npm;
+impl Npm {
+    fn read_package_json(dir: &Path) -> Result<Option<JsonValue>, Box<dyn Error>> {
+        match fs::read_to_string(dir.join("package.json")) {
+            Ok(s) => Ok(Some(serde_json::from_str(&s)?)),
+            Err(_) => Ok(None)
+        }
+    }
+    fn member_patterns(root: &Path, package_json: &JsonValue) -> Result<Vec<String>, Box<dyn Error>> {
+        let to_strings = |v: Option<&JsonValue>| v.and_then(|v| v.as_array()).map_or(vec![], |a| {
+            a.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect::<Vec<String>>()
+        });
+        if let Ok(s) = fs::read_to_string(root.join("pnpm-workspace.yaml")) {
+            let v: JsonValue = serde_yaml::from_str(&s)?;
+            return Ok(to_strings(v.get("packages")));
+        }
+        // yarn allows { packages = [...] } form
+        Ok(match package_json.get("workspaces") {
+            Some(JsonValue::Object(o)) => to_strings(o.get("packages")),
+            v => to_strings(v)
+        })
+    }
+}
+impl Resolver for Npm {
+    fn name(&self) -> &'static str { "npm" }
+    fn resolve(&self, root: &Path) -> Result<Option<Workspace>, Box<dyn Error>> {
+        let root = normalize(root);
+        let package_json = match Self::read_package_json(&root)? {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+        let patterns = Self::member_patterns(&root, &package_json)?;
+        // pnpm and yarn allows !pattern to exclude
+        let (excludes, includes): (Vec<String>, Vec<String>) = patterns.into_iter().partition(|p| p.starts_with('!'));
+        let excludes = excludes.iter().map(|p| p[1..].to_string()).collect();
+        let mut manifests = vec![(root.clone(), package_json)];
+        for dir in expand_members(&root, &includes, &excludes)? {
+            if let Some(m) = Self::read_package_json(&dir)? {
+                manifests.push((dir, m));
+            }
+        }
+        let names = manifests.iter().filter_map(|(_, m)| {
+            m.get("name").and_then(|v| v.as_str()).map(|v| v.to_string())
+        }).collect::<HashSet<String>>();
+        let mut ws = Workspace {
+            packages: HashMap::new(),
+            shared_files: vec![
+                "package.json", "package-lock.json", "yarn.lock", "pnpm-lock.yaml", "pnpm-workspace.yaml"
+            ].iter().map(|v| v.to_string()).collect()
+        };
+        for (dir, m) in &manifests {
+            let name = match m.get("name").and_then(|v| v.as_str()) {
+                Some(v) => v.to_string(),
+                None => continue
+            };
+            let mut dependencies = vec![];
+            for key in ["dependencies", "devDependencies", "peerDependencies", "optionalDependencies"] {
+                if let Some(deps) = m.get(key).and_then(|v| v.as_object()) {
+                    dependencies.extend(deps.keys().filter(|d| names.contains(*d)).cloned());
+                }
+            }
+            ws.packages.insert(name.to_string(), Package {
+                name, path: relative(&root, dir).unwrap_or_default(), dependencies
+            });
+        }
+        Ok(Some(ws))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cargo_workspace_test() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let p = root.path().join(path);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            fs::write(p, content).unwrap();
+        };
+        write("Cargo.toml", "[workspace]\nmembers = [\"crates/*\", \"cli\"]\n[workspace.dependencies]\nutil = { path = \"crates/util\" }\n");
+        write("cli/Cargo.toml", "[package]\nname = \"cli\"\n[dependencies]\ncore = { path = \"../crates/core\" }\n");
+        write("crates/core/Cargo.toml", "[package]\nname = \"core\"\n[dependencies]\nutil = { workspace = true }\n");
+        write("crates/util/Cargo.toml", "[package]\nname = \"util\"\n[target.'cfg(unix)'.dev-dependencies]\nlibs = { path = \"../../libs\" }\n");
+        write("libs/Cargo.toml", "[package]\nname = \"libs\"\n");
+        write("crates/other/Cargo.toml", "[package]\nname = \"other\"\n");
+        let ws = resolve(root.path(), None).unwrap();
+        assert_eq!(ws.packages.len(), 5);
+        assert_eq!(ws.packages["libs"].path, "libs");
+        let closure = ws.closure(&vec!["cli".to_string()]).unwrap();
+        assert_eq!(closure, ["cli", "core", "util", "libs"].iter().map(|v| v.to_string()).collect());
+        let changed = |paths: &[&str]| ws.changed(&vec!["cli".to_string()], &paths.to_vec()).unwrap();
+        assert!(changed(&["libs/src/lib.rs"]));
+        assert!(changed(&["Cargo.lock"]));
+        assert!(!changed(&["crates/other/src/lib.rs", "docs/README.md"]));
+        assert!(ws.closure(&vec!["nothing".to_string()]).is_err());
+    }
+    #[test]
+    fn npm_workspace_test() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let p = root.path().join(path);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            fs::write(p, content).unwrap();
+        };
+        write("package.json", r#"{"name": "root", "workspaces": {"packages": ["packages/*", "!packages/ignored"]}}"#);
+        write("packages/app/package.json", r#"{"name": "app", "dependencies": {"ui": "*", "react": "^18"}}"#);
+        write("packages/ui/package.json", r#"{"name": "ui", "peerDependencies": {"utils": "*"}}"#);
+        write("packages/utils/package.json", r#"{"name": "utils", "devDependencies": {"typescript": "^5"}}"#);
+        write("packages/ignored/package.json", r#"{"name": "ignored"}"#);
+        write("packages/noname/package.json", r#"{"private": true}"#);
+        let ws = resolve(root.path(), Some("npm")).unwrap();
+        assert_eq!(
+            ws.packages.keys().map(|v| v.as_str()).collect::<HashSet<&str>>(),
+            ["root", "app", "ui", "utils"].into_iter().collect()
+        );
+        assert_eq!(ws.packages["root"].path, "");
+        assert_eq!(ws.packages["ui"].path, "packages/ui");
+        // dependencies outside of workspace are not included
+        assert_eq!(ws.packages["app"].dependencies, vec!["ui"]);
+        let closure = ws.closure(&vec!["app".to_string()]).unwrap();
+        assert_eq!(closure, ["app", "ui", "utils"].iter().map(|v| v.to_string()).collect());
+        let changed = |paths: &[&str]| ws.changed(&vec!["app".to_string()], &paths.to_vec()).unwrap();
+        assert!(changed(&["packages/utils/index.ts"]));
+        assert!(changed(&["yarn.lock"]));
+        // files of root package only belong to root
+        assert!(!changed(&["README.md", "packages/ignored/index.ts"]));
+        // pnpm-workspace.yaml takes precedence over workspaces of package.json
+        write("pnpm-workspace.yaml", "packages:\n  - packages/app\n");
+        let ws = resolve(root.path(), Some("npm")).unwrap();
+        assert_eq!(ws.packages.len(), 4, "resolved workspace is cached");
+        let ws = find(root.path(), None).unwrap();
+        assert_eq!(
+            ws.packages.keys().map(|v| v.as_str()).collect::<HashSet<&str>>(),
+            ["root", "app"].into_iter().collect()
+        );
+        assert!(ws.packages["app"].dependencies.is_empty());
+    }
+}